@@ -0,0 +1,122 @@
+//! Benchmarks of tree building, metadata parsing and completion over generated trees of
+//! 1,000 and 10,000 scripts (groups of 100).
+//!
+//! ```bash
+//! cargo bench --bench tree -- --save-baseline main   # on the base branch
+//! cargo bench --bench tree -- --baseline main        # on the change, compared with it
+//! ```
+
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use shutl::command::{build_command_tree, build_completion_tree_from, collect_entries};
+use shutl::metadata::{CommandMetadata, parse_command_metadata, parse_metadata_elements};
+use shutl::source::FsSource;
+use std::hint::black_box;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+const SIZES: [usize; 2] = [1_000, 10_000];
+
+const SCRIPTS_PER_GROUP: usize = 100;
+
+/// Header of every generated script, touching the common kinds of metadata
+const HEADER: &str = "#!/bin/bash
+#@description: Deploys the service to a cluster
+#@arg:cluster - Cluster to deploy to [options:dev|staging|prod]
+#@arg:...services - Services to deploy [min:1]
+#@flag:region - Region [default:eu-west-1]
+#@flag:dry-run - Only print what would change [bool]
+#@flag:timeout - Seconds to wait [int,default:300]
+#@example: deploy prod api web
+#@env-deny: GITHUB_TOKEN
+";
+
+/// Generates `size` scripts in groups `group0/script0.sh` ... with a `.shutl` file each
+fn generate_tree(size: usize) -> TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    for group in 0..size.div_ceil(SCRIPTS_PER_GROUP) {
+        let group_dir = dir.path().join(format!("group{}", group));
+        std::fs::create_dir(&group_dir).unwrap();
+        std::fs::write(group_dir.join(".shutl"), format!("Group {}\n", group)).unwrap();
+        for script in 0..SCRIPTS_PER_GROUP.min(size - group * SCRIPTS_PER_GROUP) {
+            let path = group_dir.join(format!("script{}.sh", script));
+            std::fs::write(&path, format!("{}echo {}\n", HEADER, script)).unwrap();
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        }
+    }
+    dir
+}
+
+fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+}
+
+fn trees() -> Vec<(usize, TempDir)> {
+    // Don't let the config of the machine running the benchmarks add roots or profiles
+    unsafe { std::env::set_var("SHUTL_CONFIG", "/nonexistent/shutl/config.toml") };
+    SIZES
+        .iter()
+        .map(|&size| (size, generate_tree(size)))
+        .collect()
+}
+
+fn bench_tree(c: &mut Criterion) {
+    let trees = trees();
+    let mut group = c.benchmark_group("tree");
+    for (size, dir) in &trees {
+        let root = dir.path();
+        group.bench_with_input(BenchmarkId::new("top_level", size), root, |b, root| {
+            b.iter(|| build_command_tree(black_box(root), &[]))
+        });
+        group.bench_with_input(BenchmarkId::new("group", size), root, |b, root| {
+            b.iter(|| build_command_tree(black_box(root), &args(&["group0"])))
+        });
+        group.bench_with_input(BenchmarkId::new("list", size), root, |b, root| {
+            b.iter(|| collect_entries(black_box(root), ""))
+        });
+    }
+    group.finish();
+}
+
+fn bench_completion(c: &mut Criterion) {
+    let trees = trees();
+    let mut group = c.benchmark_group("completion");
+    let complete = |root: &Path, words: &[&str]| {
+        build_completion_tree_from(&FsSource, black_box(root), &args(words))
+    };
+    for (size, dir) in &trees {
+        let root = dir.path();
+        group.bench_with_input(BenchmarkId::new("top_level", size), root, |b, root| {
+            b.iter(|| complete(root, &["gr"]))
+        });
+        group.bench_with_input(BenchmarkId::new("group", size), root, |b, root| {
+            b.iter(|| complete(root, &["group0", "script1"]))
+        });
+        group.bench_with_input(BenchmarkId::new("flag", size), root, |b, root| {
+            b.iter(|| complete(root, &["group0", "script1", "--re"]))
+        });
+    }
+    group.finish();
+}
+
+fn bench_metadata(c: &mut Criterion) {
+    let dir = generate_tree(1);
+    let script: PathBuf = dir.path().join("group0/script0.sh");
+    let contents = std::fs::read_to_string(&script).unwrap();
+    let mut group = c.benchmark_group("metadata");
+    group.bench_function("parse_header", |b| {
+        b.iter(|| {
+            CommandMetadata::from_elements(parse_metadata_elements(
+                black_box(&script),
+                black_box(&contents),
+            ))
+        })
+    });
+    group.bench_function("read_script", |b| {
+        b.iter(|| parse_command_metadata(black_box(&script)))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_tree, bench_completion, bench_metadata);
+criterion_main!(benches);
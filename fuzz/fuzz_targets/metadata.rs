@@ -0,0 +1,16 @@
+//! Feeds arbitrary script headers and `.shutl` files to the metadata parser, which must
+//! never panic on them: `cargo +nightly fuzz run metadata`
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use shutl::metadata::{CommandMetadata, parse_dir_metadata_contents, parse_metadata_elements};
+use std::path::Path;
+
+fuzz_target!(|header: &str| {
+    // The extension selects the comment syntax; PowerShell also has block comments
+    for path in ["tool.sh", "tool.ps1", "tool.py"] {
+        CommandMetadata::from_elements(parse_metadata_elements(Path::new(path), header));
+    }
+    parse_dir_metadata_contents(Path::new("/scripts/group"), header);
+});
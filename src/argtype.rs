@@ -0,0 +1,106 @@
+//! Argument types beyond the built-in `bool`, `file`, `dir` and `path`. A type is an
+//! [`ArgTypeProvider`] known under its annotation name, so `#@flag:branch - Branch
+//! [git-branch]` gets its completion and validation from the `git-branch` provider.
+//! Library users [`register`] their own before scripts are parsed.
+
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+
+/// Completion and validation for the values of one argument type
+pub trait ArgTypeProvider: Send + Sync {
+    /// Annotation name of the type, e.g. `git-branch`
+    fn name(&self) -> &str;
+
+    /// Checks a value given on the command line; the error is shown as clap's usage
+    /// error. `option` is the text after the colon in `[name:option]`, empty without one.
+    fn validate(&self, _value: &str, _option: &str) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Lists completion candidates, each `value` or `value<TAB>description` like the
+    /// output of a `complete-self` script. Candidates are filtered by the current word.
+    fn candidates(&self, _option: &str) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+static PROVIDERS: Mutex<Vec<Arc<dyn ArgTypeProvider>>> = Mutex::new(Vec::new());
+
+/// Registers a type for all scripts parsed afterwards. It replaces a built-in or earlier
+/// type with the same name.
+pub fn register(provider: impl ArgTypeProvider + 'static) {
+    PROVIDERS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .push(Arc::new(provider));
+}
+
+/// Finds the type named `name`, preferring registered types over built-in ones
+pub fn lookup(name: &str) -> Option<Arc<dyn ArgTypeProvider>> {
+    let registered = PROVIDERS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .rev()
+        .find(|p| p.name() == name)
+        .cloned();
+    registered.or_else(|| builtin_providers().into_iter().find(|p| p.name() == name))
+}
+
+/// Types that ship with shutl
+fn builtin_providers() -> Vec<Arc<dyn ArgTypeProvider>> {
+    vec![Arc::new(GitBranch)]
+}
+
+/// Local branches of the git repository in the working directory (`[git-branch]`)
+struct GitBranch;
+
+impl ArgTypeProvider for GitBranch {
+    fn name(&self) -> &str {
+        "git-branch"
+    }
+
+    fn candidates(&self, _option: &str) -> Vec<String> {
+        let output = Command::new("git")
+            .args(["for-each-ref", "--format=%(refname:short)", "refs/heads"])
+            .output();
+        match output {
+            Ok(output) if output.status.success() => String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .map(String::from)
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Port;
+
+    impl ArgTypeProvider for Port {
+        fn name(&self) -> &str {
+            "test-port"
+        }
+
+        fn validate(&self, value: &str, _option: &str) -> Result<(), String> {
+            value
+                .parse::<u16>()
+                .map(|_| ())
+                .map_err(|_| format!("'{}' is not a port", value))
+        }
+    }
+
+    #[test]
+    fn test_lookup() {
+        assert!(lookup("git-branch").is_some());
+        assert!(lookup("test-port").is_none());
+
+        register(Port);
+        let port = lookup("test-port").unwrap();
+        assert!(port.validate("8080", "").is_ok());
+        assert!(port.validate("http", "").is_err());
+    }
+}
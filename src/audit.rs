@@ -0,0 +1,197 @@
+//! Audit log of script runs (`audit-log` in the config), for shared machines such as jump
+//! hosts. Unlike the history it records who ran what where and how it ended, and it is
+//! only ever appended to; once it reaches `audit-log-max-bytes` it is rotated to
+//! `<file>.1`, `<file>.2`, ... keeping `audit-log-keep` old files.
+
+use crate::config;
+use crate::history;
+use crate::observer::{ExecutionObserver, Run};
+use crate::paths;
+use crate::persist;
+use serde::{Deserialize, Serialize};
+use std::ffi::CStr;
+use std::io::Write;
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+use std::time::Duration;
+
+/// Size at which the audit log is rotated unless `audit-log-max-bytes` is set
+pub const DEFAULT_MAX_BYTES: u64 = 10 * 1024 * 1024;
+
+/// Number of rotated files kept unless `audit-log-keep` is set
+pub const DEFAULT_KEEP: usize = 5;
+
+/// A finished script run
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// Seconds since the Unix epoch
+    pub timestamp: u64,
+    /// Login name of the real user, looked up from the uid rather than `$USER`
+    pub user: String,
+    pub uid: u32,
+    pub host: String,
+    /// Working directory of the run
+    pub cwd: String,
+    /// Command path, e.g. `deploy/prod`
+    pub command: String,
+    pub script: String,
+    /// Arguments after the command path, with secret values masked
+    pub args: Vec<String>,
+    /// Exit code, if the script exited rather than being killed by a signal
+    pub exit_code: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signal: Option<i32>,
+    pub duration_ms: u64,
+}
+
+impl AuditEntry {
+    pub fn new(run: &Run, status: ExitStatus, elapsed: Duration) -> Self {
+        let uid = unsafe { libc::getuid() };
+        AuditEntry {
+            timestamp: history::now(),
+            user: user_name(uid).unwrap_or_else(|| uid.to_string()),
+            uid,
+            host: host_name().unwrap_or_default(),
+            cwd: std::env::current_dir()
+                .map(|dir| dir.display().to_string())
+                .unwrap_or_default(),
+            command: run.components.join("/"),
+            script: run.script_path.display().to_string(),
+            args: run.args.clone(),
+            exit_code: status.code(),
+            signal: status.signal(),
+            duration_ms: elapsed.as_millis() as u64,
+        }
+    }
+}
+
+/// Appends every finished run to the audit log
+pub struct AuditObserver {
+    pub path: PathBuf,
+    pub max_bytes: u64,
+    pub keep: usize,
+}
+
+impl AuditObserver {
+    /// Sets up the audit log of the config, if it configures one
+    pub fn from_config() -> Option<Self> {
+        let config = config::get();
+        let path = config.audit_log.as_ref()?;
+        let path = paths::expand(path)
+            .map(|p| p.to_string())
+            .unwrap_or_else(|_| path.clone());
+        Some(AuditObserver {
+            path: PathBuf::from(path),
+            max_bytes: config.audit_log_max_bytes.unwrap_or(DEFAULT_MAX_BYTES),
+            keep: config.audit_log_keep.unwrap_or(DEFAULT_KEEP),
+        })
+    }
+
+    /// Appends `entry`, rotating the log first if it is full. Runs of other shutl
+    /// processes wait for the lock, so neither entries nor rotations get mixed up.
+    pub fn append(&self, entry: &AuditEntry) -> std::io::Result<()> {
+        let line = format!("{}\n", serde_json::to_string(entry)?);
+        let mut file = persist::open_append_locked(&self.path)?;
+        if file.metadata()?.len() >= self.max_bytes {
+            rotate(&self.path, self.keep)?;
+            file = persist::open_append_locked(&self.path)?;
+        }
+        file.write_all(line.as_bytes())
+    }
+}
+
+impl ExecutionObserver for AuditObserver {
+    fn on_exit(&self, run: &Run, status: ExitStatus, elapsed: Duration) {
+        if let Err(e) = self.append(&AuditEntry::new(run, status, elapsed)) {
+            eprintln!(
+                "Warning: failed to write audit log {}: {}",
+                self.path.display(),
+                e
+            );
+        }
+    }
+}
+
+/// Moves `path` to `path.1`, `path.1` to `path.2` and so on, dropping the oldest beyond
+/// `keep`. With `keep` 0 the log starts over.
+pub fn rotate(path: &Path, keep: usize) -> std::io::Result<()> {
+    let rotated = |n: usize| {
+        let mut name = path.as_os_str().to_owned();
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    };
+    if keep == 0 {
+        return std::fs::remove_file(path);
+    }
+    for n in (1..keep).rev() {
+        let from = rotated(n);
+        if from.exists() {
+            std::fs::rename(&from, rotated(n + 1))?;
+        }
+    }
+    std::fs::rename(path, rotated(1))
+}
+
+fn user_name(uid: u32) -> Option<String> {
+    // SAFETY: getpwuid returns null or a pointer to static storage, which is copied before
+    // anything else could call it
+    let passwd = unsafe { libc::getpwuid(uid) };
+    if passwd.is_null() {
+        return None;
+    }
+    let name = unsafe { CStr::from_ptr((*passwd).pw_name) };
+    Some(name.to_string_lossy().into_owned())
+}
+
+fn host_name() -> Option<String> {
+    let mut buf = [0 as libc::c_char; 256];
+    if unsafe { libc::gethostname(buf.as_mut_ptr(), buf.len()) } != 0 {
+        return None;
+    }
+    let name = unsafe { CStr::from_ptr(buf.as_ptr()) };
+    Some(name.to_string_lossy().into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_append_and_rotate() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("logs/audit.jsonl");
+        let audit = AuditObserver {
+            path: path.clone(),
+            max_bytes: 1,
+            keep: 2,
+        };
+        let run = Run {
+            components: vec!["deploy".to_string(), "prod".to_string()],
+            script_path: PathBuf::from("/scripts/deploy/prod.sh"),
+            args: vec!["--token".to_string(), "********".to_string()],
+        };
+        let entry = AuditEntry::new(&run, ExitStatus::from_raw(3 << 8), Duration::from_millis(5));
+        assert_eq!(entry.command, "deploy/prod");
+        assert_eq!(entry.exit_code, Some(3));
+        assert_eq!(entry.signal, None);
+        assert_eq!(entry.duration_ms, 5);
+
+        // Every append after the first finds the log full
+        for _ in 0..4 {
+            audit.append(&entry).unwrap();
+        }
+        let read = |path: &Path| -> Vec<AuditEntry> {
+            std::fs::read_to_string(path)
+                .unwrap()
+                .lines()
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect()
+        };
+        assert_eq!(read(&path), vec![entry.clone()]);
+        assert_eq!(read(&dir.path().join("logs/audit.jsonl.1")).len(), 1);
+        assert_eq!(read(&dir.path().join("logs/audit.jsonl.2")).len(), 1);
+        assert!(!dir.path().join("logs/audit.jsonl.3").exists());
+    }
+}
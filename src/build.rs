@@ -0,0 +1,167 @@
+//! Compiled scripts: single-file Go and Rust programs (or anything with `#@build:`)
+//! are compiled on first run and the binary is cached until the source changes.
+
+use crate::paths;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Gets the build command of a script: the `#@build:` metadata (`declared`), else the
+/// default for `.go` and `.rs` files. `{src}` and `{out}` stand for the source file and
+/// the binary to produce.
+pub fn build_command(script_path: &Path, declared: Option<&str>) -> Option<String> {
+    if let Some(declared) = declared.filter(|d| !d.trim().is_empty()) {
+        return Some(declared.trim().to_string());
+    }
+    match script_path.extension()?.to_str()? {
+        "go" => Some("go build -o {out} {src}".to_string()),
+        "rs" => Some("rustc -O -o {out} {src}".to_string()),
+        _ => None,
+    }
+}
+
+/// Gets the directory compiled scripts are cached in
+pub fn cache_dir() -> Option<PathBuf> {
+    paths::cache_dir().map(|dir| dir.join("shutl").join("build"))
+}
+
+fn hash_of(value: impl Hash) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Prefix of all cached binaries of a script, regardless of its contents
+fn binary_prefix(script_path: &Path) -> String {
+    let stem = script_path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    format!("{}-{:016x}-", stem, hash_of(script_path))
+}
+
+/// Gets the cached binary for the current contents of a script and its build command.
+/// The binary may not exist yet, see [`ensure_built`].
+pub fn binary_path(script_path: &Path, build: &str, cache_dir: &Path) -> std::io::Result<PathBuf> {
+    let contents = std::fs::read(script_path)?;
+    Ok(cache_dir.join(format!(
+        "{}{:016x}",
+        binary_prefix(script_path),
+        hash_of((contents, build))
+    )))
+}
+
+/// Compiles the script into `binary` unless it is already there. Compiler output is only
+/// shown if the build fails. Binaries of older versions of the script are removed.
+pub fn ensure_built(script_path: &Path, build: &str, binary: &Path) -> Result<(), String> {
+    if binary.is_file() {
+        return Ok(());
+    }
+    let dir = binary.parent().ok_or("invalid cache path")?;
+    std::fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+
+    // Build next to the final path and rename, so a failed or concurrent build never
+    // leaves a half-written binary behind
+    let partial = binary.with_extension(format!("partial{}", std::process::id()));
+    let words: Vec<String> = build
+        .split_whitespace()
+        .map(|word| {
+            word.replace("{src}", &script_path.to_string_lossy())
+                .replace("{out}", &partial.to_string_lossy())
+        })
+        .collect();
+    let (program, args) = words.split_first().ok_or("empty build command")?;
+    log::debug!("ensure_built: {:?}", words);
+
+    let output = Command::new(program)
+        .args(args)
+        .current_dir(script_path.parent().unwrap_or(Path::new(".")))
+        .output()
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => format!("{} is not installed", program),
+            _ => format!("failed to run {}: {}", program, e),
+        })?;
+    if !output.status.success() || !partial.is_file() {
+        let _ = std::fs::remove_file(&partial);
+        return Err(format!(
+            "building {} failed:\n{}{}",
+            script_path.display(),
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    std::fs::rename(&partial, binary).map_err(|e| format!("{}: {}", binary.display(), e))?;
+    remove_stale(script_path, binary);
+    Ok(())
+}
+
+/// Removes cached binaries of earlier versions of a script
+fn remove_stale(script_path: &Path, current: &Path) {
+    let (Some(dir), prefix) = (current.parent(), binary_prefix(script_path)) else {
+        return;
+    };
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path != current && entry.file_name().to_string_lossy().starts_with(&prefix) {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_build_command() {
+        assert_eq!(
+            build_command(Path::new("tool.go"), None).as_deref(),
+            Some("go build -o {out} {src}")
+        );
+        assert_eq!(
+            build_command(Path::new("tool.rs"), Some("cargo-script build {src}")).as_deref(),
+            Some("cargo-script build {src}")
+        );
+        assert!(build_command(Path::new("tool.sh"), None).is_none());
+    }
+
+    #[test]
+    fn test_build_is_cached_until_source_changes() {
+        let dir = tempdir().unwrap();
+        let cache = dir.path().join("cache");
+        let script = dir.path().join("tool.txt");
+        std::fs::write(&script, "v1").unwrap();
+        let build = "cp {src} {out}";
+
+        let first = binary_path(&script, build, &cache).unwrap();
+        ensure_built(&script, build, &first).unwrap();
+        assert_eq!(std::fs::read_to_string(&first).unwrap(), "v1");
+        assert_eq!(binary_path(&script, build, &cache).unwrap(), first);
+
+        std::fs::write(&script, "v2").unwrap();
+        let second = binary_path(&script, build, &cache).unwrap();
+        assert_ne!(second, first);
+        ensure_built(&script, build, &second).unwrap();
+        assert!(!first.exists());
+        assert_eq!(std::fs::read_to_string(&second).unwrap(), "v2");
+    }
+
+    #[test]
+    fn test_failed_build_reports_output() {
+        let dir = tempdir().unwrap();
+        let script = dir.path().join("tool.txt");
+        std::fs::write(&script, "").unwrap();
+        let binary = binary_path(&script, "false", dir.path()).unwrap();
+        let err = ensure_built(&script, "false", &binary).unwrap_err();
+        assert!(err.contains("building"));
+        assert!(!binary.exists());
+
+        let err = ensure_built(&script, "no-such-compiler {src}", &binary).unwrap_err();
+        assert_eq!(err, "no-such-compiler is not installed");
+    }
+}
@@ -1,25 +1,89 @@
-//! Built-in subcommands: new, edit, list, validate.
+//! Built-in subcommands: new, edit, cp, mkdir, list, validate, recent, pick, run, explain,
+//! docs, doctor, upgrade, hook, preset, run-all, migrate, prune, diff,
+//! status, log, shadows, import, verify, sign, review, completions,
+//! command-not-found, schema.
 
 use clap::ArgMatches;
-use std::path::Path;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
 use std::process::Command;
 
-use crate::command::{build_script_command_for_help, list_scripts};
+use crate::capture;
+use crate::command::{
+    build_group_command, build_script_command_for_help, builtin_commands, is_builtin,
+    stem_collisions_from,
+};
+use crate::config;
+use crate::docs::{self, DocEntry};
+use crate::errors::{self, ErrorReport, ShutlError};
+use crate::git;
+use crate::history::{self, format_age, frecency, history_path, read_entries};
+use crate::hook;
+use crate::imports::{self, LocalState, Lock};
+use crate::install;
+use crate::list::{ListEntry, ListView, format_entries};
+use crate::metadata::{
+    ArgType, DIR_METADATA_FILE, LineType, OutputFormat, fill_template, parse_command_metadata,
+    template_refs,
+};
+use crate::pager;
+use crate::paths;
+use crate::persist;
+use crate::preset;
+use crate::prune::{self, Reason};
+use crate::review::Review;
+use crate::roots::{self, Collision, MountKind, collect_mount_entries};
+use crate::schema;
+use crate::script::{
+    self, ExecutionPlan, command_path, is_safe_component, plan_execution, resolve_command_path,
+};
+use crate::signing;
+use crate::summary::{self, ExitPolicy, Outcome, RunSummary};
+use crate::trust;
+use crate::upgrade;
 use crate::validation::{
-    Severity, format_diagnostics, format_diagnostics_as_comments, has_errors, validate_script,
+    Severity, ValidationDiagnostic, format_diagnostics_as_comments, format_diagnostics_at,
+    has_errors, validate_script,
 };
-use crate::{find_script_file, get_scripts_dir, resolve_editor};
+use crate::{execute_script_as, find_script_file, get_scripts_dir, resolve_editor};
 
 /// Create a new script under the scripts directory.
 pub fn handle_new(new_matches: &ArgMatches) {
-    let name = new_matches.get_one::<String>("name").unwrap();
     let location = new_matches.get_one::<String>("location").unwrap();
+    // `shutl new net/myip` names the location and the script in one path
+    let (location, name) = match new_matches.get_one::<String>("name") {
+        Some(name) => (location.as_str(), name.as_str()),
+        None => location.rsplit_once('/').unwrap_or(("", location)),
+    };
+    let exec = new_matches.get_one::<String>("exec").map(|s| s.as_str());
     let editor = new_matches.get_one::<String>("editor");
-    let no_edit = new_matches.get_flag("no-edit");
+    let no_edit = new_matches.get_flag("no-edit") || exec.is_some();
+    let force = new_matches.get_flag("force");
+    let dry_run = new_matches.get_flag("dry-run");
     let script_type = new_matches
         .get_one::<String>("type")
         .map(|s| s.as_str())
         .unwrap_or("zsh");
+    if exec.is_some() && !matches!(script_type, "zsh" | "bash" | "pwsh") {
+        eprintln!(
+            "--exec creates zsh, bash or pwsh scripts, not {}",
+            script_type
+        );
+        std::process::exit(1);
+    }
+
+    let location_is_safe = location
+        .split('/')
+        .filter(|c| !c.is_empty())
+        .all(is_safe_component);
+    if !location_is_safe || !is_safe_component(name) {
+        eprintln!(
+            "Invalid script location '{}/{}': it must stay inside the scripts directory",
+            location, name
+        );
+        std::process::exit(1);
+    }
 
     // Build the script path
     let mut script_path = get_scripts_dir();
@@ -27,7 +91,7 @@ pub fn handle_new(new_matches: &ArgMatches) {
         script_path.push(location);
     }
 
-    let with_extension = format!("{}.sh", name);
+    let with_extension = format!("{}.{}", name, script_extension(script_type));
     let script_name = if name.contains('.') {
         name.to_string()
     } else {
@@ -35,6 +99,33 @@ pub fn handle_new(new_matches: &ArgMatches) {
     };
     script_path.push(&script_name);
 
+    let template_config = &config::get().template;
+    if let Some(header) = &template_config.header {
+        for reference in template_refs(header) {
+            if reference != "name" && !template_config.vars.contains_key(reference) {
+                eprintln!(
+                    "Warning: the template header refers to '{{{{{}}}}}', which [template.vars] doesn't define",
+                    reference
+                );
+            }
+        }
+    }
+    let template = render_template(name, script_type, exec, template_config);
+
+    if dry_run {
+        println!("Would create script: {}", script_path.display());
+        if script_path.exists() {
+            println!("(overwriting an existing script)");
+        }
+        println!("{}", template);
+        return;
+    }
+
+    if script_path.exists() && !force && !confirm_overwrite(&script_path) {
+        eprintln!("Not overwriting {}", script_path.display());
+        std::process::exit(1);
+    }
+
     // Ensure parent directories exist
     if let Some(parent) = script_path.parent()
         && let Err(e) = std::fs::create_dir_all(parent)
@@ -43,58 +134,194 @@ pub fn handle_new(new_matches: &ArgMatches) {
         std::process::exit(1);
     }
 
-    let shebang = match script_type {
-        "bash" => "#!/bin/bash",
-        _ => "#!/bin/zsh",
-    };
-
-    // Write the script template
-    let template = format!(
-        "{}\n#@description: {}\n#@arg:input - Input file\n#@flag:verbose - Enable verbose output\n",
-        shebang,
-        name.trim_end_matches(".sh"),
-    );
-
     if let Err(e) = std::fs::write(&script_path, template) {
         eprintln!("Failed to write script {}: {}", script_path.display(), e);
         std::process::exit(1);
     }
 
     // Make the script executable
+    let mode = new_script_mode(new_matches, config::get().new_script_mode, current_umask());
+    if mode & 0o100 == 0 {
+        eprintln!(
+            "Warning: mode {:o} is not executable by the owner, shutl will not list this script",
+            mode
+        );
+    }
+    // Some filesystems (e.g. FAT or SMB mounts) refuse chmod; the script is there anyway
     if let Err(e) = std::fs::set_permissions(
         &script_path,
-        std::os::unix::fs::PermissionsExt::from_mode(0o755),
+        std::os::unix::fs::PermissionsExt::from_mode(mode),
     ) {
         eprintln!(
-            "Failed to set permissions on {}: {}",
+            "Warning: failed to set mode {:o} on {}: {}",
+            mode,
             script_path.display(),
             e
         );
-        std::process::exit(1);
     }
 
     // Open the script in an editor if required
     if !no_edit {
         let editor = resolve_editor(editor);
-
-        Command::new(editor)
-            .arg(&script_path)
-            .status()
-            .expect("Failed to open editor");
+        if let Err(e) = open_editor(&editor, [&script_path]) {
+            e.exit();
+        }
     }
 
     println!("Created script: {}", script_path.display());
+    warn_if_shadowed_by_builtin(&script_path);
+}
+
+/// Reads the process umask. It can only be read by replacing it, so it is restored right away.
+fn current_umask() -> u32 {
+    // SAFETY: umask has no preconditions and cannot fail
+    let mask = unsafe { libc::umask(0) };
+    unsafe { libc::umask(mask) };
+    // mode_t is u16 on macOS
+    #[allow(clippy::useless_conversion)]
+    u32::from(mask)
+}
+
+/// Mode for new scripts when none is configured: 0755, minus the umask
+fn default_script_mode(umask: u32) -> u32 {
+    0o755 & !umask
+}
+
+/// Mode of a script created by `shutl new`: `--private` or `--mode`, else the configured
+/// `new-script-mode`, else the default
+fn new_script_mode(new_matches: &ArgMatches, configured: Option<u32>, umask: u32) -> u32 {
+    if new_matches.get_flag("private") {
+        return 0o700;
+    }
+    new_matches
+        .get_one::<u32>("mode")
+        .copied()
+        .or(configured)
+        .unwrap_or_else(|| default_script_mode(umask))
+}
+
+/// File extension of new scripts of the given type
+fn script_extension(script_type: &str) -> &'static str {
+    match script_type {
+        "pwsh" => "ps1",
+        "ts" => "ts",
+        "go" => "go",
+        "rust" => "rs",
+        _ => "sh",
+    }
+}
+
+/// Renders the initial contents of a new script, starting with the configured header.
+/// With `exec`, the script runs that command line instead, described by it.
+fn render_template(
+    name: &str,
+    script_type: &str,
+    exec: Option<&str>,
+    template: &config::TemplateConfig,
+) -> String {
+    let shebang = match script_type {
+        "bash" => "#!/bin/bash\n",
+        "pwsh" => "#!/usr/bin/env pwsh\n",
+        "ts" => "#!/usr/bin/env -S deno run -A\n",
+        "go" | "rust" => "",
+        _ => "#!/bin/zsh\n",
+    };
+    let body = match script_type {
+        "go" => {
+            "\npackage main\n\nimport \"os\"\n\nfunc main() {\n\t_ = os.Getenv(\"SHUTL_INPUT\")\n}\n"
+        }
+        "rust" => {
+            "\nfn main() {\n    let _input = std::env::var(\"SHUTL_INPUT\").unwrap_or_default();\n}\n"
+        }
+        _ => "",
+    };
+    let extension = format!(".{}", script_extension(script_type));
+    let comment = if matches!(script_type, "ts" | "go" | "rust") {
+        "//"
+    } else {
+        "#"
+    };
+    let marker = format!("{}@", comment);
+    let command = name.trim_end_matches(extension.as_str());
+
+    let header: String = match &template.header {
+        Some(header) => {
+            let filled = fill_template(header.trim_end(), |reference| match reference {
+                "name" => Some(command.to_string()),
+                _ => template.vars.get(reference).cloned(),
+            });
+            filled
+                .lines()
+                .map(|line| match line.trim_end() {
+                    "" => format!("{}\n", comment),
+                    line => format!("{} {}\n", comment, line),
+                })
+                .collect()
+        }
+        None => String::new(),
+    };
+
+    if let Some(exec) = exec {
+        let exec = exec.trim();
+        let first_line = exec.lines().next().unwrap_or_default();
+        let description = if first_line.len() < exec.len() {
+            format!(
+                "Runs `{} ...`",
+                first_line.trim_end_matches('\\').trim_end()
+            )
+        } else {
+            format!("Runs `{}`", exec)
+        };
+        return format!("{shebang}{header}{marker}description: {description}\n\n{exec}\n");
+    }
+
+    format!(
+        "{shebang}{header}{marker}description: {command}\n{marker}arg:input - Input file\n{marker}flag:verbose - Enable verbose output\n{body}",
+    )
+}
+
+/// Summarizes an existing script and asks whether to overwrite it.
+/// Without an interactive terminal the answer is always no; use `--force` instead.
+fn confirm_overwrite(script_path: &Path) -> bool {
+    let contents = std::fs::read_to_string(script_path).unwrap_or_default();
+    let description = parse_command_metadata(script_path).description;
+    eprintln!("Script already exists: {}", script_path.display());
+    if !description.is_empty() {
+        eprintln!("  description: {}", description);
+    }
+    eprintln!("  {} lines", contents.lines().count());
+
+    if !std::io::stdin().is_terminal() {
+        eprintln!("Use --force to overwrite it.");
+        return false;
+    }
+    prompt("Overwrite it? [y/N]").is_some_and(|answer| answer.eq_ignore_ascii_case("y"))
 }
 
 /// Edit an existing script by path components, with post-edit validation.
 /// If validation fails, the user is dropped back into the editor with error
 /// comments prepended (similar to `kubectl edit`).
 pub fn handle_edit(edit_matches: &ArgMatches) {
-    let raw_components: Vec<String> = edit_matches
-        .get_many::<String>("command")
-        .unwrap()
-        .map(|s| s.to_string())
-        .collect();
+    let (raw_components, position) = match edit_matches.get_one::<String>("at") {
+        Some(location) => {
+            let Some((command, position)) = parse_location(location) else {
+                eprintln!(
+                    "Invalid location '{}', expected <command>:<line> or <command>:<line>:<column>",
+                    location
+                );
+                std::process::exit(1);
+            };
+            (vec![command.to_string()], Some(position))
+        }
+        None => (
+            edit_matches
+                .get_many::<String>("command")
+                .unwrap()
+                .map(|s| s.to_string())
+                .collect(),
+            None,
+        ),
+    };
 
     let components: Vec<String> = raw_components
         .iter()
@@ -106,8 +333,27 @@ pub fn handle_edit(edit_matches: &ArgMatches) {
     let editor = edit_matches.get_one::<String>("editor");
 
     if let Some(script_path) = find_script_file(&components) {
+        if let Some(mount) = roots::mount_for(&roots::mounts(), &script_path)
+            && mount.is_read_only()
+        {
+            match mount.kind {
+                MountKind::Commands => eprintln!(
+                    "{} is defined in {}; edit the config to change it",
+                    components.join("/"),
+                    mount.location()
+                ),
+                _ => eprintln!(
+                    "{} comes from the read-only remote root {}; copy it with `shutl cp` to change it",
+                    components.join("/"),
+                    mount.location()
+                ),
+            }
+            std::process::exit(1);
+        }
         let editor = resolve_editor(editor);
-        edit_with_validation(&script_path, &editor);
+        if let Err(e) = edit_with_validation(&script_path, &editor, position) {
+            e.exit();
+        }
         println!("Edited script: {}", script_path.display());
     } else {
         eprintln!("Script not found: {}", components.join("/"));
@@ -115,52 +361,335 @@ pub fn handle_edit(edit_matches: &ArgMatches) {
     }
 }
 
-/// Opens the script in an editor, then validates. On validation errors,
-/// prepends error comments and reopens (loop until valid or user aborts).
-fn edit_with_validation(script_path: &Path, editor: &str) {
-    let original_content =
-        std::fs::read_to_string(script_path).expect("Failed to read script file");
+/// Copy an existing script to a new command path.
+pub fn handle_cp(cp_matches: &ArgMatches) {
+    let source = cp_matches.get_one::<String>("source").unwrap();
+    let destination = cp_matches.get_one::<String>("destination").unwrap();
+    let editor = cp_matches.get_one::<String>("editor");
+    let no_edit = cp_matches.get_flag("no-edit");
+    let force = cp_matches.get_flag("force");
+    let description = cp_matches.get_one::<String>("description");
+
+    let Some(source_path) = find_script_file(&command_components(source)) else {
+        eprintln!("Script not found: {}", source);
+        std::process::exit(1);
+    };
+    if !source_path.is_file() {
+        eprintln!("Not a script: {}", source);
+        std::process::exit(1);
+    }
+
+    let Some(target_path) = copy_target(&get_scripts_dir(), &source_path, destination) else {
+        eprintln!(
+            "Invalid destination '{}': it must stay inside the scripts directory",
+            destination
+        );
+        std::process::exit(1);
+    };
+
+    if target_path.exists() && !force && !confirm_overwrite(&target_path) {
+        eprintln!("Not overwriting {}", target_path.display());
+        std::process::exit(1);
+    }
+
+    if let Some(parent) = target_path.parent()
+        && let Err(e) = std::fs::create_dir_all(parent)
+    {
+        eprintln!("Failed to create directory {}: {}", parent.display(), e);
+        std::process::exit(1);
+    }
+
+    // fs::copy also copies the permission bits, so the copy stays executable
+    if let Err(e) = std::fs::copy(&source_path, &target_path) {
+        eprintln!(
+            "Failed to copy {} to {}: {}",
+            source_path.display(),
+            target_path.display(),
+            e
+        );
+        std::process::exit(1);
+    }
+
+    if let Some(description) = description {
+        let result = read_script(&target_path).and_then(|content| {
+            write_script(&target_path, &replace_description(&content, description))
+        });
+        if let Err(e) = result {
+            e.exit();
+        }
+    }
+
+    if !no_edit {
+        let editor = resolve_editor(editor);
+        if let Err(e) = edit_with_validation(&target_path, &editor, None) {
+            e.exit();
+        }
+    }
+
+    println!(
+        "Copied script: {} -> {}",
+        source_path.display(),
+        target_path.display()
+    );
+    warn_if_shadowed_by_builtin(&target_path);
+}
+
+/// Replaces the description of a script with `description`, keeping its comment marker
+/// (`#@`, `//@`, ...). A script without one gets a `#@description:` line after the `#!`
+/// line.
+fn replace_description(content: &str, description: &str) -> String {
+    let mut lines: Vec<String> = content.lines().map(String::from).collect();
+    let existing = lines.iter().position(|line| {
+        line.trim_start()
+            .split_once("@description:")
+            .is_some_and(|(marker, _)| matches!(marker, "#" | "//" | "--" | ";"))
+    });
+    match existing {
+        Some(i) => {
+            let (marker, _) = lines[i].split_once("@description:").unwrap();
+            lines[i] = format!("{}@description: {}", marker, description);
+        }
+        None => {
+            let at = usize::from(lines.first().is_some_and(|line| line.starts_with("#!")));
+            lines.insert(at, format!("#@description: {}", description));
+        }
+    }
+    let mut out = lines.join("\n");
+    if content.ends_with('\n') || content.is_empty() {
+        out.push('\n');
+    }
+    out
+}
+
+/// Resolves the file a script is copied to. `destination` is a command path relative to
+/// `base_dir`; an existing directory receives a file of the same name as the source, and a
+/// name without extension inherits the source's extension.
+fn copy_target(base_dir: &Path, source: &Path, destination: &str) -> Option<PathBuf> {
+    let components = command_components(destination);
+    let target = resolve_command_path(base_dir, &components)?;
+    if components.is_empty() || target.is_dir() {
+        return Some(target.join(source.file_name()?));
+    }
+
+    let name = components.last()?;
+    match source.extension() {
+        Some(ext) if !name.contains('.') => {
+            Some(target.with_file_name(format!("{}.{}", name, ext.to_string_lossy())))
+        }
+        _ => Some(target),
+    }
+}
 
+/// Create a command group directory, optionally with a description.
+pub fn handle_mkdir(mkdir_matches: &ArgMatches) {
+    let path = mkdir_matches.get_one::<String>("path").unwrap();
+    let mut description = mkdir_matches.get_one::<String>("description").cloned();
+
+    let components = command_components(path);
+    let dir = match resolve_command_path(&get_scripts_dir(), &components) {
+        Some(dir) if !components.is_empty() => dir,
+        _ => {
+            eprintln!(
+                "Invalid directory '{}': it must stay inside the scripts directory",
+                path
+            );
+            std::process::exit(1);
+        }
+    };
+
+    if dir.exists() && !dir.is_dir() {
+        eprintln!("{} exists and is not a directory", dir.display());
+        std::process::exit(1);
+    }
+    let existed = dir.is_dir();
+    // A new group gets a description from the terminal if none was given; empty means none
+    if description.is_none() && !existed && std::io::stdin().is_terminal() {
+        description = prompt("Description (empty for none)").filter(|d| !d.is_empty());
+    }
+    if let Err(e) = std::fs::create_dir_all(&dir) {
+        eprintln!("Failed to create directory {}: {}", dir.display(), e);
+        std::process::exit(1);
+    }
+
+    if let Some(description) = &description
+        && let Err(e) = write_dir_description(&dir, description)
+    {
+        eprintln!("Failed to write description for {}: {}", dir.display(), e);
+        std::process::exit(1);
+    }
+
+    if existed {
+        println!("Updated command group: {}", dir.display());
+    } else {
+        println!("Created command group: {}", dir.display());
+    }
+    warn_if_shadowed_by_builtin(&dir);
+}
+
+/// Builds the warning for a script or group whose top-level name is taken by a built-in
+fn builtin_shadow_warning(command_path: &str) -> Option<String> {
+    let top_level = command_path.split('/').next()?;
+    is_builtin(top_level).then(|| {
+        format!(
+            "'{}' is a built-in command and takes precedence; run this with `shutl run {}`",
+            top_level,
+            command_path.replace('/', " ")
+        )
+    })
+}
+
+/// Warns after creating something at `path` that `shutl <name>` will not reach
+fn warn_if_shadowed_by_builtin(path: &Path) {
+    let root = get_scripts_dir();
+    if let Some(warning) = builtin_shadow_warning(&command_path(path, &root)) {
+        eprintln!("Warning: {}", warning);
+    }
+}
+
+/// Writes the description into a directory's `.shutl` file, keeping any `#@` directives
+fn write_dir_description(dir: &Path, description: &str) -> std::io::Result<()> {
+    let path = dir.join(DIR_METADATA_FILE);
+    let existing = std::fs::read_to_string(&path).unwrap_or_default();
+    let mut lines = vec![description.trim().to_string()];
+    lines.extend(
+        existing
+            .lines()
+            .filter(|l| l.trim().starts_with("#@"))
+            .map(|l| l.to_string()),
+    );
+    std::fs::write(path, lines.join("\n") + "\n")
+}
+
+/// A line, and optionally a column, of a script (`shutl edit --at deploy:12:3`)
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Position {
+    line: usize,
+    column: Option<usize>,
+}
+
+/// Splits `<command>:<line>` or `<command>:<line>:<column>`
+fn parse_location(location: &str) -> Option<(&str, Position)> {
+    let (rest, last) = location.rsplit_once(':')?;
+    let last: usize = last.parse().ok()?;
+    if let Some((command, line)) = rest.rsplit_once(':')
+        && let Ok(line) = line.parse()
+    {
+        return Some((
+            command,
+            Position {
+                line,
+                column: Some(last),
+            },
+        ));
+    }
+    Some((
+        rest,
+        Position {
+            line: last,
+            column: None,
+        },
+    ))
+}
+
+/// Gets the arguments that open `path` in `editor` at `position`. VS Code and similar
+/// editors take `--goto path:line:column`, Sublime Text, Zed and Helix `path:line:column`,
+/// everything else (vi, nano, emacs, ...) `+line path`.
+fn editor_args(editor: &str, path: &Path, position: Option<Position>) -> Vec<String> {
+    let path = path.to_string_lossy().to_string();
+    let Some(position) = position else {
+        return vec![path];
+    };
+    let name = Path::new(editor)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let with_position = match position.column {
+        Some(column) => format!("{}:{}:{}", path, position.line, column),
+        None => format!("{}:{}", path, position.line),
+    };
+    match name.as_str() {
+        "code" | "code-insiders" | "codium" | "cursor" => {
+            vec!["--goto".to_string(), with_position]
+        }
+        "subl" | "zed" | "hx" | "helix" => vec![with_position],
+        _ => vec![format!("+{}", position.line), path],
+    }
+}
+
+/// Runs the editor with `args` and waits for it to close
+fn open_editor<I, S>(editor: &str, args: I) -> Result<(), ShutlError>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<std::ffi::OsStr>,
+{
     Command::new(editor)
-        .arg(script_path)
+        .args(args)
         .status()
-        .expect("Failed to open editor");
+        .map_err(|source| ShutlError::Editor {
+            editor: editor.to_string(),
+            source,
+        })?;
+    Ok(())
+}
+
+fn read_script(script_path: &Path) -> Result<String, ShutlError> {
+    std::fs::read_to_string(script_path).map_err(|source| ShutlError::Read {
+        path: script_path.to_path_buf(),
+        source,
+    })
+}
+
+fn write_script(script_path: &Path, content: &str) -> Result<(), ShutlError> {
+    std::fs::write(script_path, content).map_err(|source| ShutlError::Write {
+        path: script_path.to_path_buf(),
+        source,
+    })
+}
+
+/// Opens the script in an editor, then validates. On validation errors,
+/// prepends error comments and reopens (loop until valid or user aborts).
+fn edit_with_validation(
+    script_path: &Path,
+    editor: &str,
+    position: Option<Position>,
+) -> Result<(), ShutlError> {
+    let original_content = read_script(script_path)?;
+
+    open_editor(editor, editor_args(editor, script_path, position))?;
 
     loop {
         let diagnostics = validate_script(script_path);
         if !has_errors(&diagnostics) {
             if !diagnostics.is_empty() {
-                eprintln!("{}", format_diagnostics(&diagnostics));
+                eprintln!("{}", format_diagnostics_at(script_path, &diagnostics));
             }
-            return;
+            return Ok(());
         }
 
-        eprintln!("\nValidation failed:\n{}", format_diagnostics(&diagnostics));
+        eprintln!(
+            "\nValidation failed:\n{}",
+            format_diagnostics_at(script_path, &diagnostics)
+        );
 
-        let current_content =
-            std::fs::read_to_string(script_path).expect("Failed to read script file");
+        let current_content = read_script(script_path)?;
         let stripped = strip_validation_comments(&current_content);
         let error_block = format_diagnostics_as_comments(&diagnostics);
         let annotated = insert_validation_comments(&stripped, &error_block);
 
-        std::fs::write(script_path, &annotated).expect("Failed to write annotated script");
+        write_script(script_path, &annotated)?;
 
-        Command::new(editor)
-            .arg(script_path)
-            .status()
-            .expect("Failed to open editor");
+        open_editor(editor, [script_path])?;
 
-        let after_edit = std::fs::read_to_string(script_path).expect("Failed to read script file");
+        let after_edit = read_script(script_path)?;
 
         if after_edit == annotated {
             eprintln!("No changes made, restoring original and aborting edit.");
-            std::fs::write(script_path, &original_content)
-                .expect("Failed to restore original script");
+            write_script(script_path, &original_content)?;
             std::process::exit(1);
         }
 
         let cleaned = strip_validation_comments(&after_edit);
-        std::fs::write(script_path, &cleaned).expect("Failed to write cleaned script");
+        write_script(script_path, &cleaned)?;
     }
 }
 
@@ -226,29 +755,40 @@ pub fn handle_validate(validate_matches: &ArgMatches) {
         .collect();
 
     if let Some(script_path) = find_script_file(&components) {
-        let diagnostics = validate_script(&script_path);
-
-        if has_errors(&diagnostics) {
-            eprintln!("{}", format_diagnostics(&diagnostics));
-            std::process::exit(1);
+        let mut diagnostics = validate_script(&script_path);
+        if let Some(message) = builtin_shadow_warning(&components.join("/")) {
+            diagnostics.push(ValidationDiagnostic {
+                severity: Severity::Warning,
+                message,
+                span: None,
+            });
         }
 
-        if !diagnostics.is_empty() {
-            let warnings: Vec<_> = diagnostics
+        if has_errors(&diagnostics) {
+            eprintln!("{}", format_diagnostics_at(&script_path, &diagnostics));
+            if let Some(span) = diagnostics
                 .iter()
-                .filter(|d| d.severity == Severity::Warning)
-                .collect();
-            if !warnings.is_empty() {
+                .find(|d| d.severity == Severity::Error)
+                .and_then(|d| d.span)
+            {
                 eprintln!(
-                    "{}",
-                    warnings
-                        .iter()
-                        .map(|d| d.to_string())
-                        .collect::<Vec<_>>()
-                        .join("\n")
+                    "\nOpen it with: shutl edit --at {}:{}:{}",
+                    components.join("/"),
+                    span.line,
+                    span.column
                 );
-                eprintln!();
             }
+            std::process::exit(1);
+        }
+
+        let warnings: Vec<_> = diagnostics
+            .iter()
+            .filter(|d| d.severity == Severity::Warning)
+            .cloned()
+            .collect();
+        if !warnings.is_empty() {
+            eprintln!("{}", format_diagnostics_at(&script_path, &warnings));
+            eprintln!();
         }
 
         let cmd_name = components.last().cloned().unwrap_or_default();
@@ -267,45 +807,1847 @@ pub fn handle_list(list_matches: &ArgMatches) {
     let subdir = list_matches
         .get_one::<String>("subdirectory")
         .map(|s| s.as_str());
-    let tree = list_matches.get_flag("tree");
-    let output = list_scripts(&get_scripts_dir(), subdir, tree);
-    println!("{}", output);
+    let mounts = roots::mounts();
+    let entries = match collect_mount_entries(&mounts, subdir) {
+        Ok(entries) => entries,
+        Err(message) => {
+            pager::page(&format!("{}\n", message));
+            return;
+        }
+    };
+    // `--depth` counts from the listed directory
+    let base = subdir.map_or(0, |subdir| command_components(subdir).len());
+    let mut view = ListView {
+        tree: list_matches.get_flag("tree"),
+        depth: list_matches
+            .get_one::<u32>("depth")
+            .map(|depth| base + *depth as usize),
+        groups: HashMap::new(),
+    };
+    if view.tree || view.depth.is_some() {
+        view.groups = group_descriptions(&mounts, &entries);
+    }
+    pager::page(&format!("{}\n", format_entries(entries, &view)));
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// Gets the descriptions of the groups the listed scripts are in, by group path
+fn group_descriptions(mounts: &[roots::Mount], entries: &[ListEntry]) -> HashMap<String, String> {
+    let mut groups = HashMap::new();
+    for entry in entries {
+        let components = command_components(&entry.path);
+        for depth in 1..components.len() {
+            let group = components[..depth].join("/");
+            if groups.contains_key(&group) {
+                continue;
+            }
+            let description = roots::resolve(mounts, &components[..depth])
+                .map(|dir| crate::metadata::parse_dir_metadata(&dir).description)
+                .unwrap_or_default();
+            groups.insert(group, description);
+        }
+    }
+    groups.retain(|_, description| !description.is_empty());
+    groups
+}
 
-    #[test]
-    fn test_strip_validation_comments() {
-        let content = "#!/bin/bash\n# ===========================================================\n# VALIDATION ERRORS — please fix and save to retry, or\n# close without saving to discard changes.\n# ===========================================================\n# error: duplicate argument name 'x'\n# ===========================================================\n#@description: my script\n";
-        let stripped = strip_validation_comments(content);
-        assert_eq!(stripped, "#!/bin/bash\n#@description: my script\n");
+/// List the most frecent commands from the execution history.
+pub fn handle_recent(recent_matches: &ArgMatches) {
+    let limit = *recent_matches.get_one::<usize>("limit").unwrap();
+    let entries = history_path().map(|p| read_entries(&p)).unwrap_or_default();
+    let now = history::now();
+
+    let usage: Vec<_> = frecency(&entries, now)
+        .into_iter()
+        .filter(|u| find_script_file(&command_components(&u.command)).is_some())
+        .take(limit)
+        .collect();
+
+    if usage.is_empty() {
+        println!("No recently used scripts.");
+        return;
     }
 
-    #[test]
-    fn test_strip_no_validation_comments() {
-        let content = "#!/bin/bash\n#@description: clean\n";
-        let stripped = strip_validation_comments(content);
-        assert_eq!(stripped, content);
+    let width = usage.iter().map(|u| u.command.len()).max().unwrap_or(0);
+    for u in usage {
+        println!(
+            "{:<width$}  {:>4} runs  {}",
+            u.command,
+            u.count,
+            format_age(u.last_used, now),
+            width = width
+        );
     }
+}
 
-    #[test]
-    fn test_insert_validation_comments_after_shebang() {
-        let content = "#!/bin/bash\n#@description: my script\n";
-        let comments = "# ===========================================================\n# error: bad\n# ===========================================================";
-        let result = insert_validation_comments(content, comments);
-        assert!(result.starts_with("#!/bin/bash\n# =========="));
-        assert!(result.contains("#@description: my script"));
+/// Interactively select a command (ranked by frecency, fuzzy filtered), prompt for its
+/// required arguments and run it.
+pub fn handle_pick(pick_matches: &ArgMatches) {
+    if !std::io::stdin().is_terminal() {
+        eprintln!("shutl pick needs an interactive terminal");
+        std::process::exit(1);
     }
+    let query = pick_matches
+        .get_one::<String>("query")
+        .cloned()
+        .unwrap_or_default();
+    pick_and_run(query);
+}
 
-    #[test]
-    fn test_insert_validation_comments_no_shebang() {
-        let content = "#@description: my script\n";
-        let comments = "# ===========================================================\n# error: bad\n# ===========================================================";
-        let result = insert_validation_comments(content, comments);
-        assert!(result.starts_with("# =========="));
-        assert!(result.contains("#@description: my script"));
+/// Interactive mode for a bare `shutl` (`interactive = true` in the config): the same
+/// picker as `shutl pick`. Without a terminal the help is printed instead.
+pub fn handle_interactive(cli: &mut clap::Command) {
+    if !std::io::stdin().is_terminal() || !std::io::stdout().is_terminal() {
+        cli.print_help().unwrap();
+        std::process::exit(1);
+    }
+    pick_and_run(String::new());
+}
+
+fn pick_and_run(mut query: String) -> ! {
+    let scores: HashMap<String, f64> = history_path()
+        .map(|p| read_entries(&p))
+        .map(|entries| frecency(&entries, history::now()))
+        .unwrap_or_default()
+        .into_iter()
+        .map(|u| (u.command, u.score))
+        .collect();
+    let entries = collect_mount_entries(&roots::mounts(), None).unwrap_or_default();
+    let candidates = rank_candidates(entries, &scores);
+
+    let selected = loop {
+        let matching: Vec<&ListEntry> = candidates
+            .iter()
+            .filter(|e| fuzzy_match(&query, &e.path))
+            .collect();
+        if matching.len() == 1 {
+            break matching[0];
+        }
+        if matching.is_empty() {
+            eprintln!("No commands match '{}'", query);
+            query.clear();
+            continue;
+        }
+        print_candidates(&matching);
+        let Some(input) = prompt("Select a number or type to filter (empty to quit)") else {
+            std::process::exit(1);
+        };
+        if input.is_empty() {
+            std::process::exit(1);
+        }
+        match input.parse::<usize>() {
+            Ok(n) if (1..=matching.len()).contains(&n) => break matching[n - 1],
+            _ => query = input,
+        }
+    };
+
+    let components = command_components(&selected.path);
+    let Some(script_path) = find_script_file(&components) else {
+        eprintln!("Script not found: {}", selected.path);
+        std::process::exit(1);
+    };
+    let Some(args) = prompt_required_args(&script_path) else {
+        std::process::exit(1);
+    };
+
+    println!("Running: shutl {} {}", components.join(" "), args.join(" "));
+    let exe = std::env::current_exe().unwrap_or_else(|e| ShutlError::from(e).exit());
+    let status = Command::new(exe)
+        .arg("run")
+        .args(&components)
+        .arg("--")
+        .args(&args)
+        .status()
+        .unwrap_or_else(|e| ShutlError::from(e).exit());
+    std::process::exit(status.code().unwrap_or(1));
+}
+
+/// Makes a script run reuse the arguments of its last run from the history
+pub const AGAIN_FLAG: &str = "--shutl-again";
+
+/// Replaces `--shutl-again`, `--shutl-preset NAME` or a leading `NAME@preset` in a
+/// script's arguments by the stored arguments, keeping the ones given now. Other
+/// arguments are returned unchanged. Exits if the stored arguments cannot be used.
+pub fn expand_stored_args(
+    components: &[String],
+    script_path: &Path,
+    args: &[String],
+) -> Vec<String> {
+    let mut again = false;
+    let mut preset = None;
+    let mut rest = Vec::new();
+    let mut iter = args.iter().enumerate();
+    while let Some((i, arg)) = iter.next() {
+        if arg == "--" {
+            rest.extend(args[i..].iter().cloned());
+            break;
+        }
+        if arg == AGAIN_FLAG {
+            again = true;
+        } else if arg == preset::PRESET_FLAG {
+            let Some((_, name)) = iter.next() else {
+                eprintln!("{} needs a preset name", preset::PRESET_FLAG);
+                std::process::exit(1);
+            };
+            preset = Some(name.clone());
+        } else if let Some(name) = arg
+            .strip_prefix(preset::PRESET_FLAG)
+            .and_then(|rest| rest.strip_prefix('='))
+        {
+            preset = Some(name.to_string());
+        } else if let Some(name) = arg.strip_suffix(preset::PRESET_SUFFIX)
+            && i == 0
+        {
+            preset = Some(name.to_string());
+        } else {
+            rest.push(arg.clone());
+        }
+    }
+
+    let command = components.join("/");
+    let (stored, source) = match (again, preset) {
+        (false, None) => return args.to_vec(),
+        (true, Some(_)) => {
+            eprintln!(
+                "{} and {} cannot be combined",
+                AGAIN_FLAG,
+                preset::PRESET_FLAG
+            );
+            std::process::exit(1);
+        }
+        (true, None) => {
+            let Some(previous) =
+                history_path().and_then(|path| history::last_args(&path, &command))
+            else {
+                eprintln!("No previous run of {} in the history", command);
+                std::process::exit(1);
+            };
+            (previous, "the last run".to_string())
+        }
+        (false, Some(name)) => {
+            let file = preset::presets_file(&get_scripts_dir(), components);
+            let Some(args) = preset::load(&file).remove(&name) else {
+                eprintln!(
+                    "No preset '{}' for {} (see 'shutl preset list {}')",
+                    name,
+                    command,
+                    components.join(" ")
+                );
+                std::process::exit(1);
+            };
+            (args, format!("preset '{}'", name))
+        }
+    };
+    match script::merge_args(script_path, &stored, &rest) {
+        Ok(merged) => {
+            eprintln!(
+                "Expanded to: shutl {} {}",
+                components.join(" "),
+                script::redact_secret_flags(&merged).join(" ")
+            );
+            merged
+        }
+        Err(e) => {
+            eprintln!("Cannot use {} of {}: {}", source, command, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// A script invocation given as a command path plus arguments after `--`, as taken by
+/// `run` and `explain`
+struct Invocation {
+    components: Vec<String>,
+    args: Vec<String>,
+    script_path: PathBuf,
+    matches: ArgMatches,
+}
+
+/// Run several scripts one after another, each like `shutl run <path>`, and print a
+/// summary table. Groups expand to their scripts in listing order. stdout of every script
+/// is also logged below the cache directory.
+pub fn handle_run_all(run_all_matches: &ArgMatches) {
+    let policy = if run_all_matches.get_flag("fail-fast") {
+        ExitPolicy::FailFast
+    } else {
+        ExitPolicy::KeepGoing
+    };
+    let mounts = roots::mounts();
+    let mut commands = Vec::new();
+    for path in run_all_matches.get_many::<String>("commands").unwrap() {
+        let components = command_components(path);
+        match roots::resolve(&mounts, &components) {
+            Some(script) if !script.is_dir() => commands.push(components.join("/")),
+            Some(_) => {
+                let entries = collect_mount_entries(&mounts, Some(&components.join("/")))
+                    .unwrap_or_else(|message| {
+                        eprintln!("{}", message);
+                        std::process::exit(1);
+                    });
+                commands.extend(entries.into_iter().map(|entry| entry.path));
+            }
+            None => {
+                eprintln!("Script not found: {}", components.join("/"));
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // A script named directly and through its group runs once
+    let mut seen = std::collections::HashSet::new();
+    commands.retain(|command| seen.insert(command.clone()));
+
+    let logs = summary::logs_dir();
+    if let Some(logs) = &logs
+        && let Err(e) = std::fs::create_dir_all(logs)
+    {
+        eprintln!("Failed to create {}: {}", logs.display(), e);
+        std::process::exit(1);
+    }
+    let exe = std::env::current_exe().unwrap_or_else(|e| ShutlError::from(e).exit());
+    let started_at = history::now();
+    let mut runs: Vec<RunSummary> = Vec::new();
+    for command in commands {
+        if policy == ExitPolicy::FailFast && runs.iter().any(|run| run.outcome != Outcome::Ok) {
+            runs.push(RunSummary {
+                command,
+                outcome: Outcome::Skipped,
+                duration: None,
+                log: None,
+            });
+            continue;
+        }
+
+        eprintln!("==> shutl {}", command.replace('/', " "));
+        let log = logs
+            .as_ref()
+            .map(|dir| dir.join(format!("{}-{}.log", started_at, command.replace('/', "-"))));
+        let mut process = Command::new(&exe);
+        process.arg("run").arg(&command);
+        let started = std::time::Instant::now();
+        let outcome = match capture::run_captured(&mut process, log.as_deref(), true, false, None) {
+            Ok((status, _)) if status.success() => Outcome::Ok,
+            Ok((status, _)) => Outcome::Failed(status.code()),
+            Err(e) => {
+                eprintln!("Failed to run {}: {}", command, e);
+                Outcome::Failed(None)
+            }
+        };
+        runs.push(RunSummary {
+            command,
+            outcome,
+            duration: Some(started.elapsed()),
+            log,
+        });
+    }
+
+    println!("\n{}", summary::render_table(&runs));
+    std::process::exit(summary::exit_code(&runs));
+}
+
+/// Resolves the command path of `run`/`explain` and parses the script arguments.
+/// Exits with the group help if the path is a directory.
+fn parse_invocation(sub_matches: &ArgMatches, builtin: &str) -> Invocation {
+    let mut components: Vec<String> = sub_matches
+        .get_many::<String>("command")
+        .unwrap()
+        .flat_map(|s| command_components(s))
+        .collect();
+    let mut args: Vec<String> = sub_matches
+        .get_many::<String>("args")
+        .map(|values| values.cloned().collect())
+        .unwrap_or_default();
+    // `run deploy prod-eu@preset` names the preset as part of the path
+    if components
+        .last()
+        .is_some_and(|c| c.ends_with(preset::PRESET_SUFFIX))
+    {
+        args.insert(0, components.pop().unwrap());
+    }
+
+    let path = resolve_command(&components);
+
+    if path.is_dir() {
+        build_group_command(&path, &components)
+            .print_help()
+            .unwrap();
+        std::process::exit(1);
+    }
+
+    let args = expand_stored_args(&components, &path, &args);
+
+    let name = components.last().cloned().unwrap_or_default();
+    let matches = build_script_command_for_help(name.clone(), &path)
+        .bin_name(format!("shutl {} {} --", builtin, components.join(" ")))
+        .try_get_matches_from(std::iter::once(name).chain(args.iter().cloned()))
+        .unwrap_or_else(|e| errors::exit_on_clap_error(e));
+    Invocation {
+        components,
+        args,
+        script_path: path,
+        matches,
+    }
+}
+
+/// Resolves the script or group a command path names, exiting with an [`ErrorReport`]
+/// (`invalid-command-path`, `script-not-found`) if there is none
+pub fn resolve_command(components: &[String]) -> PathBuf {
+    if components.iter().any(|c| !is_safe_component(c)) {
+        ErrorReport::new(
+            "invalid-command-path",
+            format!(
+                "Invalid command path: {} (components must stay inside the scripts directory)",
+                components.join("/")
+            ),
+        )
+        .exit(1);
+    }
+    match roots::resolve(&roots::mounts(), components) {
+        Some(path) => path,
+        None => {
+            let mut report = ErrorReport::new(
+                "script-not-found",
+                format!("Script not found: {}", components.join("/")),
+            );
+            if let Some(name) = components.last() {
+                report.candidates = errors::candidates_named(name);
+            }
+            report.exit(1)
+        }
+    }
+}
+
+/// Run a script by command path. Unlike `shutl <path>`, the path is never mistaken for a
+/// built-in, and script arguments follow `--`.
+pub fn handle_run(run_matches: &ArgMatches) {
+    let invocation = parse_invocation(run_matches, "run");
+    if let Err(e) = execute_script_as(
+        &invocation.components,
+        &invocation.script_path,
+        &invocation.matches,
+        &invocation.args,
+    ) {
+        e.exit();
+    }
+}
+
+/// Print how a script would be run (file, runner, working directory, environment)
+/// without running it or recording history.
+pub fn handle_explain(explain_matches: &ArgMatches) {
+    let invocation = parse_invocation(explain_matches, "explain");
+    let script_path = &invocation.script_path;
+    let plan = plan_execution(script_path, &invocation.matches).unwrap_or_else(|e| {
+        eprintln!("Cannot plan {}: {}", invocation.components.join("/"), e);
+        std::process::exit(1);
+    });
+    let mounts = roots::mounts();
+    let root = roots::mount_for(&mounts, script_path).map(|mount| match &mount.prefix {
+        Some(prefix) => format!("{} (prefix '{}')", mount.location(), prefix),
+        None => mount.location(),
+    });
+    print!(
+        "{}",
+        format_plan(&invocation.components, script_path, root, &plan)
+    );
+}
+
+/// Print the JSON Schema of a script's arguments (see [`schema`]).
+pub fn handle_schema(schema_matches: &ArgMatches) {
+    let components: Vec<String> = schema_matches
+        .get_many::<String>("command")
+        .unwrap()
+        .flat_map(|s| command_components(s))
+        .collect();
+    let path = resolve_command(&components);
+    if path.is_dir() {
+        eprintln!(
+            "{} is a group; name one of its scripts",
+            components.join("/")
+        );
+        std::process::exit(1);
+    }
+    let schema = schema::command_schema(&components.join(" "), &parse_command_metadata(&path));
+    println!("{}", serde_json::to_string_pretty(&schema).unwrap());
+}
+
+fn format_plan(
+    components: &[String],
+    script_path: &Path,
+    root: Option<String>,
+    plan: &ExecutionPlan,
+) -> String {
+    let mut out = String::new();
+    let mut line = |label: &str, value: String| out.push_str(&format!("{:<12} {}\n", label, value));
+
+    line("command:", components.join("/"));
+    line("script:", script_path.display().to_string());
+    if let Some(root) = root {
+        line("root:", root);
+    }
+    if let Some((build, binary)) = &plan.build {
+        let state = if binary.is_file() {
+            "cached"
+        } else {
+            "built on first run"
+        };
+        line("build:", format!("{} ({})", build, state));
+    }
+
+    if let Some(pod) = &plan.pod {
+        line("pod:", pod.describe());
+    }
+    let command = &plan.command;
+    line("runs:", plan.program_line());
+    let cwd = match command.get_current_dir() {
+        Some(dir) => dir.display().to_string(),
+        None => std::env::current_dir()
+            .map(|dir| format!("{} (current directory)", dir.display()))
+            .unwrap_or_else(|_| "current directory".to_string()),
+    };
+    line("cwd:", cwd);
+    if !plan.limits.is_empty() {
+        line("limits:", plan.limits.describe());
+    }
+    if let Some(stdin) = plan.stdin {
+        line("stdin:", stdin.describe().to_string());
+    }
+    if !plan.roles.is_empty() {
+        line("roles:", plan.roles.join(", "));
+    }
+    if !plan.sandbox.is_empty() {
+        line("sandbox:", plan.sandbox.describe());
+    }
+    for dir in &plan.create_dirs {
+        line("creates:", dir.display().to_string());
+    }
+    if plan.output != OutputFormat::Text {
+        line("output:", plan.output.name().to_string());
+    }
+    if !plan.env_filter.is_empty() {
+        line("inherits:", plan.env_filter.describe());
+    }
+
+    out.push_str("environment:\n");
+    for (key, value) in plan.redacted_envs() {
+        out.push_str(&format!("  {}={}\n", key, value));
+    }
+    out
+}
+
+/// Print reference documentation for all scripts (or those under a subdirectory) as
+/// Markdown or as a man page.
+pub fn handle_docs(docs_matches: &ArgMatches) {
+    let subdir = docs_matches
+        .get_one::<String>("subdirectory")
+        .map(|s| s.as_str());
+    let mounts = roots::mounts();
+    let entries = collect_mount_entries(&mounts, subdir).unwrap_or_else(|message| {
+        eprintln!("{}", message);
+        std::process::exit(1);
+    });
+    // Groups described by a README get a section linking to it, before their first script
+    let mut groups = std::collections::HashSet::new();
+    let mut docs: Vec<DocEntry> = Vec::new();
+    for entry in &entries {
+        let components = command_components(&entry.path);
+        for depth in 1..components.len() {
+            let group = &components[..depth];
+            if !groups.insert(group.to_vec()) {
+                continue;
+            }
+            let Some(dir) = roots::resolve(&mounts, group) else {
+                continue;
+            };
+            let metadata = crate::metadata::parse_dir_metadata(&dir);
+            if metadata.readme.is_some() {
+                docs.push(DocEntry {
+                    command: group.join(" "),
+                    metadata: crate::metadata::CommandMetadata {
+                        description: metadata.description,
+                        ..Default::default()
+                    },
+                    readme: metadata.readme,
+                });
+            }
+        }
+        if let Some(script_path) = roots::resolve(&mounts, &components) {
+            docs.push(DocEntry {
+                command: components.join(" "),
+                metadata: parse_command_metadata(&script_path),
+                readme: None,
+            });
+        }
+    }
+
+    match docs_matches.get_one::<String>("format").map(|s| s.as_str()) {
+        Some("man") => print!("{}", docs::render_man(&docs)),
+        _ => pager::page(&docs::render_markdown(&docs)),
+    }
+}
+
+/// Check the config file, the script roots and top-level name collisions between them.
+/// Exits non-zero if an error was found; collisions are only warnings.
+pub fn handle_doctor(_doctor_matches: &ArgMatches) {
+    let mut errors = 0;
+
+    if let Some(path) = config::config_path() {
+        match config::load_config_from(&path) {
+            Ok(_) if path.exists() => println!("ok: config {}", path.display()),
+            Ok(_) => println!("ok: no config file ({})", path.display()),
+            Err(e) => {
+                println!("error: config {}: {}", path.display(), e);
+                errors += 1;
+            }
+        }
+    }
+
+    if let Some(profile) = config::active_profile() {
+        if config::get().profiles.contains_key(&profile) {
+            println!("ok: profile '{}'", profile);
+        } else {
+            println!("error: unknown profile '{}'", profile);
+            errors += 1;
+        }
+    }
+
+    if config::get().scripts_location == config::ScriptsLocation::Auto
+        && crate::legacy_scripts_dir().is_some_and(|legacy| legacy == get_scripts_dir())
+        && let Some(xdg) = crate::xdg_scripts_dir()
+    {
+        println!(
+            "warning: scripts are in the old location ~/.shutl; run `shutl migrate` to move them to {}",
+            xdg.display()
+        );
+    }
+
+    for root in &config::get().roots {
+        if let Some(ref prefix) = root.prefix
+            && !is_safe_component(prefix)
+        {
+            println!(
+                "error: root {} has an invalid prefix {:?}",
+                root.url.as_deref().unwrap_or(&root.path),
+                prefix
+            );
+            errors += 1;
+        }
+        if let Some(ref url) = root.url
+            && root.prefix.is_none()
+        {
+            println!("error: remote root {} needs a prefix", url);
+            errors += 1;
+        }
+    }
+
+    for name in config::get().commands.keys() {
+        if !name.split('/').all(is_safe_component) {
+            println!(
+                "error: command {:?} in [commands] has an invalid name",
+                name
+            );
+            errors += 1;
+        }
+    }
+
+    let mounts = roots::mounts();
+    for mount in &mounts {
+        let label = match &mount.prefix {
+            Some(prefix) => format!("root {} (prefix '{}')", mount.location(), prefix),
+            None => format!("root {}", mount.location()),
+        };
+        match mount.kind {
+            MountKind::Remote(_) if mount.source().list_dir(&mount.path).is_empty() => {
+                println!("error: {} is unavailable or empty", label);
+                errors += 1;
+            }
+            MountKind::Local if mount.problem().is_some() => {
+                println!("error: {}", roots::root_problem(mount).unwrap_or(label));
+                errors += 1;
+            }
+            _ => println!("ok: {}", label),
+        }
+    }
+
+    let builtins: Vec<String> = builtin_commands()
+        .iter()
+        .map(|c| c.get_name().to_string())
+        .collect();
+    for collision in roots::collisions(&mounts, &builtins) {
+        let hint = if is_builtin(&collision.name) {
+            format!(" (reach it with `shutl run {}`)", collision.name)
+        } else {
+            String::new()
+        };
+        println!(
+            "warning: '{}' from {} is shadowed by {}{}",
+            collision.name, collision.shadowed, collision.kept, hint
+        );
+    }
+
+    for mount in mounts.iter().filter(|mount| mount.kind == MountKind::Local) {
+        for path in crate::source::non_utf8_entries(&mount.path) {
+            println!("warning: {}: file name is not valid UTF-8", path.display());
+        }
+    }
+
+    for mount in &mounts {
+        for (dir, stem) in stem_collisions_from(mount.source().as_ref(), &mount.path) {
+            println!(
+                "warning: several entries in {} are named '{}', so they keep their file names \
+                 (set command-names = \"filename\" to make this explicit)",
+                dir.display(),
+                stem
+            );
+        }
+    }
+
+    if errors > 0 {
+        std::process::exit(1);
+    }
+}
+
+/// Update shutl to the latest GitHub release, or with `--check` only report whether one
+/// is available.
+pub fn handle_upgrade(upgrade_matches: &ArgMatches) {
+    let current = env!("CARGO_PKG_VERSION");
+    let release = upgrade::latest_release().unwrap_or_else(|e| {
+        eprintln!("Failed to check for updates: {}", e);
+        std::process::exit(1);
+    });
+
+    if !upgrade::is_newer(&release.tag_name, current) {
+        println!("shutl {} is up to date", current);
+        return;
+    }
+    if upgrade_matches.get_flag("check") {
+        println!(
+            "shutl {} is available (installed: {})",
+            release.tag_name, current
+        );
+        return;
+    }
+
+    let Some(target) = upgrade::platform_target() else {
+        eprintln!(
+            "No release builds for {}-{}; install {} from source instead",
+            std::env::consts::ARCH,
+            std::env::consts::OS,
+            release.tag_name
+        );
+        std::process::exit(1);
+    };
+    println!("Upgrading shutl {} to {}...", current, release.tag_name);
+    if let Err(e) = upgrade::install(&release, target) {
+        eprintln!("Upgrade failed: {}", e);
+        std::process::exit(1);
+    }
+    println!("Upgraded to {}", release.tag_name);
+}
+
+/// Print the shell hook, with `--export` the statements that switch the project root for
+/// the current directory, or with `--functions` wrapper functions for top-level commands.
+pub fn handle_hook(hook_matches: &ArgMatches) {
+    if let Some((action, matches)) = hook_matches.subcommand() {
+        handle_hook_trust(action, matches);
+        return;
+    }
+    let shell = hook_matches.get_one::<String>("shell").unwrap();
+    if hook_matches.get_flag("functions") {
+        let mounts = roots::mounts();
+        let entries = collect_mount_entries(&mounts, None).unwrap_or_default();
+        let mut names: Vec<String> = entries
+            .iter()
+            .filter_map(|entry| entry.path.split('/').next())
+            .filter(|name| !is_builtin(name))
+            .map(String::from)
+            .collect();
+        names.sort();
+        names.dedup();
+        print!("{}", hook::function_definitions(shell, &names));
+        return;
+    }
+    if hook_matches.get_flag("command-not-found") {
+        print!("{}", hook::not_found_handler(shell).unwrap());
+        return;
+    }
+    if !hook_matches.get_flag("export") {
+        print!("{}", hook::hook_script(shell).unwrap());
+        return;
+    }
+
+    let root = std::env::current_dir()
+        .ok()
+        .and_then(|dir| project_root_of(&dir))
+        .filter(|root| {
+            let trusted = hook::is_trusted(root);
+            if !trusted {
+                eprintln!(
+                    "shutl: not loading scripts from {}: run `shutl hook allow` to trust them as they are",
+                    root.display()
+                );
+            }
+            trusted
+        });
+    let current = std::env::var(hook::PROJECT_ROOT_VAR).ok();
+    let statements = hook::export_statements(shell, current.as_deref(), root.as_deref());
+    if !statements.is_empty()
+        && let Some(root) = &root
+    {
+        eprintln!("shutl: loading scripts from {}", root.display());
+    }
+    print!("{}", statements);
+}
+
+/// Finds the project `.shutl` directory the hook would load for `dir`
+fn project_root_of(dir: &Path) -> Option<PathBuf> {
+    let mut ignored = vec![get_scripts_dir()];
+    ignored.extend(paths::home_dir().map(|home| home.join(".shutl")));
+    hook::find_project_root(dir, &ignored)
+}
+
+/// `shutl hook allow` / `shutl hook deny`: trust the project's scripts as they are, or
+/// stop loading them
+fn handle_hook_trust(action: &str, matches: &ArgMatches) {
+    let dir = match matches.get_one::<PathBuf>("dir") {
+        Some(dir) => dir.clone(),
+        None => std::env::current_dir().unwrap_or_else(|_| PathBuf::from(".")),
+    };
+    let Some(root) = project_root_of(&dir) else {
+        eprintln!("No project .shutl directory in {} or above", dir.display());
+        std::process::exit(1);
+    };
+    let Some(file) = trust::allowed_file() else {
+        eprintln!("No data directory to keep the allowed projects in");
+        std::process::exit(1);
+    };
+    let result = match action {
+        "allow" => trust::allow(&file, &root).map(|root| {
+            println!(
+                "Allowed {}; its scripts load the next time you change into the project",
+                root.display()
+            )
+        }),
+        _ => trust::deny(&file, &root).map(|denied| {
+            if denied {
+                println!("Denied {}", root.display());
+            } else {
+                println!("{} was not allowed", root.display());
+            }
+        }),
+    };
+    if let Err(e) = result {
+        eprintln!("Failed to update {}: {}", file.display(), e);
+        std::process::exit(1);
+    }
+}
+
+/// Offer the scripts named like a command the shell couldn't find, and run the one picked
+/// with the same arguments. Exits with 127 whenever nothing ran, so the shell's handler
+/// goes on with its usual message.
+pub fn handle_command_not_found(matches: &ArgMatches) {
+    let name = matches.get_one::<String>("name").unwrap();
+    let args: Vec<String> = matches
+        .get_many::<String>("args")
+        .map(|values| values.cloned().collect())
+        .unwrap_or_default();
+    let found = errors::candidates_named(name);
+    let Some(command) = found.first() else {
+        std::process::exit(127);
+    };
+
+    if found.len() > 1 {
+        eprintln!("shutl has scripts of that name:");
+        for command in &found {
+            eprintln!("  shutl {}", command.replace('/', " "));
+        }
+        std::process::exit(127);
+    }
+    let group = match command.rsplit_once('/') {
+        Some((group, _)) => format!("under {}/", group),
+        None => "at the top level".to_string(),
+    };
+    let invocation = format!("shutl {}", command.replace('/', " "));
+    if !std::io::stdin().is_terminal() {
+        eprintln!(
+            "`{}` exists in shutl {}; run it with `{}`",
+            name, group, invocation
+        );
+        std::process::exit(127);
+    }
+    let question = format!("`{}` exists in shutl {} - run it? [y/N]", name, group);
+    if !prompt(&question).is_some_and(|answer| answer.eq_ignore_ascii_case("y")) {
+        std::process::exit(127);
+    }
+
+    // Through `run`, so a script shadowed by a built-in runs as well
+    let exe = std::env::current_exe().unwrap_or_else(|e| ShutlError::from(e).exit());
+    let status = Command::new(exe)
+        .arg("run")
+        .args(command_components(command))
+        .arg("--")
+        .args(&args)
+        .status();
+    match status {
+        Ok(status) => std::process::exit(status.code().unwrap_or(1)),
+        Err(e) => {
+            eprintln!("Failed to run {}: {}", invocation, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Print the completion file for a shell, or with `--install` write it to where the shell
+/// looks for completions and add the rc lines loading it.
+pub fn handle_completions(completions_matches: &ArgMatches) {
+    let shell = match completions_matches.get_one::<String>("shell") {
+        Some(shell) => shell.as_str(),
+        None => {
+            install::detect_shell(std::env::var("SHELL").ok().as_deref()).unwrap_or_else(|| {
+                eprintln!(
+                    "Cannot tell the shell from $SHELL; name it, e.g. `shutl completions zsh`"
+                );
+                std::process::exit(1);
+            })
+        }
+    };
+    if !completions_matches.get_flag("install") {
+        print!("{}", install::completion_file(shell).unwrap());
+        return;
+    }
+
+    let Some(home) = paths::home_dir() else {
+        eprintln!("Cannot find the home directory");
+        std::process::exit(1);
+    };
+    let plan = install::plan(shell, &home, |var| std::env::var(var).ok()).unwrap();
+    if let Err(e) = persist::write_atomic(&plan.file, plan.contents.as_bytes()) {
+        eprintln!("Failed to write {}: {}", plan.file.display(), e);
+        std::process::exit(1);
+    }
+    println!("Wrote {}", plan.file.display());
+    if let Some((rc, snippet)) = &plan.rc {
+        match install::add_rc_snippet(rc, snippet) {
+            Ok(true) => println!("Added the completion setup to {}", rc.display()),
+            Ok(false) => println!("{} already loads it", rc.display()),
+            Err(e) => {
+                eprintln!("Failed to update {}: {}", rc.display(), e);
+                std::process::exit(1);
+            }
+        }
+    }
+    println!("Open a new {} to use it", shell);
+}
+
+/// Save, list or remove presets: named arguments of a script stored below the scripts
+/// directory (see [`preset`]).
+pub fn handle_preset(preset_matches: &ArgMatches) {
+    let scripts_dir = get_scripts_dir();
+    match preset_matches.subcommand() {
+        Some(("save", save_matches)) => {
+            let (components, name, path) = preset_target(save_matches);
+            let args: Vec<String> = save_matches
+                .get_many::<String>("args")
+                .map(|values| values.cloned().collect())
+                .unwrap_or_default();
+            // Presets complete the arguments given when running them, so none is required
+            let command_name = components.last().cloned().unwrap_or_default();
+            let matches = build_script_command_for_help(command_name.clone(), &path)
+                .mut_args(|arg| arg.required(false))
+                .bin_name(format!(
+                    "shutl preset save {} {} --",
+                    components.join(" "),
+                    name
+                ))
+                .try_get_matches_from(std::iter::once(command_name).chain(args.iter().cloned()))
+                .unwrap_or_else(|e| e.exit());
+            let stored = script::redact_args(&path, &matches, &args);
+            if stored != args {
+                eprintln!("Secret values are not saved; pass them when running the preset");
+            }
+
+            let file = preset::presets_file(&scripts_dir, &components);
+            if let Err(e) = preset::save(&file, &name, &stored) {
+                eprintln!("Failed to write {}: {}", file.display(), e);
+                std::process::exit(1);
+            }
+            println!(
+                "Saved preset '{}', run it with: shutl {} {}{}",
+                name,
+                components.join(" "),
+                name,
+                preset::PRESET_SUFFIX
+            );
+        }
+        Some(("list", list_matches)) => {
+            let components: Vec<String> = list_matches
+                .get_many::<String>("command")
+                .map(|values| values.flat_map(|s| command_components(s)).collect())
+                .unwrap_or_default();
+            let all = if components.is_empty() {
+                preset::load_all(&scripts_dir)
+            } else {
+                let file = preset::presets_file(&scripts_dir, &components);
+                BTreeMap::from([(components.join("/"), preset::load(&file))])
+            };
+            if all.values().all(|presets| presets.is_empty()) {
+                println!("No presets found.");
+                return;
+            }
+            for (command, presets) in all {
+                for (name, args) in presets {
+                    println!(
+                        "{} {}{}  {}",
+                        command.replace('/', " "),
+                        name,
+                        preset::PRESET_SUFFIX,
+                        args.join(" ")
+                    );
+                }
+            }
+        }
+        Some(("rm", rm_matches)) => {
+            let (components, name, _) = preset_target(rm_matches);
+            let file = preset::presets_file(&scripts_dir, &components);
+            match preset::remove(&file, &name) {
+                Ok(true) => println!("Removed preset '{}'", name),
+                Ok(false) => {
+                    eprintln!("No preset '{}' for {}", name, components.join("/"));
+                    std::process::exit(1);
+                }
+                Err(e) => {
+                    eprintln!("Failed to update {}: {}", file.display(), e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        _ => unreachable!("clap requires a subcommand"),
+    }
+}
+
+/// Splits the `command` values of `preset save`/`rm` into the command path, which must
+/// name a script, and the preset name
+fn preset_target(matches: &ArgMatches) -> (Vec<String>, String, PathBuf) {
+    let mut words: Vec<String> = matches
+        .get_many::<String>("command")
+        .unwrap()
+        .cloned()
+        .collect();
+    let name = words.pop().unwrap();
+    let components: Vec<String> = words.iter().flat_map(|s| command_components(s)).collect();
+    if !preset::is_valid_name(&name) {
+        eprintln!(
+            "Invalid preset name: {} (no whitespace, '/' or '@', and not starting with '-')",
+            name
+        );
+        std::process::exit(1);
+    }
+    if components.iter().any(|c| !is_safe_component(c)) {
+        eprintln!(
+            "Invalid command path: {} (components must stay inside the scripts directory)",
+            components.join("/")
+        );
+        std::process::exit(1);
+    }
+    match roots::resolve(&roots::mounts(), &components) {
+        Some(path) if !path.is_dir() => (components, name, path),
+        _ => {
+            eprintln!("Script not found: {}", components.join("/"));
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Move the scripts from `~/.shutl` to the data directory, where new installs keep them.
+/// Refuses to merge into an existing, non-empty directory.
+pub fn handle_migrate(migrate_matches: &ArgMatches) {
+    let dry_run = migrate_matches.get_flag("dry-run");
+    let link = migrate_matches.get_flag("link");
+    let (Some(from), Some(to)) = (crate::legacy_scripts_dir(), crate::xdg_scripts_dir()) else {
+        eprintln!("Could not determine the home and data directories");
+        std::process::exit(1);
+    };
+
+    if let Ok(target) = std::fs::read_link(&from) {
+        println!(
+            "Nothing to migrate: {} links to {}",
+            from.display(),
+            target.display()
+        );
+        return;
+    }
+    if !from.is_dir() {
+        println!("Nothing to migrate: {} does not exist", from.display());
+        return;
+    }
+    if to.exists() && std::fs::read_dir(&to).is_ok_and(|mut entries| entries.next().is_some()) {
+        eprintln!(
+            "{} already contains files; merge {} into it by hand",
+            to.display(),
+            from.display()
+        );
+        std::process::exit(1);
+    }
+    if dry_run {
+        println!("Would move {} to {}", from.display(), to.display());
+        return;
+    }
+
+    let moved = std::fs::create_dir_all(&to)
+        .and_then(|_| std::fs::remove_dir(&to))
+        .and_then(|_| paths::move_dir(&from, &to));
+    if let Err(e) = moved {
+        eprintln!("Failed to move {}: {}", from.display(), e);
+        std::process::exit(1);
+    }
+    println!("Moved {} to {}", from.display(), to.display());
+    if link {
+        if let Err(e) = std::os::unix::fs::symlink(&to, &from) {
+            eprintln!("Failed to link {}: {}", from.display(), e);
+            std::process::exit(1);
+        }
+        println!("Linked {} to the new location", from.display());
+    }
+
+    let config = config::get();
+    if let Ok(dir) = std::env::var("SHUTL_DIR") {
+        println!(
+            "note: SHUTL_DIR is set to {}, which still takes precedence",
+            dir
+        );
+    } else if let Some(ref dir) = config.scripts_dir {
+        println!(
+            "note: scripts-dir is set to {} in the config, which still takes precedence",
+            dir
+        );
+    } else if config.scripts_location == config::ScriptsLocation::Home && !link {
+        println!(
+            "note: scripts-location = \"home\" in the config still selects {}",
+            from.display()
+        );
+    }
+}
+
+/// List, or with `--delete` remove, what is no longer needed in the scripts directory and
+/// the cache.
+pub fn handle_prune(prune_matches: &ArgMatches) {
+    let days = *prune_matches.get_one::<u64>("older-than").unwrap();
+    let max_age = std::time::Duration::from_secs(days * 24 * 60 * 60);
+    let delete = prune_matches.get_flag("delete");
+
+    let mounts = roots::mounts();
+    let mut stale = prune::find_in_scripts_dir(&get_scripts_dir(), |components| {
+        roots::resolve(&mounts, components).is_some_and(|path| path.is_file())
+    });
+    for dir in [crate::build::cache_dir(), crate::completion::cache_dir()]
+        .into_iter()
+        .flatten()
+    {
+        stale.extend(prune::find_old_files(&dir, Reason::StaleCache, max_age));
+    }
+    if let Some(dir) = summary::logs_dir() {
+        stale.extend(prune::find_old_files(&dir, Reason::OldLog, max_age));
+    }
+
+    if stale.is_empty() {
+        println!("Nothing to prune");
+        return;
+    }
+    let mut failed = false;
+    for entry in &stale {
+        println!("{}: {}", entry.reason.describe(), entry.path.display());
+        if delete && let Err(e) = prune::remove(entry) {
+            eprintln!("Failed to remove {}: {}", entry.path.display(), e);
+            failed = true;
+        }
+    }
+    if !delete {
+        println!(
+            "{} entries can be removed; run `shutl prune --delete` to remove them",
+            stale.len()
+        );
+    } else if failed {
+        std::process::exit(1);
+    }
+}
+
+/// Show the changes to one script or directory, or the whole scripts directory, versus the
+/// remote branch its git checkout tracks.
+pub fn handle_diff(diff_matches: &ArgMatches) {
+    let components: Vec<String> = diff_matches
+        .get_many::<String>("command")
+        .into_iter()
+        .flatten()
+        .flat_map(|s| s.split('/'))
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_string())
+        .collect();
+    let path = if components.is_empty() {
+        get_scripts_dir()
+    } else {
+        match roots::resolve(&roots::mounts(), &components) {
+            Some(path) => path,
+            None => {
+                eprintln!("Command not found: {}", components.join("/"));
+                std::process::exit(1);
+            }
+        }
+    };
+    match git::diff(&path) {
+        Ok(status) => std::process::exit(status.code().unwrap_or(1)),
+        Err(e) => {
+            eprintln!("Cannot diff {}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Summarize how the scripts directory differs from the remote branch it syncs with.
+pub fn handle_status(status_matches: &ArgMatches) {
+    let scripts_dir = get_scripts_dir();
+    if status_matches.get_flag("fetch")
+        && let Err(e) = git::fetch(&scripts_dir)
+    {
+        eprintln!("Failed to fetch: {}", e);
+        std::process::exit(1);
+    }
+    let status = match git::status(&scripts_dir) {
+        Ok(status) => status,
+        Err(e) => {
+            eprintln!("Cannot get the sync status: {}", e);
+            std::process::exit(1);
+        }
+    };
+    println!("{}", status.summary());
+    for change in &status.changes {
+        println!("  {}", change);
+    }
+    if status.behind > 0 {
+        println!("Run `git -C {} pull` to update", scripts_dir.display());
+    } else if status.ahead > 0 || !status.changes.is_empty() {
+        println!("Run `shutl diff` to see the local changes");
+    }
+}
+
+/// Report the commands provided by more than one root (or by a root and a built-in), and
+/// which of them runs.
+pub fn handle_shadows(_shadows_matches: &ArgMatches) {
+    let builtins: Vec<String> = builtin_commands()
+        .iter()
+        .map(|c| c.get_name().to_string())
+        .collect();
+    print!(
+        "{}",
+        format_shadows(&roots::collisions(&roots::mounts(), &builtins))
+    );
+}
+
+/// Formats collisions by command name: the source that runs, then the hidden ones
+fn format_shadows(collisions: &[Collision]) -> String {
+    if collisions.is_empty() {
+        return "No command is defined more than once.\n".to_string();
+    }
+    let mut names: Vec<&str> = Vec::new();
+    for collision in collisions {
+        if !names.contains(&collision.name.as_str()) {
+            names.push(&collision.name);
+        }
+    }
+    let mut output = String::new();
+    for name in names {
+        let group: Vec<&Collision> = collisions.iter().filter(|c| c.name == name).collect();
+        output.push_str(&format!("{}\n  runs:   {}\n", name, group[0].kept));
+        for collision in &group {
+            output.push_str(&format!("  hidden: {}\n", collision.shadowed));
+        }
+        if is_builtin(name) {
+            output.push_str(&format!(
+                "  `shutl run {}` runs {}\n",
+                name, group[0].shadowed
+            ));
+        }
+    }
+    output
+}
+
+/// Download a script into the scripts directory and pin its URL and checksum in the
+/// lockfile.
+pub fn handle_import(import_matches: &ArgMatches) {
+    let url = import_matches.get_one::<String>("url").unwrap();
+    let command = import_matches.get_one::<String>("command");
+    let relative = imports::destination(url, command.map(|s| s.as_str())).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    let scripts_dir = get_scripts_dir();
+    let path = scripts_dir.join(&relative);
+    if path.exists() && !import_matches.get_flag("force") {
+        eprintln!(
+            "{} already exists; use --force to replace it",
+            path.display()
+        );
+        std::process::exit(1);
+    }
+    match imports::import(&scripts_dir, url, &relative) {
+        Ok(pin) => {
+            let signer = match pin.signer {
+                Some(signer) => format!(", signed by {}", signer),
+                None => String::new(),
+            };
+            println!(
+                "Imported {} (sha256 {}{})",
+                path.display(),
+                pin.sha256,
+                signer
+            );
+        }
+        Err(e) => {
+            eprintln!("Failed to import {}: {}", url, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Check the imported scripts against the lockfile (local changes) and, unless offline,
+/// against their URLs (upstream changes).
+pub fn handle_verify(verify_matches: &ArgMatches) {
+    let scripts_dir = get_scripts_dir();
+    let lock = Lock::load(&scripts_dir).unwrap_or_else(|e| {
+        eprintln!("Cannot read the lockfile: {}", e);
+        std::process::exit(1);
+    });
+    if lock.scripts.is_empty() {
+        println!("No imported scripts");
+        return;
+    }
+    let offline = verify_matches.get_flag("offline");
+    let mut problems = 0;
+    for (relative, pin) in &lock.scripts {
+        let mut ok = true;
+        match imports::local_state(&scripts_dir.join(relative), pin) {
+            LocalState::Unchanged => {}
+            LocalState::Modified => {
+                println!(
+                    "modified: {} (changed since it was imported from {})",
+                    relative, pin.url
+                );
+                ok = false;
+            }
+            LocalState::Missing => {
+                println!("missing: {}", relative);
+                ok = false;
+            }
+        }
+        if !offline {
+            match imports::changed_upstream(pin) {
+                Ok(false) => {}
+                Ok(true) => {
+                    println!(
+                        "changed upstream: {} ({} differs; re-import it with `shutl import --force {} {}`)",
+                        relative, pin.url, pin.url, relative
+                    );
+                    ok = false;
+                }
+                Err(e) => println!("warning: cannot check {} upstream: {}", relative, e),
+            }
+        }
+        if ok {
+            println!("ok: {}", relative);
+        } else {
+            problems += 1;
+        }
+    }
+    if problems > 0 {
+        std::process::exit(1);
+    }
+}
+
+/// Sign files with the configured (or given) secret key, for publishing them to be
+/// imported with a verified signature.
+pub fn handle_sign(sign_matches: &ArgMatches) {
+    let key = sign_matches
+        .get_one::<PathBuf>("key")
+        .cloned()
+        .or_else(|| {
+            config::get().signing_key.as_ref().map(|key| {
+                PathBuf::from(
+                    paths::expand(key)
+                        .map(|k| k.to_string())
+                        .unwrap_or_else(|_| key.clone()),
+                )
+            })
+        })
+        .unwrap_or_else(|| {
+            eprintln!("No key to sign with; pass --key or set signing-key in the config");
+            std::process::exit(1);
+        });
+    for file in sign_matches.get_many::<PathBuf>("files").unwrap() {
+        match signing::sign(&key, file) {
+            Ok(signature) => println!("Signed {} ({})", file.display(), signature.display()),
+            Err(e) => {
+                eprintln!("Failed to sign {}: {}", file.display(), e);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+/// Go through the scripts that are new or changed since they were last reviewed, showing
+/// their diffs and marking the accepted ones as reviewed.
+pub fn handle_review(review_matches: &ArgMatches) {
+    let scripts_dir = get_scripts_dir();
+    let review = Review::open(&scripts_dir).unwrap_or_else(|e| {
+        eprintln!("Cannot read the reviewed scripts: {}", e);
+        std::process::exit(1);
+    });
+    let pending = review.pending();
+    if pending.is_empty() {
+        println!("Nothing to review");
+        return;
+    }
+    let yes = review_matches.get_flag("yes");
+    let list = review_matches.get_flag("list") || (!yes && !std::io::stdin().is_terminal());
+    for (script, state) in &pending {
+        let relative = script.strip_prefix(&scripts_dir).unwrap_or(script);
+        println!("{}: {}", state.describe(), relative.display());
+        if list {
+            continue;
+        }
+        if !yes {
+            review.show_diff(script);
+            match prompt("Mark as reviewed? [y/N/q]").as_deref() {
+                Some("y" | "Y") => {}
+                Some("q" | "Q") | None => break,
+                _ => continue,
+            }
+        }
+        if let Err(e) = review.approve(script) {
+            eprintln!("Failed to mark {} as reviewed: {}", relative.display(), e);
+            std::process::exit(1);
+        }
+    }
+    if list {
+        println!("Run `shutl review` in a terminal to look at the diffs");
+    }
+}
+
+/// Show the git history of a script: who changed it, when and why.
+pub fn handle_log(log_matches: &ArgMatches) {
+    let components: Vec<String> = log_matches
+        .get_many::<String>("command")
+        .unwrap()
+        .flat_map(|s| s.split('/'))
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_string())
+        .collect();
+    let Some(script_path) = find_script_file(&components) else {
+        eprintln!("Command not found: {}", components.join("/"));
+        std::process::exit(1);
+    };
+    let patch = log_matches.get_flag("patch");
+    let max_count = log_matches.get_one::<usize>("max-count").copied();
+    match git::log(&script_path, patch, max_count) {
+        Ok(status) => std::process::exit(status.code().unwrap_or(1)),
+        Err(e) => {
+            eprintln!("Cannot show the history of {}: {}", components.join("/"), e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn command_components(command: &str) -> Vec<String> {
+    command
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_string())
+        .collect()
+}
+
+/// Orders candidates by frecency score; commands never run keep their listing order.
+fn rank_candidates(mut entries: Vec<ListEntry>, scores: &HashMap<String, f64>) -> Vec<ListEntry> {
+    let score = |e: &ListEntry| scores.get(&e.path).copied().unwrap_or(0.0);
+    entries.sort_by(|a, b| score(b).total_cmp(&score(a)));
+    entries
+}
+
+/// Case-insensitive subsequence match, e.g. `dpr` matches `deploy/prod`
+fn fuzzy_match(query: &str, candidate: &str) -> bool {
+    let mut chars = candidate.chars().flat_map(char::to_lowercase);
+    query
+        .chars()
+        .flat_map(char::to_lowercase)
+        .all(|q| chars.any(|c| c == q))
+}
+
+fn print_candidates(candidates: &[&ListEntry]) {
+    const MAX_SHOWN: usize = 20;
+    let width = candidates
+        .iter()
+        .take(MAX_SHOWN)
+        .map(|e| e.path.len())
+        .max()
+        .unwrap_or(0);
+    for (i, entry) in candidates.iter().take(MAX_SHOWN).enumerate() {
+        println!(
+            "{:>3}) {:<width$}  {}",
+            i + 1,
+            entry.path,
+            entry.description,
+            width = width
+        );
+    }
+    if candidates.len() > MAX_SHOWN {
+        println!("     ... {} more", candidates.len() - MAX_SHOWN);
+    }
+}
+
+/// Prints a prompt and reads one trimmed line from stdin. Returns `None` on EOF.
+fn prompt(label: &str) -> Option<String> {
+    print!("{}: ", label);
+    std::io::stdout().flush().ok()?;
+    let mut line = String::new();
+    match std::io::stdin().lock().read_line(&mut line) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(line.trim().to_string()),
+    }
+}
+
+/// Prompts for every required argument of a script and returns the matching command line.
+fn prompt_required_args(script_path: &Path) -> Option<Vec<String>> {
+    let metadata = parse_command_metadata(script_path);
+    let mut args = Vec::new();
+    for arg in metadata.arguments {
+        match arg {
+            LineType::Positional(name, description, cfg) => {
+                if cfg.is_multiple() {
+                    if cfg.is_required() || cfg.min_values() > 0 {
+                        let values =
+                            prompt(&format!("{} ({}, space separated)", name, description))?;
+                        args.extend(values.split_whitespace().map(|v| v.to_string()));
+                    }
+                } else if cfg.default.is_none() {
+                    args.push(prompt(&format!("{} ({})", name, description))?);
+                }
+            }
+            LineType::Flag(name, description, cfg) => {
+                if cfg.is_required() && cfg.arg_type != Some(ArgType::Bool) {
+                    let value = prompt(&format!("--{} ({})", name, description))?;
+                    args.push(format!("--{}", name));
+                    args.push(value);
+                }
+            }
+            LineType::Description(_) => {}
+        }
+    }
+    Some(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env_filter::EnvFilter;
+    use crate::limits::{Priority, ResourceLimits};
+    use crate::metadata::StdinMode;
+
+    #[test]
+    fn test_builtin_shadow_warning() {
+        let warning = builtin_shadow_warning("list/all").unwrap();
+        assert!(warning.contains("`shutl run list all`"));
+        assert!(builtin_shadow_warning("new").is_some());
+        assert!(builtin_shadow_warning("deploy/new").is_none());
+    }
+
+    #[test]
+    fn test_strip_validation_comments() {
+        let content = "#!/bin/bash\n# ===========================================================\n# VALIDATION ERRORS — please fix and save to retry, or\n# close without saving to discard changes.\n# ===========================================================\n# error: duplicate argument name 'x'\n# ===========================================================\n#@description: my script\n";
+        let stripped = strip_validation_comments(content);
+        assert_eq!(stripped, "#!/bin/bash\n#@description: my script\n");
+    }
+
+    #[test]
+    fn test_strip_no_validation_comments() {
+        let content = "#!/bin/bash\n#@description: clean\n";
+        let stripped = strip_validation_comments(content);
+        assert_eq!(stripped, content);
+    }
+
+    #[test]
+    fn test_insert_validation_comments_after_shebang() {
+        let content = "#!/bin/bash\n#@description: my script\n";
+        let comments = "# ===========================================================\n# error: bad\n# ===========================================================";
+        let result = insert_validation_comments(content, comments);
+        assert!(result.starts_with("#!/bin/bash\n# =========="));
+        assert!(result.contains("#@description: my script"));
+    }
+
+    #[test]
+    fn test_insert_validation_comments_no_shebang() {
+        let content = "#@description: my script\n";
+        let comments = "# ===========================================================\n# error: bad\n# ===========================================================";
+        let result = insert_validation_comments(content, comments);
+        assert!(result.starts_with("# =========="));
+        assert!(result.contains("#@description: my script"));
+    }
+
+    #[test]
+    fn test_default_script_mode_respects_umask() {
+        assert_eq!(default_script_mode(0o022), 0o755);
+        assert_eq!(default_script_mode(0o077), 0o700);
+        assert_eq!(default_script_mode(0o027), 0o750);
+        // Not group-writable under the common umask of user private groups
+        assert_eq!(default_script_mode(0o002), 0o755);
+    }
+
+    #[test]
+    fn test_replace_description() {
+        assert_eq!(
+            replace_description(
+                "#!/bin/bash\n#@description: Deploy to prod\n#@flag:force - Force\n",
+                "Deploy to staging"
+            ),
+            "#!/bin/bash\n#@description: Deploy to staging\n#@flag:force - Force\n"
+        );
+        assert_eq!(
+            replace_description("//@description: Tool\nfn main() {}\n", "Other tool"),
+            "//@description: Other tool\nfn main() {}\n"
+        );
+        assert_eq!(
+            replace_description("#!/bin/sh\necho hi\n", "Greet"),
+            "#!/bin/sh\n#@description: Greet\necho hi\n"
+        );
+    }
+
+    #[test]
+    fn test_new_script_mode() {
+        let matches = |args: &[&str]| {
+            crate::command::build_new_command()
+                .try_get_matches_from([&["new", "tools", "deploy"], args].concat())
+        };
+        let mode = |args: &[&str]| new_script_mode(&matches(args).unwrap(), Some(0o750), 0o022);
+        assert_eq!(mode(&["--private"]), 0o700);
+        assert_eq!(mode(&["--mode", "755"]), 0o755);
+        assert_eq!(mode(&[]), 0o750);
+        assert_eq!(new_script_mode(&matches(&[]).unwrap(), None, 0o022), 0o755);
+        assert!(matches(&["--private", "--mode", "755"]).is_err());
+    }
+
+    #[test]
+    fn test_format_shadows() {
+        assert_eq!(
+            format_shadows(&[]),
+            "No command is defined more than once.\n"
+        );
+        let collision = |name: &str, kept: &str, shadowed: &str| Collision {
+            name: name.to_string(),
+            kept: kept.to_string(),
+            shadowed: shadowed.to_string(),
+        };
+        let output = format_shadows(&[
+            collision("list", "built-in", "/a/list.sh"),
+            collision("build", "/a/build.sh", "/b/build.sh"),
+            collision("build", "/a/build.sh", "/c/build"),
+        ]);
+        assert_eq!(
+            output,
+            "list\n  runs:   built-in\n  hidden: /a/list.sh\n  `shutl run list` runs /a/list.sh\n\
+             build\n  runs:   /a/build.sh\n  hidden: /b/build.sh\n  hidden: /c/build\n"
+        );
+    }
+
+    #[test]
+    fn test_format_plan() {
+        let mut command = Command::new("pwsh");
+        command
+            .args(["-File", "/s/report.ps1"])
+            .env("SHUTL_MONTH", "may")
+            .env("SHUTL_PIN", "1234");
+        let plan = ExecutionPlan {
+            command,
+            build: Some(("cp {src} {out}".to_string(), PathBuf::from("/nonexistent"))),
+            secret_vars: ["SHUTL_PIN".to_string()].into(),
+            limits: ResourceLimits {
+                max_memory: Some(1 << 30),
+                priority: Some(Priority::Low),
+                ..Default::default()
+            },
+            stdin: Some(StdinMode::Required),
+            output: OutputFormat::Json,
+            env_filter: EnvFilter {
+                allow: Some(vec!["AWS_*".to_string()]),
+                deny: vec!["*_TOKEN".to_string()],
+            },
+            roles: vec!["ci".to_string()],
+            create_dirs: vec![PathBuf::from("/s/out")],
+            sandbox: "net-off".parse().unwrap(),
+            pod: Some("deployment/tools -n ops".parse().unwrap()),
+            input: None,
+        };
+        let components = vec!["report".to_string()];
+        let output = format_plan(
+            &components,
+            Path::new("/s/report.ps1"),
+            Some("/s".into()),
+            &plan,
+        );
+        assert!(output.contains("script:      /s/report.ps1\n"));
+        assert!(output.contains("root:        /s\n"));
+        assert!(output.contains("build:       cp {src} {out} (built on first run)\n"));
+        assert!(output.contains("pod:         deployment/tools in namespace ops\n"));
+        assert!(output.contains("runs:        pwsh -File /s/report.ps1\n"));
+        assert!(output.contains("limits:      memory 1G, priority low\n"));
+        assert!(
+            output.contains("stdin:       required: pipe or redirect input into the command\n")
+        );
+        assert!(output.contains("roles:       ci\n"));
+        assert!(output.contains("creates:     /s/out\n"));
+        assert!(output.contains("sandbox:     net-off\n"));
+        assert!(output.ends_with("environment:\n  SHUTL_MONTH=may\n  SHUTL_PIN=********\n"));
+    }
+
+    #[test]
+    fn test_render_template() {
+        let render_template =
+            |name, script_type| render_template(name, script_type, None, &Default::default());
+        let template = render_template("deploy", "bash");
+        assert!(template.starts_with("#!/bin/bash\n#@description: deploy\n"));
+        assert!(
+            render_template("deploy.sh", "zsh").starts_with("#!/bin/zsh\n#@description: deploy\n")
+        );
+        assert!(
+            render_template("deploy.ps1", "pwsh")
+                .starts_with("#!/usr/bin/env pwsh\n#@description: deploy\n")
+        );
+        assert_eq!(script_extension("pwsh"), "ps1");
+        assert!(
+            render_template("fetch", "ts")
+                .starts_with("#!/usr/bin/env -S deno run -A\n//@description: fetch\n//@arg:")
+        );
+        assert!(render_template("tool.rs", "rust").starts_with("//@description: tool\n"));
+        assert!(render_template("tool", "go").contains("package main"));
+    }
+
+    #[test]
+    fn test_render_template_header() {
+        let template = config::TemplateConfig {
+            header: Some("Copyright {{ company }}\n\n{{name}} by {{author}}\n".into()),
+            vars: BTreeMap::from([("company".into(), "ACME".into())]),
+        };
+        assert_eq!(
+            render_template("deploy.sh", "bash", None, &template),
+            "#!/bin/bash\n# Copyright ACME\n#\n# deploy by {{author}}\n#@description: deploy\n#@arg:input - Input file\n#@flag:verbose - Enable verbose output\n"
+        );
+        assert!(
+            render_template("tool", "rust", None, &template).starts_with(
+                "// Copyright ACME\n//\n// tool by {{author}}\n//@description: tool\n"
+            )
+        );
+    }
+
+    #[test]
+    fn test_render_template_exec() {
+        let template = config::TemplateConfig::default();
+        assert_eq!(
+            render_template("myip", "bash", Some(" curl -s ifconfig.me\n"), &template),
+            "#!/bin/bash\n#@description: Runs `curl -s ifconfig.me`\n\ncurl -s ifconfig.me\n"
+        );
+        assert!(
+            render_template("pods", "zsh", Some("kubectl get pods \\\n  -A"), &template)
+                .starts_with("#!/bin/zsh\n#@description: Runs `kubectl get pods ...`\n\nkubectl")
+        );
+    }
+
+    #[test]
+    fn test_copy_target() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path();
+        std::fs::create_dir(base.join("tools")).unwrap();
+        let source = base.join("deploy").join("prod.sh");
+
+        assert_eq!(
+            copy_target(base, &source, "deploy/staging"),
+            Some(base.join("deploy").join("staging.sh"))
+        );
+        assert_eq!(
+            copy_target(base, &source, "deploy/staging.bash"),
+            Some(base.join("deploy").join("staging.bash"))
+        );
+        assert_eq!(
+            copy_target(base, &source, "tools"),
+            Some(base.join("tools").join("prod.sh"))
+        );
+        assert_eq!(copy_target(base, &source, "../escape"), None);
+    }
+
+    #[test]
+    fn test_write_dir_description_keeps_directives() {
+        let dir = tempfile::tempdir().unwrap();
+        write_dir_description(dir.path(), "First").unwrap();
+        assert_eq!(
+            crate::metadata::parse_dir_metadata(dir.path()).description,
+            "First"
+        );
+
+        std::fs::write(
+            dir.path().join(DIR_METADATA_FILE),
+            "Old text\n#@order: b, a\n",
+        )
+        .unwrap();
+        write_dir_description(dir.path(), "Second").unwrap();
+        let metadata = crate::metadata::parse_dir_metadata(dir.path());
+        assert_eq!(metadata.description, "Second");
+        assert_eq!(metadata.order, vec!["b", "a"]);
+    }
+
+    #[test]
+    fn test_fuzzy_match() {
+        assert!(fuzzy_match("", "deploy/prod"));
+        assert!(fuzzy_match("dpr", "deploy/prod"));
+        assert!(fuzzy_match("DEP", "deploy/prod"));
+        assert!(!fuzzy_match("prd2", "deploy/prod"));
+    }
+
+    #[test]
+    fn test_edit_at_location() {
+        let at = |line, column| Position { line, column };
+        assert_eq!(
+            parse_location("deploy/prod:12"),
+            Some(("deploy/prod", at(12, None)))
+        );
+        assert_eq!(
+            parse_location("deploy:12:3"),
+            Some(("deploy", at(12, Some(3))))
+        );
+        assert_eq!(parse_location("deploy"), None);
+        assert_eq!(parse_location("deploy:x"), None);
+
+        let path = Path::new("/s/deploy.sh");
+        assert_eq!(editor_args("vim", path, None), vec!["/s/deploy.sh"]);
+        assert_eq!(
+            editor_args("/usr/bin/nvim", path, Some(at(12, Some(3)))),
+            vec!["+12", "/s/deploy.sh"]
+        );
+        assert_eq!(
+            editor_args("code", path, Some(at(12, Some(3)))),
+            vec!["--goto", "/s/deploy.sh:12:3"]
+        );
+        assert_eq!(
+            editor_args("hx", path, Some(at(12, None))),
+            vec!["/s/deploy.sh:12"]
+        );
+    }
+
+    #[test]
+    fn test_rank_candidates_by_score() {
+        let entry = |path: &str| ListEntry {
+            path: path.to_string(),
+            description: String::new(),
+            folded: 0,
+        };
+        let scores = HashMap::from([("c".to_string(), 1.0), ("b".to_string(), 4.0)]);
+        let ranked = rank_candidates(
+            vec![entry("a"), entry("b"), entry("c"), entry("d")],
+            &scores,
+        );
+        let paths: Vec<_> = ranked.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(paths, vec!["b", "c", "a", "d"]);
     }
 
     #[test]
@@ -314,6 +2656,7 @@ mod tests {
         let comments = format_diagnostics_as_comments(&[crate::validation::ValidationDiagnostic {
             severity: crate::validation::Severity::Error,
             message: "test error".into(),
+            span: None,
         }]);
         let annotated = insert_validation_comments(original, &comments);
         let stripped = strip_validation_comments(&annotated);
@@ -0,0 +1,139 @@
+//! Capturing a script's stdout for `--shutl-capture <file>` (a copy in a file, the output
+//! still reaches the terminal) and `--shutl-json` (a JSON report of the run with the
+//! output embedded, parsed if the script declares `#@output: json`). `--shutl-copy` collects
+//! it for the clipboard.
+
+use crate::metadata::OutputFormat;
+use serde_json::{Value, json};
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::time::Duration;
+
+/// What a script's stdin gets when shutl has to write to it (see [`spawn`])
+#[derive(Debug, Clone)]
+pub struct Input {
+    /// Written first, e.g. the script and variables of a `#@k8s:` run
+    pub prefix: Vec<u8>,
+    /// Whether shutl's own stdin follows the prefix
+    pub forward: bool,
+}
+
+/// Starts `command`. With `input`, its stdin is a pipe that gets the prefix and then
+/// shutl's own stdin, copied by a thread that ends with the input or with shutl.
+pub fn spawn(command: &mut Command, input: Option<&Input>) -> std::io::Result<Child> {
+    let Some(input) = input else {
+        return command.spawn();
+    };
+    let mut child = command.stdin(Stdio::piped()).spawn()?;
+    let mut pipe = child.stdin.take().expect("stdin is piped");
+    let input = input.clone();
+    std::thread::spawn(move || {
+        // A script that exits early closes the pipe; there is nobody left to tell
+        if pipe.write_all(&input.prefix).is_ok() && input.forward {
+            std::io::copy(&mut std::io::stdin().lock(), &mut pipe).ok();
+        }
+    });
+    Ok(child)
+}
+
+/// Runs `command` with its stdout piped through shutl. The output is copied to `file` if
+/// given, printed as it arrives with `echo` and returned with `collect`; `input` goes to
+/// its stdin as with [`spawn`].
+pub fn run_captured(
+    command: &mut Command,
+    file: Option<&Path>,
+    echo: bool,
+    collect: bool,
+    input: Option<&Input>,
+) -> std::io::Result<(ExitStatus, Vec<u8>)> {
+    let mut file = match file {
+        Some(path) => Some(File::create(path).map_err(|e| {
+            std::io::Error::new(
+                e.kind(),
+                format!("cannot write capture file {}: {}", path.display(), e),
+            )
+        })?),
+        None => None,
+    };
+    let mut child = spawn(command.stdout(Stdio::piped()), input)?;
+    let mut output = child.stdout.take().expect("stdout is piped");
+
+    let mut collected = Vec::new();
+    let mut echo = echo.then(std::io::stdout);
+    let mut buffer = [0u8; 8192];
+    loop {
+        let n = match output.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        let chunk = &buffer[..n];
+        // A closed terminal pipe (`| head`) stops the echo, not the script
+        if let Some(stdout) = &mut echo
+            && stdout
+                .write_all(chunk)
+                .and_then(|_| stdout.flush())
+                .is_err()
+        {
+            echo = None;
+        }
+        if let Some(file) = &mut file {
+            file.write_all(chunk)?;
+        }
+        if collect {
+            collected.extend_from_slice(chunk);
+        }
+    }
+    Ok((child.wait()?, collected))
+}
+
+/// Builds the `--shutl-json` report of a run. Output declared as JSON is embedded as a
+/// value; if it does not parse, or is text, it is embedded as a string.
+pub fn json_report(
+    command: &str,
+    status: ExitStatus,
+    elapsed: Duration,
+    stdout: &[u8],
+    format: OutputFormat,
+) -> Value {
+    let text = String::from_utf8_lossy(stdout);
+    let stdout = match format {
+        OutputFormat::Json => serde_json::from_str(&text).unwrap_or_else(|e| {
+            log::warn!("{} declares JSON output, but it is invalid: {}", command, e);
+            Value::String(text.to_string())
+        }),
+        OutputFormat::Text => Value::String(text.to_string()),
+    };
+    json!({
+        "command": command,
+        "exit_code": status.code(),
+        "duration_ms": elapsed.as_millis() as u64,
+        "stdout": stdout,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_capture_and_report() {
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("out.json");
+        let mut command = Command::new("sh");
+        command.args(["-c", "echo '{\"pods\": 2}'; exit 3"]);
+        let (status, stdout) = run_captured(&mut command, Some(&file), false, true, None).unwrap();
+        assert_eq!(status.code(), Some(3));
+        assert_eq!(std::fs::read(&file).unwrap(), stdout);
+
+        let report = json_report("pods", status, Duration::ZERO, &stdout, OutputFormat::Json);
+        assert_eq!(report["exit_code"], 3);
+        assert_eq!(report["stdout"]["pods"], 2);
+        let report = json_report("pods", status, Duration::ZERO, &stdout, OutputFormat::Text);
+        assert_eq!(report["stdout"], "{\"pods\": 2}\n");
+    }
+}
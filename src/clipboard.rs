@@ -0,0 +1,125 @@
+//! Copying a script's stdout to the system clipboard for `--shutl-copy`, through the
+//! clipboard tool of the session: `pbcopy` on macOS, `wl-copy` under Wayland, `xclip` or
+//! `xsel` under X11 and `clip.exe` in WSL.
+
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+/// Clipboard tools to try, in order, as program and arguments. `macos` and `var` (the
+/// environment) describe the session.
+pub fn candidates(
+    macos: bool,
+    var: impl Fn(&str) -> Option<String>,
+) -> Vec<&'static [&'static str]> {
+    let set = |name: &str| var(name).is_some_and(|value| !value.is_empty());
+    let mut tools: Vec<&'static [&'static str]> = Vec::new();
+    if macos {
+        tools.push(&["pbcopy"]);
+        return tools;
+    }
+    if set("WAYLAND_DISPLAY") {
+        tools.push(&["wl-copy"]);
+    }
+    if set("DISPLAY") {
+        tools.push(&["xclip", "-selection", "clipboard"]);
+        tools.push(&["xsel", "--clipboard", "--input"]);
+    }
+    if set("WSL_DISTRO_NAME") {
+        tools.push(&["clip.exe"]);
+    }
+    tools
+}
+
+fn find_program(name: &str) -> Option<PathBuf> {
+    std::env::split_paths(&std::env::var_os("PATH")?)
+        .map(|dir| dir.join(name))
+        .find(|path| path.is_file())
+}
+
+/// Gets what is copied of `output`: everything but the trailing line break, so a pasted
+/// token doesn't submit the line it is pasted into
+pub fn clip_text(output: &[u8]) -> &[u8] {
+    let mut end = output.len();
+    while end > 0 && matches!(output[end - 1], b'\n' | b'\r') {
+        end -= 1;
+    }
+    &output[..end]
+}
+
+/// Copies `text` with the first clipboard tool that is installed. Returns the name of the
+/// tool, or why nothing was copied.
+pub fn copy(text: &[u8]) -> Result<&'static str, String> {
+    let tools = candidates(cfg!(target_os = "macos"), |name| std::env::var(name).ok());
+    let Some((tool, path)) = tools
+        .iter()
+        .find_map(|tool| Some((tool, find_program(tool[0])?)))
+    else {
+        return Err(if tools.is_empty() {
+            "no clipboard in this session (no Wayland or X11 display)".to_string()
+        } else {
+            let names: Vec<&str> = tools.iter().map(|tool| tool[0]).collect();
+            format!("none of {} is installed", names.join(", "))
+        });
+    };
+    let mut child = Command::new(path)
+        .args(&tool[1..])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .map_err(|e| format!("cannot run {}: {}", tool[0], e))?;
+    let written = child.stdin.take().expect("stdin is piped").write_all(text);
+    let status = child
+        .wait()
+        .map_err(|e| format!("cannot run {}: {}", tool[0], e))?;
+    match written {
+        Ok(()) if status.success() => Ok(tool[0]),
+        Ok(()) => Err(format!("{} failed ({})", tool[0], status)),
+        Err(e) => Err(format!("cannot write to {}: {}", tool[0], e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_candidates() {
+        let session = |vars: &'static [(&'static str, &'static str)]| {
+            move |name: &str| {
+                vars.iter()
+                    .find(|(var, _)| *var == name)
+                    .map(|(_, value)| value.to_string())
+            }
+        };
+        assert_eq!(
+            candidates(true, session(&[("DISPLAY", ":0")])),
+            vec![&["pbcopy"][..]]
+        );
+        assert_eq!(
+            candidates(
+                false,
+                session(&[("WAYLAND_DISPLAY", "wayland-0"), ("DISPLAY", ":0")])
+            )
+            .iter()
+            .map(|tool| tool[0])
+            .collect::<Vec<_>>(),
+            vec!["wl-copy", "xclip", "xsel"]
+        );
+        assert_eq!(
+            candidates(
+                false,
+                session(&[("DISPLAY", ""), ("WSL_DISTRO_NAME", "Ubuntu")])
+            ),
+            vec![&["clip.exe"][..]]
+        );
+        assert!(candidates(false, session(&[])).is_empty());
+    }
+
+    #[test]
+    fn test_clip_text() {
+        assert_eq!(clip_text(b"s3cr3t\n"), b"s3cr3t");
+        assert_eq!(clip_text(b"a\nb\r\n\n"), b"a\nb");
+        assert_eq!(clip_text(b""), b"");
+    }
+}
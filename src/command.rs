@@ -1,12 +1,24 @@
+use crate::argtype;
+#[cfg(feature = "completion")]
+use crate::completion::{complete_with_script, parse_candidates};
+use crate::config;
+use crate::docs;
 use crate::get_scripts_dir;
-use crate::metadata::{ArgType, Config, LineType, parse_command_metadata};
+use crate::hook;
+use crate::install;
+use crate::limits::Priority;
+use crate::list::ListEntry;
+use crate::metadata::{ArgType, CommandMetadata, CommandNames, Config, LineType, OutputFormat};
+use crate::roots;
+use crate::script::{REDACTED, is_runnable_from, is_safe_component, redact_secret_flags};
+use crate::sort::{compare_paths, sort_by_name};
+use crate::source::{EntryKind, FsSource, ScriptSource, SourceEntry};
 use clap::{Arg, Command, crate_authors, crate_description, crate_name, crate_version};
+#[cfg(feature = "completion")]
 use clap_complete::{ArgValueCompleter, CompletionCandidate, PathCompleter};
-use is_executable::IsExecutable;
-use shellexpand;
-use std::collections::HashMap;
-use std::fs;
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 
 /// A command with its associated file path
 pub struct CommandWithPath {
@@ -16,11 +28,12 @@ pub struct CommandWithPath {
 
 /// Resolves the completion start directory from complete options.
 /// Checks env var first, then falls back to the default path.
+#[cfg(feature = "completion")]
 fn resolve_completion_dir(complete_options: &crate::metadata::CompleteOptions) -> Option<PathBuf> {
     // Check env var override first
     if let Some(ref env_var) = complete_options.env_var
         && let Ok(env_value) = std::env::var(env_var)
-        && let Ok(expanded) = shellexpand::full(&env_value)
+        && let Ok(expanded) = crate::paths::expand(&env_value)
     {
         return Some(PathBuf::from(expanded.to_string()));
     }
@@ -28,7 +41,7 @@ fn resolve_completion_dir(complete_options: &crate::metadata::CompleteOptions) -
     // Fall back to default path
     if let Some(path_str) = complete_options.path.to_str()
         && !path_str.is_empty()
-        && let Ok(expanded) = shellexpand::full(path_str)
+        && let Ok(expanded) = crate::paths::expand(path_str)
     {
         return Some(PathBuf::from(expanded.to_string()));
     }
@@ -36,49 +49,197 @@ fn resolve_completion_dir(complete_options: &crate::metadata::CompleteOptions) -
     None
 }
 
-/// Adds a path completer to an argument based on its config
-fn add_path_completer(arg: Arg, cfg: &Config) -> Arg {
-    match &cfg.arg_type {
-        Some(ArgType::Dir) => {
-            let mut pc = PathCompleter::dir();
-            if let Some(ref complete_options) = cfg.complete_options
-                && let Some(dir) = resolve_completion_dir(complete_options)
-            {
-                pc = pc.current_dir(dir);
-            }
-            arg.add(ArgValueCompleter::new(pc))
-        }
-        Some(ArgType::File) => {
-            let mut pc = PathCompleter::file();
-            if let Some(ref complete_options) = cfg.complete_options
-                && let Some(dir) = resolve_completion_dir(complete_options)
-            {
-                pc = pc.current_dir(dir);
-            }
-            arg.add(ArgValueCompleter::new(pc))
+/// Adds a completer that runs a script (`complete-self`/`complete-script`), if configured
+#[cfg(feature = "completion")]
+fn add_command_completer(arg: Arg, cfg: &Config, script_path: &Path) -> Arg {
+    let Some(ref complete) = cfg.complete_command else {
+        return arg;
+    };
+    let complete = complete.clone();
+    let script_path = script_path.to_path_buf();
+    let arg_name = arg.get_id().to_string();
+    let help = arg.get_help().map(|help| help.to_string());
+    arg.add(ArgValueCompleter::new(move |current: &std::ffi::OsStr| {
+        describe_candidates(
+            complete_with_script(
+                &script_path,
+                &complete,
+                &arg_name,
+                &current.to_string_lossy(),
+            ),
+            help.as_deref(),
+        )
+    }))
+}
+
+/// Adds the completer of the argument's type: paths for `file`/`dir`/`path`, the
+/// provider's candidates for registered types
+#[cfg(feature = "completion")]
+fn add_type_completer(arg: Arg, cfg: &Config) -> Arg {
+    let mut pc = match &cfg.arg_type {
+        Some(ArgType::Dir) => PathCompleter::dir(),
+        Some(ArgType::File) => PathCompleter::file(),
+        Some(ArgType::Path) => PathCompleter::any(),
+        Some(ArgType::Custom(custom)) => {
+            let Some(provider) = argtype::lookup(&custom.name) else {
+                return arg;
+            };
+            let option = custom.option.clone();
+            let help = arg.get_help().map(|help| help.to_string());
+            return arg.add(ArgValueCompleter::new(move |current: &std::ffi::OsStr| {
+                describe_candidates(
+                    parse_candidates(
+                        &provider.candidates(&option).join("\n"),
+                        &current.to_string_lossy(),
+                    ),
+                    help.as_deref(),
+                )
+            }));
         }
-        Some(ArgType::Path) => {
-            let mut pc = PathCompleter::any();
-            if let Some(ref complete_options) = cfg.complete_options
-                && let Some(dir) = resolve_completion_dir(complete_options)
-            {
-                pc = pc.current_dir(dir);
-            }
-            arg.add(ArgValueCompleter::new(pc))
+        _ => return arg,
+    };
+    if let Some(ref complete_options) = cfg.complete_options
+        && let Some(dir) = resolve_completion_dir(complete_options)
+    {
+        pc = pc.current_dir(dir);
+    }
+    arg.add(ArgValueCompleter::new(pc))
+}
+
+/// Gives value candidates without a description of their own the argument's help, in zsh
+/// only: it shows descriptions next to the candidates, other shells list bare values
+#[cfg(feature = "completion")]
+fn describe_candidates(
+    candidates: Vec<CompletionCandidate>,
+    help: Option<&str>,
+) -> Vec<CompletionCandidate> {
+    let Some(help) = help.filter(|help| !help.is_empty()) else {
+        return candidates;
+    };
+    if !ZSH_COMPLETION.load(Ordering::Relaxed) {
+        return candidates;
+    }
+    candidates
+        .into_iter()
+        .map(|candidate| match candidate.get_help() {
+            Some(_) => candidate,
+            None => candidate.help(Some(help.to_string().into())),
+        })
+        .collect()
+}
+
+/// Parser accepting the `options` of an argument, with their descriptions for help and
+/// completion. In zsh completion the values without one carry the argument's help, so it
+/// shows next to them like it does for commands and flags.
+fn options_parser(cfg: &Config, help: &str) -> clap::builder::PossibleValuesParser {
+    let zsh = ZSH_COMPLETION.load(Ordering::Relaxed) && !help.is_empty();
+    clap::builder::PossibleValuesParser::new(cfg.options.iter().map(|option| {
+        let value = clap::builder::PossibleValue::new(option);
+        match cfg.option_help.get(option) {
+            Some(option_help) => value.help(option_help.clone()),
+            None if zsh => value.help(help.to_string()),
+            None => value,
         }
-        _ => arg,
+    }))
+}
+
+/// Sets the default of an argument, which help shows as `[default: value]`. Defaults of
+/// `[secret]` arguments are masked there like their values everywhere else.
+fn with_default(arg: Arg, default: &str, cfg: &Config) -> Arg {
+    let arg = arg.default_value(default.to_string());
+    if !cfg.secret {
+        return arg;
     }
+    let help = arg
+        .get_help()
+        .map(|help| help.to_string())
+        .unwrap_or_default();
+    arg.hide_default_value(true).help(
+        format!("{} [default: {}]", help, REDACTED)
+            .trim_start()
+            .to_string(),
+    )
 }
 
-/// Builds a clap Command for a script, useful for rendering help output during validation.
+/// Validates values with the provider of a registered type. Arguments with `options`
+/// keep their possible-values parser.
+fn add_type_validator(arg: Arg, cfg: &Config) -> Arg {
+    let Some(ArgType::Custom(custom)) = &cfg.arg_type else {
+        return arg;
+    };
+    let Some(provider) = argtype::lookup(&custom.name) else {
+        return arg;
+    };
+    if !cfg.options.is_empty() {
+        return arg;
+    }
+    let option = custom.option.clone();
+    arg.value_parser(move |value: &str| {
+        provider
+            .validate(value, &option)
+            .map(|()| value.to_string())
+    })
+}
+
+/// Builds a clap Command for a script, useful for rendering help output during validation
+/// or parsing the arguments given to `run`.
 pub fn build_script_command_for_help(name: String, path: &Path) -> Command {
-    build_script_command(name, path).command
+    build_script_command(&FsSource, name, path).command
+}
+
+/// Builds the command for a directory reached by `components`, whose help lists its children
+pub fn build_group_command(path: &Path, components: &[String]) -> Command {
+    let mut dir_cli = Command::new(components.join(" "));
+    for cmd_with_path in build_command_tree(path, components) {
+        dir_cli = dir_cli.subcommand(cmd_with_path.command);
+    }
+    dir_cli
+}
+
+/// Set while generating zsh completions (see [`detect_completion_shell`])
+static ZSH_COMPLETION: AtomicBool = AtomicBool::new(false);
+
+/// Remembers whether the shell asking for completions is zsh. Must run before
+/// `CompleteEnv::complete()`, which removes the `COMPLETE` variable.
+pub fn detect_completion_shell() {
+    let zsh = std::env::var("COMPLETE").is_ok_and(|shell| shell == "zsh");
+    ZSH_COMPLETION.store(zsh, Ordering::Relaxed);
+}
+
+/// Checks whether a command shows up in help, completion and `list`: its `#@enabled-if:`
+/// conditions hold and it is meant for this machine's roles, unless `--all` is given
+pub fn is_listed(metadata: &CommandMetadata) -> bool {
+    metadata.is_enabled() && (config::all_roles() || metadata.is_for_roles(&config::get().roles))
+}
+
+/// Builds the parts of a script's command that listing it needs: its name, its
+/// description and whether it is hidden
+fn listed_script_command(name: String, metadata: &CommandMetadata) -> Command {
+    let mut cmd = Command::new(name);
+    if !metadata.description.is_empty() {
+        cmd = cmd.about(&metadata.description);
+    }
+    // zsh shows command descriptions next to the candidates, so the first example
+    // travels along with the command there
+    if let Some(example) = metadata.examples.first()
+        && ZSH_COMPLETION.load(Ordering::Relaxed)
+    {
+        let about = match metadata.description.as_str() {
+            "" => format!("e.g. {}", example),
+            description => format!("{} (e.g. {})", description, example),
+        };
+        cmd = cmd.about(about);
+    }
+    if !is_listed(metadata) {
+        cmd = cmd.hide(true);
+    }
+    cmd
 }
 
 /// Builds a command for a script file
-fn build_script_command(name: String, path: &Path) -> CommandWithPath {
-    let metadata = parse_command_metadata(path);
-    let mut cmd = Command::new(&name)
+fn build_script_command(source: &dyn ScriptSource, name: String, path: &Path) -> CommandWithPath {
+    let metadata = source.command_metadata(path);
+    let mut cmd = listed_script_command(name, &metadata)
         .disable_help_subcommand(true)
         .arg(
             Arg::new("shutlverboseid")
@@ -95,47 +256,138 @@ fn build_script_command(name: String, path: &Path) -> CommandWithPath {
                 .hide(true)
                 .long("shutl-noexec")
                 .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("shutlpriority")
+                .help("Run with this CPU and IO priority instead of the script's `#@priority`")
+                .long("shutl-priority")
+                .value_name("PRIORITY")
+                .value_parser(Priority::NAMES)
+                .hide(true),
+        )
+        .arg(
+            Arg::new("shutlcapture")
+                .help("Also write the script's stdout to this file")
+                .long("shutl-capture")
+                .value_name("FILE")
+                .value_hint(clap::ValueHint::FilePath)
+                .hide(true),
+        )
+        .arg(
+            Arg::new("shutljson")
+                .help("Print a JSON report of the run, with the script's stdout embedded")
+                .long("shutl-json")
+                .hide(true)
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("shutlcopy")
+                .help("Copy the script's stdout to the clipboard after a successful run")
+                .long("shutl-copy")
+                .hide(true)
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("shutltmux")
+                .help("Start the script in a new tmux window named after the command")
+                .long("shutl-tmux")
+                .hide(true)
+                .action(clap::ArgAction::SetTrue),
         );
 
-    if !metadata.description.is_empty() {
-        cmd = cmd.about(&metadata.description);
+    let mut after_help = Vec::new();
+    if let Some(stdin) = metadata.stdin {
+        after_help.push(format!("Stdin: {}", stdin.describe()));
+    }
+    if metadata.output != OutputFormat::Text {
+        after_help.push(format!("Output: {}", metadata.output.name()));
+    }
+    if !metadata.examples.is_empty() {
+        let examples: Vec<String> = metadata
+            .examples
+            .iter()
+            .map(|e| format!("  {}", e))
+            .collect();
+        after_help.push(format!("Examples:\n{}", examples.join("\n")));
+    }
+    if !after_help.is_empty() {
+        cmd = cmd.after_help(after_help.join("\n\n"));
     }
 
+    // `-h` is help; clap rejects a short form that is taken twice
+    let mut shorts = vec!['h'];
     for cmdarg in &metadata.arguments {
         match cmdarg {
             LineType::Positional(name, description, cfg) => {
                 let mut arg = Arg::new(name).help(description);
                 arg = if let Some(ref default_value) = cfg.default {
-                    arg.default_value(default_value.clone())
+                    with_default(arg, default_value, cfg)
                 } else {
                     arg.required(true)
                 };
                 if !cfg.options.is_empty() {
-                    arg = arg.value_parser(clap::builder::PossibleValuesParser::new(&cfg.options))
+                    arg = arg.value_parser(options_parser(cfg, description))
                 }
 
-                if let Some(ArgType::CatchAll) = cfg.arg_type {
-                    arg = arg.num_args(1..).action(clap::ArgAction::Append);
-                    arg = arg.required(cfg.required);
-                } else {
-                    arg = add_path_completer(arg, cfg);
+                if cfg.is_multiple() {
+                    let min = cfg.min_values().max(1);
+                    arg = match cfg.max {
+                        Some(max) => arg.num_args(min..=max.max(min)),
+                        None => arg.num_args(min..),
+                    };
+                    arg = arg
+                        .action(clap::ArgAction::Append)
+                        .required(cfg.is_required() || cfg.min_values() > 0);
+                }
+                if cfg.arg_type != Some(ArgType::CatchAll) {
+                    arg = add_type_validator(arg, cfg);
+                    #[cfg(feature = "completion")]
+                    {
+                        arg = add_type_completer(arg, cfg);
+                    }
+                }
+                #[cfg(feature = "completion")]
+                {
+                    arg = add_command_completer(arg, cfg, path);
                 }
 
-                if cfg.required {
+                if cfg.is_required() {
                     arg = arg.required(true);
                 }
+                if cfg.allow_hyphen {
+                    // Once the values start, everything after them belongs to them,
+                    // e.g. `shutl run-tool -v --dry-run`
+                    arg = arg
+                        .allow_hyphen_values(true)
+                        .trailing_var_arg(cfg.is_multiple());
+                }
 
                 cmd = cmd.arg(arg);
             }
 
             LineType::Flag(name, description, cfg) => {
                 let mut arg = Arg::new(name).help(description).long(name);
+                if let Some(short) = cfg.short {
+                    if shorts.contains(&short) {
+                        log::warn!("Ignoring short flag -{} of '{}', it is taken", short, name);
+                    } else {
+                        shorts.push(short);
+                        arg = arg.short(short);
+                    }
+                }
 
                 if let Some(ArgType::Bool) = cfg.arg_type {
                     let negated_name = format!("no-{}", name);
                     arg = arg
                         .action(clap::ArgAction::SetTrue)
                         .conflicts_with(&negated_name);
+                    // A `default:true` flag is on unless `--no-<name>` is given. Help
+                    // doesn't show defaults of flags without values, so it's spelled out.
+                    if cfg.bool_default() == Some(true) {
+                        arg = arg
+                            .default_value("true")
+                            .help(format!("{} [default: true]", description));
+                    }
                     cmd = cmd.arg(
                         Arg::new(&negated_name)
                             .help(format!("Disable the '{}' flag", name))
@@ -145,19 +397,26 @@ fn build_script_command(name: String, path: &Path) -> CommandWithPath {
                     );
                 } else {
                     if let Some(ref default) = cfg.default {
-                        arg = arg.default_value(default.clone());
+                        arg = with_default(arg, default, cfg);
                     }
                     if !cfg.options.is_empty() {
-                        arg = arg
-                            .value_parser(clap::builder::PossibleValuesParser::new(&cfg.options));
+                        arg = arg.value_parser(options_parser(cfg, description));
                     }
+                    arg = add_type_validator(arg, cfg);
                 }
 
-                if cfg.required {
+                if cfg.is_required() {
                     arg = arg.required(true);
                 }
+                if cfg.allow_hyphen {
+                    arg = arg.allow_hyphen_values(true);
+                }
 
-                arg = add_path_completer(arg, cfg);
+                #[cfg(feature = "completion")]
+                {
+                    arg = add_type_completer(arg, cfg);
+                    arg = add_command_completer(arg, cfg, path);
+                }
                 cmd = cmd.arg(arg);
             }
             _ => unreachable!(),
@@ -170,12 +429,53 @@ fn build_script_command(name: String, path: &Path) -> CommandWithPath {
     }
 }
 
+/// How much of the commands off the path named by the arguments is built
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Detail<'a> {
+    /// Everything, as help and error messages show the commands next to the path
+    Full,
+    /// Completing the word given: commands it does not start are name-only stubs without
+    /// metadata, and the others are only built as far as listing them needs
+    Completing(&'a str),
+}
+
 /// Builds a list of commands from a directory
 pub fn build_command_tree(dir_path: &Path, active_args: &[String]) -> Vec<CommandWithPath> {
+    build_command_tree_from(&FsSource, dir_path, active_args)
+}
+
+/// Builds a list of commands from a directory of `source`. Only the directories named by
+/// `active_args` are expanded; other groups are listed without their children.
+pub fn build_command_tree_from(
+    source: &dyn ScriptSource,
+    dir_path: &Path,
+    active_args: &[String],
+) -> Vec<CommandWithPath> {
+    build_tree(source, dir_path, active_args, Detail::Full)
+}
+
+/// Builds the commands of a directory of `source` as completing the last of `active_args`
+/// needs them (see [`build_command_tree_from`]): only the script the arguments name is
+/// built in full, the commands next to the path are cheap stubs.
+pub fn build_completion_tree_from(
+    source: &dyn ScriptSource,
+    dir_path: &Path,
+    active_args: &[String],
+) -> Vec<CommandWithPath> {
+    let word = active_args.last().map_or("", String::as_str);
+    build_tree(source, dir_path, active_args, Detail::Completing(word))
+}
+
+fn build_tree(
+    source: &dyn ScriptSource,
+    dir_path: &Path,
+    active_args: &[String],
+    detail: Detail,
+) -> Vec<CommandWithPath> {
     log::debug!(
         "build_command_tree: dir_path {:?}, active_args: {:?}",
         dir_path,
-        active_args
+        redact_secret_flags(active_args)
     );
     let mut commands = Vec::new();
     let first_arg = active_args.first().cloned().unwrap_or_default();
@@ -188,26 +488,38 @@ pub fn build_command_tree(dir_path: &Path, active_args: &[String]) -> Vec<Comman
     log::debug!(
         "build_command_tree: First arg: {:?}, active_args(rest): {:?}",
         first_arg,
-        rest
+        redact_secret_flags(rest)
     );
 
     if first_arg.is_empty() {
-        return commands_for_dir(dir_path);
+        return commands_for_dir(source, dir_path, detail);
+    }
+
+    // `help` is the help subcommand of every group, never a script
+    if first_arg == HELP_COMMAND {
+        return build_tree(source, dir_path, rest, detail);
+    }
+
+    // Never let an argument such as `..` walk the tree outside of `dir_path`
+    if !is_safe_component(&first_arg) {
+        return build_tree(source, dir_path, rest, detail);
     }
 
     let first_arg_path = dir_path.join(&first_arg);
     log::debug!("build_command_tree: First arg path: {:?}", first_arg_path);
 
-    if first_arg_path.is_dir() {
+    if source.is_dir(&first_arg_path) {
         let dir_name = first_arg_path
             .file_name()
             .unwrap()
             .to_string_lossy()
             .to_string();
         let dir_cmd = add_dir_subcommands(
-            dir_command(&first_arg_path, &dir_name),
+            source,
+            dir_command(source, &first_arg_path, &dir_name),
             &first_arg_path,
             rest,
+            detail,
         );
         commands.push(CommandWithPath {
             command: dir_cmd,
@@ -216,20 +528,22 @@ pub fn build_command_tree(dir_path: &Path, active_args: &[String]) -> Vec<Comman
         return commands;
     }
 
-    if let Some(script_path) = find_script_file(dir_path, &first_arg) {
-        commands.push(build_script_command(first_arg, &script_path));
+    if let Some(script_path) = find_script_file(source, dir_path, &first_arg) {
+        commands.push(build_script_command(source, first_arg, &script_path));
         return commands;
     }
 
-    build_command_tree(dir_path, rest)
+    build_tree(source, dir_path, rest, detail)
 }
 
 fn add_dir_subcommands(
+    source: &dyn ScriptSource,
     mut dir_cmd: Command,
     first_arg_path: &Path,
     active_args: &[String],
+    detail: Detail,
 ) -> Command {
-    for subcmd in build_command_tree(first_arg_path, active_args) {
+    for subcmd in build_tree(source, first_arg_path, active_args, detail) {
         log::debug!(
             "build_command_tree: subcmd: {:?}",
             subcmd.command.get_name()
@@ -239,89 +553,240 @@ fn add_dir_subcommands(
     dir_cmd
 }
 
-fn dir_command(path: &Path, dir_name: &String) -> Command {
-    let mut dir_cmd = Command::new(dir_name).disable_help_subcommand(true);
+fn dir_command(source: &dyn ScriptSource, path: &Path, dir_name: &String) -> Command {
+    // Groups get a `help` subcommand (`shutl deploy help`); scripts do not, since `help`
+    // may be a legitimate argument value for them
+    let mut dir_cmd = Command::new(dir_name);
 
-    if let Ok(about) = fs::read_to_string(path.join(".shutl")) {
-        dir_cmd = dir_cmd.about(about.trim().to_owned());
+    let metadata = source.dir_metadata(path);
+    // A group's own `#@enabled-if:` or `#@roles:` hides it along with the scripts
+    // inheriting it
+    if !is_listed(&CommandMetadata::from_elements(metadata.defaults)) {
+        dir_cmd = dir_cmd.hide(true);
+    }
+    if !metadata.description.is_empty() {
+        dir_cmd = dir_cmd.about(metadata.description);
+    } else if let Some(summary) = summarize_group(source, path) {
+        dir_cmd = dir_cmd.about(summary);
     }
 
     dir_cmd
 }
 
-fn commands_for_dir(dir: &Path) -> Vec<CommandWithPath> {
+/// Summarizes a group without a description by the names of its children,
+/// e.g. `3 commands: apply, delete, diff`
+fn summarize_group(source: &dyn ScriptSource, path: &Path) -> Option<String> {
+    const SHOWN: usize = 3;
+    let mut names: Vec<String> = source
+        .list_dir(path)
+        .into_iter()
+        .filter(|entry| {
+            !entry.name.starts_with('.')
+                && (entry.kind == EntryKind::Dir || is_runnable_from(source, &entry.path))
+        })
+        .map(|entry| {
+            let name = entry.name;
+            name.rsplitn(2, '.').last().unwrap_or(&name).to_string()
+        })
+        .filter(|name| name != HELP_COMMAND)
+        .collect();
+    if names.is_empty() {
+        return None;
+    }
+    names.sort();
+    names.dedup();
+    sort_by_name(&mut names, &[], config::get().sort, |n| n);
+
+    let noun = if names.len() == 1 {
+        "command"
+    } else {
+        "commands"
+    };
+    let mut shown = names[..names.len().min(SHOWN)].join(", ");
+    if names.len() > SHOWN {
+        shown.push_str(", ...");
+    }
+    Some(format!("{} {}: {}", names.len(), noun, shown))
+}
+
+/// Splits the visible entries of `dir` into directories and runnable scripts
+fn visible_entries(source: &dyn ScriptSource, dir: &Path) -> (Vec<SourceEntry>, Vec<SourceEntry>) {
+    let (directories, files): (Vec<_>, Vec<_>) = source
+        .list_dir(dir)
+        .into_iter()
+        .filter(|entry| !entry.name.starts_with('.'))
+        .partition(|entry| entry.kind == EntryKind::Dir);
+    let files = files
+        .into_iter()
+        .filter(|entry| is_runnable_from(source, &entry.path))
+        .collect();
+    (directories, files)
+}
+
+fn commands_for_dir(source: &dyn ScriptSource, dir: &Path, detail: Detail) -> Vec<CommandWithPath> {
     let mut commands = Vec::new();
     log::debug!("commands_for_dir: {:?}", dir);
+    let is_stub =
+        |name: &str| matches!(detail, Detail::Completing(word) if !name.starts_with(word));
 
-    if let Ok(entries) = fs::read_dir(dir) {
-        let (mut directories, mut files): (Vec<_>, Vec<_>) = entries
-            .filter_map(Result::ok)
-            .partition(|entry| entry.path().is_dir());
+    let (directories, files) = visible_entries(source, dir);
+    let naming = command_names_from(source, dir);
+    let mut command_names = Vec::new();
+    let mut use_extension = HashMap::new();
 
-        directories.retain(|entry| !entry.file_name().to_string_lossy().starts_with('.'));
-        files.retain(|entry| {
-            !entry.file_name().to_string_lossy().starts_with('.')
-                && entry.path().is_file()
-                && entry.path().is_executable()
+    for entry in &directories {
+        command_names.push(entry.name.clone());
+        let command = if is_stub(&entry.name) {
+            Command::new(&entry.name)
+        } else {
+            dir_command(source, &entry.path, &entry.name)
+        };
+        commands.push(CommandWithPath {
+            command,
+            file_path: entry.path.clone(),
         });
+    }
 
-        let mut command_names = Vec::new();
-        let mut use_extension = HashMap::new();
-
-        for path in &directories {
-            let dir_name = path.file_name().to_string_lossy().to_string();
-            command_names.push(dir_name.clone());
-            commands.push(CommandWithPath {
-                command: dir_command(&path.path(), &dir_name),
-                file_path: path.path(),
-            });
-        }
-
-        for path in &files {
-            let name = path.file_name().to_string_lossy().to_string();
-            let clean_name = name.rsplitn(2, '.').last().unwrap_or(&name).to_string();
-            if command_names.contains(&clean_name) {
-                use_extension.insert(clean_name.clone(), true);
-            } else {
-                command_names.push(clean_name.clone());
-            }
+    for entry in &files {
+        let clean_name = entry
+            .name
+            .rsplitn(2, '.')
+            .last()
+            .unwrap_or(&entry.name)
+            .to_string();
+        if command_names.contains(&clean_name) {
+            use_extension.insert(clean_name.clone(), true);
+        } else {
+            command_names.push(clean_name.clone());
         }
+    }
 
-        for path in files {
-            let name = path.file_name().to_string_lossy().to_string();
-            let clean_name = name.rsplitn(2, '.').last().unwrap_or(&name).to_string();
-            let command_name = if use_extension.contains_key(&clean_name) {
+    for entry in files {
+        let name = entry.name;
+        let clean_name = name.rsplitn(2, '.').last().unwrap_or(&name).to_string();
+        let command_name =
+            if naming == CommandNames::Filename || use_extension.contains_key(&clean_name) {
                 name
             } else {
                 clean_name
             };
-            commands.push(build_script_command(command_name, &path.path()));
-        }
+        let command = match detail {
+            Detail::Full => build_script_command(source, command_name, &entry.path).command,
+            Detail::Completing(_) if is_stub(&command_name) => Command::new(command_name),
+            Detail::Completing(_) => {
+                listed_script_command(command_name, &source.command_metadata(&entry.path))
+            }
+        };
+        commands.push(CommandWithPath {
+            command,
+            file_path: entry.path,
+        });
     }
 
+    commands.retain(|c| {
+        let reserved = c.command.get_name() == HELP_COMMAND;
+        if reserved {
+            log::warn!(
+                "Skipping {}: 'help' is reserved for the help subcommand",
+                c.file_path.display()
+            );
+        }
+        !reserved
+    });
+
+    let order = source.dir_metadata(dir).order;
+    sort_by_name(&mut commands, &order, config::get().sort, |c| {
+        c.command.get_name()
+    });
+
     commands
 }
 
-fn find_script_file(dir_path: &Path, name: &str) -> Option<PathBuf> {
+/// Gets how scripts in `dir` are named: its `#@command-names` directive, else the config
+pub fn command_names_in(dir: &Path) -> CommandNames {
+    command_names_from(&FsSource, dir)
+}
+
+/// Gets how scripts in `dir` of `source` are named (see [`command_names_in`])
+pub fn command_names_from(source: &dyn ScriptSource, dir: &Path) -> CommandNames {
+    source
+        .dir_metadata(dir)
+        .command_names
+        .unwrap_or(config::get().command_names)
+}
+
+/// Finds stems in directories using [`CommandNames::Stem`] that are shared by several
+/// scripts or by a script and a directory, so some commands fall back to file names.
+/// Returns the directory and the stem of each ambiguous name.
+pub fn stem_collisions(root: &Path) -> Vec<(PathBuf, String)> {
+    stem_collisions_from(&FsSource, root)
+}
+
+/// Finds ambiguous stems below `root` of `source` (see [`stem_collisions`])
+pub fn stem_collisions_from(source: &dyn ScriptSource, root: &Path) -> Vec<(PathBuf, String)> {
+    let mut collisions = Vec::new();
+    let mut visited = HashSet::new();
+    find_stem_collisions(source, root, &mut collisions, &mut visited);
+    collisions
+}
+
+fn find_stem_collisions(
+    source: &dyn ScriptSource,
+    dir: &Path,
+    collisions: &mut Vec<(PathBuf, String)>,
+    visited: &mut HashSet<PathBuf>,
+) {
+    if !visited.insert(source.canonicalize(dir)) {
+        return;
+    }
+    let (directories, files) = visible_entries(source, dir);
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for entry in &directories {
+        *counts.entry(entry.name.clone()).or_default() += 1;
+    }
+    for entry in &files {
+        let stem = entry.name.rsplitn(2, '.').last().unwrap_or(&entry.name);
+        *counts.entry(stem.to_string()).or_default() += 1;
+    }
+
+    if command_names_from(source, dir) == CommandNames::Stem {
+        let mut ambiguous: Vec<_> = counts.into_iter().filter(|(_, n)| *n > 1).collect();
+        ambiguous.sort();
+        collisions.extend(
+            ambiguous
+                .into_iter()
+                .map(|(stem, _)| (dir.to_path_buf(), stem)),
+        );
+    }
+
+    let mut subdirs: Vec<_> = directories.into_iter().map(|entry| entry.path).collect();
+    subdirs.sort();
+    for subdir in subdirs {
+        find_stem_collisions(source, &subdir, collisions, visited);
+    }
+}
+
+fn find_script_file(source: &dyn ScriptSource, dir_path: &Path, name: &str) -> Option<PathBuf> {
     let script_path = dir_path.join(name);
-    if script_path.is_file() && script_path.is_executable() {
+    if is_runnable_from(source, &script_path) {
         return Some(script_path);
     }
 
-    if let Ok(entries) = fs::read_dir(dir_path) {
-        for entry in entries.flatten() {
-            let path = entry.path();
-            let filename = path.file_name().unwrap().to_string_lossy().to_string();
-            if path.is_file() && filename.rsplitn(2, ".").last().unwrap_or(&filename) == name {
-                if path.is_executable() {
-                    return Some(path);
-                }
-                return None;
-            }
-        }
+    if command_names_from(source, dir_path) == CommandNames::Filename {
+        return None;
     }
 
-    None
+    let entry = source.list_dir(dir_path).into_iter().find(|entry| {
+        entry.kind == EntryKind::File
+            && entry.name.rsplitn(2, '.').last().unwrap_or(&entry.name) == name
+    })?;
+    is_runnable_from(source, &entry.path).then_some(entry.path)
+}
+
+/// Resolves symlinks so a script reached through several links has a single identity.
+/// Falls back to the given path if it cannot be canonicalized (e.g. a broken link).
+pub fn canonical_script_path(path: &Path) -> PathBuf {
+    FsSource.canonicalize(path)
 }
 
 /// Builds the complete CLI command structure
@@ -338,27 +803,181 @@ pub fn build_cli_command() -> Command {
     } else {
         args
     };
+    let detail = if is_completion {
+        Detail::Completing(active_args.last().map_or("", String::as_str))
+    } else {
+        Detail::Full
+    };
 
     let mut cli = Command::new(crate_name!())
         .version(crate_version!())
         .about(crate_description!())
         .author(crate_authors!())
-        .disable_help_subcommand(true);
+        .arg(
+            Arg::new("profile")
+                .long("profile")
+                .value_name("NAME")
+                .help("Use a profile from the config file (or set SHUTL_PROFILE)")
+                .complete_with(Completer::Profiles),
+        )
+        .arg(
+            Arg::new("shutlroot")
+                .long("shutl-root")
+                .value_name("DIR")
+                .help("Use the scripts in DIR instead of the scripts directory (or set SHUTL_DIR)")
+                .complete_with(Completer::Dirs(PathBuf::from("."))),
+        )
+        .arg(
+            Arg::new("errorformat")
+                .long("error-format")
+                .value_name("FORMAT")
+                .help("Report unknown commands and invalid arguments as text or JSON (or set SHUTL_ERROR_FORMAT)")
+                .value_parser(crate::errors::FORMATS),
+        )
+        .arg(
+            Arg::new("frozen")
+                .long("frozen")
+                .help("Refuse to run imported scripts changed since they were imported (or set SHUTL_FROZEN=1)")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("all")
+                .long("all")
+                .help("Include commands meant for machines with other roles")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("nopager")
+                .long("no-pager")
+                .help("Print long help, list and docs output without a pager (or set SHUTL_NO_PAGER=1)")
+                .action(clap::ArgAction::SetTrue),
+        );
 
     // Add built-in commands
-    cli = cli
-        .subcommand(build_new_command())
-        .subcommand(build_edit_command())
-        .subcommand(build_list_command())
-        .subcommand(build_validate_command());
+    for builtin in builtin_commands() {
+        cli = cli.subcommand(builtin);
+    }
 
-    for cmd_with_path in build_command_tree(&get_scripts_dir(), &active_args) {
-        cli = cli.subcommand(cmd_with_path.command);
+    // Prefixed roots first, then flat roots; the first provider of a name wins and
+    // `shutl doctor` reports the rest
+    let mounts = roots::mounts();
+    for mount in &mounts {
+        let Some(ref prefix) = mount.prefix else {
+            continue;
+        };
+        if is_builtin(prefix) || cli.find_subcommand(prefix).is_some() {
+            continue;
+        }
+        let position = active_args.iter().position(|arg| arg == prefix);
+        let rest = position.map_or(&[][..], |i| &active_args[i + 1..]);
+        let source = mount.source();
+        let mut prefix_cmd = dir_command(source.as_ref(), &mount.path, prefix);
+        if prefix_cmd.get_about().is_none() {
+            prefix_cmd = prefix_cmd.about(format!("Scripts from {}", mount.location()));
+        }
+        // Only a root that is used needs its scripts; listing a remote root's downloads them
+        if position.is_some() {
+            prefix_cmd =
+                add_dir_subcommands(source.as_ref(), prefix_cmd, &mount.path, rest, detail);
+        }
+        cli = cli.subcommand(prefix_cmd);
+    }
+    for mount in mounts.iter().filter(|m| m.prefix.is_none()) {
+        let source = mount.source();
+        for cmd_with_path in build_tree(source.as_ref(), &mount.path, &active_args, detail) {
+            let name = cmd_with_path.command.get_name();
+            if is_builtin(name) || cli.find_subcommand(name).is_some() {
+                log::debug!(
+                    "build_cli_command: {:?} is shadowed by an existing '{}' command",
+                    cmd_with_path.file_path,
+                    name
+                );
+                continue;
+            }
+            cli = cli.subcommand(cmd_with_path.command);
+        }
     }
 
     cli
 }
 
+/// Names of the built-in subcommands. They take precedence over scripts and directories
+/// of the same name, which stay reachable through `shutl run`.
+pub const BUILTIN_NAMES: &[&str] = &[
+    "new",
+    "edit",
+    "cp",
+    "mkdir",
+    "list",
+    "validate",
+    "recent",
+    "pick",
+    "run",
+    "explain",
+    "docs",
+    "doctor",
+    "upgrade",
+    "hook",
+    "preset",
+    "run-all",
+    "migrate",
+    "prune",
+    "diff",
+    "status",
+    "log",
+    "shadows",
+    "import",
+    "verify",
+    "sign",
+    "review",
+    "completions",
+    "command-not-found",
+    "schema",
+];
+
+/// Name of clap's help subcommand, available on the top level and on every group
+pub const HELP_COMMAND: &str = "help";
+
+/// Returns true if `name` is taken by a built-in subcommand (including `help`)
+pub fn is_builtin(name: &str) -> bool {
+    name == HELP_COMMAND || BUILTIN_NAMES.contains(&name)
+}
+
+/// Builds the built-in subcommands, in the order they appear in help
+pub fn builtin_commands() -> Vec<Command> {
+    vec![
+        build_new_command(),
+        build_edit_command(),
+        build_cp_command(),
+        build_mkdir_command(),
+        build_list_command(),
+        build_validate_command(),
+        build_recent_command(),
+        build_pick_command(),
+        build_run_command(),
+        build_explain_command(),
+        build_docs_command(),
+        build_doctor_command(),
+        build_upgrade_command(),
+        build_hook_command(),
+        build_preset_command(),
+        build_run_all_command(),
+        build_migrate_command(),
+        build_prune_command(),
+        build_diff_command(),
+        build_status_command(),
+        build_log_command(),
+        build_shadows_command(),
+        build_import_command(),
+        build_verify_command(),
+        build_sign_command(),
+        build_review_command(),
+        build_completions_command(),
+        build_command_not_found_command(),
+        build_schema_command(),
+    ]
+}
+
 /// Builds the 'new' subcommand for creating new scripts
 pub fn build_new_command() -> Command {
     let scripts_dir = get_scripts_dir();
@@ -366,17 +985,17 @@ pub fn build_new_command() -> Command {
         .about("Create a new script")
         .arg(
             Arg::new("location")
-                .help("Location to create the script (relative to ~/.shutl)")
-                .default_value("")
+                .help("Location to create the script (relative to ~/.shutl), or the whole path (e.g. net/myip) without NAME")
                 .required(true)
-                .add(ArgValueCompleter::new(
-                    PathCompleter::dir().current_dir(scripts_dir),
-                )),
+                .complete_with(Completer::Dirs(scripts_dir)),
         )
+        .arg(Arg::new("name").help("Name of the script (without .sh extension)"))
         .arg(
-            Arg::new("name")
-                .help("Name of the script (without .sh extension)")
-                .required(true),
+            Arg::new("exec")
+                .help("Create a script running this command line, without opening an editor")
+                .long("exec")
+                .value_name("COMMAND")
+                .conflicts_with("editor"),
         )
         .arg(
             Arg::new("editor")
@@ -386,11 +1005,11 @@ pub fn build_new_command() -> Command {
         )
         .arg(
             Arg::new("type")
-                .help("Shell type for the script")
+                .help("Type of the script (pwsh, ts, go and rust create .ps1, .ts, .go and .rs files)")
                 .long("type")
                 .short('t')
                 .value_parser(clap::builder::PossibleValuesParser::new(vec![
-                    "zsh", "bash",
+                    "zsh", "bash", "pwsh", "ts", "go", "rust",
                 ]))
                 .default_value("zsh"),
         )
@@ -400,6 +1019,33 @@ pub fn build_new_command() -> Command {
                 .long("no-edit")
                 .action(clap::ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("force")
+                .help("Overwrite an existing script without asking")
+                .long("force")
+                .short('f')
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("mode")
+                .help("Permissions for the new script in octal (defaults to 0755 minus the umask)")
+                .long("mode")
+                .short('m')
+                .value_parser(crate::config::parse_mode),
+        )
+        .arg(
+            Arg::new("private")
+                .help("Make the new script readable and executable only by you (mode 0700)")
+                .long("private")
+                .conflicts_with("mode")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("dry-run")
+                .help("Print the target path and template without writing anything")
+                .long("dry-run")
+                .action(clap::ArgAction::SetTrue),
+        )
 }
 
 /// Builds the 'edit' subcommand for editing existing scripts
@@ -409,9 +1055,44 @@ pub fn build_edit_command() -> Command {
         .arg(
             Arg::new("command")
                 .help("Command path components (e.g., 'subdir myscript')")
-                .required(true)
+                .required_unless_present("at")
                 .num_args(1..)
-                .add(ArgValueCompleter::new(complete_script_names)),
+                .complete_with(Completer::ScriptNames),
+        )
+        .arg(
+            Arg::new("at")
+                .help(
+                    "Open a command at a line, e.g. 'subdir/myscript:12' or 'subdir/myscript:12:3'",
+                )
+                .long("at")
+                .value_name("COMMAND:LINE")
+                .conflicts_with("command")
+                .complete_with(Completer::ScriptNames),
+        )
+        .arg(
+            Arg::new("editor")
+                .help("Editor to use (defaults to $EDITOR or 'vim')")
+                .long("editor")
+                .short('e'),
+        )
+}
+
+/// Builds the 'cp' subcommand for copying an existing script to a new command
+pub fn build_cp_command() -> Command {
+    let scripts_dir = get_scripts_dir();
+    Command::new("cp")
+        .about("Copy an existing script as a starting point for a new one")
+        .arg(
+            Arg::new("source")
+                .help("Command to copy (e.g., 'subdir/myscript')")
+                .required(true)
+                .complete_with(Completer::ScriptNames),
+        )
+        .arg(
+            Arg::new("destination")
+                .help("New command path relative to ~/.shutl (e.g., 'subdir/newscript')")
+                .required(true)
+                .complete_with(Completer::Dirs(scripts_dir)),
         )
         .arg(
             Arg::new("editor")
@@ -419,101 +1100,578 @@ pub fn build_edit_command() -> Command {
                 .long("editor")
                 .short('e'),
         )
+        .arg(
+            Arg::new("no-edit")
+                .help("Don't open the copy in an editor")
+                .long("no-edit")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("force")
+                .help("Overwrite an existing script without asking")
+                .long("force")
+                .short('f')
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("description")
+                .help("Description of the copy, replacing the #@description line")
+                .long("description")
+                .short('d'),
+        )
+}
+
+/// Builds the 'mkdir' subcommand for creating command groups
+pub fn build_mkdir_command() -> Command {
+    let scripts_dir = get_scripts_dir();
+    Command::new("mkdir")
+        .about("Create a command group (directory), optionally with a description")
+        .arg(
+            Arg::new("path")
+                .help("Directory to create, relative to ~/.shutl (e.g., 'deploy/aws')")
+                .required(true)
+                .complete_with(Completer::Dirs(scripts_dir)),
+        )
+        .arg(
+            Arg::new("description")
+                .help("Description shown in help output (stored in the directory's .shutl file)")
+                .long("description")
+                .short('d'),
+        )
+}
+
+/// Builds the 'validate' subcommand for validating script metadata
+pub fn build_validate_command() -> Command {
+    Command::new("validate")
+        .about("Validate a script's metadata configuration")
+        .arg(
+            Arg::new("command")
+                .help("Command path components (e.g., 'subdir myscript')")
+                .required(true)
+                .num_args(1..)
+                .complete_with(Completer::ScriptNames),
+        )
+}
+
+/// Builds the 'list' subcommand for listing available scripts
+pub fn build_list_command() -> Command {
+    let scripts_dir = get_scripts_dir();
+    Command::new("list")
+        .about("List available scripts")
+        .arg(
+            Arg::new("subdirectory")
+                .help("Only list scripts under this subdirectory")
+                .required(false)
+                .complete_with(Completer::Dirs(scripts_dir)),
+        )
+        .arg(
+            Arg::new("tree")
+                .help("Show hierarchical tree view")
+                .long("tree")
+                .overrides_with("flat")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("flat")
+                .help("Show one path per line; the default, only needed to override an earlier --tree")
+                .long("flat")
+                .overrides_with("tree")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("depth")
+                .help("Show groups more than this many levels down folded, with their script count")
+                .long("depth")
+                .short('d')
+                .value_name("LEVELS")
+                .value_parser(clap::value_parser!(u32).range(1..)),
+        )
+}
+
+/// Builds the 'recent' subcommand for listing frequently and recently used scripts
+pub fn build_recent_command() -> Command {
+    Command::new("recent")
+        .about("List recently used scripts, most frecent first")
+        .arg(
+            Arg::new("limit")
+                .help("Maximum number of commands to show")
+                .long("limit")
+                .short('n')
+                .value_parser(clap::value_parser!(usize))
+                .default_value("10"),
+        )
+}
+
+/// Builds the 'pick' subcommand for interactively selecting a script to run
+pub fn build_pick_command() -> Command {
+    Command::new("pick")
+        .about("Interactively pick a script to run, most frecent first")
+        .arg(Arg::new("query").help("Initial fuzzy filter for command names"))
+}
+
+/// Builds the 'run' subcommand for running a script by path, even if a built-in has its name
+pub fn build_run_command() -> Command {
+    Command::new("run")
+        .about("Run a script by command path (e.g., 'deploy prod' or 'deploy/prod')")
+        .arg(
+            Arg::new("command")
+                .help("Command path components")
+                .required(true)
+                .num_args(1..)
+                .complete_with(Completer::ScriptNames),
+        )
+        .arg(
+            Arg::new("args")
+                .help("Arguments for the script, after '--'")
+                .num_args(0..)
+                .last(true)
+                .allow_hyphen_values(true),
+        )
+}
+
+/// Builds the 'run-all' subcommand for running several scripts with a summary
+pub fn build_run_all_command() -> Command {
+    Command::new("run-all")
+        .about("Run several scripts, or every script of a group, and summarize the results")
+        .arg(
+            Arg::new("commands")
+                .help("Command paths (e.g. 'checks/lint') or groups (e.g. 'checks')")
+                .required(true)
+                .num_args(1..)
+                .complete_with(Completer::ScriptNames),
+        )
+        .arg(
+            Arg::new("fail-fast")
+                .help("Skip the remaining scripts after the first failure")
+                .long("fail-fast")
+                .action(clap::ArgAction::SetTrue)
+                .conflicts_with("keep-going"),
+        )
+        .arg(
+            Arg::new("keep-going")
+                .help("Run every script even if one fails (default)")
+                .long("keep-going")
+                .action(clap::ArgAction::SetTrue),
+        )
+}
+
+/// Builds the 'explain' subcommand for showing how a script would be run
+pub fn build_explain_command() -> Command {
+    Command::new("explain")
+        .about("Show how a script would be run, without running it")
+        .arg(
+            Arg::new("command")
+                .help("Command path components")
+                .required(true)
+                .num_args(1..)
+                .complete_with(Completer::ScriptNames),
+        )
+        .arg(
+            Arg::new("args")
+                .help("Arguments for the script, after '--'")
+                .num_args(0..)
+                .last(true)
+                .allow_hyphen_values(true),
+        )
+}
+
+/// Builds the 'schema' subcommand that prints the JSON Schema of a script's arguments
+pub fn build_schema_command() -> Command {
+    Command::new("schema")
+        .about("Print a JSON Schema of a script's arguments, e.g. to generate forms")
+        .arg(
+            Arg::new("command")
+                .help("Command path components")
+                .required(true)
+                .num_args(1..)
+                .complete_with(Completer::ScriptNames),
+        )
+}
+
+/// Builds the 'docs' subcommand for generating reference documentation
+pub fn build_docs_command() -> Command {
+    Command::new("docs")
+        .about("Generate Markdown or man page documentation for the scripts")
+        .arg(
+            Arg::new("subdirectory")
+                .help("Only document scripts under this subdirectory")
+                .required(false),
+        )
+        .arg(
+            Arg::new("format")
+                .help("Output format")
+                .long("format")
+                .short('f')
+                .value_parser(clap::builder::PossibleValuesParser::new(docs::FORMATS))
+                .default_value("markdown"),
+        )
+}
+
+/// Builds the 'doctor' subcommand for checking the setup
+pub fn build_doctor_command() -> Command {
+    Command::new("doctor").about("Check the configuration and script roots for problems")
+}
+
+/// Builds the 'migrate' subcommand that moves `~/.shutl` to the data directory
+pub fn build_migrate_command() -> Command {
+    Command::new("migrate")
+        .about("Move the scripts from ~/.shutl to the data directory")
+        .arg(
+            Arg::new("dry-run")
+                .help("Only show what would be moved")
+                .long("dry-run")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("link")
+                .help("Leave a symlink at ~/.shutl pointing to the new location")
+                .long("link")
+                .action(clap::ArgAction::SetTrue),
+        )
+}
+
+/// Builds the 'prune' subcommand that cleans up stale files
+pub fn build_prune_command() -> Command {
+    Command::new("prune")
+        .about(
+            "Find broken symlinks, empty directories, orphaned presets, old cache entries and logs",
+        )
+        .arg(
+            Arg::new("older-than")
+                .help("Age in days from which cache entries and logs are stale (at most 36500)")
+                .long("older-than")
+                .value_name("DAYS")
+                // Bounded so the age in seconds cannot overflow
+                .value_parser(clap::value_parser!(u64).range(..=36500))
+                .default_value("30"),
+        )
+        .arg(
+            Arg::new("delete")
+                .help("Remove what was found instead of only listing it")
+                .long("delete")
+                .action(clap::ArgAction::SetTrue),
+        )
+}
+
+/// Builds the 'diff' subcommand showing local changes versus the synced remote branch
+pub fn build_diff_command() -> Command {
+    Command::new("diff")
+        .about("Show local changes to the scripts versus the remote branch they sync with")
+        .arg(
+            Arg::new("command")
+                .help("Command or directory path components (defaults to the whole tree)")
+                .num_args(1..)
+                .complete_with(Completer::ScriptNames),
+        )
+}
+
+/// Builds the 'status' subcommand summarizing the sync state of the scripts directory
+pub fn build_status_command() -> Command {
+    Command::new("status")
+        .about("Show whether the scripts are ahead of, behind or modified from the remote branch")
+        .arg(
+            Arg::new("fetch")
+                .help("Fetch the remote branch first")
+                .long("fetch")
+                .action(clap::ArgAction::SetTrue),
+        )
+}
+
+/// Builds the 'log' subcommand showing the git history of a script
+pub fn build_log_command() -> Command {
+    Command::new("log")
+        .about("Show who changed a script and when, from the git history of its directory")
+        .arg(
+            Arg::new("command")
+                .help("Command path components (e.g., 'subdir myscript')")
+                .required(true)
+                .num_args(1..)
+                .complete_with(Completer::ScriptNames),
+        )
+        .arg(
+            Arg::new("patch")
+                .help("Show the changes made by each commit")
+                .long("patch")
+                .short('p')
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("max-count")
+                .help("Show at most this many commits")
+                .long("max-count")
+                .short('n')
+                .value_name("N")
+                .value_parser(clap::value_parser!(usize)),
+        )
+}
+
+/// Builds the 'shadows' subcommand reporting commands that more than one root provides
+pub fn build_shadows_command() -> Command {
+    Command::new("shadows").about("Show commands defined in more than one root and which one runs")
+}
+
+/// Builds the 'import' subcommand downloading a script and pinning its checksum
+pub fn build_import_command() -> Command {
+    Command::new("import")
+        .about("Download a script from a URL and pin its checksum in the lockfile")
+        .arg(Arg::new("url").help("URL of the script").required(true))
+        .arg(Arg::new("command").help(
+            "Command path to import it as, e.g. 'tools/deploy' (defaults to the URL's file name)",
+        ))
+        .arg(
+            Arg::new("force")
+                .help("Replace an existing script")
+                .long("force")
+                .short('f')
+                .action(clap::ArgAction::SetTrue),
+        )
+}
+
+/// Builds the 'verify' subcommand checking imported scripts against their pins
+pub fn build_verify_command() -> Command {
+    Command::new("verify")
+        .about("Check imported scripts for local changes and changes at their URL")
+        .arg(
+            Arg::new("offline")
+                .help("Only check for local changes")
+                .long("offline")
+                .action(clap::ArgAction::SetTrue),
+        )
+}
+
+/// Builds the 'sign' subcommand writing detached signatures for publishing scripts
+pub fn build_sign_command() -> Command {
+    Command::new("sign")
+        .about("Sign files for publishing, writing <file>.sig (SSH key) or <file>.minisig (minisign key)")
+        .arg(
+            Arg::new("files")
+                .help("Files to sign, e.g. scripts to publish for `shutl import`")
+                .required(true)
+                .num_args(1..)
+                .value_parser(clap::value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("key")
+                .help("Secret key to sign with (defaults to signing-key from the config)")
+                .long("key")
+                .short('k')
+                .value_name("KEY")
+                .value_parser(clap::value_parser!(PathBuf)),
+        )
+}
+
+/// Builds the 'review' subcommand going through scripts changed since the last review
+pub fn build_review_command() -> Command {
+    Command::new("review")
+        .about("Show the diffs of new and changed scripts and mark them as reviewed")
+        .arg(
+            Arg::new("list")
+                .help("Only list the scripts that need a review")
+                .long("list")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("yes")
+                .help("Mark all of them as reviewed without showing the diffs")
+                .long("yes")
+                .action(clap::ArgAction::SetTrue)
+                .conflicts_with("list"),
+        )
+}
+
+/// Builds the 'completions' subcommand that prints or installs shell completion
+pub fn build_completions_command() -> Command {
+    Command::new("completions")
+        .about("Print the shell completion file, or install it with --install")
+        .arg(
+            Arg::new("shell")
+                .help("Shell to complete in [default: from $SHELL]")
+                .value_parser(clap::builder::PossibleValuesParser::new(install::SHELLS)),
+        )
+        .arg(
+            Arg::new("install")
+                .help("Write the completion file where the shell finds it and load it from the rc file if needed")
+                .long("install")
+                .action(clap::ArgAction::SetTrue),
+        )
+}
+
+/// Builds the 'command-not-found' subcommand that the shell's command-not-found handler
+/// calls with the command line it couldn't run
+pub fn build_command_not_found_command() -> Command {
+    Command::new("command-not-found")
+        .about("Offer the scripts named like a command the shell didn't find")
+        .after_help("Install the handler calling it with `eval \"$(shutl hook zsh --command-not-found)\"` (bash alike, fish: `... | source`).")
+        .arg(
+            Arg::new("name")
+                .help("The command that wasn't found")
+                .required(true),
+        )
+        .arg(
+            Arg::new("args")
+                .help("Its arguments, passed on to the script")
+                .num_args(0..)
+                .trailing_var_arg(true)
+                .allow_hyphen_values(true),
+        )
+}
+
+/// Builds the 'upgrade' subcommand for updating shutl itself
+pub fn build_upgrade_command() -> Command {
+    Command::new("upgrade")
+        .about("Update shutl to the latest release")
+        .arg(
+            Arg::new("check")
+                .help("Only report whether a newer release is available")
+                .long("check")
+                .action(clap::ArgAction::SetTrue),
+        )
 }
 
-/// Builds the 'validate' subcommand for validating script metadata
-pub fn build_validate_command() -> Command {
-    Command::new("validate")
-        .about("Validate a script's metadata configuration")
+/// Builds the 'hook' subcommand that prints the shell integration
+pub fn build_hook_command() -> Command {
+    let project_dir = || {
+        Arg::new("dir")
+            .help("Directory in the project, the current one if not given")
+            .value_parser(clap::value_parser!(PathBuf))
+            .complete_with(Completer::Dirs(PathBuf::from(".")))
+    };
+    Command::new("hook")
+        .about("Print a shell hook that loads project scripts from ./.shutl directories")
+        .after_help("Add `eval \"$(shutl hook zsh)\"` to ~/.zshrc (bash: ~/.bashrc), or `shutl hook fish | source` to config.fish. A project's scripts are only loaded once you ran `shutl hook allow` in it.")
+        .args_conflicts_with_subcommands(true)
+        .subcommand_negates_reqs(true)
+        .subcommand(
+            Command::new("allow")
+                .about("Load the scripts of the project in this directory, as they are now")
+                .arg(project_dir()),
+        )
+        .subcommand(
+            Command::new("deny")
+                .about("Stop loading the scripts of the project in this directory")
+                .arg(project_dir()),
+        )
+        .disable_help_subcommand(true)
         .arg(
-            Arg::new("command")
-                .help("Command path components (e.g., 'subdir myscript')")
+            Arg::new("shell")
+                .help("Shell to integrate with")
                 .required(true)
-                .num_args(1..)
-                .add(ArgValueCompleter::new(complete_script_names)),
+                .value_parser(clap::builder::PossibleValuesParser::new(hook::SHELLS)),
         )
-}
-
-/// Builds the 'list' subcommand for listing available scripts
-pub fn build_list_command() -> Command {
-    let scripts_dir = get_scripts_dir();
-    Command::new("list")
-        .about("List available scripts")
         .arg(
-            Arg::new("subdirectory")
-                .help("Only list scripts under this subdirectory")
-                .required(false)
-                .add(ArgValueCompleter::new(
-                    PathCompleter::dir().current_dir(scripts_dir),
-                )),
+            Arg::new("functions")
+                .help("Print a shell function for every top-level command instead, e.g. `deploy` for `shutl deploy`")
+                .long("functions")
+                .action(clap::ArgAction::SetTrue)
+                .conflicts_with("export"),
         )
         .arg(
-            Arg::new("tree")
-                .help("Show hierarchical tree view")
-                .long("tree")
+            Arg::new("command-not-found")
+                .help("Print a handler that offers matching scripts when the shell finds no command instead")
+                .long("command-not-found")
+                .action(clap::ArgAction::SetTrue)
+                .conflicts_with_all(["functions", "export"]),
+        )
+        .arg(
+            Arg::new("export")
+                .help("Print the statements for the current directory (used by the hook)")
+                .long("export")
+                .hide(true)
                 .action(clap::ArgAction::SetTrue),
         )
 }
 
-/// An entry representing a script found during listing
-pub struct ListEntry {
-    pub path: String,
-    pub description: String,
+/// Builds the 'preset' subcommand for managing saved script arguments
+pub fn build_preset_command() -> Command {
+    let path_and_name = || {
+        Arg::new("command")
+            .help("Command path components, followed by the preset name")
+            .required(true)
+            .num_args(2..)
+            .complete_with(Completer::ScriptNames)
+    };
+    Command::new("preset")
+        .about("Save named arguments for a script, run with 'shutl <command> NAME@preset'")
+        .subcommand_required(true)
+        .disable_help_subcommand(true)
+        .subcommand(
+            Command::new("save")
+                .about("Save the arguments after '--' as a preset")
+                .arg(path_and_name())
+                .arg(
+                    Arg::new("args")
+                        .help("Arguments for the script, after '--'")
+                        .num_args(0..)
+                        .last(true)
+                        .allow_hyphen_values(true),
+                ),
+        )
+        .subcommand(
+            Command::new("list")
+                .about("List the presets, of all commands or of one")
+                .arg(
+                    Arg::new("command")
+                        .help("Command path components")
+                        .num_args(0..)
+                        .complete_with(Completer::ScriptNames),
+                ),
+        )
+        .subcommand(
+            Command::new("rm")
+                .about("Remove a preset")
+                .arg(path_and_name()),
+        )
 }
 
-/// Lists all scripts in the given directory, optionally filtered to a subdirectory.
-/// Returns a formatted string ready for display.
-pub fn list_scripts(base_dir: &Path, subdir_filter: Option<&str>, tree: bool) -> String {
-    let normalized: Option<PathBuf> = subdir_filter.map(|s| Path::new(s).components().collect());
-    let subdir_filter = normalized.as_deref().and_then(|p| p.to_str());
-    let search_dir = if let Some(subdir) = subdir_filter {
-        let p = base_dir.join(subdir);
-        if !p.is_dir() {
-            return format!("Directory not found: {}", subdir);
-        }
-        p
-    } else {
-        base_dir.to_path_buf()
-    };
+/// Collects all scripts below `dir`, with paths relative to the scripts directory
+/// (`prefix` being the path of `dir` itself), sorted in the configured order.
+pub fn collect_entries(dir: &Path, prefix: &str) -> Vec<ListEntry> {
+    collect_entries_from(&FsSource, dir, prefix)
+}
 
-    let prefix = subdir_filter.unwrap_or("");
+/// Collects all scripts below `dir` of `source` (see [`collect_entries`])
+pub fn collect_entries_from(source: &dyn ScriptSource, dir: &Path, prefix: &str) -> Vec<ListEntry> {
     let mut entries = Vec::new();
-    collect_scripts(&search_dir, prefix, &mut entries);
-    entries.sort_by(|a, b| a.path.cmp(&b.path));
-
-    if entries.is_empty() {
-        return "No scripts found.".to_string();
-    }
-
-    if tree {
-        format_tree(&entries)
-    } else {
-        format_flat(&entries)
-    }
+    let mut visited = HashSet::new();
+    collect_scripts(source, dir, prefix, &mut entries, &mut visited);
+    let order = config::get().sort;
+    entries.sort_by(|a, b| compare_paths(order, &a.path, &b.path));
+    entries
 }
 
-fn collect_scripts(dir: &Path, prefix: &str, entries: &mut Vec<ListEntry>) {
-    let Ok(read_dir) = fs::read_dir(dir) else {
+/// Walks `dir` recursively, following symlinks. `visited` holds the canonical paths of
+/// directories and scripts already seen, which guards against symlink cycles and lists
+/// a script reachable through multiple links only once.
+fn collect_scripts(
+    source: &dyn ScriptSource,
+    dir: &Path,
+    prefix: &str,
+    entries: &mut Vec<ListEntry>,
+    visited: &mut HashSet<PathBuf>,
+) {
+    if !visited.insert(source.canonicalize(dir)) {
+        log::debug!("collect_scripts: skipping already visited {:?}", dir);
         return;
-    };
-
-    let (mut directories, mut files): (Vec<_>, Vec<_>) = read_dir
-        .filter_map(Result::ok)
-        .partition(|entry| entry.path().is_dir());
+    }
 
-    directories.retain(|entry| !entry.file_name().to_string_lossy().starts_with('.'));
-    directories.sort_by_key(|e| e.file_name());
-    files.retain(|entry| {
-        !entry.file_name().to_string_lossy().starts_with('.')
-            && entry.path().is_file()
-            && entry.path().is_executable()
-    });
-    files.sort_by_key(|e| e.file_name());
+    let (mut directories, mut files) = visible_entries(source, dir);
+    directories.sort_by(|a, b| a.name.cmp(&b.name));
+    files.sort_by(|a, b| a.name.cmp(&b.name));
+    let naming = command_names_from(source, dir);
 
     for entry in &files {
-        let name = entry.file_name().to_string_lossy().to_string();
-        let clean_name = name.rsplitn(2, '.').last().unwrap_or(&name).to_string();
-        let metadata = parse_command_metadata(&entry.path());
+        if !visited.insert(source.canonicalize(&entry.path)) {
+            continue;
+        }
+        let name = &entry.name;
+        let clean_name = match naming {
+            CommandNames::Stem => name.rsplitn(2, '.').last().unwrap_or(name).to_string(),
+            CommandNames::Filename => name.clone(),
+        };
+        let metadata = source.command_metadata(&entry.path);
+        if !is_listed(&metadata) {
+            continue;
+        }
         let path = if prefix.is_empty() {
             clean_name
         } else {
@@ -522,131 +1680,74 @@ fn collect_scripts(dir: &Path, prefix: &str, entries: &mut Vec<ListEntry>) {
         entries.push(ListEntry {
             path,
             description: metadata.description,
+            folded: 0,
         });
     }
 
     for entry in &directories {
-        let dir_name = entry.file_name().to_string_lossy().to_string();
         let sub_prefix = if prefix.is_empty() {
-            dir_name.clone()
+            entry.name.clone()
         } else {
-            format!("{}/{}", prefix, dir_name)
+            format!("{}/{}", prefix, entry.name)
         };
-        collect_scripts(&entry.path(), &sub_prefix, entries);
+        collect_scripts(source, &entry.path, &sub_prefix, entries, visited);
     }
 }
 
-fn format_flat(entries: &[ListEntry]) -> String {
-    let max_path_len = entries.iter().map(|e| e.path.len()).max().unwrap_or(0);
-    entries
-        .iter()
-        .map(|e| {
-            if e.description.is_empty() {
-                e.path.clone()
-            } else {
-                format!(
-                    "{:<width$}  {}",
-                    e.path,
-                    e.description,
-                    width = max_path_len
-                )
-            }
-        })
-        .collect::<Vec<_>>()
-        .join("\n")
-}
-
-use std::io::IsTerminal;
-
-fn use_color() -> bool {
-    std::io::stdout().is_terminal()
-}
-
-fn format_tree(entries: &[ListEntry]) -> String {
-    let mut lines = Vec::new();
-    let mut printed_dirs: Vec<String> = Vec::new();
-    let color = use_color();
-    let max_name_len = entries
-        .iter()
-        .map(|e| e.path.rsplit('/').next().unwrap_or(&e.path).len())
-        .max()
-        .unwrap_or(0);
-
-    for entry in entries {
-        let parts: Vec<&str> = entry.path.rsplitn(2, '/').collect();
-        if parts.len() == 2 {
-            let dir_path = parts[1];
-            let name = parts[0];
-
-            // Print any directory headers not yet printed
-            let components: Vec<&str> = dir_path.split('/').collect();
-            for depth in 0..components.len() {
-                let ancestor: String = components[..=depth].join("/");
-                if !printed_dirs.contains(&ancestor) {
-                    let indent = "  ".repeat(depth);
-                    let dir_label = if color {
-                        format!("\x1b[1;34m{}/\x1b[0m", components[depth])
-                    } else {
-                        format!("{}/", components[depth])
-                    };
-                    lines.push(format!("{}{}", indent, dir_label));
-                    printed_dirs.push(ancestor);
-                }
-            }
+/// Dynamic completion offered for an argument of a built-in command
+#[cfg_attr(not(feature = "completion"), allow(dead_code))]
+enum Completer {
+    /// Profile names from the config file
+    Profiles,
+    /// Command paths under the scripts directory
+    ScriptNames,
+    /// Directories under the given one
+    Dirs(PathBuf),
+}
 
-            let indent = "  ".repeat(components.len());
-            let styled_name = if color {
-                format!("\x1b[32m{}\x1b[0m", name)
-            } else {
-                name.to_string()
-            };
-            if entry.description.is_empty() {
-                lines.push(format!("{}{}", indent, styled_name));
-            } else {
-                // Pad based on raw name length, then apply color
-                let padding = max_name_len.saturating_sub(name.len());
-                let desc = if color {
-                    format!("\x1b[2m{}\x1b[0m", entry.description)
-                } else {
-                    entry.description.clone()
-                };
-                lines.push(format!(
-                    "{}{}{}  {}",
-                    indent,
-                    styled_name,
-                    " ".repeat(padding),
-                    desc
-                ));
-            }
-        } else {
-            let styled_name = if color {
-                format!("\x1b[32m{}\x1b[0m", entry.path)
-            } else {
-                entry.path.clone()
-            };
-            if entry.description.is_empty() {
-                lines.push(styled_name.to_string());
-            } else {
-                let padding = max_name_len.saturating_sub(entry.path.len());
-                let desc = if color {
-                    format!("\x1b[2m{}\x1b[0m", entry.description)
-                } else {
-                    entry.description.clone()
-                };
-                lines.push(format!("{}{}  {}", styled_name, " ".repeat(padding), desc));
-            }
+/// Attaches a [`Completer`] to an argument. Without the `completion` feature this is a no-op.
+trait CompleteWith {
+    fn complete_with(self, completer: Completer) -> Self;
+}
+
+impl CompleteWith for Arg {
+    #[cfg(feature = "completion")]
+    fn complete_with(self, completer: Completer) -> Self {
+        match completer {
+            Completer::Profiles => self.add(ArgValueCompleter::new(complete_profiles)),
+            Completer::ScriptNames => self.add(ArgValueCompleter::new(complete_script_names)),
+            Completer::Dirs(dir) => self.add(ArgValueCompleter::new(
+                PathCompleter::dir().current_dir(dir),
+            )),
         }
     }
 
-    lines.join("\n")
+    #[cfg(not(feature = "completion"))]
+    fn complete_with(self, _completer: Completer) -> Self {
+        self
+    }
+}
+
+/// Completer for profile names from the config file
+#[cfg(feature = "completion")]
+fn complete_profiles(current: &std::ffi::OsStr) -> Vec<CompletionCandidate> {
+    let current = current.to_string_lossy();
+    config::get()
+        .profiles
+        .keys()
+        .filter(|name| name.starts_with(current.as_ref()))
+        .map(CompletionCandidate::new)
+        .collect()
 }
 
 /// Completer for script names in the edit command
+#[cfg(feature = "completion")]
 fn complete_script_names(current: &std::ffi::OsStr) -> Vec<CompletionCandidate> {
     complete_script_names_in_dir(current, &get_scripts_dir())
 }
 
 /// Completer for script names in a given directory (testable version)
+#[cfg(feature = "completion")]
 fn complete_script_names_in_dir(
     current: &std::ffi::OsStr,
     base_dir: &Path,
@@ -670,36 +1771,37 @@ fn complete_script_names_in_dir(
     };
 
     let mut completions = Vec::new();
+    let naming = command_names_in(&search_dir);
 
-    if let Ok(entries) = fs::read_dir(&search_dir) {
-        for entry in entries.flatten() {
-            let file_name = entry.file_name();
-            let name_str = file_name.to_string_lossy();
+    for entry in FsSource.list_dir(&search_dir) {
+        let name_str = entry.name;
 
-            // Skip hidden files
-            if name_str.starts_with('.') {
-                continue;
-            }
+        // Skip hidden files
+        if name_str.starts_with('.') {
+            continue;
+        }
 
-            let path = entry.path();
+        let path = entry.path;
 
-            if path.is_dir() {
-                // Directory - add with trailing slash to indicate more completions
-                if name_str.starts_with(prefix) {
-                    completions.push(CompletionCandidate::new(format!(
-                        "{}{}/",
-                        path_prefix, name_str
-                    )));
-                }
-            } else if path.is_file() && path.is_executable() {
-                // Executable file - strip extension for completion
-                let clean_name = name_str.rsplitn(2, '.').last().unwrap_or(&name_str);
-                if clean_name.starts_with(prefix) {
-                    completions.push(CompletionCandidate::new(format!(
-                        "{}{}",
-                        path_prefix, clean_name
-                    )));
-                }
+        if entry.kind == EntryKind::Dir {
+            // Directory - add with trailing slash to indicate more completions
+            if name_str.starts_with(prefix) {
+                completions.push(CompletionCandidate::new(format!(
+                    "{}{}/",
+                    path_prefix, name_str
+                )));
+            }
+        } else if is_runnable_from(&FsSource, &path) {
+            // Executable file - strip extension for completion unless named by file name
+            let clean_name = match naming {
+                CommandNames::Stem => name_str.rsplitn(2, '.').last().unwrap_or(&name_str),
+                CommandNames::Filename => &name_str,
+            };
+            if clean_name.starts_with(prefix) {
+                completions.push(CompletionCandidate::new(format!(
+                    "{}{}",
+                    path_prefix, clean_name
+                )));
             }
         }
     }
@@ -711,7 +1813,7 @@ fn complete_script_names_in_dir(
 mod tests {
     use super::*;
     use crate::script::find_script_file_in_dir;
-    use std::fs::File;
+    use std::fs::{self, File};
     use std::io::Write;
     use std::os::unix::fs::PermissionsExt;
     use tempfile::tempdir;
@@ -729,6 +1831,199 @@ mod tests {
         script_path
     }
 
+    #[test]
+    fn test_command_names_filename_directive() {
+        let dir = tempdir().unwrap();
+        create_test_script(dir.path(), "build.sh", "#!/bin/bash\n");
+        create_test_script(dir.path(), "deploy.py", "#!/bin/bash\n");
+        create_test_script(dir.path(), "deploy.sh", "#!/bin/bash\n");
+
+        let names = |dir: &Path| -> Vec<String> {
+            build_command_tree(dir, &[])
+                .iter()
+                .map(|c| c.command.get_name().to_string())
+                .collect()
+        };
+        assert_eq!(names(dir.path()), vec!["build", "deploy.py", "deploy.sh"]);
+        assert_eq!(
+            stem_collisions(dir.path()),
+            vec![(dir.path().to_path_buf(), "deploy".to_string())]
+        );
+
+        fs::write(dir.path().join(".shutl"), "#@command-names: filename\n").unwrap();
+        assert_eq!(
+            names(dir.path()),
+            vec!["build.sh", "deploy.py", "deploy.sh"]
+        );
+        assert!(find_script_file(&FsSource, dir.path(), "build").is_none());
+        assert!(find_script_file(&FsSource, dir.path(), "build.sh").is_some());
+        assert!(stem_collisions(dir.path()).is_empty());
+    }
+
+    #[test]
+    fn test_group_summary_without_description() {
+        let dir = tempdir().unwrap();
+        let k8s = dir.path().join("k8s");
+        fs::create_dir(&k8s).unwrap();
+        for name in ["apply.sh", "delete.sh", "diff.sh", "logs.sh"] {
+            create_test_script(&k8s, name, "#!/bin/bash\n");
+        }
+        fs::create_dir(k8s.join("extra")).unwrap();
+        fs::write(k8s.join("notes.txt"), "not executable").unwrap();
+
+        let commands = build_command_tree(dir.path(), &[]);
+        let about = commands[0].command.get_about().unwrap().to_string();
+        assert_eq!(about, "5 commands: apply, delete, diff, ...");
+
+        fs::write(k8s.join(".shutl"), "Kubernetes helpers\n").unwrap();
+        let commands = build_command_tree(dir.path(), &[]);
+        let about = commands[0].command.get_about().unwrap().to_string();
+        assert_eq!(about, "Kubernetes helpers");
+    }
+
+    #[test]
+    fn test_stdin_and_examples_in_help() {
+        let dir = tempdir().unwrap();
+        let script = create_test_script(
+            dir.path(),
+            "count.sh",
+            "#!/bin/bash\n#@description: Count lines\n#@stdin: required\n#@example: cat f | shutl count\n",
+        );
+        let help = build_script_command_for_help("count".into(), &script)
+            .render_help()
+            .to_string();
+        assert!(help.contains(
+            "Stdin: required: pipe or redirect input into the command\n\nExamples:\n  cat f | shutl count"
+        ));
+    }
+
+    /// Help of representative scripts, kept in `src/snapshots`. After an intended change,
+    /// review and accept the new output with `cargo insta review` (or run the tests with
+    /// `INSTA_UPDATE=always`).
+    #[test]
+    fn test_help_snapshots() {
+        let cases = [
+            (
+                "help_plain",
+                "#@description: Greet someone\n#@arg:name - Who to greet\n#@flag:greeting - Greeting to use\n",
+            ),
+            (
+                "help_defaults_and_options",
+                "#@description: Deploy the service\n\
+                 #@arg:env - Environment [options:dev|!staging!|prod]\n\
+                 #@flag:region - Region [default:eu-west-1]\n\
+                 #@flag:replicas - Replica count [required]\n\
+                 #@flag:dry-run - Only print what would change [bool]\n",
+            ),
+            (
+                "help_multiple_values",
+                "#@description: Run a tool\n\
+                 #@arg:target - Target to run it on\n\
+                 #@arg:...tool-args - Passed on to the tool [allow-hyphen]\n",
+            ),
+            (
+                "help_value_counts",
+                "#@arg:files - Files to process [min:1, max:3]\n",
+            ),
+            (
+                "help_paths_and_secrets",
+                "#@arg:config - Config file [file, must-exist]\n\
+                 #@flag:out - Output directory [dir:./out, create]\n\
+                 #@flag:token - API token [secret]\n",
+            ),
+            (
+                "help_templated_and_secret_defaults",
+                "#@arg:input - Input file\n\
+                 #@flag:output - Output file [default:{{input}}.out]\n\
+                 #@flag:retries - Retries [int, default:3]\n\
+                 #@flag:password - Password [secret, default:hunter2]\n",
+            ),
+            (
+                "help_after_help",
+                "#@description: Count lines\n\
+                 #@stdin: required\n\
+                 #@output: json\n\
+                 #@example: cat f | shutl count\n\
+                 #@example: shutl count < f\n",
+            ),
+        ];
+        for (name, header) in cases {
+            let mut source = crate::source::MemorySource::new();
+            source.add_script("/s/tool.sh", &format!("#!/bin/bash\n{}", header));
+            let mut command =
+                build_script_command(&source, "tool".into(), Path::new("/s/tool.sh")).command;
+            insta::assert_snapshot!(name, command.render_help().to_string());
+        }
+
+        // Descriptions of options only show in the long help
+        let mut source = crate::source::MemorySource::new();
+        source.add_script(
+            "/s/tool.sh",
+            "#!/bin/bash\n\
+             #@description: Build it\n\
+             #@arg:target - Target to build [options:app(the binary)|docs]\n\
+             #@flag:mode - Build mode [options:fast(quick but risky)|!safe!(slow)]\n",
+        );
+        let mut command =
+            build_script_command(&source, "tool".into(), Path::new("/s/tool.sh")).command;
+        insta::assert_snapshot!(
+            "help_option_descriptions",
+            command.render_long_help().to_string()
+        );
+    }
+
+    #[test]
+    fn test_help_subcommand_at_every_depth() {
+        let dir = tempdir().unwrap();
+        fs::create_dir(dir.path().join("deploy")).unwrap();
+        create_test_script(
+            dir.path(),
+            "deploy/prod.sh",
+            "#!/bin/bash\n#@description: Deploy to prod\n#@arg:target - Target\n",
+        );
+        create_test_script(dir.path(), "deploy/help.sh", "#!/bin/bash\n");
+
+        let build = |args: &[&str]| {
+            let active: Vec<String> = args.iter().map(|s| s.to_string()).collect();
+            let mut cli = Command::new("shutl");
+            for cmd in build_command_tree(dir.path(), &active) {
+                cli = cli.subcommand(cmd.command);
+            }
+            cli.try_get_matches_from(args)
+        };
+
+        for args in [
+            &["shutl", "help", "deploy", "prod"][..],
+            &["shutl", "deploy", "help"],
+            &["shutl", "deploy", "help", "prod"],
+        ] {
+            let err = build(args).unwrap_err();
+            assert_eq!(
+                err.kind(),
+                clap::error::ErrorKind::DisplayHelp,
+                "{:?}",
+                args
+            );
+        }
+
+        // Scripts have no help subcommand, so `help` stays a plain argument value
+        let matches = build(&["shutl", "deploy", "prod", "help"]).unwrap();
+        let (_, deploy) = matches.subcommand().unwrap();
+        let (_, prod) = deploy.subcommand().unwrap();
+        assert_eq!(prod.get_one::<String>("target").unwrap(), "help");
+    }
+
+    #[test]
+    fn test_builtin_names_match_builtin_commands() {
+        let names: Vec<String> = builtin_commands()
+            .iter()
+            .map(|c| c.get_name().to_string())
+            .collect();
+        assert_eq!(names, BUILTIN_NAMES);
+        assert!(is_builtin("new"));
+        assert!(!is_builtin("deploy"));
+    }
+
     #[test]
     fn test_build_script_command_full() {
         let script_content = r#"#!/bin/bash
@@ -754,8 +2049,8 @@ mod tests {
 "#;
 
         let dir = tempdir().unwrap();
-        let script_path = create_test_script(&dir.path(), "test.sh", script_content);
-        let cmd_with_path = build_script_command("test".to_string(), &script_path);
+        let script_path = create_test_script(dir.path(), "test.sh", script_content);
+        let cmd_with_path = build_script_command(&FsSource, "test".to_string(), &script_path);
 
         // Test command name
         assert_eq!(cmd_with_path.command.get_name(), "test");
@@ -768,7 +2063,7 @@ mod tests {
 
         // Test arguments
         let args: Vec<_> = cmd_with_path.command.get_arguments().collect();
-        assert_eq!(args.len(), 23);
+        assert_eq!(args.len(), 28);
 
         validate_arg(&args, "pos", "positional", true, None, None);
         validate_arg(
@@ -799,9 +2094,9 @@ mod tests {
         validate_arg(
             &args,
             "flag-bool-true",
-            "flag bool with default true",
+            "flag bool with default true [default: true]",
             false,
-            None,
+            Some("true".to_string()),
             None,
         );
         validate_arg(
@@ -925,8 +2220,8 @@ mod tests {
 "#;
 
         let dir = tempdir().unwrap();
-        let script_path = create_test_script(&dir.path(), "test.sh", script_content);
-        let cmd_with_path = build_script_command("test".to_string(), &script_path);
+        let script_path = create_test_script(dir.path(), "test.sh", script_content);
+        let cmd_with_path = build_script_command(&FsSource, "test".to_string(), &script_path);
 
         // Test command name
         assert_eq!(cmd_with_path.command.get_name(), "test");
@@ -939,7 +2234,7 @@ mod tests {
 
         // Test arguments
         let args: Vec<_> = cmd_with_path.command.get_arguments().collect();
-        assert_eq!(args.len(), 5); // input, verbose, no-verbose
+        assert_eq!(args.len(), 10); // input, verbose, no-verbose and the hidden --shutl-* options
 
         // Test input argument
         let input_arg = args.iter().find(|a| a.get_id() == "input").unwrap();
@@ -971,8 +2266,8 @@ mod tests {
 "#;
 
         let dir = tempdir().unwrap();
-        let script_path = create_test_script(&dir.path(), "test.sh", script_content);
-        let cmd_with_path = build_script_command("test".to_string(), &script_path);
+        let script_path = create_test_script(dir.path(), "test.sh", script_content);
+        let cmd_with_path = build_script_command(&FsSource, "test".to_string(), &script_path);
 
         // Test that using both --verbose and --no-verbose results in an error
         let result = cmd_with_path.command.clone().try_get_matches_from(vec![
@@ -1127,6 +2422,21 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_commands_for_dir_sorted_naturally_with_manual_order() {
+        let dir = tempdir().unwrap();
+        let scripts_dir = dir.path();
+        for name in ["task10.sh", "task2.sh", "build.sh", "release.sh"] {
+            create_test_script(scripts_dir, name, "#!/bin/bash");
+        }
+        fs::create_dir(scripts_dir.join("deploy")).unwrap();
+        fs::write(scripts_dir.join(".shutl"), "#@order: release\n").unwrap();
+
+        let commands = build_command_tree(scripts_dir, &[]);
+        let names: Vec<_> = commands.iter().map(|c| c.command.get_name()).collect();
+        assert_eq!(names, vec!["release", "build", "deploy", "task2", "task10"]);
+    }
+
     #[test]
     fn test_build_command_tree_ignores_hidden() {
         let dir = tempdir().unwrap();
@@ -1361,6 +2671,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "completion")]
     fn test_complete_script_names_root_level() {
         let dir = tempdir().unwrap();
         let scripts_dir = dir.path();
@@ -1403,6 +2714,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "completion")]
     fn test_complete_script_names_nested() {
         let dir = tempdir().unwrap();
         let scripts_dir = dir.path();
@@ -1439,6 +2751,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "completion")]
     fn test_complete_script_names_nonexistent_dir() {
         let dir = tempdir().unwrap();
         let scripts_dir = dir.path();
@@ -1457,8 +2770,8 @@ mod tests {
 "#;
 
         let dir = tempdir().unwrap();
-        let script_path = create_test_script(&dir.path(), "test.sh", script_content);
-        let cmd_with_path = build_script_command("test".to_string(), &script_path);
+        let script_path = create_test_script(dir.path(), "test.sh", script_content);
+        let cmd_with_path = build_script_command(&FsSource, "test".to_string(), &script_path);
 
         let args: Vec<_> = cmd_with_path.command.get_arguments().collect();
         let catchall = args
@@ -1483,8 +2796,8 @@ mod tests {
 "#;
 
         let dir = tempdir().unwrap();
-        let script_path = create_test_script(&dir.path(), "test.sh", script_content);
-        let cmd_with_path = build_script_command("test".to_string(), &script_path);
+        let script_path = create_test_script(dir.path(), "test.sh", script_content);
+        let cmd_with_path = build_script_command(&FsSource, "test".to_string(), &script_path);
 
         let args: Vec<_> = cmd_with_path.command.get_arguments().collect();
         let catchall = args
@@ -1501,6 +2814,95 @@ mod tests {
         assert!(result.is_ok());
     }
 
+    #[test]
+    fn test_positional_options() {
+        let mut source = crate::source::MemorySource::new();
+        source.add_script(
+            "/s/tool.sh",
+            "#!/bin/bash\n\
+             #@arg:env - Environment [options:dev|!staging!|prod]\n\
+             #@arg:...services - Services [options:api|web]\n",
+        );
+        let command = build_script_command(&source, "tool".into(), Path::new("/s/tool.sh")).command;
+        let env = command
+            .get_arguments()
+            .find(|a| a.get_id() == "env")
+            .unwrap();
+        let values: Vec<_> = env
+            .get_possible_values()
+            .iter()
+            .map(|value| value.get_name().to_string())
+            .collect();
+        assert_eq!(values, ["dev", "staging", "prod"]);
+
+        let matches = command.clone().try_get_matches_from(["tool"]).unwrap();
+        assert_eq!(matches.get_one::<String>("env").unwrap(), "staging");
+        let matches = command
+            .clone()
+            .try_get_matches_from(["tool", "prod", "api", "web"])
+            .unwrap();
+        assert_eq!(matches.get_one::<String>("env").unwrap(), "prod");
+        assert!(
+            command
+                .clone()
+                .try_get_matches_from(["tool", "qa"])
+                .is_err()
+        );
+        // Every value of a catch-all is checked
+        assert!(
+            command
+                .clone()
+                .try_get_matches_from(["tool", "prod", "api", "db"])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_required_with_default() {
+        let mut source = crate::source::MemorySource::new();
+        source.add_script(
+            "/s/tool.sh",
+            "#!/bin/bash\n\
+             #@arg:env - Environment [required, options:dev|!staging!]\n\
+             #@arg:...files - Files [required, default:a.txt]\n\
+             #@flag:output-dir - Output directory [dir, required, default:./output]\n",
+        );
+        let command = build_script_command(&source, "tool".into(), Path::new("/s/tool.sh")).command;
+        // The default wins: nothing is required and the defaults are used
+        assert!(command.get_arguments().all(|arg| !arg.is_required_set()));
+        let matches = command.try_get_matches_from(["tool"]).unwrap();
+        assert_eq!(matches.get_one::<String>("env").unwrap(), "staging");
+        assert_eq!(matches.get_one::<String>("files").unwrap(), "a.txt");
+        assert_eq!(matches.get_one::<String>("output-dir").unwrap(), "./output");
+    }
+
+    #[test]
+    fn test_bool_flag_default() {
+        let mut source = crate::source::MemorySource::new();
+        source.add_script(
+            "/s/tool.sh",
+            "#!/bin/bash\n\
+             #@flag:color - Colored output [bool, default:true]\n\
+             #@flag:quiet - Less output [bool, default:false]\n",
+        );
+        let command = build_script_command(&source, "tool".into(), Path::new("/s/tool.sh")).command;
+        let matches = command.clone().try_get_matches_from(["tool"]).unwrap();
+        assert!(matches.get_flag("color"));
+        assert!(!matches.get_flag("quiet"));
+        let matches = command
+            .clone()
+            .try_get_matches_from(["tool", "--no-color"])
+            .unwrap();
+        assert!(matches.get_flag("no-color"));
+
+        let help = |id: &str| {
+            let arg = command.get_arguments().find(|a| a.get_id() == id).unwrap();
+            arg.get_help().unwrap().to_string()
+        };
+        assert_eq!(help("color"), "Colored output [default: true]");
+        assert_eq!(help("quiet"), "Less output");
+    }
+
     #[test]
     fn test_named_catchall_arg() {
         let script_content = r#"#!/bin/bash
@@ -1509,8 +2911,8 @@ mod tests {
 "#;
 
         let dir = tempdir().unwrap();
-        let script_path = create_test_script(&dir.path(), "test.sh", script_content);
-        let cmd_with_path = build_script_command("test".to_string(), &script_path);
+        let script_path = create_test_script(dir.path(), "test.sh", script_content);
+        let cmd_with_path = build_script_command(&FsSource, "test".to_string(), &script_path);
 
         let args: Vec<_> = cmd_with_path.command.get_arguments().collect();
         let catchall = args.iter().find(|a| a.get_id() == "files").unwrap();
@@ -1539,8 +2941,8 @@ mod tests {
 "#;
 
         let dir = tempdir().unwrap();
-        let script_path = create_test_script(&dir.path(), "test.sh", script_content);
-        let cmd_with_path = build_script_command("test".to_string(), &script_path);
+        let script_path = create_test_script(dir.path(), "test.sh", script_content);
+        let cmd_with_path = build_script_command(&FsSource, "test".to_string(), &script_path);
 
         let args: Vec<_> = cmd_with_path.command.get_arguments().collect();
         let catchall = args
@@ -1551,164 +2953,261 @@ mod tests {
     }
 
     #[test]
-    fn test_list_scripts_flat() {
+    fn test_value_counts() {
+        let script_content = r#"#!/bin/bash
+#@arg:files - Files [min:2,max:3]
+"#;
+
         let dir = tempdir().unwrap();
-        let scripts_dir = dir.path();
+        let script_path = create_test_script(dir.path(), "test.sh", script_content);
+        let cmd = build_script_command(&FsSource, "test".to_string(), &script_path).command;
 
-        // Create nested structure
-        let docker_dir = scripts_dir.join("docker");
-        fs::create_dir(&docker_dir).unwrap();
-        create_test_script(
-            &docker_dir,
-            "build.sh",
-            "#!/bin/bash\n#@description: Build a Docker image",
-        );
-        create_test_script(
-            &docker_dir,
-            "push.sh",
-            "#!/bin/bash\n#@description: Push image to registry",
+        assert!(cmd.clone().try_get_matches_from(["test", "a"]).is_err());
+        assert!(
+            cmd.clone()
+                .try_get_matches_from(["test", "a", "b", "c", "d"])
+                .is_err()
         );
+        let matches = cmd.try_get_matches_from(["test", "a", "b", "c"]).unwrap();
+        assert_eq!(matches.get_many::<String>("files").unwrap().count(), 3);
+    }
 
-        create_test_script(
-            scripts_dir,
-            "hello.sh",
-            "#!/bin/bash\n#@description: Say hello",
+    #[test]
+    fn test_allow_hyphen_values() {
+        let script_content = r#"#!/bin/bash
+#@flag:offset - Offset [allow-hyphen]
+#@arg:...tool-args - Passed to the tool [allow-hyphen]
+"#;
+
+        let dir = tempdir().unwrap();
+        let script_path = create_test_script(dir.path(), "test.sh", script_content);
+        let cmd = build_script_command(&FsSource, "test".to_string(), &script_path).command;
+
+        let matches = cmd
+            .try_get_matches_from(["test", "--offset", "-5", "run", "-v", "--dry-run"])
+            .unwrap();
+        assert_eq!(matches.get_one::<String>("offset").unwrap(), "-5");
+        assert_eq!(
+            matches
+                .get_many::<String>("tool-args")
+                .unwrap()
+                .collect::<Vec<_>>(),
+            vec!["run", "-v", "--dry-run"]
         );
+    }
+
+    #[test]
+    fn test_short_flags() {
+        let script_content = r#"#!/bin/bash
+#@flag:verbose - Verbose [bool,short:v]
+#@flag:region - Region [short:r]
+#@flag:remote - Remote [short:r]
+#@flag:host - Host [short:h]
+"#;
+
+        let dir = tempdir().unwrap();
+        let script_path = create_test_script(dir.path(), "test.sh", script_content);
+        let cmd = build_script_command(&FsSource, "test".to_string(), &script_path).command;
 
-        let output = list_scripts(scripts_dir, None, false);
-        let lines: Vec<&str> = output.lines().collect();
-        assert_eq!(lines.len(), 3);
-        assert!(lines[0].starts_with("docker/build"));
-        assert!(lines[0].contains("Build a Docker image"));
-        assert!(lines[1].starts_with("docker/push"));
-        assert!(lines[1].contains("Push image to registry"));
-        assert!(lines[2].starts_with("hello"));
-        assert!(lines[2].contains("Say hello"));
+        let matches = cmd
+            .clone()
+            .try_get_matches_from(["test", "-v", "-r", "eu", "--remote", "origin"])
+            .unwrap();
+        assert!(matches.get_flag("verbose"));
+        assert_eq!(matches.get_one::<String>("region").unwrap(), "eu");
+        assert_eq!(matches.get_one::<String>("remote").unwrap(), "origin");
+        // Taken short forms are left out rather than breaking the command
+        assert!(cmd.try_get_matches_from(["test", "-h"]).is_err());
     }
 
     #[test]
-    fn test_list_scripts_tree() {
+    fn test_non_utf8_names_are_skipped() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
         let dir = tempdir().unwrap();
         let scripts_dir = dir.path();
+        create_test_script(scripts_dir, "good.sh", "#!/bin/bash\n#@description: Good");
+        let bad_name = OsStr::from_bytes(b"bad\xff.sh");
+        // Some filesystems (e.g. APFS) reject non-UTF-8 names outright
+        if fs::write(scripts_dir.join(bad_name), "#!/bin/bash\n").is_err() {
+            return;
+        }
+        fs::set_permissions(scripts_dir.join(bad_name), PermissionsExt::from_mode(0o755)).unwrap();
 
-        let docker_dir = scripts_dir.join("docker");
-        fs::create_dir(&docker_dir).unwrap();
-        create_test_script(
-            &docker_dir,
-            "build.sh",
-            "#!/bin/bash\n#@description: Build a Docker image",
-        );
-        create_test_script(
-            &docker_dir,
-            "push.sh",
-            "#!/bin/bash\n#@description: Push image to registry",
-        );
+        let commands = build_command_tree(scripts_dir, &[]);
+        let names: Vec<_> = commands.iter().map(|c| c.command.get_name()).collect();
+        assert_eq!(names, vec!["good"]);
 
-        let compose_dir = docker_dir.join("compose");
-        fs::create_dir(&compose_dir).unwrap();
-        create_test_script(
-            &compose_dir,
-            "up.sh",
-            "#!/bin/bash\n#@description: Start services",
-        );
-        create_test_script(
-            &compose_dir,
-            "down.sh",
-            "#!/bin/bash\n#@description: Stop services",
-        );
+        let output = crate::list::list_scripts(scripts_dir, None, false);
+        assert_eq!(output.lines().count(), 1);
 
-        create_test_script(
-            scripts_dir,
-            "hello.sh",
-            "#!/bin/bash\n#@description: Say hello",
-        );
-
-        let output = list_scripts(scripts_dir, None, true);
-        let lines: Vec<&str> = output.lines().collect();
-        assert_eq!(lines.len(), 7);
-        assert_eq!(lines[0], "docker/");
-        assert!(lines[1].starts_with("  build"));
-        assert_eq!(lines[2], "  compose/");
-        assert!(lines[3].starts_with("    down"));
-        assert!(lines[3].contains("Stop services"));
-        assert!(lines[4].starts_with("    up"));
-        assert!(lines[4].contains("Start services"));
-        assert!(lines[5].starts_with("  push"));
-        assert!(lines[5].contains("Push image to registry"));
-        assert!(lines[6].starts_with("hello"));
-        assert!(lines[6].contains("Say hello"));
+        #[cfg(feature = "completion")]
+        {
+            let completions = complete_script_names_in_dir(OsStr::new(""), scripts_dir);
+            assert_eq!(completions.len(), 1);
+        }
     }
 
     #[test]
-    fn test_list_scripts_filtered() {
+    fn test_build_command_tree_ignores_parent_components() {
         let dir = tempdir().unwrap();
-        let scripts_dir = dir.path();
+        let scripts_dir = dir.path().join(".shutl");
+        fs::create_dir(&scripts_dir).unwrap();
+        create_test_script(dir.path(), "outside.sh", "#!/bin/bash");
+        create_test_script(&scripts_dir, "inside.sh", "#!/bin/bash");
 
-        let docker_dir = scripts_dir.join("docker");
-        fs::create_dir(&docker_dir).unwrap();
-        create_test_script(
-            &docker_dir,
-            "build.sh",
-            "#!/bin/bash\n#@description: Build a Docker image",
-        );
+        let commands = build_command_tree(&scripts_dir, &["..".to_string(), "outside".to_string()]);
+        assert!(commands.iter().all(|c| c.command.get_name() != ".."));
+        assert!(commands.iter().all(|c| c.command.get_name() != "outside"));
+    }
 
-        let k8s_dir = scripts_dir.join("k8s");
-        fs::create_dir(&k8s_dir).unwrap();
-        create_test_script(
-            &k8s_dir,
-            "deploy.sh",
-            "#!/bin/bash\n#@description: Deploy to Kubernetes",
+    #[test]
+    fn test_command_tree_includes_symlinked_script() {
+        let dir = tempdir().unwrap();
+        let scripts_dir = dir.path().join(".shutl");
+        let shared = dir.path().join("dotfiles");
+        fs::create_dir_all(&scripts_dir).unwrap();
+        fs::create_dir_all(&shared).unwrap();
+        let target = create_test_script(&shared, "hello.sh", "#!/bin/bash\n#@description: Hi");
+        std::os::unix::fs::symlink(&target, scripts_dir.join("hello.sh")).unwrap();
+        // Broken links are ignored
+        std::os::unix::fs::symlink(shared.join("missing.sh"), scripts_dir.join("gone.sh")).unwrap();
+
+        let commands = build_command_tree(&scripts_dir, &[]);
+        assert_eq!(commands.len(), 1);
+        assert_eq!(commands[0].command.get_name(), "hello");
+        assert_eq!(
+            canonical_script_path(&commands[0].file_path),
+            canonical_script_path(&target)
         );
+    }
 
-        create_test_script(
-            scripts_dir,
-            "hello.sh",
-            "#!/bin/bash\n#@description: Say hello",
+    #[test]
+    fn test_command_tree_from_memory_source() {
+        let mut source = crate::source::MemorySource::new();
+        source
+            .add_script("/s/build.sh", "#!/bin/bash\n#@description: Build it\n")
+            .add_script("/s/deploy/prod.sh", "#!/bin/bash\n#@arg:region - Region\n")
+            .add_file("/s/deploy/.shutl", "Deployments\n", false)
+            .add_file("/s/notes.txt", "not a script", false);
+        let root = Path::new("/s");
+
+        let commands = build_command_tree_from(&source, root, &[]);
+        let names: Vec<_> = commands.iter().map(|c| c.command.get_name()).collect();
+        assert_eq!(names, vec!["build", "deploy"]);
+        assert_eq!(
+            commands[1].command.get_about().map(|a| a.to_string()),
+            Some("Deployments".to_string())
         );
 
-        let output = list_scripts(scripts_dir, Some("docker"), false);
-        let lines: Vec<&str> = output.lines().collect();
-        assert_eq!(lines.len(), 1);
-        assert!(lines[0].starts_with("docker/build"));
-        assert!(lines[0].contains("Build a Docker image"));
-        // k8s and hello should NOT appear
-        assert!(!output.contains("k8s"));
-        assert!(!output.contains("hello"));
+        let commands = build_command_tree_from(&source, root, &["deploy".to_string()]);
+        let prod = commands[0].command.find_subcommand("prod").unwrap();
+        assert!(prod.get_arguments().any(|a| a.get_id() == "region"));
+
+        let entries = collect_entries_from(&source, root, "");
+        let paths: Vec<_> = entries.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(paths, vec!["build", "deploy/prod"]);
+        assert_eq!(entries[0].description, "Build it");
     }
 
     #[test]
-    fn test_list_scripts_empty() {
-        let dir = tempdir().unwrap();
-        let scripts_dir = dir.path();
+    fn test_completion_tree_stubs_other_commands() {
+        let script = "#!/bin/bash\n#@description: Does things\n#@flag:force - Force [bool]\n";
+        let mut source = crate::source::MemorySource::new();
+        source
+            .add_script("/s/deploy.sh", script)
+            .add_script("/s/build.sh", script)
+            .add_script("/s/db/dump.sh", script)
+            .add_file("/s/db/.shutl", "Databases\n", false);
+        let root = Path::new("/s");
+        let about = |commands: &[CommandWithPath], name: &str| {
+            let command = commands.iter().find(|c| c.command.get_name() == name);
+            command.unwrap().command.get_about().map(|a| a.to_string())
+        };
+
+        let active = ["de".to_string()];
+        let commands = build_tree(&source, root, &active, Detail::Completing("de"));
+        assert_eq!(commands.len(), 3);
+        // Candidates of the word are listed without their arguments, the rest are stubs
+        assert_eq!(about(&commands, "deploy").as_deref(), Some("Does things"));
+        let deploy = commands.iter().find(|c| c.command.get_name() == "deploy");
+        assert!(deploy.unwrap().command.get_arguments().next().is_none());
+        assert_eq!(about(&commands, "build"), None);
+        assert_eq!(about(&commands, "db"), None);
+
+        let active = ["db".to_string(), "".to_string()];
+        let commands = build_tree(&source, root, &active, Detail::Completing(""));
+        assert_eq!(about(&commands, "db").as_deref(), Some("Databases"));
+        let dump = commands[0].command.find_subcommand("dump").unwrap();
+        assert_eq!(
+            dump.get_about().map(|a| a.to_string()).as_deref(),
+            Some("Does things")
+        );
 
-        let output = list_scripts(scripts_dir, None, false);
-        assert_eq!(output, "No scripts found.");
+        // The script being completed is built in full
+        let active = ["deploy".to_string(), "--f".to_string()];
+        let commands = build_tree(&source, root, &active, Detail::Completing("--f"));
+        assert!(
+            commands[0]
+                .command
+                .get_arguments()
+                .any(|a| a.get_id() == "force")
+        );
     }
 
-    #[test]
-    fn test_list_scripts_nonexistent_subdir() {
-        let dir = tempdir().unwrap();
-        let scripts_dir = dir.path();
+    struct Weekday;
+
+    impl argtype::ArgTypeProvider for Weekday {
+        fn name(&self) -> &str {
+            "test-weekday"
+        }
 
-        let output = list_scripts(scripts_dir, Some("nonexistent"), false);
-        assert_eq!(output, "Directory not found: nonexistent");
+        fn validate(&self, value: &str, _option: &str) -> Result<(), String> {
+            match value {
+                "mon" | "tue" => Ok(()),
+                _ => Err(format!("'{}' is not a weekday", value)),
+            }
+        }
     }
 
     #[test]
-    fn test_list_scripts_trailing_slash() {
-        let dir = tempdir().unwrap();
-        let scripts_dir = dir.path();
+    fn test_prune_older_than_is_bounded() {
+        let parse = |days: &str| {
+            build_prune_command().try_get_matches_from(["prune", "--older-than", days])
+        };
+        assert_eq!(
+            parse("36500").unwrap().get_one::<u64>("older-than"),
+            Some(&36500)
+        );
+        // Would overflow as seconds and make --delete remove everything
+        assert!(parse("213503982334601").is_err());
+    }
 
-        let docker_dir = scripts_dir.join("docker");
-        fs::create_dir(&docker_dir).unwrap();
-        create_test_script(
-            &docker_dir,
-            "build.sh",
-            "#!/bin/bash\n#@description: Build image",
+    #[test]
+    fn test_registered_arg_type_validates_values() {
+        argtype::register(Weekday);
+        let mut source = crate::source::MemorySource::new();
+        source.add_script(
+            "/s/backup.sh",
+            "#!/bin/bash\n#@arg:day - Day [test-weekday]\n#@flag:also - Day [test-weekday]\n",
         );
+        let cmd =
+            build_script_command(&source, "backup".to_string(), Path::new("/s/backup.sh")).command;
 
-        let output = list_scripts(scripts_dir, Some("docker/"), false);
-        assert!(output.contains("docker/build"));
-        assert!(!output.contains("docker//build"));
+        let matches = cmd
+            .clone()
+            .try_get_matches_from(["backup", "mon", "--also", "tue"])
+            .unwrap();
+        assert_eq!(matches.get_one::<String>("day").unwrap(), "mon");
+        let err = cmd
+            .clone()
+            .try_get_matches_from(["backup", "sun"])
+            .unwrap_err();
+        assert!(err.to_string().contains("'sun' is not a weekday"));
+        assert!(
+            cmd.try_get_matches_from(["backup", "mon", "--also", "sat"])
+                .is_err()
+        );
     }
 }
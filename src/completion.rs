@@ -0,0 +1,282 @@
+//! Completion candidates produced by running a script (`[complete-self:...]`,
+//! `[complete-script:...]`) or an external completer (`[complete:...]`). Results are
+//! cached for a short time so repeated tab presses don't rerun the script.
+
+use crate::config;
+use crate::get_scripts_dir;
+use crate::metadata::{CompleteCommand, CompleterScript};
+use crate::paths;
+use crate::persist;
+use crate::script::{find_script_file_in_dir, script_context_env};
+use clap_complete::CompletionCandidate;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant, SystemTime};
+
+/// How long cached completer output stays valid unless configured otherwise
+pub const DEFAULT_CACHE_SECONDS: u64 = 60;
+
+/// Completers that take longer than this are killed so the shell doesn't hang
+const COMPLETER_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Directory in the scripts directory that holds executables for `[complete:NAME]`. It
+/// is hidden, so the completers never show up as commands.
+pub const COMPLETERS_DIR: &str = ".completers";
+
+/// Resolves the script that produces candidates: the script itself, a sibling by name or
+/// an external completer
+pub fn completer_script(script_path: &Path, complete: &CompleteCommand) -> Option<PathBuf> {
+    match &complete.script {
+        CompleterScript::Own => Some(script_path.to_path_buf()),
+        CompleterScript::Sibling(name) => {
+            find_script_file_in_dir(std::slice::from_ref(name), script_path.parent()?)
+        }
+        CompleterScript::External(name) => find_script_file_in_dir(
+            std::slice::from_ref(name),
+            &get_scripts_dir().join(COMPLETERS_DIR),
+        ),
+    }
+}
+
+/// Gets candidates for `arg_name` from the completer, filtered by the current word.
+/// External completers also get the current word as their last argument and the
+/// script's context env vars (`SHUTL_COMMAND_PATH`, ...).
+pub fn complete_with_script(
+    script_path: &Path,
+    complete: &CompleteCommand,
+    arg_name: &str,
+    current: &str,
+) -> Vec<CompletionCandidate> {
+    let Some(completer) = completer_script(script_path, complete) else {
+        log::debug!("complete_with_script: no completer for {:?}", complete);
+        return Vec::new();
+    };
+
+    let mut args = complete.args.clone();
+    let mut envs = Vec::new();
+    if let CompleterScript::External(_) = complete.script {
+        args.push(current.to_string());
+        envs = script_context_env(script_path);
+    }
+    let output = cached_output(&completer, &args, &envs, arg_name).unwrap_or_default();
+    parse_candidates(&output, current)
+}
+
+/// Parses completer output: one candidate per line, optionally `value<TAB>description`
+pub fn parse_candidates(output: &str, current: &str) -> Vec<CompletionCandidate> {
+    output
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| {
+            let (value, help) = match line.split_once('\t') {
+                Some((value, help)) => (value.trim(), Some(help.trim())),
+                None => (line.trim(), None),
+            };
+            if !value.starts_with(current) {
+                return None;
+            }
+            let candidate = CompletionCandidate::new(value.to_string());
+            Some(match help {
+                Some(help) if !help.is_empty() => candidate.help(Some(help.to_string().into())),
+                _ => candidate,
+            })
+        })
+        .collect()
+}
+
+/// Gets the directory completer output is cached in
+pub fn cache_dir() -> Option<PathBuf> {
+    paths::cache_dir().map(|dir| dir.join("shutl").join("completions"))
+}
+
+/// Gets the cache file for a run of `completer`. The key covers everything the completer
+/// is given, including the script's context in `envs`, so scripts sharing a completer
+/// never see each other's candidates.
+fn cache_file(
+    completer: &Path,
+    args: &[String],
+    envs: &[(&str, String)],
+    arg_name: &str,
+) -> Option<PathBuf> {
+    let mut hasher = DefaultHasher::new();
+    completer.hash(&mut hasher);
+    args.hash(&mut hasher);
+    envs.hash(&mut hasher);
+    arg_name.hash(&mut hasher);
+    Some(cache_dir()?.join(format!("{:016x}", hasher.finish())))
+}
+
+/// Returns the completer output from the cache if it is younger than the TTL and than the
+/// completer script itself, otherwise runs the completer and refreshes the cache.
+fn cached_output(
+    completer: &Path,
+    args: &[String],
+    envs: &[(&str, String)],
+    arg_name: &str,
+) -> Option<String> {
+    let ttl = Duration::from_secs(
+        config::get()
+            .completion_cache_seconds
+            .unwrap_or(DEFAULT_CACHE_SECONDS),
+    );
+    let cache = cache_file(completer, args, envs, arg_name);
+
+    if let Some(ref cache) = cache
+        && is_fresh(cache, completer, ttl)
+        && let Ok(contents) = std::fs::read_to_string(cache)
+    {
+        return Some(contents);
+    }
+
+    let output = run_completer(completer, args, envs, arg_name)?;
+    if let Some(cache) = cache
+        && !ttl.is_zero()
+    {
+        // Other completions may read the cache while it is written
+        if let Err(e) = persist::write_atomic(&cache, output.as_bytes()) {
+            log::debug!("cached_output: failed to write {:?}: {}", cache, e);
+        }
+    }
+    Some(output)
+}
+
+fn is_fresh(cache: &Path, completer: &Path, ttl: Duration) -> bool {
+    let modified = |p: &Path| std::fs::metadata(p).and_then(|m| m.modified()).ok();
+    let (Some(cached_at), Some(script_changed)) = (modified(cache), modified(completer)) else {
+        return false;
+    };
+    let age = SystemTime::now()
+        .duration_since(cached_at)
+        .unwrap_or(Duration::MAX);
+    age < ttl && script_changed <= cached_at
+}
+
+/// Runs the completer with a timeout. `SHUTL_COMPLETE_ARG` tells it which argument is
+/// being completed. Its output is read while it runs, so a completer printing more than
+/// the pipe holds is not blocked until the timeout.
+fn run_completer(
+    completer: &Path,
+    args: &[String],
+    envs: &[(&str, String)],
+    arg_name: &str,
+) -> Option<String> {
+    let mut child = Command::new(completer)
+        .args(args)
+        .envs(envs.iter().map(|(key, value)| (key, value)))
+        .env("SHUTL_COMPLETE_ARG", arg_name)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .ok()?;
+    let mut stdout = child.stdout.take()?;
+    let reader = std::thread::spawn(move || {
+        let mut output = String::new();
+        stdout.read_to_string(&mut output).map(|_| output)
+    });
+
+    let deadline = Instant::now() + COMPLETER_TIMEOUT;
+    loop {
+        match child.try_wait() {
+            Ok(Some(_)) => break,
+            Ok(None) if Instant::now() < deadline => std::thread::sleep(Duration::from_millis(10)),
+            _ => {
+                log::warn!("Completer {} timed out", completer.display());
+                let _ = child.kill();
+                let _ = child.wait();
+                return None;
+            }
+        }
+    }
+
+    reader.join().ok()?.ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_parse_candidates() {
+        let output = "dev\tDevelopment\nprod\nstaging\t\n\n";
+        let candidates = parse_candidates(output, "");
+        let values: Vec<_> = candidates
+            .iter()
+            .map(|c| c.get_value().to_string_lossy().to_string())
+            .collect();
+        assert_eq!(values, vec!["dev", "prod", "staging"]);
+        assert_eq!(
+            candidates[0].get_help().map(|h| h.to_string()),
+            Some("Development".to_string())
+        );
+        assert!(candidates[2].get_help().is_none());
+
+        let filtered = parse_candidates(output, "pr");
+        assert_eq!(filtered.len(), 1);
+    }
+
+    #[test]
+    fn test_run_completer_and_sibling_resolution() {
+        let dir = tempdir().unwrap();
+        let script = dir.path().join("deploy.sh");
+        let sibling = dir.path().join("envs.sh");
+        std::fs::write(&script, "#!/bin/bash\n").unwrap();
+        std::fs::write(&sibling, "#!/bin/bash\necho \"$1-$SHUTL_COMPLETE_ARG\"\n").unwrap();
+        std::fs::set_permissions(&sibling, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let complete = CompleteCommand {
+            script: CompleterScript::Sibling("envs".to_string()),
+            args: vec!["--list".to_string()],
+        };
+        let completer = completer_script(&script, &complete).unwrap();
+        assert_eq!(completer, sibling);
+        let output = run_completer(&completer, &complete.args, &[], "env").unwrap();
+        assert_eq!(output.trim(), "--list-env");
+
+        let own = CompleteCommand {
+            script: CompleterScript::Own,
+            args: Vec::new(),
+        };
+        assert_eq!(completer_script(&script, &own).unwrap(), script);
+    }
+
+    #[test]
+    fn test_run_completer_with_context_env() {
+        let dir = tempdir().unwrap();
+        let completer = dir.path().join("contexts");
+        std::fs::write(
+            &completer,
+            "#!/bin/bash\necho \"$1:$2:$SHUTL_COMMAND_PATH\"\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&completer, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let args = vec!["--current".to_string(), "pro".to_string()];
+        let envs = vec![("SHUTL_COMMAND_PATH", "deploy/prod".to_string())];
+        let output = run_completer(&completer, &args, &envs, "context").unwrap();
+        assert_eq!(output.trim(), "--current:pro:deploy/prod");
+    }
+
+    #[test]
+    fn test_run_completer_with_large_output() {
+        let dir = tempdir().unwrap();
+        let completer = dir.path().join("branches");
+        // More than the 64 KiB a pipe holds on Linux
+        std::fs::write(
+            &completer,
+            "#!/bin/bash\nfor i in $(seq 20000); do echo \"feature/branch-$i\"; done\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&completer, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let output = run_completer(&completer, &[], &[], "branch").unwrap();
+        assert!(output.len() > 64 * 1024);
+        assert_eq!(output.lines().count(), 20000);
+        assert_eq!(output.lines().last(), Some("feature/branch-20000"));
+    }
+}
@@ -0,0 +1,641 @@
+//! User configuration, read from `$SHUTL_CONFIG` or `<config dir>/shutl/config.toml`.
+
+use crate::metadata::CommandNames;
+use crate::paths;
+use crate::sort::SortOrder;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// Settings loaded from the config file. Every field is optional in the file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct ShutlConfig {
+    /// Order of commands in help output and listings
+    pub sort: SortOrder,
+    /// Whether commands are named after the file stem or the full file name
+    pub command_names: CommandNames,
+    /// Permissions for scripts created by `new` (octal string, e.g. "0750").
+    /// Defaults to 0755 minus the umask.
+    #[serde(deserialize_with = "deserialize_mode")]
+    pub new_script_mode: Option<u32>,
+    /// How long output of `complete-self`/`complete-script` completers is cached (0 disables)
+    pub completion_cache_seconds: Option<u64>,
+    /// Additional script roots, searched after the scripts directory
+    pub roots: Vec<RootConfig>,
+    /// Scripts directory, used when `SHUTL_DIR` is not set
+    pub scripts_dir: Option<String>,
+    /// Where the scripts directory is when neither `SHUTL_DIR` nor `scripts-dir` is set
+    pub scripts_location: ScriptsLocation,
+    /// Editor for `new`, `edit` and `cp`, preferred over `$EDITOR`
+    pub editor: Option<String>,
+    /// Environment variables passed to scripts unless already set
+    pub env: BTreeMap<String, String>,
+    /// Patterns of inherited variables scripts get (`["AWS_*", "HOME"]`); all without it
+    pub env_allow: Option<Vec<String>>,
+    /// Patterns of inherited variables scripts never get, e.g. `["*_TOKEN"]`
+    pub env_deny: Vec<String>,
+    /// Named profiles, selected with `--profile` or `SHUTL_PROFILE`
+    pub profiles: BTreeMap<String, Profile>,
+    /// Open the command picker when `shutl` is run without a command in a terminal
+    pub interactive: bool,
+    /// How long the index of a remote root is cached before it is fetched again
+    /// (defaults to `remote::DEFAULT_REFRESH_SECONDS`)
+    pub remote_refresh_seconds: Option<u64>,
+    /// Commands without a script file, merged into the top level after all roots
+    pub commands: BTreeMap<String, CommandConfig>,
+    /// Env var name fragments (case-insensitive) whose values are masked in output,
+    /// history and logs. Defaults to `script::DEFAULT_SECRET_PATTERNS`.
+    pub secret_patterns: Option<Vec<String>>,
+    /// Roles of this machine, e.g. `["laptop"]`. Commands declaring other `#@roles:` are
+    /// hidden; without roles every command is shown.
+    pub roles: Vec<String>,
+    /// Secret key `shutl sign` signs with: a minisign key (`*.key`) or an SSH key
+    pub signing_key: Option<String>,
+    /// Signers whose imported scripts are trusted, as an `ssh-keygen -Y` allowed-signers file
+    pub allowed_signers: Option<String>,
+    /// minisign public key whose imported scripts are trusted
+    pub minisign_public_key: Option<String>,
+    /// Refuse to import scripts without a valid signature of a trusted key
+    pub require_signatures: bool,
+    /// Only run scripts of the scripts directory that are new or changed since they were
+    /// last reviewed on this machine after showing their diff (see `shutl review`)
+    pub require_review: bool,
+    /// File every finished run is appended to with who ran it where (see `audit.rs`)
+    pub audit_log: Option<String>,
+    /// Size in bytes at which the audit log is rotated (defaults to `audit::DEFAULT_MAX_BYTES`)
+    pub audit_log_max_bytes: Option<u64>,
+    /// Rotated audit logs kept (defaults to `audit::DEFAULT_KEEP`)
+    pub audit_log_keep: Option<usize>,
+    /// Boilerplate of scripts created by `new`
+    pub template: TemplateConfig,
+    /// Language of descriptions, e.g. `de`, overriding the locale of the environment
+    pub locale: Option<String>,
+    /// Pager for help, list and docs output that doesn't fit the terminal, run by `sh`
+    /// (defaults to `$PAGER`, then `less`); empty to turn paging off
+    pub pager: Option<String>,
+}
+
+/// Default location of the scripts directory
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ScriptsLocation {
+    /// `~/.shutl` while it exists and the data directory has no scripts yet (see
+    /// `shutl migrate`), else the data directory
+    #[default]
+    Auto,
+    /// Below the data directory, e.g. `~/.local/share/shutl/scripts`
+    Xdg,
+    /// `~/.shutl`
+    Home,
+}
+
+/// Settings of a named profile. Set values replace the top-level ones; `env` is merged.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Profile {
+    pub scripts_dir: Option<String>,
+    pub roots: Option<Vec<RootConfig>>,
+    pub editor: Option<String>,
+    pub env: BTreeMap<String, String>,
+    pub roles: Option<Vec<String>>,
+}
+
+/// Boilerplate of new scripts (`[template]` in the config file)
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct TemplateConfig {
+    /// Text put at the top of new scripts as comment lines, e.g. a license header.
+    /// `{{name}}` is the command name, other references are looked up in `vars`.
+    pub header: Option<String>,
+    /// Values for `{{...}}` in the header, e.g. `author = "Jane Doe"`
+    pub vars: BTreeMap<String, String>,
+}
+
+/// An additional script root (`[[roots]]` in the config file): a directory (`path`) or
+/// a remote catalog (`url`)
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct RootConfig {
+    /// Directory of the root; `~` and env vars are expanded
+    #[serde(default)]
+    pub path: String,
+    /// Index URL of a remote root (see `remote.rs`), mounted read-only
+    #[serde(default)]
+    pub url: Option<String>,
+    /// Command under which the root is mounted. Without a prefix its commands are
+    /// merged into the top level.
+    #[serde(default)]
+    pub prefix: Option<String>,
+}
+
+/// A command defined in the config (`[commands.NAME]`), run by `sh`. A `/` in the name
+/// places it in a group, e.g. `k8s/pods`.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct CommandConfig {
+    pub description: String,
+    /// Shell command line; arguments are available as `$SHUTL_<NAME>` like in scripts
+    pub run: String,
+    /// Arguments as `#@` metadata without the prefix, e.g. `"arg:pod - Pod name"`
+    pub args: Vec<String>,
+}
+
+impl ShutlConfig {
+    /// Applies the profile `name` on top of this config
+    pub fn apply_profile(&mut self, name: &str) -> Result<(), String> {
+        let Some(profile) = self.profiles.get(name).cloned() else {
+            return Err(format!("unknown profile '{}'", name));
+        };
+        if profile.scripts_dir.is_some() {
+            self.scripts_dir = profile.scripts_dir;
+        }
+        if let Some(roots) = profile.roots {
+            self.roots = roots;
+        }
+        if profile.editor.is_some() {
+            self.editor = profile.editor;
+        }
+        if let Some(roles) = profile.roles {
+            self.roles = roles;
+        }
+        self.env.extend(profile.env);
+        Ok(())
+    }
+}
+
+/// Gets the selected profile from `SHUTL_PROFILE` (which `--profile` sets)
+pub fn active_profile() -> Option<String> {
+    std::env::var("SHUTL_PROFILE")
+        .ok()
+        .filter(|p| !p.is_empty())
+}
+
+/// Options of `shutl` itself that take a separate value, e.g. `--profile work`
+pub const GLOBAL_VALUE_OPTIONS: [&str; 3] = ["--profile", "--shutl-root", "--error-format"];
+
+/// Finds `--profile NAME` or `--profile=NAME` among the options before the first command.
+/// `args` are the raw process arguments, including the binary name.
+pub fn profile_from_args(args: &[String]) -> Option<String> {
+    global_option_from_args(args, "--profile")
+}
+
+/// Finds `--shutl-root DIR` or `--shutl-root=DIR` among the options before the first
+/// command, like [`profile_from_args`]
+pub fn root_from_args(args: &[String]) -> Option<String> {
+    global_option_from_args(args, "--shutl-root")
+}
+
+/// Finds `--error-format FORMAT` or `--error-format=FORMAT` among the options before the
+/// first command, like [`profile_from_args`]
+pub fn error_format_from_args(args: &[String]) -> Option<String> {
+    global_option_from_args(args, "--error-format")
+}
+
+/// Checks whether `--all` is among the options before the first command, like
+/// [`profile_from_args`]
+pub fn all_from_args(args: &[String]) -> bool {
+    global_flag_from_args(args, "--all")
+}
+
+/// Checks whether `--frozen` is among the options before the first command, like
+/// [`profile_from_args`]
+pub fn frozen_from_args(args: &[String]) -> bool {
+    global_flag_from_args(args, "--frozen")
+}
+
+/// Checks whether `--no-pager` is among the options before the first command, like
+/// [`profile_from_args`]
+pub fn no_pager_from_args(args: &[String]) -> bool {
+    global_flag_from_args(args, "--no-pager")
+}
+
+/// Checks whether the global `flag` is among the options before the first command
+fn global_flag_from_args(args: &[String], flag: &str) -> bool {
+    let mut iter = global_args(args).iter();
+    while let Some(arg) = iter.next() {
+        if arg == flag {
+            return true;
+        }
+        if GLOBAL_VALUE_OPTIONS.contains(&arg.as_str()) {
+            iter.next();
+        }
+    }
+    false
+}
+
+/// Set to `1` by `--all` to include commands meant for machines with other roles
+pub const ALL_ROLES_VAR: &str = "SHUTL_ALL_ROLES";
+
+/// Set to `1` by `--frozen` to refuse running imported scripts that were changed
+pub const FROZEN_VAR: &str = "SHUTL_FROZEN";
+
+/// Selects the language of descriptions, before the `locale` setting and `LANG`
+pub const LOCALE_VAR: &str = "SHUTL_LOCALE";
+
+/// Gets the languages to show descriptions in, most specific first: `de_AT` and `de` for
+/// `de_AT.UTF-8`. The language comes from `SHUTL_LOCALE`, the `locale` setting, or
+/// `LC_ALL`, `LC_MESSAGES` and `LANG`.
+pub fn locales() -> Vec<String> {
+    let var = |name: &str| std::env::var(name).ok().filter(|value| !value.is_empty());
+    let locale = var(LOCALE_VAR)
+        .or_else(|| get().locale.clone())
+        .or_else(|| var("LC_ALL"))
+        .or_else(|| var("LC_MESSAGES"))
+        .or_else(|| var("LANG"));
+    locale
+        .map(|locale| locale_candidates(&locale))
+        .unwrap_or_default()
+}
+
+/// Expands a locale such as `de_AT.UTF-8@euro` into `de_AT` and `de`. The `C` and
+/// `POSIX` locales have no language.
+pub fn locale_candidates(locale: &str) -> Vec<String> {
+    let name = locale.split(['.', '@']).next().unwrap_or_default().trim();
+    if name.is_empty() || name == "C" || name == "POSIX" {
+        return Vec::new();
+    }
+    let mut candidates = vec![name.to_string()];
+    if let Some((language, _)) = name.split_once(['_', '-']) {
+        candidates.push(language.to_string());
+    }
+    candidates
+}
+
+/// Set to `1` by `--no-pager` to print long output directly
+pub const NO_PAGER_VAR: &str = "SHUTL_NO_PAGER";
+
+/// Checks whether paging is turned off (`--no-pager`, or `SHUTL_NO_PAGER=1`)
+pub fn no_pager() -> bool {
+    std::env::var_os(NO_PAGER_VAR).is_some_and(|value| value == "1")
+}
+
+/// Checks whether modified imported scripts are refused (`--frozen`, or `SHUTL_FROZEN=1`)
+pub fn frozen() -> bool {
+    std::env::var_os(FROZEN_VAR).is_some_and(|value| value == "1")
+}
+
+/// Checks whether commands of all roles are included (`--all`, or `SHUTL_ALL_ROLES=1`)
+pub fn all_roles() -> bool {
+    std::env::var_os(ALL_ROLES_VAR).is_some_and(|value| value == "1")
+}
+
+/// Gets the first command, e.g. `doctor` for `shutl --profile work doctor`, like
+/// [`profile_from_args`]
+pub fn command_from_args(args: &[String]) -> Option<&str> {
+    split_global_args(args).1.first().map(String::as_str)
+}
+
+/// Gets the first command and everything after it, e.g. `deploy prod --dry-run` for
+/// `shutl --shutl-root ../team deploy prod --dry-run`
+pub fn command_line_from_args(args: &[String]) -> &[String] {
+    split_global_args(args).1
+}
+
+/// Gets the options before the first command, with the values of global options
+fn global_args(args: &[String]) -> &[String] {
+    split_global_args(args).0
+}
+
+/// Splits the arguments after the binary name into the global options and the rest. In a
+/// completion request (`shutl -- shutl ...`) the command line being completed is split.
+fn split_global_args(args: &[String]) -> (&[String], &[String]) {
+    let skip = if std::env::var_os("COMPLETE").is_some() && args.get(1).is_some_and(|a| a == "--") {
+        3
+    } else {
+        1
+    };
+    let rest = args.get(skip..).unwrap_or_default();
+    let mut end = 0;
+    while let Some(arg) = rest.get(end) {
+        if GLOBAL_VALUE_OPTIONS.contains(&arg.as_str()) {
+            end += 2;
+        } else if arg.starts_with('-') {
+            end += 1;
+        } else {
+            break;
+        }
+    }
+    rest.split_at(end.min(rest.len()))
+}
+
+/// Finds the value of a global `option` among the options before the first command
+fn global_option_from_args(args: &[String], option: &str) -> Option<String> {
+    let mut iter = global_args(args).iter();
+    while let Some(arg) = iter.next() {
+        if arg == option {
+            return iter.next().cloned();
+        }
+        if let Some(value) = arg
+            .strip_prefix(option)
+            .and_then(|rest| rest.strip_prefix('='))
+        {
+            return Some(value.to_string());
+        }
+        if GLOBAL_VALUE_OPTIONS.contains(&arg.as_str()) {
+            iter.next();
+        }
+    }
+    None
+}
+
+/// Parses an octal file mode such as `755`, `0755` or `0o755`
+pub fn parse_mode(s: &str) -> Result<u32, String> {
+    let digits = s.trim().trim_start_matches("0o");
+    match u32::from_str_radix(digits, 8) {
+        Ok(mode) if mode <= 0o7777 => Ok(mode),
+        _ => Err(format!(
+            "invalid file mode '{}', expected octal like 0755",
+            s
+        )),
+    }
+}
+
+fn deserialize_mode<'de, D>(deserializer: D) -> Result<Option<u32>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let value = String::deserialize(deserializer)?;
+    parse_mode(&value)
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+/// Gets the path of the config file, honoring `SHUTL_CONFIG`
+pub fn config_path() -> Option<PathBuf> {
+    if let Ok(path) = std::env::var("SHUTL_CONFIG") {
+        if let Ok(expanded) = paths::expand(&path) {
+            return Some(PathBuf::from(expanded.to_string()));
+        }
+        return Some(PathBuf::from(path));
+    }
+    paths::config_dir().map(|dir| dir.join("shutl").join("config.toml"))
+}
+
+/// Parses a config file. A missing file yields the default config.
+pub fn load_config_from(path: &Path) -> Result<ShutlConfig, String> {
+    match std::fs::read_to_string(path) {
+        Ok(contents) => toml::from_str(&contents).map_err(|e| e.to_string()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(ShutlConfig::default()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Gets the process-wide config with the active profile applied, loading it on first use.
+/// An invalid config file is reported once and replaced by the defaults; an unknown
+/// profile is left for the caller to report (see [`active_profile`]).
+pub fn get() -> &'static ShutlConfig {
+    static CONFIG: OnceLock<ShutlConfig> = OnceLock::new();
+    CONFIG.get_or_init(|| {
+        let Some(path) = config_path() else {
+            return ShutlConfig::default();
+        };
+        let mut config = load_config_from(&path).unwrap_or_else(|e| {
+            eprintln!("Warning: ignoring config {}: {}", path.display(), e);
+            ShutlConfig::default()
+        });
+        if let Some(name) = active_profile()
+            && let Err(e) = config.apply_profile(&name)
+        {
+            log::debug!("config: {}", e);
+        }
+        config
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_missing_config_is_default() {
+        let dir = tempdir().unwrap();
+        let config = load_config_from(&dir.path().join("config.toml")).unwrap();
+        assert_eq!(config.sort, SortOrder::Natural);
+    }
+
+    #[test]
+    fn test_load_sort_order() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(&path, "sort = \"alphabetical\"\n").unwrap();
+        let config = load_config_from(&path).unwrap();
+        assert_eq!(config.sort, SortOrder::Alphabetical);
+    }
+
+    #[test]
+    fn test_load_command_names() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(&path, "command-names = \"filename\"\n").unwrap();
+        let config = load_config_from(&path).unwrap();
+        assert_eq!(config.command_names, CommandNames::Filename);
+    }
+
+    #[test]
+    fn test_load_scripts_location() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        assert_eq!(
+            load_config_from(&path).unwrap().scripts_location,
+            ScriptsLocation::Auto
+        );
+        std::fs::write(&path, "scripts-location = \"home\"\n").unwrap();
+        assert_eq!(
+            load_config_from(&path).unwrap().scripts_location,
+            ScriptsLocation::Home
+        );
+    }
+
+    #[test]
+    fn test_load_interactive() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        assert!(!load_config_from(&path).unwrap().interactive);
+        std::fs::write(&path, "interactive = true\n").unwrap();
+        assert!(load_config_from(&path).unwrap().interactive);
+    }
+
+    #[test]
+    fn test_load_env_filter() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        let config = load_config_from(&path).unwrap();
+        assert_eq!(config.env_allow, None);
+        std::fs::write(
+            &path,
+            "env-allow = [\"AWS_*\", \"HOME\"]\nenv-deny = [\"*_TOKEN\"]\n",
+        )
+        .unwrap();
+        let config = load_config_from(&path).unwrap();
+        assert_eq!(config.env_allow.unwrap(), vec!["AWS_*", "HOME"]);
+        assert_eq!(config.env_deny, vec!["*_TOKEN"]);
+    }
+
+    #[test]
+    fn test_parse_mode() {
+        assert_eq!(parse_mode("755"), Ok(0o755));
+        assert_eq!(parse_mode("0750"), Ok(0o750));
+        assert_eq!(parse_mode("0o700"), Ok(0o700));
+        assert!(parse_mode("999").is_err());
+        assert!(parse_mode("rwx").is_err());
+    }
+
+    #[test]
+    fn test_load_new_script_mode() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(&path, "new-script-mode = \"0750\"\n").unwrap();
+        let config = load_config_from(&path).unwrap();
+        assert_eq!(config.new_script_mode, Some(0o750));
+    }
+
+    #[test]
+    fn test_load_roots() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(
+            &path,
+            "[[roots]]\npath = \"~/team\"\nprefix = \"team\"\n\n[[roots]]\npath = \"/opt/scripts\"\n",
+        )
+        .unwrap();
+        let config = load_config_from(&path).unwrap();
+        assert_eq!(
+            config.roots,
+            vec![
+                RootConfig {
+                    path: "~/team".to_string(),
+                    url: None,
+                    prefix: Some("team".to_string()),
+                },
+                RootConfig {
+                    path: "/opt/scripts".to_string(),
+                    url: None,
+                    prefix: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_profile_overrides() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(
+            &path,
+            r#"
+editor = "vim"
+env = { REGION = "eu", TEAM = "none" }
+roles = ["laptop"]
+
+[[roots]]
+path = "~/shared"
+
+[profiles.work]
+scripts-dir = "~/work/shutl"
+roots = []
+env = { TEAM = "platform" }
+roles = ["laptop", "work"]
+"#,
+        )
+        .unwrap();
+        let mut work = load_config_from(&path).unwrap();
+        assert!(work.apply_profile("home").is_err());
+
+        work.apply_profile("work").unwrap();
+        assert_eq!(work.scripts_dir.as_deref(), Some("~/work/shutl"));
+        assert!(work.roots.is_empty());
+        assert_eq!(work.editor.as_deref(), Some("vim"));
+        assert_eq!(work.env["REGION"], "eu");
+        assert_eq!(work.env["TEAM"], "platform");
+        assert_eq!(work.roles, vec!["laptop", "work"]);
+    }
+
+    #[test]
+    fn test_profile_from_args() {
+        let args = |s: &str| s.split(' ').map(String::from).collect::<Vec<_>>();
+        assert_eq!(
+            profile_from_args(&args("shutl --profile work deploy")),
+            Some("work".to_string())
+        );
+        assert_eq!(
+            profile_from_args(&args("shutl --profile=home list")),
+            Some("home".to_string())
+        );
+        assert_eq!(
+            profile_from_args(&args("shutl deploy --profile work")),
+            None
+        );
+        assert_eq!(
+            root_from_args(&args("shutl --profile work --shutl-root ../team deploy")),
+            Some("../team".to_string())
+        );
+        assert_eq!(
+            profile_from_args(&args("shutl --shutl-root=../team --profile work")),
+            Some("work".to_string())
+        );
+        assert!(all_from_args(&args("shutl --profile work --all deploy")));
+        assert!(!all_from_args(&args("shutl --profile --all")));
+        assert!(!all_from_args(&args("shutl list --all")));
+        assert_eq!(
+            command_from_args(&args("shutl --profile work doctor")),
+            Some("doctor")
+        );
+        assert_eq!(command_from_args(&args("shutl --all")), None);
+        assert_eq!(
+            command_line_from_args(&args("shutl --shutl-root ../team --all deploy --all")),
+            ["deploy", "--all"]
+        );
+        assert!(frozen_from_args(&args("shutl --frozen deploy")));
+        assert!(!frozen_from_args(&args("shutl deploy --frozen")));
+        assert!(no_pager_from_args(&args("shutl --no-pager list")));
+    }
+
+    #[test]
+    fn test_locale_candidates() {
+        assert_eq!(locale_candidates("de_AT.UTF-8@euro"), vec!["de_AT", "de"]);
+        assert_eq!(locale_candidates("fr"), vec!["fr"]);
+        assert_eq!(locale_candidates("pt-BR"), vec!["pt-BR", "pt"]);
+        assert!(locale_candidates("C.UTF-8").is_empty());
+        assert!(locale_candidates("POSIX").is_empty());
+    }
+
+    #[test]
+    fn test_load_commands() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(
+            &path,
+            r#"
+[commands.ports]
+description = "List listening ports"
+run = "ss -tlnp"
+
+[commands."k8s/logs"]
+run = "kubectl logs -f $SHUTL_POD"
+args = ["arg:pod - Pod name"]
+"#,
+        )
+        .unwrap();
+        let config = load_config_from(&path).unwrap();
+        assert_eq!(config.commands["ports"].description, "List listening ports");
+        assert_eq!(
+            config.commands["k8s/logs"],
+            CommandConfig {
+                description: String::new(),
+                run: "kubectl logs -f $SHUTL_POD".to_string(),
+                args: vec!["arg:pod - Pod name".to_string()],
+            }
+        );
+    }
+
+    #[test]
+    fn test_invalid_config_is_error() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(&path, "sort = \"sideways\"\n").unwrap();
+        assert!(load_config_from(&path).is_err());
+    }
+}
@@ -0,0 +1,247 @@
+//! Reference documentation for the script tree (`shutl docs`), rendered as Markdown or as
+//! a man page from the same metadata that drives the CLI.
+
+use crate::metadata::{ArgType, CommandMetadata, Config, LineType};
+use crate::script::REDACTED;
+use std::path::PathBuf;
+
+/// A documented command: its path (e.g. `deploy prod`) and parsed metadata
+pub struct DocEntry {
+    pub command: String,
+    pub metadata: CommandMetadata,
+    /// Set for a group described by its `README.md`, which the docs link to; such an
+    /// entry has no usage or arguments of its own
+    pub readme: Option<PathBuf>,
+}
+
+/// Output formats of `shutl docs`
+pub const FORMATS: [&str; 2] = ["markdown", "man"];
+
+/// Builds the usage line of a command, e.g. `shutl deploy prod <env> [--region <region>]`
+pub fn usage_line(entry: &DocEntry) -> String {
+    let mut parts = vec!["shutl".to_string(), entry.command.clone()];
+    for arg in &entry.metadata.arguments {
+        match arg {
+            LineType::Positional(name, _, cfg) if cfg.is_multiple() => {
+                parts.push(if cfg.is_required() || cfg.min_values() > 0 {
+                    format!("<{}>...", name)
+                } else {
+                    format!("[{}]...", name)
+                })
+            }
+            LineType::Positional(name, _, cfg) if cfg.default.is_some() => {
+                parts.push(format!("[{}]", name))
+            }
+            LineType::Positional(name, _, _) => parts.push(format!("<{}>", name)),
+            LineType::Flag(name, _, cfg) if cfg.arg_type == Some(ArgType::Bool) => {
+                parts.push(format!("[--{}]", name))
+            }
+            LineType::Flag(name, _, cfg) if cfg.is_required() => {
+                parts.push(format!("--{} <{}>", name, name))
+            }
+            LineType::Flag(name, _, _) => parts.push(format!("[--{} <{}>]", name, name)),
+            LineType::Description(_) => {}
+        }
+    }
+    parts.join(" ")
+}
+
+/// Describes an argument's default and allowed values, e.g. `default: eu; one of: eu, us`
+fn annotations(cfg: &Config) -> String {
+    let mut notes = Vec::new();
+    if let Some(default) = &cfg.default {
+        let default = if cfg.secret { REDACTED } else { default };
+        notes.push(format!("default: {}", default));
+    }
+    if !cfg.options.is_empty() {
+        let options: Vec<String> = cfg
+            .options
+            .iter()
+            .map(|option| match cfg.option_help.get(option) {
+                Some(help) => format!("{} ({})", option, help),
+                None => option.clone(),
+            })
+            .collect();
+        notes.push(format!("one of: {}", options.join(", ")));
+    }
+    notes.join("; ")
+}
+
+fn arguments(entry: &DocEntry) -> impl Iterator<Item = (String, &String, &Config)> {
+    entry.metadata.arguments.iter().filter_map(|arg| match arg {
+        LineType::Positional(name, description, cfg) => Some((name.clone(), description, cfg)),
+        LineType::Flag(name, description, cfg) => {
+            let flag = match cfg.short {
+                Some(short) => format!("-{}, --{}", short, name),
+                None => format!("--{}", name),
+            };
+            Some((flag, description, cfg))
+        }
+        LineType::Description(_) => None,
+    })
+}
+
+/// Renders the commands as a Markdown reference
+pub fn render_markdown(entries: &[DocEntry]) -> String {
+    let mut out = String::from("# shutl commands\n");
+    for entry in entries {
+        out.push_str(&format!("\n## {}\n\n", entry.command));
+        if !entry.metadata.description.is_empty() {
+            out.push_str(&format!("{}\n\n", entry.metadata.description));
+        }
+        if let Some(readme) = &entry.readme {
+            out.push_str(&format!("See [README.md]({}).\n", readme.display()));
+            continue;
+        }
+        out.push_str(&format!("```\n{}\n```\n", usage_line(entry)));
+
+        let args: Vec<_> = arguments(entry).collect();
+        if !args.is_empty() {
+            out.push('\n');
+            for (name, description, cfg) in args {
+                let notes = annotations(cfg);
+                let notes = if notes.is_empty() {
+                    String::new()
+                } else {
+                    format!(" ({})", notes)
+                };
+                out.push_str(&format!("- `{}`: {}{}\n", name, description, notes));
+            }
+        }
+
+        if !entry.metadata.examples.is_empty() {
+            out.push_str("\nExamples:\n\n```bash\n");
+            for example in &entry.metadata.examples {
+                out.push_str(&format!("{}\n", example));
+            }
+            out.push_str("```\n");
+        }
+    }
+    out
+}
+
+/// Escapes text for roff: backslashes, and leading dots or quotes that would start a request
+fn roff(text: &str) -> String {
+    let escaped = text.replace('\\', "\\e").replace('-', "\\-");
+    if escaped.starts_with('.') || escaped.starts_with('\'') {
+        format!("\\&{}", escaped)
+    } else {
+        escaped
+    }
+}
+
+/// Renders the commands as a man page (section 1)
+pub fn render_man(entries: &[DocEntry]) -> String {
+    let mut out = String::from(
+        ".TH SHUTL-COMMANDS 1\n.SH NAME\nshutl-commands \\- scripts available through shutl\n.SH COMMANDS\n",
+    );
+    for entry in entries {
+        out.push_str(&format!(".SS {}\n", roff(&entry.command)));
+        if !entry.metadata.description.is_empty() {
+            out.push_str(&format!("{}\n.PP\n", roff(&entry.metadata.description)));
+        }
+        if let Some(readme) = &entry.readme {
+            out.push_str(&format!("See {}\n", roff(&readme.display().to_string())));
+            continue;
+        }
+        out.push_str(&format!(".B {}\n", roff(&usage_line(entry))));
+        for (name, description, cfg) in arguments(entry) {
+            let notes = annotations(cfg);
+            out.push_str(&format!(".TP\n.B {}\n{}\n", roff(&name), roff(description)));
+            if !notes.is_empty() {
+                out.push_str(&format!("({})\n", roff(&notes)));
+            }
+        }
+        if !entry.metadata.examples.is_empty() {
+            out.push_str(".PP\nExamples:\n.PP\n.nf\n");
+            for example in &entry.metadata.examples {
+                out.push_str(&format!("    {}\n", roff(example)));
+            }
+            out.push_str(".fi\n");
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry() -> DocEntry {
+        let arguments = vec![
+            LineType::Positional("env".into(), "Target".into(), Config::default()),
+            LineType::Flag(
+                "region".into(),
+                "Region".into(),
+                Config {
+                    default: Some("eu".into()),
+                    options: vec!["eu".into(), "us".into()],
+                    ..Default::default()
+                },
+            ),
+            LineType::Flag(
+                "dry-run".into(),
+                "Only print".into(),
+                Config {
+                    arg_type: Some(ArgType::Bool),
+                    ..Default::default()
+                },
+            ),
+        ];
+        DocEntry {
+            command: "deploy prod".into(),
+            metadata: CommandMetadata {
+                description: "Deploy to production".into(),
+                arguments,
+                examples: vec!["shutl deploy prod web --region us".into()],
+                ..Default::default()
+            },
+            readme: None,
+        }
+    }
+
+    fn group() -> DocEntry {
+        DocEntry {
+            command: "deploy".into(),
+            metadata: CommandMetadata {
+                description: "Deployment scripts".into(),
+                ..Default::default()
+            },
+            readme: Some(PathBuf::from("/scripts/deploy/README.md")),
+        }
+    }
+
+    #[test]
+    fn test_usage_line() {
+        assert_eq!(
+            usage_line(&entry()),
+            "shutl deploy prod <env> [--region <region>] [--dry-run]"
+        );
+    }
+
+    #[test]
+    fn test_render_markdown() {
+        let markdown = render_markdown(&[group(), entry()]);
+        assert!(markdown.contains(
+            "## deploy\n\nDeployment scripts\n\nSee [README.md](/scripts/deploy/README.md).\n\n## deploy prod\n"
+        ));
+        assert!(markdown.contains("## deploy prod\n\nDeploy to production\n"));
+        assert!(markdown.contains("- `--region`: Region (default: eu; one of: eu, us)\n"));
+        assert!(
+            markdown.contains("Examples:\n\n```bash\nshutl deploy prod web --region us\n```\n")
+        );
+    }
+
+    #[test]
+    fn test_render_man() {
+        let man = render_man(&[group(), entry()]);
+        assert!(man.starts_with(".TH SHUTL-COMMANDS 1\n"));
+        assert!(man.contains(
+            ".SS deploy\nDeployment scripts\n.PP\nSee /scripts/deploy/README.md\n.SS deploy prod\n"
+        ));
+        assert!(man.contains(".SS deploy prod\n"));
+        assert!(man.contains(".TP\n.B \\-\\-dry\\-run\nOnly print\n"));
+        assert!(man.contains("    shutl deploy prod web \\-\\-region us\n"));
+        assert_eq!(roff(".hidden"), "\\&.hidden");
+    }
+}
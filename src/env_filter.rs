@@ -0,0 +1,138 @@
+//! Which variables of shutl's own environment a script inherits. An allowlist
+//! (`env-allow` in the config, `#@env-allow: AWS_*, HOME` in a script) turns on a clean
+//! environment with only the listed variables; a denylist (`env-deny`,
+//! `#@env-deny: GITHUB_TOKEN`) removes variables either way. Variables shutl sets itself
+//! (arguments, `SHUTL_*` context, the config's `env`) are not affected.
+
+use std::process::Command;
+
+/// Always inherited, so interpreters (`#!/usr/bin/env bash`) are still found
+const ALWAYS_PASSED: &[&str] = &["PATH"];
+
+/// Patterns deciding which inherited variables a script gets. `*` matches any run of
+/// characters, e.g. `AWS_*`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EnvFilter {
+    /// Only these are inherited if set; `None` inherits everything
+    pub allow: Option<Vec<String>>,
+    /// Never inherited, even if allowed
+    pub deny: Vec<String>,
+}
+
+impl EnvFilter {
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_none() && self.deny.is_empty()
+    }
+
+    /// Checks whether the inherited variable `name` is passed to the script
+    pub fn passes(&self, name: &str) -> bool {
+        if ALWAYS_PASSED.contains(&name) {
+            return true;
+        }
+        let allowed = self
+            .allow
+            .as_ref()
+            .is_none_or(|allow| allow.iter().any(|p| matches(p, name)));
+        allowed && !self.deny.iter().any(|p| matches(p, name))
+    }
+
+    /// Describes the filter for `explain`, e.g. `PATH, AWS_* except *_TOKEN`
+    pub fn describe(&self) -> String {
+        let allowed = match &self.allow {
+            Some(allow) => ALWAYS_PASSED
+                .iter()
+                .map(|name| name.to_string())
+                .chain(allow.iter().cloned())
+                .collect::<Vec<_>>()
+                .join(", "),
+            None => "everything".to_string(),
+        };
+        if self.deny.is_empty() {
+            allowed
+        } else {
+            format!("{} except {}", allowed, self.deny.join(", "))
+        }
+    }
+
+    /// Removes the inherited variables that do not pass from `command`
+    pub fn apply(&self, command: &mut Command) {
+        if self.is_empty() {
+            return;
+        }
+        for (name, _) in std::env::vars_os() {
+            if !self.passes(&name.to_string_lossy()) {
+                command.env_remove(name);
+            }
+        }
+    }
+}
+
+/// Checks a pattern: a variable name that may contain `*`
+pub fn is_valid_pattern(pattern: &str) -> bool {
+    !pattern.is_empty()
+        && pattern
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '*')
+}
+
+/// Splits a comma-separated pattern list, e.g. `AWS_*, HOME`
+pub fn parse_patterns(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(|p| p.trim().to_string())
+        .filter(|p| !p.is_empty())
+        .collect()
+}
+
+/// Matches `name` against a pattern where `*` stands for any run of characters
+fn matches(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_env_filter() {
+        assert!(matches("AWS_*", "AWS_PROFILE"));
+        assert!(matches("*_TOKEN", "GITHUB_TOKEN"));
+        assert!(matches("A*B*C", "AxxBxxC"));
+        assert!(!matches("AWS_*", "MY_AWS_KEY"));
+        assert!(!matches("HOME", "HOMEBREW_PREFIX"));
+
+        let filter = EnvFilter {
+            allow: Some(parse_patterns("AWS_*, HOME")),
+            deny: vec!["AWS_SECRET_ACCESS_KEY".to_string()],
+        };
+        assert!(filter.passes("HOME"));
+        assert!(filter.passes("AWS_REGION"));
+        assert!(filter.passes("PATH"));
+        assert!(!filter.passes("AWS_SECRET_ACCESS_KEY"));
+        assert!(!filter.passes("GITHUB_TOKEN"));
+
+        let deny_only = EnvFilter {
+            allow: None,
+            deny: vec!["*_TOKEN".to_string()],
+        };
+        assert!(deny_only.passes("HOME"));
+        assert_eq!(deny_only.describe(), "everything except *_TOKEN");
+        assert!(!deny_only.passes("GITHUB_TOKEN"));
+        assert!(is_valid_pattern("AWS_*"));
+        assert!(!is_valid_pattern("AWS-*"));
+    }
+}
@@ -0,0 +1,341 @@
+//! Errors about a command line: unknown commands, invalid arguments and runs that could
+//! not start. With `--error-format json` (or `SHUTL_ERROR_FORMAT=json`) they are printed
+//! to stderr as one JSON object, so wrappers and editor plugins don't scrape the text.
+//! Library functions return a [`ShutlError`] instead of exiting; the binary reports it.
+
+use crate::pager;
+use crate::roots;
+use clap::error::{ContextKind, ContextValue, ErrorKind};
+use serde::Serialize;
+use std::fmt;
+use std::path::PathBuf;
+use std::process::ExitStatus;
+
+/// Variable selecting the error format; `--error-format` sets it for nested calls
+pub const FORMAT_VAR: &str = "SHUTL_ERROR_FORMAT";
+
+/// Names accepted by `--error-format`
+pub const FORMATS: [&str; 2] = ["text", "json"];
+
+/// Checks whether errors are reported as JSON
+pub fn is_json() -> bool {
+    std::env::var(FORMAT_VAR).is_ok_and(|format| format == "json")
+}
+
+/// An error about a command line
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ErrorReport {
+    /// Stable identifier, e.g. `unknown-command` or `invalid-value`
+    pub code: &'static str,
+    pub message: String,
+    /// Corrections, e.g. a similarly named command or flag, or the valid values
+    pub suggestions: Vec<String>,
+    /// Command paths that may have been meant, e.g. `aws/deploy` for `deploy`
+    pub candidates: Vec<String>,
+}
+
+impl ErrorReport {
+    pub fn new(code: &'static str, message: impl Into<String>) -> Self {
+        ErrorReport {
+            code,
+            message: message.into(),
+            suggestions: Vec::new(),
+            candidates: Vec::new(),
+        }
+    }
+
+    /// Describes a clap parse error
+    pub fn from_clap(error: &clap::Error) -> Self {
+        let code = match error.kind() {
+            ErrorKind::InvalidSubcommand => "unknown-command",
+            ErrorKind::UnknownArgument => "unknown-argument",
+            ErrorKind::MissingRequiredArgument => "missing-argument",
+            ErrorKind::MissingSubcommand | ErrorKind::DisplayHelpOnMissingArgumentOrSubcommand => {
+                "missing-command"
+            }
+            ErrorKind::InvalidValue | ErrorKind::ValueValidation | ErrorKind::InvalidUtf8 => {
+                "invalid-value"
+            }
+            ErrorKind::TooManyValues | ErrorKind::TooFewValues | ErrorKind::WrongNumberOfValues => {
+                "wrong-number-of-values"
+            }
+            ErrorKind::ArgumentConflict => "argument-conflict",
+            _ => "invalid-arguments",
+        };
+        let rendered = error.render().to_string();
+        let message = rendered
+            .lines()
+            .next()
+            .unwrap_or_default()
+            .trim_start_matches("error: ")
+            .to_string();
+        let mut report = ErrorReport::new(code, message);
+        for (kind, value) in error.context() {
+            if matches!(
+                kind,
+                ContextKind::SuggestedSubcommand
+                    | ContextKind::SuggestedArg
+                    | ContextKind::SuggestedValue
+                    | ContextKind::ValidValue
+            ) {
+                report.suggestions.extend(context_strings(value));
+            }
+        }
+        report
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+
+    /// Prints the error to stderr in the selected format and exits with `status`
+    pub fn exit(&self, status: i32) -> ! {
+        if is_json() {
+            eprintln!("{}", self.to_json());
+        } else {
+            eprintln!("{}", self.message);
+            if !self.suggestions.is_empty() {
+                eprintln!("Did you mean: {}", self.suggestions.join(", "));
+            }
+            if !self.candidates.is_empty() {
+                eprintln!("Commands with that name: {}", self.candidates.join(", "));
+            }
+        }
+        std::process::exit(status)
+    }
+}
+
+/// An error that ends a shutl invocation
+#[derive(Debug)]
+pub enum ShutlError {
+    /// There is no home directory for the default scripts directory
+    NoHomeDir,
+    /// The editor could not be started
+    Editor {
+        editor: String,
+        source: std::io::Error,
+    },
+    /// A file shutl manages could not be read
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// A file shutl manages could not be written
+    Write {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// A script could not be run, e.g. its interpreter is missing or a check refused it
+    Execution(std::io::Error),
+    /// A script ran and failed; it reported why itself
+    ScriptFailed(ExitStatus),
+}
+
+impl ShutlError {
+    /// Stable identifier, as in [`ErrorReport::code`]
+    pub fn code(&self) -> &'static str {
+        match self {
+            ShutlError::NoHomeDir => "no-home-dir",
+            ShutlError::Editor { .. } => "editor-failed",
+            ShutlError::Read { .. } => "read-failed",
+            ShutlError::Write { .. } => "write-failed",
+            ShutlError::Execution(_) => "execution-failed",
+            ShutlError::ScriptFailed(_) => "script-failed",
+        }
+    }
+
+    /// Gets the exit status of shutl: the script's own for a failed script, else 1
+    pub fn exit_status(&self) -> i32 {
+        match self {
+            ShutlError::ScriptFailed(status) => status.code().unwrap_or(1),
+            _ => 1,
+        }
+    }
+
+    /// Prints the error in the selected format (see [`ErrorReport::exit`]) and exits. A
+    /// failed script has printed its own errors, so shutl only passes its status on.
+    pub fn exit(&self) -> ! {
+        if let ShutlError::ScriptFailed(_) = self {
+            std::process::exit(self.exit_status());
+        }
+        ErrorReport::new(self.code(), self.to_string()).exit(self.exit_status())
+    }
+}
+
+impl fmt::Display for ShutlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShutlError::NoHomeDir => write!(
+                f,
+                "Cannot find the home directory for the default scripts directory; set SHUTL_DIR or scripts-dir in the config"
+            ),
+            ShutlError::Editor { editor, source } => {
+                write!(f, "Cannot start the editor '{}': {}", editor, source)
+            }
+            ShutlError::Read { path, source } => {
+                write!(f, "Cannot read {}: {}", path.display(), source)
+            }
+            ShutlError::Write { path, source } => {
+                write!(f, "Cannot write {}: {}", path.display(), source)
+            }
+            ShutlError::Execution(source) => write!(f, "Error executing command: {}", source),
+            ShutlError::ScriptFailed(status) => write!(f, "The script failed ({})", status),
+        }
+    }
+}
+
+impl std::error::Error for ShutlError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ShutlError::Editor { source, .. }
+            | ShutlError::Read { source, .. }
+            | ShutlError::Write { source, .. }
+            | ShutlError::Execution(source) => Some(source),
+            ShutlError::NoHomeDir | ShutlError::ScriptFailed(_) => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for ShutlError {
+    fn from(error: std::io::Error) -> Self {
+        ShutlError::Execution(error)
+    }
+}
+
+/// Exits on a clap parse error: clap's own output in text mode (and always for `--help`
+/// and `--version`), else an [`ErrorReport`]
+pub fn exit_on_clap_error(error: clap::Error) -> ! {
+    if error.kind() == ErrorKind::DisplayHelp {
+        let help = error.render().ansi().to_string();
+        if let Some(pager) = pager::pager_for(&help)
+            && pager::show(&pager, &help)
+        {
+            std::process::exit(0);
+        }
+    }
+    if !is_json()
+        || matches!(
+            error.kind(),
+            ErrorKind::DisplayHelp | ErrorKind::DisplayVersion
+        )
+    {
+        error.exit();
+    }
+    let mut report = ErrorReport::from_clap(&error);
+    if let Some(ContextValue::String(name)) = error.get(ContextKind::InvalidSubcommand) {
+        report.candidates = candidates_named(name);
+    }
+    report.exit(error.exit_code())
+}
+
+/// Finds the command paths whose last component is `name`
+pub fn candidates_named(name: &str) -> Vec<String> {
+    let entries = roots::collect_mount_entries(&roots::mounts(), None).unwrap_or_default();
+    named(entries.into_iter().map(|entry| entry.path), name)
+}
+
+/// Filters the command `paths` (e.g. `db/backup-db`) whose last component is `name`
+fn named(paths: impl IntoIterator<Item = String>, name: &str) -> Vec<String> {
+    paths
+        .into_iter()
+        .filter(|path| path.rsplit('/').next() == Some(name))
+        .collect()
+}
+
+fn context_strings(value: &ContextValue) -> Vec<String> {
+    match value {
+        ContextValue::String(s) => vec![s.clone()],
+        ContextValue::Strings(strings) => strings.clone(),
+        ContextValue::StyledStr(s) => vec![s.to_string()],
+        ContextValue::StyledStrs(strings) => strings.iter().map(|s| s.to_string()).collect(),
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{Arg, Command};
+
+    #[test]
+    fn test_report_from_clap_error() {
+        let cli = || {
+            Command::new("shutl")
+                .subcommand(Command::new("deploy"))
+                .subcommand(
+                    Command::new("backup").arg(
+                        Arg::new("mode")
+                            .long("mode")
+                            .value_parser(["full", "incremental"]),
+                    ),
+                )
+        };
+
+        let error = cli().try_get_matches_from(["shutl", "deplyo"]).unwrap_err();
+        let report = ErrorReport::from_clap(&error);
+        assert_eq!(report.code, "unknown-command");
+        assert_eq!(report.message, "unrecognized subcommand 'deplyo'");
+        assert_eq!(report.suggestions, vec!["deploy"]);
+
+        let error = cli()
+            .try_get_matches_from(["shutl", "backup", "--mode", "daily"])
+            .unwrap_err();
+        let report = ErrorReport::from_clap(&error);
+        assert_eq!(report.code, "invalid-value");
+        assert_eq!(report.suggestions, vec!["full", "incremental"]);
+        assert_eq!(
+            report.to_json(),
+            format!(
+                r#"{{"code":"invalid-value","message":{},"suggestions":["full","incremental"],"candidates":[]}}"#,
+                serde_json::to_string(&report.message).unwrap()
+            )
+        );
+    }
+
+    #[test]
+    fn test_named() {
+        let paths = || {
+            [
+                "db/backup-db",
+                "backup-db",
+                "db/backup-db-full",
+                "ops/db/backup-db",
+            ]
+            .map(String::from)
+        };
+        assert_eq!(
+            named(paths(), "backup-db"),
+            vec!["db/backup-db", "backup-db", "ops/db/backup-db"]
+        );
+        assert!(named(paths(), "backup").is_empty());
+    }
+
+    #[test]
+    fn test_shutl_error() {
+        use std::os::unix::process::ExitStatusExt;
+
+        let error = ShutlError::ScriptFailed(ExitStatus::from_raw(3 << 8));
+        assert_eq!(error.code(), "script-failed");
+        assert_eq!(error.exit_status(), 3);
+
+        let error = ShutlError::Editor {
+            editor: "nvim".to_string(),
+            source: std::io::Error::from(std::io::ErrorKind::NotFound),
+        };
+        assert_eq!(error.code(), "editor-failed");
+        assert_eq!(error.exit_status(), 1);
+        assert!(
+            error
+                .to_string()
+                .starts_with("Cannot start the editor 'nvim': ")
+        );
+        assert!(std::error::Error::source(&error).is_some());
+
+        let error = ShutlError::from(std::io::Error::other("sh is not installed"));
+        assert_eq!(error.code(), "execution-failed");
+        assert_eq!(
+            error.to_string(),
+            "Error executing command: sh is not installed"
+        );
+    }
+}
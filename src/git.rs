@@ -0,0 +1,211 @@
+//! The scripts directory as a git checkout synced with a remote branch: local changes
+//! versus the upstream branch (`shutl diff`) and the ahead/behind/dirty state
+//! (`shutl status`), and the history of a script (`shutl log`). Everything goes through
+//! the `git` command line.
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+
+/// Sync state of the checkout holding the scripts directory
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SyncStatus {
+    pub branch: String,
+    /// Tracked remote branch, e.g. `origin/main`
+    pub upstream: String,
+    /// Local commits not on the upstream branch
+    pub ahead: usize,
+    /// Upstream commits not merged locally
+    pub behind: usize,
+    /// Uncommitted changes below the scripts directory, as `git status --short` lines
+    pub changes: Vec<String>,
+}
+
+impl SyncStatus {
+    /// Summarizes the state in one line, e.g. `main...origin/main: 2 ahead, dirty`
+    pub fn summary(&self) -> String {
+        let mut parts = Vec::new();
+        if self.ahead > 0 {
+            parts.push(format!("{} ahead", self.ahead));
+        }
+        if self.behind > 0 {
+            parts.push(format!("{} behind", self.behind));
+        }
+        if !self.changes.is_empty() {
+            parts.push("dirty".to_string());
+        }
+        if parts.is_empty() {
+            parts.push("up to date".to_string());
+        }
+        format!("{}...{}: {}", self.branch, self.upstream, parts.join(", "))
+    }
+}
+
+/// Runs git in `dir` and returns its trimmed stdout, or its stderr as the error
+fn git(dir: &Path, args: &[&str]) -> Result<String, String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .map_err(|e| format!("cannot run git: {}", e))?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .trim_end()
+            .to_string())
+    } else {
+        Err(String::from_utf8_lossy(&output.stderr).trim().to_string())
+    }
+}
+
+/// Gets the root of the git checkout `dir` belongs to
+pub fn repo_root(dir: &Path) -> Option<PathBuf> {
+    git(dir, &["rev-parse", "--show-toplevel"])
+        .ok()
+        .map(PathBuf::from)
+}
+
+/// Gets the remote branch the current branch of `dir` tracks, e.g. `origin/main`
+pub fn upstream(dir: &Path) -> Result<String, String> {
+    if repo_root(dir).is_none() {
+        return Err(format!("{} is not in a git repository", dir.display()));
+    }
+    git(
+        dir,
+        &["rev-parse", "--abbrev-ref", "--symbolic-full-name", "@{upstream}"],
+    )
+    .map_err(|_| {
+        format!(
+            "the current branch of {} tracks no remote branch; set one with `git -C {} branch --set-upstream-to origin/main`",
+            dir.display(),
+            dir.display()
+        )
+    })
+}
+
+/// Updates the remote branches of `dir`
+pub fn fetch(dir: &Path) -> Result<(), String> {
+    git(dir, &["fetch", "--quiet"]).map(|_| ())
+}
+
+/// Works out the sync state of `dir` against its upstream branch, as of the last fetch
+pub fn status(dir: &Path) -> Result<SyncStatus, String> {
+    let upstream = upstream(dir)?;
+    let branch = git(dir, &["rev-parse", "--abbrev-ref", "HEAD"])?;
+    let counts = git(
+        dir,
+        &["rev-list", "--left-right", "--count", "HEAD...@{upstream}"],
+    )?;
+    let (ahead, behind) = parse_ahead_behind(&counts)
+        .ok_or_else(|| format!("unexpected output of git rev-list: {}", counts))?;
+    let changes = git(dir, &["status", "--short", "--", "."])?
+        .lines()
+        .map(String::from)
+        .collect();
+    Ok(SyncStatus {
+        branch,
+        upstream,
+        ahead,
+        behind,
+        changes,
+    })
+}
+
+/// Shows the differences of `path` (a script or a directory) between the upstream branch
+/// and the working tree, through git's pager
+pub fn diff(path: &Path) -> Result<ExitStatus, String> {
+    let (dir, pathspec) = if path.is_dir() {
+        (path, Path::new("."))
+    } else {
+        match (path.parent(), path.file_name()) {
+            (Some(dir), Some(name)) => (dir, Path::new(name)),
+            _ => return Err(format!("{} is not a script", path.display())),
+        }
+    };
+    upstream(dir)?;
+    Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["diff", "@{upstream}", "--"])
+        .arg(pathspec)
+        .stdin(Stdio::inherit())
+        .status()
+        .map_err(|e| format!("cannot run git: {}", e))
+}
+
+/// Shows the commits that changed the script at `path`, following renames, through git's
+/// pager. `patch` includes the changes themselves; `max_count` limits the number of commits.
+pub fn log(path: &Path, patch: bool, max_count: Option<usize>) -> Result<ExitStatus, String> {
+    let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+        return Err(format!("{} is not a script", path.display()));
+    };
+    if repo_root(dir).is_none() {
+        return Err(format!("{} is not in a git repository", dir.display()));
+    }
+    Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(log_args(patch, max_count))
+        .arg(name)
+        .stdin(Stdio::inherit())
+        .status()
+        .map_err(|e| format!("cannot run git: {}", e))
+}
+
+/// Builds the `git log` arguments for `log`, up to the trailing `--`
+fn log_args(patch: bool, max_count: Option<usize>) -> Vec<String> {
+    let mut args = vec![
+        "log".to_string(),
+        "--follow".to_string(),
+        "--date=format:%Y-%m-%d %H:%M".to_string(),
+        "--format=%C(yellow)%h%C(reset) %ad %C(bold)%an%C(reset) <%ae>%n    %s".to_string(),
+    ];
+    if patch {
+        args.push("--patch".to_string());
+    }
+    if let Some(n) = max_count {
+        args.push(format!("--max-count={}", n));
+    }
+    args.push("--".to_string());
+    args
+}
+
+/// Parses the `<ahead>\t<behind>` output of `git rev-list --left-right --count`
+fn parse_ahead_behind(output: &str) -> Option<(usize, usize)> {
+    let mut counts = output.split_whitespace().map(|n| n.parse().ok());
+    Some((counts.next()??, counts.next()??))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sync_status() {
+        assert_eq!(parse_ahead_behind("2\t1"), Some((2, 1)));
+        assert_eq!(parse_ahead_behind("2"), None);
+
+        let mut status = SyncStatus {
+            branch: "main".to_string(),
+            upstream: "origin/main".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(status.summary(), "main...origin/main: up to date");
+        status.ahead = 2;
+        status.changes.push(" M deploy.sh".to_string());
+        assert_eq!(status.summary(), "main...origin/main: 2 ahead, dirty");
+    }
+
+    #[test]
+    fn test_log_args() {
+        let args = log_args(true, Some(5));
+        assert_eq!(args[..2], ["log", "--follow"]);
+        assert!(args.contains(&"--patch".to_string()));
+        assert!(args.contains(&"--max-count=5".to_string()));
+        assert_eq!(args.last().unwrap(), "--");
+        assert!(
+            !log_args(false, None)
+                .iter()
+                .any(|a| a.starts_with("--max-count"))
+        );
+    }
+}
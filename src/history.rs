@@ -0,0 +1,214 @@
+//! Execution history, stored as JSON lines under the platform data directory.
+
+use crate::observer::{ExecutionObserver, Run};
+use crate::paths;
+use crate::persist;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A single recorded script run
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    /// Seconds since the Unix epoch
+    pub timestamp: u64,
+    /// Command path, e.g. `deploy/prod`
+    pub command: String,
+    /// Arguments passed after the command path
+    #[serde(default)]
+    pub args: Vec<String>,
+}
+
+/// Aggregated usage of one command
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommandUsage {
+    pub command: String,
+    pub count: usize,
+    pub last_used: u64,
+    pub score: f64,
+}
+
+/// Gets the path of the history file, honoring `SHUTL_HISTORY`
+pub fn history_path() -> Option<PathBuf> {
+    if let Ok(path) = std::env::var("SHUTL_HISTORY") {
+        return Some(PathBuf::from(path));
+    }
+    paths::data_local_dir().map(|dir| dir.join("shutl").join("history.jsonl"))
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Appends an entry to the history file, creating it if needed
+pub fn append_entry(path: &Path, entry: &HistoryEntry) -> std::io::Result<()> {
+    let line = serde_json::to_string(entry)?;
+    persist::append(path, format!("{}\n", line).as_bytes())
+}
+
+/// Records a run in the default history file. Failures are logged, never fatal.
+pub fn record(components: &[String], args: &[String]) {
+    let Some(path) = history_path() else {
+        return;
+    };
+    let entry = HistoryEntry {
+        timestamp: now(),
+        command: components.join("/"),
+        args: args.to_vec(),
+    };
+    if let Err(e) = append_entry(&path, &entry) {
+        log::warn!("Failed to write history {}: {}", path.display(), e);
+    }
+}
+
+/// Records every run that starts in the default history file
+pub struct HistoryObserver;
+
+impl ExecutionObserver for HistoryObserver {
+    fn on_start(&self, run: &Run) {
+        record(&run.components, &run.args);
+    }
+}
+
+/// Reads all entries, skipping lines that cannot be parsed
+pub fn read_entries(path: &Path) -> Vec<HistoryEntry> {
+    let Ok(contents) = std::fs::read_to_string(path) else {
+        return Vec::new();
+    };
+    contents
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect()
+}
+
+/// Gets the arguments of the most recent run of `command` (e.g. `deploy/prod`)
+pub fn last_args(path: &Path, command: &str) -> Option<Vec<String>> {
+    read_entries(path)
+        .into_iter()
+        .rev()
+        .find(|entry| entry.command == command)
+        .map(|entry| entry.args)
+}
+
+/// Weight of a single run depending on how long ago it happened
+fn recency_weight(age_secs: u64) -> f64 {
+    const HOUR: u64 = 60 * 60;
+    const DAY: u64 = 24 * HOUR;
+    const WEEK: u64 = 7 * DAY;
+    match age_secs {
+        a if a < HOUR => 4.0,
+        a if a < DAY => 2.0,
+        a if a < WEEK => 1.0,
+        _ => 0.25,
+    }
+}
+
+/// Aggregates entries per command and sorts them by frecency (frequency weighted by recency),
+/// highest first
+pub fn frecency(entries: &[HistoryEntry], now: u64) -> Vec<CommandUsage> {
+    let mut usage: HashMap<&str, CommandUsage> = HashMap::new();
+    for entry in entries {
+        let u = usage
+            .entry(entry.command.as_str())
+            .or_insert_with(|| CommandUsage {
+                command: entry.command.clone(),
+                count: 0,
+                last_used: 0,
+                score: 0.0,
+            });
+        u.count += 1;
+        u.last_used = u.last_used.max(entry.timestamp);
+        u.score += recency_weight(now.saturating_sub(entry.timestamp));
+    }
+
+    let mut result: Vec<_> = usage.into_values().collect();
+    result.sort_by(|a, b| {
+        b.score
+            .total_cmp(&a.score)
+            .then_with(|| b.last_used.cmp(&a.last_used))
+            .then_with(|| a.command.cmp(&b.command))
+    });
+    result
+}
+
+/// Formats a timestamp relative to `now`, e.g. `5m ago`
+pub fn format_age(timestamp: u64, now: u64) -> String {
+    let age = now.saturating_sub(timestamp);
+    match age {
+        a if a < 60 => "just now".to_string(),
+        a if a < 60 * 60 => format!("{}m ago", a / 60),
+        a if a < 24 * 60 * 60 => format!("{}h ago", a / (60 * 60)),
+        a => format!("{}d ago", a / (24 * 60 * 60)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn entry(command: &str, timestamp: u64) -> HistoryEntry {
+        HistoryEntry {
+            timestamp,
+            command: command.to_string(),
+            args: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_append_and_read_entries() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("nested").join("history.jsonl");
+        let first = HistoryEntry {
+            timestamp: 10,
+            command: "deploy/prod".into(),
+            args: vec!["--region".into(), "eu".into()],
+        };
+        append_entry(&path, &first).unwrap();
+        append_entry(&path, &entry("build", 20)).unwrap();
+        std::fs::write(
+            &path,
+            std::fs::read_to_string(&path).unwrap() + "not json\n",
+        )
+        .unwrap();
+
+        let entries = read_entries(&path);
+        assert_eq!(entries, vec![first, entry("build", 20)]);
+        assert_eq!(
+            last_args(&path, "deploy/prod"),
+            Some(vec!["--region".to_string(), "eu".to_string()])
+        );
+        assert_eq!(last_args(&path, "deploy"), None);
+    }
+
+    #[test]
+    fn test_frecency_prefers_recent_and_frequent() {
+        let now = 10_000_000;
+        let entries = vec![
+            entry("old", now - 30 * 24 * 60 * 60),
+            entry("old", now - 30 * 24 * 60 * 60),
+            entry("old", now - 30 * 24 * 60 * 60),
+            entry("recent", now - 60),
+            entry("daily", now - 2 * 60 * 60),
+            entry("daily", now - 3 * 60 * 60),
+            entry("daily", now - 4 * 60 * 60),
+        ];
+        let usage = frecency(&entries, now);
+        let order: Vec<_> = usage.iter().map(|u| u.command.as_str()).collect();
+        assert_eq!(order, vec!["daily", "recent", "old"]);
+        assert_eq!(usage[0].count, 3);
+        assert_eq!(usage[0].last_used, now - 2 * 60 * 60);
+    }
+
+    #[test]
+    fn test_format_age() {
+        assert_eq!(format_age(100, 130), "just now");
+        assert_eq!(format_age(0, 300), "5m ago");
+        assert_eq!(format_age(0, 2 * 60 * 60), "2h ago");
+        assert_eq!(format_age(0, 3 * 24 * 60 * 60), "3d ago");
+    }
+}
@@ -0,0 +1,314 @@
+//! Shell integration (`shutl hook <shell>`): a hook that runs on every directory change
+//! and points `SHUTL_PROJECT_ROOT` at the `.shutl` directory of the current project, so
+//! its scripts (and their completions) are available while inside it, once they were
+//! allowed with `shutl hook allow` (see `trust.rs`). With `--functions` it prints shell
+//! functions for the top-level commands instead, with `--command-not-found` a handler
+//! that offers scripts when the shell finds no command.
+
+use crate::SCRIPTS_DIR_NAME;
+use std::path::{Path, PathBuf};
+
+/// Environment variable holding the active project-local script root
+pub const PROJECT_ROOT_VAR: &str = "SHUTL_PROJECT_ROOT";
+
+/// Shells `shutl hook` supports
+pub const SHELLS: [&str; 3] = ["zsh", "bash", "fish"];
+
+const ZSH_HOOK: &str = r#"_shutl_hook() {
+  eval "$(command shutl hook --export zsh)"
+}
+typeset -ag chpwd_functions
+if (( ! ${chpwd_functions[(I)_shutl_hook]} )); then
+  chpwd_functions+=(_shutl_hook)
+fi
+_shutl_hook
+"#;
+
+const BASH_HOOK: &str = r#"_shutl_hook() {
+  if [[ "$PWD" != "${_SHUTL_HOOK_PWD:-}" ]]; then
+    _SHUTL_HOOK_PWD="$PWD"
+    eval "$(command shutl hook --export bash)"
+  fi
+}
+if [[ ";${PROMPT_COMMAND:-};" != *";_shutl_hook;"* ]]; then
+  PROMPT_COMMAND="_shutl_hook${PROMPT_COMMAND:+;$PROMPT_COMMAND}"
+fi
+_shutl_hook
+"#;
+
+const FISH_HOOK: &str = r#"function _shutl_hook --on-variable PWD
+    command shutl hook --export fish | source
+end
+_shutl_hook
+"#;
+
+/// Gets the hook to evaluate in the rc file of `shell`
+pub fn hook_script(shell: &str) -> Option<&'static str> {
+    match shell {
+        "zsh" => Some(ZSH_HOOK),
+        "bash" => Some(BASH_HOOK),
+        "fish" => Some(FISH_HOOK),
+        _ => None,
+    }
+}
+
+// The handlers keep a handler defined before them (e.g. the distribution's package
+// suggestions) and fall back to it when `shutl command-not-found` exits with 127
+const BASH_NOT_FOUND: &str = r#"if declare -F command_not_found_handle >/dev/null; then
+  eval "_shutl_previous_not_found$(declare -f command_not_found_handle | tail -n +2)"
+fi
+command_not_found_handle() {
+  if command -v shutl >/dev/null; then
+    command shutl command-not-found -- "$@"
+    local code=$?
+    (( code == 127 )) || return $code
+  fi
+  if declare -F _shutl_previous_not_found >/dev/null; then
+    _shutl_previous_not_found "$@"
+    return $?
+  fi
+  printf 'bash: %s: command not found\n' "$1" >&2
+  return 127
+}
+"#;
+
+const ZSH_NOT_FOUND: &str = r#"if (( $+functions[command_not_found_handler] )); then
+  functions[_shutl_previous_not_found]=$functions[command_not_found_handler]
+fi
+command_not_found_handler() {
+  if (( $+commands[shutl] )); then
+    command shutl command-not-found -- "$@"
+    local code=$?
+    (( code == 127 )) || return $code
+  fi
+  if (( $+functions[_shutl_previous_not_found] )); then
+    _shutl_previous_not_found "$@"
+    return $?
+  fi
+  print -u2 "zsh: command not found: $1"
+  return 127
+}
+"#;
+
+const FISH_NOT_FOUND: &str = r#"if functions -q fish_command_not_found; and not functions -q _shutl_previous_not_found
+    functions -c fish_command_not_found _shutl_previous_not_found
+end
+function fish_command_not_found
+    if command -q shutl
+        command shutl command-not-found -- $argv
+        set -l code $status
+        test $code -eq 127; or return $code
+    end
+    if functions -q _shutl_previous_not_found
+        _shutl_previous_not_found $argv
+    else
+        __fish_default_command_not_found_handler $argv
+    end
+end
+"#;
+
+/// Gets the command-not-found handler to evaluate in the rc file of `shell`
+pub fn not_found_handler(shell: &str) -> Option<&'static str> {
+    match shell {
+        "zsh" => Some(ZSH_NOT_FOUND),
+        "bash" => Some(BASH_NOT_FOUND),
+        "fish" => Some(FISH_NOT_FOUND),
+        _ => None,
+    }
+}
+
+const BASH_FUNCTION_COMPLETE: &str = r#"_shutl_function_complete() {
+  declare -F _clap_complete_shutl >/dev/null || return
+  local COMP_WORDS=(shutl "${COMP_WORDS[@]}")
+  local COMP_CWORD=$((COMP_CWORD + 1))
+  _clap_complete_shutl shutl "$2" "$3"
+}
+"#;
+
+const ZSH_FUNCTION_COMPLETE: &str = r#"_shutl_function_complete() {
+  (( $+functions[_clap_dynamic_completer_shutl] )) || return
+  words=(shutl "${words[@]}")
+  (( CURRENT++ ))
+  _clap_dynamic_completer_shutl
+}
+"#;
+
+/// Checks that `name` can be defined as a function in every supported shell
+pub fn is_function_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('-')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// Renders shell functions named after top-level commands that call `shutl <name>`, with
+/// completion forwarded to shutl's own (bash and zsh need shutl's completion loaded).
+/// Names that are not valid function names are skipped.
+pub fn function_definitions(shell: &str, names: &[String]) -> String {
+    let names = names.iter().filter(|name| is_function_name(name));
+    let mut out = String::new();
+    match shell {
+        "fish" => {
+            for name in names {
+                out.push_str(&format!(
+                    "function {name} --wraps 'shutl {name}'\n    command shutl {name} $argv\nend\n"
+                ));
+            }
+        }
+        "zsh" => {
+            out.push_str(ZSH_FUNCTION_COMPLETE);
+            for name in names {
+                out.push_str(&format!(
+                    "{name}() {{ command shutl {name} \"$@\"; }}\n\
+                     (( $+functions[compdef] )) && compdef _shutl_function_complete {name}\n"
+                ));
+            }
+        }
+        _ => {
+            out.push_str(BASH_FUNCTION_COMPLETE);
+            for name in names {
+                out.push_str(&format!(
+                    "{name}() {{ command shutl {name} \"$@\"; }}\n\
+                     complete -o nospace -o bashdefault -F _shutl_function_complete {name}\n"
+                ));
+            }
+        }
+    }
+    out
+}
+
+/// Finds the nearest `.shutl` directory in `dir` or its parents, skipping `ignored`
+/// directories such as the scripts directory and `~/.shutl`, which are not projects
+pub fn find_project_root(dir: &Path, ignored: &[PathBuf]) -> Option<PathBuf> {
+    dir.ancestors()
+        .map(|ancestor| ancestor.join(SCRIPTS_DIR_NAME))
+        .find(|candidate| candidate.is_dir() && !ignored.contains(candidate))
+}
+
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// Renders the statements that set (or clear) the project root for `shell`. Returns an
+/// empty string if nothing changes.
+pub fn export_statements(shell: &str, current: Option<&str>, root: Option<&Path>) -> String {
+    let root = root.map(|r| r.to_string_lossy().to_string());
+    if current == root.as_deref() {
+        return String::new();
+    }
+    match (shell, root) {
+        ("fish", Some(root)) => format!("set -gx {} {}\n", PROJECT_ROOT_VAR, quote(&root)),
+        ("fish", None) => format!("set -e {}\n", PROJECT_ROOT_VAR),
+        (_, Some(root)) => format!("export {}={}\n", PROJECT_ROOT_VAR, quote(&root)),
+        (_, None) => format!("unset {}\n", PROJECT_ROOT_VAR),
+    }
+}
+
+/// Gets the active project root, if the hook set one, it still exists and it is still
+/// allowed as it is now. Scripts pulled into the project since stay off until allowed.
+pub fn active_project_root() -> Option<PathBuf> {
+    let root = PathBuf::from(std::env::var_os(PROJECT_ROOT_VAR)?);
+    if !root.is_dir() {
+        return None;
+    }
+    if !is_trusted(&root) {
+        log::warn!(
+            "Ignoring project root {}: not allowed with `shutl hook allow` as it is",
+            root.display()
+        );
+        return None;
+    }
+    Some(root)
+}
+
+/// Checks that `root` was allowed with `shutl hook allow` and has not changed since
+#[cfg(feature = "remote")]
+pub fn is_trusted(root: &Path) -> bool {
+    crate::trust::allowed_file().is_some_and(|file| crate::trust::is_allowed(&file, root))
+}
+
+/// Without the allow list (the `remote` feature), no project is trusted
+#[cfg(not(feature = "remote"))]
+pub fn is_trusted(_root: &Path) -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_find_project_root() {
+        let dir = tempdir().unwrap();
+        let home_scripts = dir.path().join(SCRIPTS_DIR_NAME);
+        let project = dir.path().join("code").join("app");
+        let nested = project.join("src").join("bin");
+        std::fs::create_dir_all(&home_scripts).unwrap();
+        std::fs::create_dir_all(project.join(SCRIPTS_DIR_NAME)).unwrap();
+        std::fs::create_dir_all(&nested).unwrap();
+
+        let ignored = [home_scripts];
+        assert_eq!(
+            find_project_root(&nested, &ignored),
+            Some(project.join(SCRIPTS_DIR_NAME))
+        );
+        assert_eq!(find_project_root(&dir.path().join("code"), &ignored), None);
+    }
+
+    #[test]
+    fn test_function_definitions() {
+        let names = vec!["deploy".to_string(), "it's".to_string()];
+        let bash = function_definitions("bash", &names);
+        assert!(bash.ends_with(
+            "deploy() { command shutl deploy \"$@\"; }\n\
+             complete -o nospace -o bashdefault -F _shutl_function_complete deploy\n"
+        ));
+        assert!(!bash.contains("it's"));
+        assert!(
+            function_definitions("zsh", &names)
+                .contains("compdef _shutl_function_complete deploy\n")
+        );
+        assert_eq!(
+            function_definitions("fish", &names),
+            "function deploy --wraps 'shutl deploy'\n    command shutl deploy $argv\nend\n"
+        );
+    }
+
+    #[test]
+    fn test_export_statements() {
+        let root = Path::new("/work/it's/.shutl");
+        assert_eq!(
+            export_statements("zsh", None, Some(root)),
+            "export SHUTL_PROJECT_ROOT='/work/it'\\''s/.shutl'\n"
+        );
+        assert_eq!(
+            export_statements("fish", None, Some(root)),
+            "set -gx SHUTL_PROJECT_ROOT '/work/it'\\''s/.shutl'\n"
+        );
+        assert_eq!(
+            export_statements("bash", Some("/old"), None),
+            "unset SHUTL_PROJECT_ROOT\n"
+        );
+        assert_eq!(
+            export_statements("fish", Some("/x/.shutl"), Some(Path::new("/x/.shutl"))),
+            ""
+        );
+        assert_eq!(export_statements("bash", None, None), "");
+    }
+
+    #[test]
+    fn test_hook_scripts() {
+        for shell in SHELLS {
+            assert!(hook_script(shell).unwrap().contains("hook --export"));
+            assert!(
+                not_found_handler(shell)
+                    .unwrap()
+                    .contains("command shutl command-not-found -- ")
+            );
+        }
+        assert!(hook_script("tcsh").is_none());
+        assert!(not_found_handler("tcsh").is_none());
+    }
+}
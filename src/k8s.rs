@@ -0,0 +1,291 @@
+//! Running a script inside a Kubernetes pod (`#@k8s: deployment/tools -n ops`), for
+//! scripts that need to run in the cluster. With the `k8s` feature, `kubectl exec` runs
+//! a small loader in the pod. shutl writes the script and the variables it sets to the
+//! loader's stdin, never to the command line, where other users and audit logs could see
+//! them; then the script runs with the rest of stdin, and stdout and stderr are streamed
+//! through kubectl. Without the feature, such scripts are refused rather than run locally.
+
+#[cfg(feature = "k8s")]
+use std::ffi::OsStr;
+#[cfg(feature = "k8s")]
+use std::path::Path;
+#[cfg(feature = "k8s")]
+use std::process::Command;
+use std::str::FromStr;
+
+/// Where a script runs, as `kubectl exec` selects it
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Pod {
+    /// A pod name or a resource with pods, e.g. `deployment/tools`
+    pub target: String,
+    /// `-n`/`--namespace`; kubectl's current namespace if not given
+    pub namespace: Option<String>,
+    /// `-c`/`--container`; the pod's default container if not given
+    pub container: Option<String>,
+    /// `--context`; kubectl's current context if not given
+    pub context: Option<String>,
+}
+
+/// Stands for the script file in the command the loader runs
+#[cfg(feature = "k8s")]
+const SCRIPT_PLACEHOLDER: &str = "{script}";
+
+/// Reads the script and a file of `export` lines from stdin, each after a line with its
+/// length in bytes, then runs its arguments with the placeholder replaced by the script.
+/// `dd bs=1` reads no further than the given length, so the rest of stdin is the script's.
+#[cfg(feature = "k8s")]
+const LOADER: &str = r#"f=$(mktemp) && e=$(mktemp) || exit 1
+trap 'rm -f "$f" "$e"' EXIT
+read -r n && dd bs=1 count="$n" of="$f" 2>/dev/null && chmod +x "$f" || exit 1
+read -r n && dd bs=1 count="$n" of="$e" 2>/dev/null || exit 1
+. "$e"
+rm -f "$e"
+for a; do shift; [ "$a" = '{script}' ] && a=$f; set -- "$@" "$a"; done
+"$@""#;
+
+impl FromStr for Pod {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut pod = Pod::default();
+        let mut words = s.split_whitespace();
+        while let Some(word) = words.next() {
+            let (option, inline) = match word.split_once('=') {
+                Some((option, value)) if option.starts_with("--") => (option, Some(value)),
+                _ => (word, None),
+            };
+            let slot = match option {
+                "-n" | "--namespace" => &mut pod.namespace,
+                "-c" | "--container" => &mut pod.container,
+                "--context" => &mut pod.context,
+                _ if word.starts_with('-') => {
+                    return Err(format!(
+                        "unknown option '{}', expected -n, -c or --context",
+                        word
+                    ));
+                }
+                _ if pod.target.is_empty() => {
+                    pod.target = word.to_string();
+                    continue;
+                }
+                _ => {
+                    return Err(format!(
+                        "more than one pod: '{}' and '{}'",
+                        pod.target, word
+                    ));
+                }
+            };
+            let value = match inline {
+                Some(value) => value.to_string(),
+                None => words
+                    .next()
+                    .ok_or_else(|| format!("{} needs a value", option))?
+                    .to_string(),
+            };
+            *slot = Some(value);
+        }
+        if pod.target.is_empty() {
+            return Err("no pod, expected e.g. 'deployment/tools -n ops'".to_string());
+        }
+        Ok(pod)
+    }
+}
+
+impl Pod {
+    /// Describes the pod for `explain`, e.g. `deployment/tools in namespace ops`
+    pub fn describe(&self) -> String {
+        let mut out = self.target.clone();
+        if let Some(container) = &self.container {
+            out.push_str(&format!(", container {}", container));
+        }
+        if let Some(namespace) = &self.namespace {
+            out.push_str(&format!(" in namespace {}", namespace));
+        }
+        if let Some(context) = &self.context {
+            out.push_str(&format!(" (context {})", context));
+        }
+        out
+    }
+
+    /// Gets the `kubectl` arguments up to the command run in the pod
+    #[cfg(feature = "k8s")]
+    pub fn exec_args(&self) -> Vec<String> {
+        let mut args = vec!["exec".to_string(), "-i".to_string()];
+        for (option, value) in [
+            ("--context", &self.context),
+            ("-n", &self.namespace),
+            ("-c", &self.container),
+        ] {
+            if let Some(value) = value {
+                args.extend([option.to_string(), value.clone()]);
+            }
+        }
+        args.extend([self.target.clone(), "--".to_string()]);
+        args
+    }
+
+    /// Wraps `command`, which runs `script_path` locally, so it runs in the pod, and gets
+    /// what has to be written to its stdin first: the script and the variables set on
+    /// `command`. The variables are also set for kubectl so they show up in `explain`;
+    /// inherited ones stay local.
+    #[cfg(feature = "k8s")]
+    pub fn wrap(
+        &self,
+        command: &Command,
+        script_path: &Path,
+    ) -> std::io::Result<(Command, Vec<u8>)> {
+        let script = std::fs::read(script_path)?;
+        let mut exports = String::new();
+        let mut wrapped = Command::new("kubectl");
+        wrapped
+            .args(self.exec_args())
+            .args(["sh", "-c", LOADER, "shutl"]);
+        for (key, value) in command.get_envs() {
+            match value {
+                Some(value) => {
+                    wrapped.env(key, value);
+                    if let Some(line) = export_line(key, value) {
+                        exports.push_str(&line);
+                    }
+                }
+                None => {
+                    wrapped.env_remove(key);
+                }
+            }
+        }
+        for arg in std::iter::once(command.get_program()).chain(command.get_args()) {
+            if arg == script_path {
+                wrapped.arg(SCRIPT_PLACEHOLDER);
+            } else {
+                wrapped.arg(arg);
+            }
+        }
+        let mut input = format!("{}\n", script.len()).into_bytes();
+        input.extend_from_slice(&script);
+        input.extend_from_slice(format!("{}\n{}", exports.len(), exports).as_bytes());
+        Ok((wrapped, input))
+    }
+}
+
+/// Gets the `export` line that sets `key` in the pod, with the value in single quotes.
+/// Names sh cannot export are left out.
+#[cfg(feature = "k8s")]
+fn export_line(key: &OsStr, value: &OsStr) -> Option<String> {
+    let key = key.to_str()?;
+    let valid = key.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid {
+        log::warn!("Not passing {} to the pod: not a shell variable name", key);
+        return None;
+    }
+    let value = value.to_string_lossy().replace('\'', "'\\''");
+    Some(format!("export {}='{}'\n", key, value))
+}
+
+/// Explains why a script for a pod is not run by a shutl built without the `k8s` feature
+pub fn unsupported_message(command: &str, pod: &Pod) -> String {
+    format!(
+        "{} runs in the pod {} (#@k8s:), but this shutl is built without Kubernetes support (the `k8s` feature)",
+        command,
+        pod.describe()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_pod() {
+        let pod: Pod = "deployment/tools -n ops".parse().unwrap();
+        assert_eq!(pod.target, "deployment/tools");
+        assert_eq!(pod.namespace.as_deref(), Some("ops"));
+        assert_eq!(pod.describe(), "deployment/tools in namespace ops");
+
+        let pod: Pod = "--context=prod tools-0 --container shell".parse().unwrap();
+        assert_eq!(pod.target, "tools-0");
+        assert_eq!(pod.context.as_deref(), Some("prod"));
+        assert_eq!(pod.container.as_deref(), Some("shell"));
+
+        assert!("".parse::<Pod>().is_err());
+        assert!("deployment/tools -n".parse::<Pod>().is_err());
+        assert!("deployment/tools --all-namespaces".parse::<Pod>().is_err());
+        assert!("tools-0 tools-1".parse::<Pod>().is_err());
+    }
+
+    #[cfg(feature = "k8s")]
+    #[test]
+    fn test_wrap() {
+        let dir = tempfile::tempdir().unwrap();
+        let script_path = dir.path().join("report.py");
+        std::fs::write(&script_path, "print('hi')\n").unwrap();
+        let mut command = Command::new("python3");
+        command
+            .arg(&script_path)
+            .env("SHUTL_COMMAND_PATH", "report")
+            .env("SHUTL_TOKEN", "it's s3cret");
+        command.env_remove("HOME");
+
+        let pod: Pod = "deployment/tools -n ops".parse().unwrap();
+        let (wrapped, input) = pod.wrap(&command, &script_path).unwrap();
+        assert_eq!(wrapped.get_program(), "kubectl");
+        let args: Vec<_> = wrapped.get_args().collect();
+        assert_eq!(
+            args[..8],
+            [
+                "exec",
+                "-i",
+                "-n",
+                "ops",
+                "deployment/tools",
+                "--",
+                "sh",
+                "-c"
+            ]
+        );
+        assert_eq!(args[9..], ["shutl", "python3", "{script}"]);
+        // Secrets and the script only go over stdin
+        assert!(
+            !args
+                .iter()
+                .any(|arg| arg.to_string_lossy().contains("s3cret"))
+        );
+        let exports = "export SHUTL_COMMAND_PATH='report'\nexport SHUTL_TOKEN='it'\\''s s3cret'\n";
+        assert_eq!(
+            String::from_utf8(input).unwrap(),
+            format!("12\nprint('hi')\n{}\n{}", exports.len(), exports)
+        );
+    }
+
+    #[cfg(feature = "k8s")]
+    #[test]
+    fn test_loader() {
+        let dir = tempfile::tempdir().unwrap();
+        let script_path = dir.path().join("token.sh");
+        std::fs::write(
+            &script_path,
+            "#!/bin/sh\nprintf '%s|' \"$SHUTL_TOKEN\"\ncat\n",
+        )
+        .unwrap();
+        let mut command = Command::new(&script_path);
+        command.env("SHUTL_TOKEN", "it's\ns3cret");
+
+        let pod: Pod = "tools-0".parse().unwrap();
+        let (wrapped, prefix) = pod.wrap(&command, &script_path).unwrap();
+        // Run what kubectl would run in the pod, here
+        let args: Vec<_> = wrapped.get_args().skip(4).collect();
+        let mut local = Command::new(args[0]);
+        local.args(&args[1..]);
+        let input = crate::capture::Input {
+            prefix: [prefix, b"rest of stdin".to_vec()].concat(),
+            forward: false,
+        };
+        let (status, stdout) =
+            crate::capture::run_captured(&mut local, None, false, true, Some(&input)).unwrap();
+        assert!(status.success());
+        assert_eq!(
+            String::from_utf8(stdout).unwrap(),
+            "it's\ns3cret|rest of stdin"
+        );
+    }
+}
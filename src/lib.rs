@@ -1,44 +1,141 @@
-use dirs::home_dir;
+use config::ScriptsLocation;
+use paths::home_dir;
 use std::path::PathBuf;
 
+pub mod argtype;
+pub mod audit;
+pub mod build;
+#[cfg(feature = "binary")]
 pub mod builtin;
+pub mod capture;
+pub mod clipboard;
 pub mod command;
+#[cfg(feature = "completion")]
+pub mod completion;
+pub mod config;
+pub mod docs;
+pub mod env_filter;
+pub mod errors;
+pub mod git;
+pub mod history;
+pub mod hook;
+#[cfg(feature = "remote")]
+pub mod imports;
+pub mod install;
+pub mod k8s;
+pub mod limits;
+pub mod list;
 pub mod metadata;
+pub mod observer;
+pub mod pager;
+pub mod paths;
+pub mod persist;
+pub mod preset;
+pub mod prune;
+#[cfg(feature = "remote")]
+pub mod remote;
+pub mod review;
+pub mod roots;
+pub mod sandbox;
+pub mod schema;
 pub mod script;
+pub mod signing;
+pub mod sort;
+pub mod source;
+pub mod summary;
+#[cfg(feature = "test-support")]
+pub mod testing;
+pub mod tmux;
+#[cfg(feature = "remote")]
+pub mod trust;
+#[cfg(feature = "binary")]
+pub mod upgrade;
 pub mod validation;
+pub mod virtual_commands;
 
 pub use command::build_cli_command;
+pub use errors::ShutlError;
 pub use metadata::CommandMetadata;
-pub use script::{execute_script, find_script_file};
+pub use script::{execute_script, execute_script_as, find_script_file};
 
 /// The directory name where scripts are stored
 const SCRIPTS_DIR_NAME: &str = ".shutl";
 
-/// Gets the path to the scripts directory
+/// Gets the path to the scripts directory. Without a home directory (and no configured
+/// one), it falls back to `.shutl` in the current directory; [`scripts_dir`] reports it.
 pub fn get_scripts_dir() -> PathBuf {
-    // check if SHUTL_DIR is set
-    if let Ok(shutl_dir) = std::env::var("SHUTL_DIR") {
+    scripts_dir().unwrap_or_else(|e| {
+        log::warn!("{}", e);
+        PathBuf::from(SCRIPTS_DIR_NAME)
+    })
+}
+
+/// Gets the path to the scripts directory, creating it if needed
+pub fn scripts_dir() -> Result<PathBuf, ShutlError> {
+    // check if SHUTL_DIR is set, then the config (or active profile)
+    let configured = std::env::var("SHUTL_DIR")
+        .ok()
+        .or_else(|| config::get().scripts_dir.clone());
+    if let Some(shutl_dir) = configured {
         // Expand ~ and env vars in the path
-        if let Ok(expanded) = shellexpand::full(&shutl_dir) {
-            return PathBuf::from(expanded.to_string());
+        if let Ok(expanded) = paths::expand(&shutl_dir) {
+            return Ok(PathBuf::from(expanded.to_string()));
         }
-        return PathBuf::from(shutl_dir);
+        return Ok(PathBuf::from(shutl_dir));
     }
-    let mut path = home_dir().expect("Could not determine home directory");
-    path.push(SCRIPTS_DIR_NAME);
+    let path = pick_scripts_dir(
+        config::get().scripts_location,
+        legacy_scripts_dir(),
+        xdg_scripts_dir(),
+    )
+    .ok_or(ShutlError::NoHomeDir)?;
 
-    // Create the directory if it doesn't exist
-    if !path.exists() {
-        std::fs::create_dir_all(&path).expect("Failed to create scripts directory");
+    // Create the directory if it doesn't exist. If that fails, `roots::root_problem`
+    // reports it and the other roots keep working.
+    if !path.exists()
+        && let Err(e) = std::fs::create_dir_all(&path)
+    {
+        log::debug!("Cannot create {}: {}", path.display(), e);
     }
 
-    path
+    Ok(path)
+}
+
+/// Gets the scripts directory of earlier versions, `~/.shutl`
+pub fn legacy_scripts_dir() -> Option<PathBuf> {
+    home_dir().map(|home| home.join(SCRIPTS_DIR_NAME))
 }
 
-/// Resolves the editor to use, checking the provided override, then $EDITOR, then defaulting to vim
+/// Gets the scripts directory below the data directory, e.g. `~/.local/share/shutl/scripts`
+pub fn xdg_scripts_dir() -> Option<PathBuf> {
+    paths::data_local_dir().map(|dir| dir.join("shutl").join("scripts"))
+}
+
+/// Picks the default scripts directory. With [`ScriptsLocation::Auto`] an existing
+/// `legacy` directory is kept until the `xdg` one exists.
+fn pick_scripts_dir(
+    location: ScriptsLocation,
+    legacy: Option<PathBuf>,
+    xdg: Option<PathBuf>,
+) -> Option<PathBuf> {
+    match location {
+        ScriptsLocation::Home => legacy,
+        ScriptsLocation::Xdg => xdg.or(legacy),
+        ScriptsLocation::Auto => match xdg {
+            Some(xdg) if xdg.exists() || !legacy.as_ref().is_some_and(|dir| dir.exists()) => {
+                Some(xdg)
+            }
+            xdg => legacy.or(xdg),
+        },
+    }
+}
+
+/// Resolves the editor to use, checking the provided override, then the config (or active
+/// profile), then $EDITOR, then defaulting to vim
 pub fn resolve_editor(editor_override: Option<&String>) -> String {
     editor_override
         .cloned()
+        .or_else(|| config::get().editor.clone())
         .or_else(|| std::env::var("EDITOR").ok())
         .unwrap_or_else(|| "vim".to_string())
 }
@@ -47,6 +144,22 @@ pub fn resolve_editor(editor_override: Option<&String>) -> String {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_pick_scripts_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let legacy = dir.path().join(".shutl");
+        let xdg = dir.path().join("share/shutl/scripts");
+        let pick = |location| pick_scripts_dir(location, Some(legacy.clone()), Some(xdg.clone()));
+
+        assert_eq!(pick(ScriptsLocation::Auto), Some(xdg.clone()));
+        std::fs::create_dir(&legacy).unwrap();
+        assert_eq!(pick(ScriptsLocation::Auto), Some(legacy.clone()));
+        assert_eq!(pick(ScriptsLocation::Xdg), Some(xdg.clone()));
+        std::fs::create_dir_all(&xdg).unwrap();
+        assert_eq!(pick(ScriptsLocation::Auto), Some(xdg.clone()));
+        assert_eq!(pick(ScriptsLocation::Home), Some(legacy.clone()));
+    }
+
     #[test]
     fn test_resolve_editor_with_override() {
         let editor = String::from("nano");
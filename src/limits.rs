@@ -0,0 +1,246 @@
+//! Resource limits of a script run (`#@max-memory: 1G`, `#@max-cpu-seconds: 300`) and its
+//! scheduling priority (`#@priority: low`). They are set in the child process right before
+//! it execs the script, so they also cover everything the script starts.
+
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+
+/// Limits for a script run; `None` leaves the inherited limit alone
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResourceLimits {
+    /// Address space in bytes (`RLIMIT_AS`); allocations beyond it fail
+    pub max_memory: Option<u64>,
+    /// CPU time in seconds (`RLIMIT_CPU`); the script is killed when it is used up
+    pub max_cpu_seconds: Option<u64>,
+    /// CPU and IO scheduling priority
+    pub priority: Option<Priority>,
+}
+
+/// Scheduling priority of a script (`#@priority:`, `--shutl-priority`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    /// Inherited from shutl
+    Normal,
+    /// Nice 10 and the lowest best-effort IO priority
+    Low,
+    /// Nice 19 and idle IO: only runs when nothing else wants the CPU or disk
+    Idle,
+}
+
+impl Priority {
+    /// Names accepted by `#@priority:` and `--shutl-priority`
+    pub const NAMES: [&str; 3] = ["normal", "low", "idle"];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Priority::Normal => "normal",
+            Priority::Low => "low",
+            Priority::Idle => "idle",
+        }
+    }
+}
+
+impl std::str::FromStr for Priority {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "normal" => Ok(Priority::Normal),
+            "low" => Ok(Priority::Low),
+            "idle" => Ok(Priority::Idle),
+            other => Err(format!(
+                "invalid priority '{}', expected {}",
+                other,
+                Priority::NAMES.join(", ")
+            )),
+        }
+    }
+}
+
+impl ResourceLimits {
+    pub fn is_empty(&self) -> bool {
+        self.max_memory.is_none()
+            && self.max_cpu_seconds.is_none()
+            && matches!(self.priority, None | Some(Priority::Normal))
+    }
+
+    /// Makes `command` set the limits in the child before it execs
+    pub fn apply(&self, command: &mut Command) {
+        if self.is_empty() {
+            return;
+        }
+        let limits = *self;
+        // SAFETY: the closure only calls getrlimit/setrlimit, which are async-signal-safe,
+        // and does not allocate
+        unsafe {
+            command.pre_exec(move || {
+                if let Some(bytes) = limits.max_memory {
+                    lower_limit(libc::RLIMIT_AS as _, bytes)?;
+                }
+                if let Some(seconds) = limits.max_cpu_seconds {
+                    lower_limit(libc::RLIMIT_CPU as _, seconds)?;
+                }
+                match limits.priority {
+                    Some(Priority::Low) => lower_priority(10, IOPRIO_CLASS_BE, 7),
+                    Some(Priority::Idle) => lower_priority(19, IOPRIO_CLASS_IDLE, 0),
+                    _ => Ok(()),
+                }
+            });
+        }
+    }
+
+    /// Describes the limits for `explain`, e.g. `memory 1G, cpu 300s`
+    pub fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(bytes) = self.max_memory {
+            parts.push(format!("memory {}", format_memory(bytes)));
+        }
+        if let Some(seconds) = self.max_cpu_seconds {
+            parts.push(format!("cpu {}s", seconds));
+        }
+        if let Some(priority) = self.priority {
+            parts.push(format!("priority {}", priority.name()));
+        }
+        parts.join(", ")
+    }
+}
+
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+type Resource = libc::__rlimit_resource_t;
+#[cfg(not(all(target_os = "linux", target_env = "gnu")))]
+type Resource = libc::c_int;
+
+/// Sets the soft and hard limit of `resource` to `value`, or leaves them if the current
+/// hard limit is already lower (raising it would fail)
+fn lower_limit(resource: Resource, value: u64) -> std::io::Result<()> {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    if unsafe { libc::getrlimit(resource, &mut limit) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let value = (value as libc::rlim_t).min(limit.rlim_max);
+    limit.rlim_cur = value;
+    limit.rlim_max = value;
+    if unsafe { libc::setrlimit(resource, &limit) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+const IOPRIO_CLASS_BE: i32 = 2;
+const IOPRIO_CLASS_IDLE: i32 = 3;
+
+/// Raises the nice value to `nice` (never lowers it) and, on Linux, sets the IO
+/// scheduling class and level. An IO scheduler that ignores priorities is not an error.
+fn lower_priority(nice: i32, io_class: i32, io_level: i32) -> std::io::Result<()> {
+    // SAFETY: getpriority/setpriority are async-signal-safe. getpriority can return -1 as
+    // a valid value, which only makes the comparison below more conservative.
+    unsafe {
+        let current = libc::getpriority(libc::PRIO_PROCESS as _, 0);
+        if current < nice && libc::setpriority(libc::PRIO_PROCESS as _, 0, nice) != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    #[cfg(target_os = "linux")]
+    {
+        const IOPRIO_WHO_PROCESS: libc::c_long = 1;
+        let ioprio = ((io_class << 13) | io_level) as libc::c_long;
+        // SAFETY: a plain syscall without pointers
+        unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, ioprio) };
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = (io_class, io_level);
+    Ok(())
+}
+
+/// Parses a memory size like `512M`, `1G` or `1048576` (bytes). Suffixes are binary
+/// (`K` = 1024) and may be followed by `B` or `iB`.
+pub fn parse_memory(s: &str) -> Result<u64, String> {
+    let text = s.trim();
+    let upper = text.to_ascii_uppercase();
+    let number = upper.trim_end_matches("IB").trim_end_matches('B');
+    let (digits, shift) = match number.chars().last() {
+        Some('K') => (&number[..number.len() - 1], 10),
+        Some('M') => (&number[..number.len() - 1], 20),
+        Some('G') => (&number[..number.len() - 1], 30),
+        Some('T') => (&number[..number.len() - 1], 40),
+        _ => (number, 0),
+    };
+    digits
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .filter(|&n| n > 0)
+        .and_then(|n| n.checked_mul(1 << shift))
+        .ok_or_else(|| format!("invalid memory size '{}', expected e.g. 512M or 2G", text))
+}
+
+/// Parses a positive number of seconds
+pub fn parse_seconds(s: &str) -> Result<u64, String> {
+    s.trim()
+        .parse::<u64>()
+        .ok()
+        .filter(|&n| n > 0)
+        .ok_or_else(|| format!("invalid number of seconds '{}'", s.trim()))
+}
+
+/// Formats bytes with the largest binary suffix that divides them, e.g. `1G`
+fn format_memory(bytes: u64) -> String {
+    for (suffix, shift) in [("T", 40), ("G", 30), ("M", 20), ("K", 10)] {
+        if bytes.is_multiple_of(1 << shift) {
+            return format!("{}{}", bytes >> shift, suffix);
+        }
+    }
+    format!("{}B", bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_memory() {
+        assert_eq!(parse_memory("1G"), Ok(1 << 30));
+        assert_eq!(parse_memory("512m"), Ok(512 << 20));
+        assert_eq!(parse_memory("64KiB"), Ok(64 << 10));
+        assert_eq!(parse_memory("2048"), Ok(2048));
+        assert!(parse_memory("lots").is_err());
+        assert!(parse_memory("0G").is_err());
+        assert_eq!(format_memory(1 << 30), "1G");
+        assert_eq!(format_memory(1536 << 20), "1536M");
+    }
+
+    #[test]
+    fn test_cpu_limit_kills_script() {
+        let limits = ResourceLimits {
+            max_cpu_seconds: Some(1),
+            ..Default::default()
+        };
+        let mut command = Command::new("sh");
+        command.args(["-c", "while :; do :; done"]);
+        limits.apply(&mut command);
+        let status = command.status().unwrap();
+        assert!(!status.success());
+    }
+
+    #[test]
+    fn test_low_priority_raises_nice() {
+        let limits = ResourceLimits {
+            priority: Some(Priority::Low),
+            ..Default::default()
+        };
+        let mut command = Command::new("sh");
+        command.args(["-c", "ps -o nice= -p $$"]);
+        limits.apply(&mut command);
+        let output = command.output().unwrap();
+        let nice: i32 = String::from_utf8_lossy(&output.stdout)
+            .trim()
+            .parse()
+            .unwrap();
+        assert!(nice >= 10);
+        assert_eq!("idle".parse(), Ok(Priority::Idle));
+        assert!("urgent".parse::<Priority>().is_err());
+    }
+}
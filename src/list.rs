@@ -0,0 +1,488 @@
+//! How `shutl list` shows the scripts: one path per line or nested below their groups,
+//! with groups deeper than `--depth` folded into one line.
+
+use crate::roots::{Mount, collect_mount_entries};
+use std::collections::HashMap;
+use std::io::IsTerminal;
+use std::path::Path;
+
+/// An entry representing a script found during listing
+pub struct ListEntry {
+    pub path: String,
+    pub description: String,
+    /// Number of scripts of a group folded into this entry by `--depth`, 0 for a script
+    pub folded: usize,
+}
+
+/// How `shutl list` shows the scripts
+#[derive(Debug, Clone, Default)]
+pub struct ListView {
+    /// Nested below their groups instead of one path per line
+    pub tree: bool,
+    /// Most path components shown; deeper scripts are folded into their group
+    pub depth: Option<usize>,
+    /// Descriptions of groups by path, shown next to the folded groups and in the tree
+    pub groups: HashMap<String, String>,
+}
+
+/// Lists all scripts in the given directory, optionally filtered to a subdirectory.
+/// Returns a formatted string ready for display.
+pub fn list_scripts(base_dir: &Path, subdir_filter: Option<&str>, tree: bool) -> String {
+    let mount = Mount::local(base_dir.to_path_buf(), None);
+    let view = ListView {
+        tree,
+        ..Default::default()
+    };
+    match collect_mount_entries(&[mount], subdir_filter) {
+        Ok(entries) => format_entries(entries, &view),
+        Err(message) => message,
+    }
+}
+
+/// Formats listed scripts as a flat list or a tree
+pub fn format_entries(entries: Vec<ListEntry>, view: &ListView) -> String {
+    if entries.is_empty() {
+        return "No scripts found.".to_string();
+    }
+
+    let entries = match view.depth {
+        Some(depth) => fold_below(entries, depth),
+        None => entries,
+    };
+    if view.tree {
+        format_tree(&entries, &view.groups)
+    } else {
+        format_flat(&entries, &view.groups)
+    }
+}
+
+/// Replaces the scripts more than `depth` path components deep with one entry per group
+/// at that depth, where the first of its scripts was
+pub fn fold_below(entries: Vec<ListEntry>, depth: usize) -> Vec<ListEntry> {
+    let mut folded: Vec<ListEntry> = Vec::new();
+    let mut groups: HashMap<String, usize> = HashMap::new();
+    for entry in entries {
+        let components: Vec<&str> = entry.path.split('/').collect();
+        if components.len() <= depth {
+            folded.push(entry);
+            continue;
+        }
+        let group = components[..depth.max(1)].join("/");
+        match groups.get(&group) {
+            Some(&index) => folded[index].folded += 1,
+            None => {
+                groups.insert(group.clone(), folded.len());
+                folded.push(ListEntry {
+                    path: group,
+                    description: String::new(),
+                    folded: 1,
+                });
+            }
+        }
+    }
+    folded
+}
+
+/// Describes a folded group: its own description and how many scripts it holds
+fn folded_description(entry: &ListEntry, groups: &HashMap<String, String>) -> String {
+    let count = if entry.folded == 1 {
+        "1 script".to_string()
+    } else {
+        format!("{} scripts", entry.folded)
+    };
+    match groups.get(&entry.path) {
+        Some(description) => format!("{} ({})", description, count),
+        None => format!("({})", count),
+    }
+}
+
+fn format_flat(entries: &[ListEntry], groups: &HashMap<String, String>) -> String {
+    let label = |e: &ListEntry| {
+        if e.folded > 0 {
+            format!("{}/", e.path)
+        } else {
+            e.path.clone()
+        }
+    };
+    let max_path_len = entries.iter().map(|e| label(e).len()).max().unwrap_or(0);
+    entries
+        .iter()
+        .map(|e| {
+            let description = if e.folded > 0 {
+                folded_description(e, groups)
+            } else {
+                e.description.clone()
+            };
+            if description.is_empty() {
+                label(e)
+            } else {
+                format!(
+                    "{:<width$}  {}",
+                    label(e),
+                    description,
+                    width = max_path_len
+                )
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn use_color() -> bool {
+    std::io::stdout().is_terminal()
+}
+
+fn format_tree(entries: &[ListEntry], groups: &HashMap<String, String>) -> String {
+    let mut lines = Vec::new();
+    let mut printed_dirs: Vec<String> = Vec::new();
+    let color = use_color();
+    let max_name_len = entries
+        .iter()
+        .map(|e| e.path.rsplit('/').next().unwrap_or(&e.path).len())
+        .max()
+        .unwrap_or(0);
+    let dimmed = |text: &str| {
+        if color {
+            format!("\x1b[2m{}\x1b[0m", text)
+        } else {
+            text.to_string()
+        }
+    };
+    let dir_label = |name: &str, description: Option<String>| {
+        let label = if color {
+            format!("\x1b[1;34m{}/\x1b[0m", name)
+        } else {
+            format!("{}/", name)
+        };
+        match description {
+            Some(description) => format!("{}  {}", label, dimmed(&description)),
+            None => label,
+        }
+    };
+
+    for entry in entries {
+        let components: Vec<&str> = entry.path.split('/').collect();
+        let (name, dirs) = components.split_last().expect("paths are not empty");
+
+        // Print any directory headers not yet printed
+        for depth in 0..dirs.len() {
+            let ancestor: String = dirs[..=depth].join("/");
+            if !printed_dirs.contains(&ancestor) {
+                let indent = "  ".repeat(depth);
+                let description = groups.get(&ancestor).cloned();
+                lines.push(format!("{}{}", indent, dir_label(dirs[depth], description)));
+                printed_dirs.push(ancestor);
+            }
+        }
+
+        let indent = "  ".repeat(dirs.len());
+        if entry.folded > 0 {
+            let description = folded_description(entry, groups);
+            lines.push(format!("{}{}", indent, dir_label(name, Some(description))));
+            continue;
+        }
+        let styled_name = if color {
+            format!("\x1b[32m{}\x1b[0m", name)
+        } else {
+            name.to_string()
+        };
+        if entry.description.is_empty() {
+            lines.push(format!("{}{}", indent, styled_name));
+        } else {
+            // Pad based on raw name length, then apply color
+            let padding = max_name_len.saturating_sub(name.len());
+            lines.push(format!(
+                "{}{}{}  {}",
+                indent,
+                styled_name,
+                " ".repeat(padding),
+                dimmed(&entry.description)
+            ));
+        }
+    }
+
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{self, File};
+    use std::io::Write;
+    use tempfile::tempdir;
+
+    fn create_test_script(dir: &Path, name: &str, content: &str) -> std::path::PathBuf {
+        let script_path = dir.join(name);
+        let mut file = File::create(&script_path).unwrap();
+        file.write_all(content.as_bytes()).unwrap();
+        fs::set_permissions(
+            &script_path,
+            std::os::unix::fs::PermissionsExt::from_mode(0o755),
+        )
+        .unwrap();
+        script_path
+    }
+
+    #[test]
+    fn test_list_scripts_flat() {
+        let dir = tempdir().unwrap();
+        let scripts_dir = dir.path();
+
+        // Create nested structure
+        let docker_dir = scripts_dir.join("docker");
+        fs::create_dir(&docker_dir).unwrap();
+        create_test_script(
+            &docker_dir,
+            "build.sh",
+            "#!/bin/bash\n#@description: Build a Docker image",
+        );
+        create_test_script(
+            &docker_dir,
+            "push.sh",
+            "#!/bin/bash\n#@description: Push image to registry",
+        );
+
+        create_test_script(
+            scripts_dir,
+            "hello.sh",
+            "#!/bin/bash\n#@description: Say hello",
+        );
+
+        let output = list_scripts(scripts_dir, None, false);
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("docker/build"));
+        assert!(lines[0].contains("Build a Docker image"));
+        assert!(lines[1].starts_with("docker/push"));
+        assert!(lines[1].contains("Push image to registry"));
+        assert!(lines[2].starts_with("hello"));
+        assert!(lines[2].contains("Say hello"));
+    }
+
+    #[test]
+    fn test_list_scripts_tree() {
+        let dir = tempdir().unwrap();
+        let scripts_dir = dir.path();
+
+        let docker_dir = scripts_dir.join("docker");
+        fs::create_dir(&docker_dir).unwrap();
+        create_test_script(
+            &docker_dir,
+            "build.sh",
+            "#!/bin/bash\n#@description: Build a Docker image",
+        );
+        create_test_script(
+            &docker_dir,
+            "push.sh",
+            "#!/bin/bash\n#@description: Push image to registry",
+        );
+
+        let compose_dir = docker_dir.join("compose");
+        fs::create_dir(&compose_dir).unwrap();
+        create_test_script(
+            &compose_dir,
+            "up.sh",
+            "#!/bin/bash\n#@description: Start services",
+        );
+        create_test_script(
+            &compose_dir,
+            "down.sh",
+            "#!/bin/bash\n#@description: Stop services",
+        );
+
+        create_test_script(
+            scripts_dir,
+            "hello.sh",
+            "#!/bin/bash\n#@description: Say hello",
+        );
+
+        let output = list_scripts(scripts_dir, None, true);
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 7);
+        assert_eq!(lines[0], "docker/");
+        assert!(lines[1].starts_with("  build"));
+        assert_eq!(lines[2], "  compose/");
+        assert!(lines[3].starts_with("    down"));
+        assert!(lines[3].contains("Stop services"));
+        assert!(lines[4].starts_with("    up"));
+        assert!(lines[4].contains("Start services"));
+        assert!(lines[5].starts_with("  push"));
+        assert!(lines[5].contains("Push image to registry"));
+        assert!(lines[6].starts_with("hello"));
+        assert!(lines[6].contains("Say hello"));
+    }
+
+    #[test]
+    fn test_format_entries_depth() {
+        let entries = || {
+            [
+                "db/dump",
+                "deploy/aws/ec2",
+                "deploy/aws/s3",
+                "deploy/prod",
+                "hello",
+            ]
+            .iter()
+            .map(|path| ListEntry {
+                path: path.to_string(),
+                description: format!("Run {}", path),
+                folded: 0,
+            })
+            .collect::<Vec<_>>()
+        };
+        let groups = HashMap::from([
+            ("deploy".to_string(), "Deployments".to_string()),
+            ("deploy/aws".to_string(), "AWS".to_string()),
+        ]);
+
+        let flat = ListView {
+            depth: Some(1),
+            groups: groups.clone(),
+            ..Default::default()
+        };
+        assert_eq!(
+            format_entries(entries(), &flat),
+            "db/      (1 script)\ndeploy/  Deployments (3 scripts)\nhello    Run hello"
+        );
+
+        let tree = ListView {
+            tree: true,
+            depth: Some(2),
+            groups,
+        };
+        let output = format_entries(entries(), &tree);
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(
+            lines,
+            vec![
+                "db/",
+                "  dump   Run db/dump",
+                "deploy/  Deployments",
+                "  aws/  AWS (2 scripts)",
+                "  prod   Run deploy/prod",
+                "hello  Run hello",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_list_scripts_filtered() {
+        let dir = tempdir().unwrap();
+        let scripts_dir = dir.path();
+
+        let docker_dir = scripts_dir.join("docker");
+        fs::create_dir(&docker_dir).unwrap();
+        create_test_script(
+            &docker_dir,
+            "build.sh",
+            "#!/bin/bash\n#@description: Build a Docker image",
+        );
+
+        let k8s_dir = scripts_dir.join("k8s");
+        fs::create_dir(&k8s_dir).unwrap();
+        create_test_script(
+            &k8s_dir,
+            "deploy.sh",
+            "#!/bin/bash\n#@description: Deploy to Kubernetes",
+        );
+
+        create_test_script(
+            scripts_dir,
+            "hello.sh",
+            "#!/bin/bash\n#@description: Say hello",
+        );
+
+        let output = list_scripts(scripts_dir, Some("docker"), false);
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].starts_with("docker/build"));
+        assert!(lines[0].contains("Build a Docker image"));
+        // k8s and hello should NOT appear
+        assert!(!output.contains("k8s"));
+        assert!(!output.contains("hello"));
+    }
+
+    #[test]
+    fn test_list_scripts_empty() {
+        let dir = tempdir().unwrap();
+        let scripts_dir = dir.path();
+
+        let output = list_scripts(scripts_dir, None, false);
+        assert_eq!(output, "No scripts found.");
+    }
+
+    #[test]
+    fn test_list_scripts_nonexistent_subdir() {
+        let dir = tempdir().unwrap();
+        let scripts_dir = dir.path();
+
+        let output = list_scripts(scripts_dir, Some("nonexistent"), false);
+        assert_eq!(output, "Directory not found: nonexistent");
+    }
+
+    #[test]
+    fn test_list_scripts_trailing_slash() {
+        let dir = tempdir().unwrap();
+        let scripts_dir = dir.path();
+
+        let docker_dir = scripts_dir.join("docker");
+        fs::create_dir(&docker_dir).unwrap();
+        create_test_script(
+            &docker_dir,
+            "build.sh",
+            "#!/bin/bash\n#@description: Build image",
+        );
+
+        let output = list_scripts(scripts_dir, Some("docker/"), false);
+        assert!(output.contains("docker/build"));
+        assert!(!output.contains("docker//build"));
+    }
+
+    #[test]
+    fn test_list_scripts_rejects_traversal() {
+        let dir = tempdir().unwrap();
+        let scripts_dir = dir.path().join(".shutl");
+        fs::create_dir(&scripts_dir).unwrap();
+        create_test_script(dir.path(), "outside.sh", "#!/bin/bash");
+
+        let output = list_scripts(&scripts_dir, Some(".."), false);
+        assert_eq!(output, "Invalid directory: ..");
+    }
+
+    #[test]
+    fn test_list_scripts_follows_symlinked_dirs_without_looping() {
+        let dir = tempdir().unwrap();
+        let scripts_dir = dir.path().join(".shutl");
+        let shared = dir.path().join("dotfiles");
+        fs::create_dir_all(&scripts_dir).unwrap();
+        fs::create_dir_all(&shared).unwrap();
+        create_test_script(&shared, "sync.sh", "#!/bin/bash\n#@description: Sync");
+
+        std::os::unix::fs::symlink(&shared, scripts_dir.join("shared")).unwrap();
+        // A cycle back to the root must not recurse forever
+        std::os::unix::fs::symlink(&scripts_dir, shared.join("loop")).unwrap();
+
+        let output = list_scripts(&scripts_dir, None, false);
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 1, "unexpected output: {}", output);
+        assert!(lines[0].starts_with("shared/sync"));
+    }
+
+    #[test]
+    fn test_list_scripts_dedupes_symlinked_scripts() {
+        let dir = tempdir().unwrap();
+        let scripts_dir = dir.path();
+        create_test_script(
+            scripts_dir,
+            "deploy.sh",
+            "#!/bin/bash\n#@description: Deploy",
+        );
+        let sub = scripts_dir.join("sub");
+        fs::create_dir(&sub).unwrap();
+        std::os::unix::fs::symlink(scripts_dir.join("deploy.sh"), sub.join("deploy.sh")).unwrap();
+
+        let output = list_scripts(scripts_dir, None, false);
+        assert_eq!(output.lines().count(), 1, "unexpected output: {}", output);
+        assert!(output.starts_with("deploy"));
+    }
+}
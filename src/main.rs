@@ -1,24 +1,134 @@
 use clap::ArgMatches;
+use shutl::audit;
 use shutl::builtin;
-use shutl::{build_cli_command, execute_script, find_script_file, get_scripts_dir};
+use shutl::command::{build_group_command, is_builtin};
+use shutl::config;
+use shutl::errors;
+use shutl::history;
+use shutl::observer;
+use shutl::preset;
+use shutl::roots;
+use shutl::script::{self, is_safe_component};
+use shutl::{build_cli_command, execute_script_as};
 
 fn main() {
     env_logger::builder().init();
 
-    log::debug!("args: {:?}", std::env::args().collect::<Vec<_>>());
+    let args: Vec<String> = std::env::args().collect();
+    log::debug!("args: {:?}", script::redact_secret_flags(&args));
 
+    // The profile decides which scripts exist, so it is applied before the CLI is built.
+    // Exporting it also selects it for nested shutl calls.
+    if let Some(profile) = config::profile_from_args(&args) {
+        // SAFETY: nothing else runs yet that could read the environment concurrently
+        unsafe { std::env::set_var("SHUTL_PROFILE", profile) };
+    }
+
+    // Like the profile, a root given for this invocation is exported to nested calls
+    if let Some(root) = config::root_from_args(&args) {
+        let root = std::path::absolute(&root).unwrap_or_else(|_| root.into());
+        if !root.is_dir() && std::env::var_os("COMPLETE").is_none() {
+            eprintln!("Scripts directory not found: {}", root.display());
+            std::process::exit(1);
+        }
+        // SAFETY: nothing else runs yet that could read the environment concurrently
+        unsafe { std::env::set_var("SHUTL_DIR", root) };
+    }
+
+    if let Some(format) = config::error_format_from_args(&args) {
+        // SAFETY: nothing else runs yet that could read the environment concurrently
+        unsafe { std::env::set_var(errors::FORMAT_VAR, format) };
+    }
+
+    // Commands of other roles are hidden and refused unless `--all` is given
+    if config::all_from_args(&args) {
+        // SAFETY: nothing else runs yet that could read the environment concurrently
+        unsafe { std::env::set_var(config::ALL_ROLES_VAR, "1") };
+    }
+
+    // Imported scripts changed since their import are refused with `--frozen`
+    if config::frozen_from_args(&args) {
+        // SAFETY: nothing else runs yet that could read the environment concurrently
+        unsafe { std::env::set_var(config::FROZEN_VAR, "1") };
+    }
+
+    // Every command needs the scripts directory; without one, say why once instead of
+    // falling back to `.shutl` here. Completion stays quiet and completes what it finds.
+    if std::env::var_os("COMPLETE").is_none()
+        && let Err(e) = shutl::scripts_dir()
+    {
+        e.exit();
+    }
+
+    // Long output is printed directly with `--no-pager`
+    if config::no_pager_from_args(&args) {
+        // SAFETY: nothing else runs yet that could read the environment concurrently
+        unsafe { std::env::set_var(config::NO_PAGER_VAR, "1") };
+    }
+
+    shutl::command::detect_completion_shell();
     clap_complete::CompleteEnv::with_factory(build_cli_command).complete();
 
+    if let Some(profile) = config::active_profile()
+        && !config::get().profiles.contains_key(&profile)
+    {
+        eprintln!("Unknown profile: {}", profile);
+        std::process::exit(1);
+    }
+
+    // A root that cannot be listed is reported rather than silently missing from the tree;
+    // `shutl doctor` reports it itself
+    if config::command_from_args(&args) != Some("doctor") {
+        for problem in roots::mounts().iter().filter_map(roots::root_problem) {
+            eprintln!("warning: {}", problem);
+        }
+    }
+
+    observer::register(history::HistoryObserver);
+    if let Some(audit) = audit::AuditObserver::from_config() {
+        observer::register(audit);
+    }
+
+    let args = expand_stored_args(args);
+
     let cli = build_cli_command();
     let mut cli_for_help = cli.clone();
-    let matches = cli.get_matches();
+    let matches = cli
+        .try_get_matches_from(&args)
+        .unwrap_or_else(|e| errors::exit_on_clap_error(e));
 
     match matches.subcommand() {
         Some(("new", sub_matches)) => builtin::handle_new(sub_matches),
         Some(("edit", sub_matches)) => builtin::handle_edit(sub_matches),
+        Some(("cp", sub_matches)) => builtin::handle_cp(sub_matches),
+        Some(("mkdir", sub_matches)) => builtin::handle_mkdir(sub_matches),
         Some(("list", sub_matches)) => builtin::handle_list(sub_matches),
         Some(("validate", sub_matches)) => builtin::handle_validate(sub_matches),
-        Some((command, sub_matches)) => execute_command(command, sub_matches),
+        Some(("recent", sub_matches)) => builtin::handle_recent(sub_matches),
+        Some(("pick", sub_matches)) => builtin::handle_pick(sub_matches),
+        Some(("run", sub_matches)) => builtin::handle_run(sub_matches),
+        Some(("explain", sub_matches)) => builtin::handle_explain(sub_matches),
+        Some(("docs", sub_matches)) => builtin::handle_docs(sub_matches),
+        Some(("doctor", sub_matches)) => builtin::handle_doctor(sub_matches),
+        Some(("upgrade", sub_matches)) => builtin::handle_upgrade(sub_matches),
+        Some(("hook", sub_matches)) => builtin::handle_hook(sub_matches),
+        Some(("preset", sub_matches)) => builtin::handle_preset(sub_matches),
+        Some(("run-all", sub_matches)) => builtin::handle_run_all(sub_matches),
+        Some(("migrate", sub_matches)) => builtin::handle_migrate(sub_matches),
+        Some(("prune", sub_matches)) => builtin::handle_prune(sub_matches),
+        Some(("diff", sub_matches)) => builtin::handle_diff(sub_matches),
+        Some(("status", sub_matches)) => builtin::handle_status(sub_matches),
+        Some(("log", sub_matches)) => builtin::handle_log(sub_matches),
+        Some(("shadows", sub_matches)) => builtin::handle_shadows(sub_matches),
+        Some(("import", sub_matches)) => builtin::handle_import(sub_matches),
+        Some(("verify", sub_matches)) => builtin::handle_verify(sub_matches),
+        Some(("sign", sub_matches)) => builtin::handle_sign(sub_matches),
+        Some(("review", sub_matches)) => builtin::handle_review(sub_matches),
+        Some(("completions", sub_matches)) => builtin::handle_completions(sub_matches),
+        Some(("command-not-found", sub_matches)) => builtin::handle_command_not_found(sub_matches),
+        Some(("schema", sub_matches)) => builtin::handle_schema(sub_matches),
+        Some((command, sub_matches)) => execute_command(command, sub_matches, &args),
+        None if config::get().interactive => builtin::handle_interactive(&mut cli_for_help),
         None => {
             cli_for_help.print_help().unwrap();
             std::process::exit(1);
@@ -26,7 +136,46 @@ fn main() {
     }
 }
 
-fn execute_command(command: &str, sub_m: &ArgMatches) {
+/// Replaces `--shutl-again` and presets in a script invocation by the stored arguments
+/// (see [`builtin::expand_stored_args`]). Other invocations are returned unchanged.
+fn expand_stored_args(mut args: Vec<String>) -> Vec<String> {
+    let stored = |arg: &String| {
+        arg == builtin::AGAIN_FLAG
+            || arg.starts_with(preset::PRESET_FLAG)
+            || arg.ends_with(preset::PRESET_SUFFIX)
+    };
+    if !args.iter().any(stored) {
+        return args;
+    }
+    // Skip the global options to the command path
+    let start = args.len() - config::command_line_from_args(&args).len();
+    if args.get(start).is_none_or(|arg| is_builtin(arg)) {
+        return args;
+    }
+
+    let mounts = roots::mounts();
+    let mut components = Vec::new();
+    for arg in &args[start..] {
+        if !is_safe_component(arg) {
+            break;
+        }
+        components.push(arg.clone());
+        match roots::resolve(&mounts, &components) {
+            Some(path) if path.is_dir() => continue,
+            Some(path) => {
+                let end = start + components.len();
+                let expanded = builtin::expand_stored_args(&components, &path, &args[end..]);
+                args.truncate(end);
+                args.extend(expanded);
+                return args;
+            }
+            None => break,
+        }
+    }
+    args
+}
+
+fn execute_command(command: &str, sub_m: &ArgMatches, cli_args: &[String]) {
     // Collect all command components
     let mut components = vec![command.to_string()];
     let mut current = sub_m;
@@ -35,32 +184,24 @@ fn execute_command(command: &str, sub_m: &ArgMatches) {
         current = sub_matches;
     }
 
-    // Check if this is a directory command
-    let mut path = get_scripts_dir();
-    for component in &components {
-        path.push(component);
-    }
+    let path = builtin::resolve_command(&components);
 
+    // Check if this is a directory command
     if path.is_dir() {
-        // Build a new command tree starting from this directory
-        let mut dir_cli = clap::Command::new(components.join(" ")).disable_help_subcommand(true);
-        for cmd_with_path in shutl::command::build_command_tree(&path, &components) {
-            dir_cli = dir_cli.subcommand(cmd_with_path.command);
-        }
         // Show help for this directory command
-        dir_cli.print_help().unwrap();
+        build_group_command(&path, &components)
+            .print_help()
+            .unwrap();
         std::process::exit(1);
     }
 
-    // Find the script file in the original directory structure
-    if let Some(script_path) = find_script_file(&components) {
-        // Execute the script with the arguments
-        if let Err(e) = execute_script(&script_path, current) {
-            eprintln!("Error executing command: {}", e);
-            std::process::exit(1);
-        }
-    } else {
-        eprintln!("Script not found: {}", components.join("/"));
-        std::process::exit(1);
+    // Execute the script with the arguments after the global options and command path
+    let args: Vec<String> = config::command_line_from_args(cli_args)
+        .iter()
+        .skip(components.len())
+        .cloned()
+        .collect();
+    if let Err(e) = execute_script_as(&components, &path, current, &args) {
+        e.exit();
     }
 }
@@ -1,11 +1,383 @@
-use std::fs;
+use crate::env_filter::{EnvFilter, parse_patterns};
+use crate::k8s::Pod;
+use crate::limits::{ResourceLimits, parse_memory, parse_seconds};
+use crate::sandbox::Sandbox;
+use crate::source::{FsSource, ScriptSource};
+use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
 
 /// Metadata for a command parsed from its shell script
 #[derive(Default)]
 pub struct CommandMetadata {
+    /// The description, in the user's language once [`CommandMetadata::localize`]d
     pub description: String,
+    /// Translations of the description by language (`#@description[de]:`)
+    pub descriptions: BTreeMap<String, String>,
     pub arguments: Vec<LineType>, // (name, description, required, default, options)
+    /// Program that runs the script (`#@interpreter: bun`), overriding the defaults
+    pub interpreter: Option<String>,
+    /// Command that compiles the script (`#@build: go build -o {out} {src}`)
+    pub build: Option<String>,
+    /// Example invocations (`#@example:`), shown in help, docs and zsh completions
+    pub examples: Vec<String>,
+    /// Resource limits and priority (`#@max-memory:`, `#@max-cpu-seconds:`,
+    /// `#@priority:`); invalid values are left out and reported by `validate`
+    pub limits: ResourceLimits,
+    /// Whether the script reads stdin (`#@stdin:`); `None` if not declared or invalid
+    pub stdin: Option<StdinMode>,
+    /// Format of the script's stdout (`#@output: json`); text if not declared or invalid
+    pub output: OutputFormat,
+    /// Inherited variables the script gets (`#@env-allow:`) or never gets
+    /// (`#@env-deny:`), in addition to the config's
+    pub env: EnvFilter,
+    /// Prerequisites on the machine (`#@enabled-if:`); the command is hidden from help,
+    /// completion and `list` unless all hold. Invalid ones are reported by `validate`
+    pub enabled_if: Vec<Condition>,
+    /// Roles of the machines the command is meant for (`#@roles: laptop, ci`)
+    pub roles: Vec<String>,
+    /// Restrictions the script runs under (`#@sandbox: ro-home, net-off`); an invalid line
+    /// applies all of them and is reported by `validate`
+    pub sandbox: Sandbox,
+    /// Pod the script runs in (`#@k8s: deployment/tools -n ops`); an invalid line is
+    /// kept so the run is refused, and reported by `validate`
+    pub k8s: Option<Result<Pod, String>>,
+    /// Every metadata line in source order with its position; the fields above are
+    /// derived from these
+    pub elements: Vec<SpannedElement>,
+}
+
+/// Position of a metadata line in its script. Lines and columns are 1-based; `column` is
+/// the comment marker and `end_column` is one past the last character of the line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+    pub end_column: usize,
+}
+
+/// A single metadata line
+#[derive(Debug, Clone, PartialEq)]
+pub enum MetadataElement {
+    Description(String),
+    /// A `#@description[de]:` line: the language and the description in it
+    LocalizedDescription(String, String),
+    /// A `#@flag:` or `#@arg:` line (never [`LineType::Description`]), boxed as it is
+    /// much larger than the other elements
+    Argument(Box<LineType>),
+    Interpreter(String),
+    Build(String),
+    Example(String),
+    MaxMemory(String),
+    MaxCpuSeconds(String),
+    Priority(String),
+    Stdin(String),
+    Output(String),
+    EnvAllow(String),
+    EnvDeny(String),
+    EnabledIf(String),
+    Roles(String),
+    Sandbox(String),
+    K8s(String),
+    /// A metadata line shutl does not understand, e.g. a typo like `#@flg:`
+    Unknown(String),
+}
+
+impl MetadataElement {
+    /// Checks whether scripts can inherit the element from a directory's `.shutl` file:
+    /// flags and settings, but not descriptions, examples or positionals
+    pub fn is_inheritable(&self) -> bool {
+        match self {
+            MetadataElement::Argument(arg) => matches!(**arg, LineType::Flag(..)),
+            MetadataElement::Description(_)
+            | MetadataElement::LocalizedDescription(..)
+            | MetadataElement::Example(_)
+            | MetadataElement::Unknown(_) => false,
+            _ => true,
+        }
+    }
+}
+
+/// A metadata element and where it was written
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpannedElement {
+    pub element: MetadataElement,
+    pub span: Span,
+}
+
+impl CommandMetadata {
+    /// Builds the metadata from parsed elements. Later descriptions, interpreters, build
+    /// commands and limits replace earlier ones.
+    pub fn from_elements(elements: Vec<SpannedElement>) -> Self {
+        let mut metadata = CommandMetadata::default();
+        for spanned in &elements {
+            metadata.apply(&spanned.element);
+        }
+        metadata.elements = elements;
+        metadata
+    }
+
+    fn apply(&mut self, element: &MetadataElement) {
+        match element {
+            MetadataElement::Description(description) => self.description = description.clone(),
+            MetadataElement::LocalizedDescription(language, description) => {
+                self.descriptions
+                    .insert(language.clone(), description.clone());
+            }
+            MetadataElement::Argument(arg) => self.arguments.push((**arg).clone()),
+            MetadataElement::Interpreter(interpreter) => {
+                self.interpreter = Some(interpreter.clone())
+            }
+            MetadataElement::Build(build) => self.build = Some(build.clone()),
+            MetadataElement::Example(example) => self.examples.push(example.clone()),
+            MetadataElement::MaxMemory(size) => self.limits.max_memory = parse_memory(size).ok(),
+            MetadataElement::MaxCpuSeconds(seconds) => {
+                self.limits.max_cpu_seconds = parse_seconds(seconds).ok()
+            }
+            MetadataElement::Priority(priority) => self.limits.priority = priority.parse().ok(),
+            MetadataElement::Stdin(mode) => self.stdin = mode.parse().ok(),
+            MetadataElement::Output(format) => self.output = format.parse().unwrap_or_default(),
+            MetadataElement::EnvAllow(list) => self
+                .env
+                .allow
+                .get_or_insert_with(Vec::new)
+                .extend(parse_patterns(list)),
+            MetadataElement::EnvDeny(list) => self.env.deny.extend(parse_patterns(list)),
+            MetadataElement::EnabledIf(condition) => self.enabled_if.extend(condition.parse().ok()),
+            MetadataElement::Roles(list) => {
+                self.roles = list
+                    .split(',')
+                    .map(|role| role.trim().to_string())
+                    .filter(|role| !role.is_empty())
+                    .collect()
+            }
+            MetadataElement::Sandbox(list) => {
+                self.sandbox = list.parse().unwrap_or_else(|_| Sandbox::strictest())
+            }
+            MetadataElement::K8s(pod) => self.k8s = Some(pod.parse()),
+            MetadataElement::Unknown(_) => {}
+        }
+    }
+
+    /// Replaces the description by its translation into the first of `locales` (see
+    /// [`crate::config::locales`]) that has one
+    pub fn localize(&mut self, locales: &[String]) {
+        if let Some(description) = translation(&self.descriptions, locales) {
+            self.description = description.to_string();
+        }
+    }
+
+    /// Adds the defaults of the `.shutl` files above the script, outermost first. Flags
+    /// the script doesn't define itself are appended; settings it doesn't declare are
+    /// taken from the nearest directory declaring them; env patterns and conditions are
+    /// combined.
+    pub fn inherit(&mut self, defaults: &[SpannedElement]) {
+        let mut declared: Vec<_> = self
+            .elements
+            .iter()
+            .map(|own| std::mem::discriminant(&own.element))
+            .collect();
+        let mut flags = Vec::new();
+        for spanned in defaults.iter().rev() {
+            let element = &spanned.element;
+            match element {
+                MetadataElement::Argument(arg) => {
+                    let LineType::Flag(name, _, _) = &**arg else {
+                        continue;
+                    };
+                    let defined = self.arguments.iter().chain(&flags).any(|own| {
+                        matches!(own, LineType::Flag(n, _, _) | LineType::Positional(n, _, _) if n == name)
+                    });
+                    if !defined {
+                        flags.push((**arg).clone());
+                    }
+                }
+                MetadataElement::EnvAllow(_)
+                | MetadataElement::EnvDeny(_)
+                | MetadataElement::EnabledIf(_) => self.apply(element),
+                _ if element.is_inheritable() => {
+                    let kind = std::mem::discriminant(element);
+                    if !declared.contains(&kind) {
+                        declared.push(kind);
+                        self.apply(element);
+                    }
+                }
+                _ => {}
+            }
+        }
+        flags.reverse();
+        self.arguments.extend(flags);
+    }
+
+    /// Checks whether all `#@enabled-if:` conditions hold on this machine
+    pub fn is_enabled(&self) -> bool {
+        self.enabled_if.iter().all(Condition::holds)
+    }
+
+    /// Checks whether the command is meant for a machine with the roles `machine`: it
+    /// declares none, the machine has none, or they share one
+    pub fn is_for_roles(&self, machine: &[String]) -> bool {
+        roles_match(&self.roles, machine)
+    }
+
+    /// Gets where the argument at `index` (in [`CommandMetadata::arguments`]) was declared
+    pub fn argument_span(&self, index: usize) -> Option<Span> {
+        self.elements
+            .iter()
+            .filter(|e| matches!(e.element, MetadataElement::Argument(_)))
+            .nth(index)
+            .map(|e| e.span)
+    }
+}
+
+/// Whether a script reads input from stdin (`#@stdin: required|optional|none`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StdinMode {
+    /// Input must be piped or redirected; running it from a terminal is an error
+    Required,
+    /// Input is read if piped or redirected
+    Optional,
+    /// stdin is not read and is connected to `/dev/null`
+    None,
+}
+
+impl StdinMode {
+    /// Names accepted by `#@stdin:`
+    pub const NAMES: [&str; 3] = ["required", "optional", "none"];
+
+    /// Describes the expectation for help and `explain`
+    pub fn describe(&self) -> &'static str {
+        match self {
+            StdinMode::Required => "required: pipe or redirect input into the command",
+            StdinMode::Optional => "optional: read if piped or redirected",
+            StdinMode::None => "not read",
+        }
+    }
+}
+
+impl std::str::FromStr for StdinMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "required" => Ok(StdinMode::Required),
+            "optional" => Ok(StdinMode::Optional),
+            "none" => Ok(StdinMode::None),
+            other => Err(format!(
+                "invalid stdin mode '{}', expected {}",
+                other,
+                StdinMode::NAMES.join(", ")
+            )),
+        }
+    }
+}
+
+/// Format of a script's stdout (`#@output: text|json`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    /// A single JSON document, embedded as such in `--shutl-json` reports
+    Json,
+}
+
+impl OutputFormat {
+    /// Names accepted by `#@output:`
+    pub const NAMES: [&str; 2] = ["text", "json"];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            OutputFormat::Text => "text",
+            OutputFormat::Json => "json",
+        }
+    }
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            other => Err(format!(
+                "invalid output format '{}', expected {}",
+                other,
+                OutputFormat::NAMES.join(", ")
+            )),
+        }
+    }
+}
+
+/// Checks whether a command meant for the roles `wanted` (`#@roles:`) belongs on a
+/// machine with the roles `machine` (config `roles`); either being empty matches all
+pub fn roles_match(wanted: &[String], machine: &[String]) -> bool {
+    wanted.is_empty() || machine.is_empty() || wanted.iter().any(|role| machine.contains(role))
+}
+
+/// A prerequisite of a command (`#@enabled-if: env:KUBECONFIG`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Condition {
+    /// The variable is set and not empty
+    Env(String),
+    /// The shell command exits successfully, e.g. `exec:which kubectl`
+    Exec(String),
+}
+
+impl Condition {
+    /// Checks the condition. Commands are run with `sh -c` without any output and at
+    /// most once per shutl invocation, however many scripts share them.
+    pub fn holds(&self) -> bool {
+        static RESULTS: std::sync::Mutex<Vec<(String, bool)>> = std::sync::Mutex::new(Vec::new());
+        match self {
+            Condition::Env(name) => std::env::var_os(name).is_some_and(|value| !value.is_empty()),
+            Condition::Exec(command) => {
+                let mut results = RESULTS.lock().unwrap_or_else(|e| e.into_inner());
+                if let Some((_, holds)) = results.iter().find(|(c, _)| c == command) {
+                    return *holds;
+                }
+                let holds = std::process::Command::new("sh")
+                    .args(["-c", command])
+                    .stdin(std::process::Stdio::null())
+                    .stdout(std::process::Stdio::null())
+                    .stderr(std::process::Stdio::null())
+                    .status()
+                    .is_ok_and(|status| status.success());
+                results.push((command.clone(), holds));
+                holds
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for Condition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, value) = split_once_or_all(s.trim(), ':');
+        let value = value.trim();
+        match kind.trim() {
+            _ if value.is_empty() => Err(format!(
+                "invalid condition '{}', expected env:VAR or exec:COMMAND",
+                s.trim()
+            )),
+            "env" => Ok(Condition::Env(value.to_string())),
+            "exec" => Ok(Condition::Exec(value.to_string())),
+            other => Err(format!(
+                "unknown condition '{}', expected env:VAR or exec:COMMAND",
+                other
+            )),
+        }
+    }
+}
+
+/// Gets the line comment marker of a script: `//` for TypeScript, JavaScript, Go and Rust,
+/// else `#`.
+/// Metadata lines are this marker followed by `@`.
+pub fn comment_prefix(path: &Path) -> &'static str {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("ts" | "mts" | "js" | "mjs" | "go" | "rs") => "//",
+        _ => "#",
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -22,6 +394,16 @@ pub enum ArgType {
     File,
     Dir,
     Path,
+    /// A type from the `argtype` registry
+    Custom(Box<CustomType>),
+}
+
+/// A registered argument type as annotated, e.g. `[git-branch:all]`
+#[derive(Debug, PartialEq, Clone)]
+pub struct CustomType {
+    pub name: String,
+    /// Text after the colon, empty without one
+    pub option: String,
 }
 
 #[derive(Clone, Debug, PartialEq, Default)]
@@ -30,44 +412,423 @@ pub struct CompleteOptions {
     pub env_var: Option<String>,
 }
 
+/// The executable that produces completion candidates
+#[derive(Clone, Debug, PartialEq, Default)]
+pub enum CompleterScript {
+    /// The script itself (`complete-self`)
+    #[default]
+    Own,
+    /// A script next to it (`complete-script`)
+    Sibling(String),
+    /// An executable in the `.completers` directory of the scripts directory (`complete`)
+    External(String),
+}
+
+/// A command whose output provides completion candidates for an argument
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct CompleteCommand {
+    pub script: CompleterScript,
+    pub args: Vec<String>,
+}
+
 #[derive(Default, Clone, Debug, PartialEq)]
 pub struct Config {
     pub default: Option<String>,
     pub arg_type: Option<ArgType>,
     pub options: Vec<String>,
+    /// Descriptions of options written as `name(description)`, by option
+    pub option_help: BTreeMap<String, String>,
     pub complete_options: Option<CompleteOptions>,
+    pub complete_command: Option<CompleteCommand>,
+    /// `[required]` as written; a default wins over it (see [`Config::is_required`])
     pub required: bool,
+    /// Value is masked in verbose output, `explain`, history and logs (`[secret]`)
+    pub secret: bool,
+    /// Fewest values a positional takes (`[min:1]`); makes it take several
+    pub min: Option<usize>,
+    /// Most values a positional takes (`[max:10]`); makes it take several
+    pub max: Option<usize>,
+    /// Values may start with `-`, e.g. `--offset -5` (`[allow-hyphen]`)
+    pub allow_hyphen: bool,
+    /// A `bool` flag given neither as `--name` nor `--no-name` leaves its variable unset
+    /// instead of `false` (`[tristate]`, implies `bool`)
+    pub tristate: bool,
+    /// `~` and `$VAR` in the value and default are expanded before the script gets them
+    /// (`[expand]`)
+    pub expand: bool,
+    /// The `file`/`dir`/`path` value must exist (`[must-exist]`)
+    pub must_exist: bool,
+    /// The directory of a `file`/`path` value, or the `dir` value itself, is created if
+    /// missing (`[create]`)
+    pub create: bool,
+    /// Short form of a flag, e.g. `-v` for `--verbose` (`[short:v]`)
+    pub short: Option<char>,
 }
 
-pub fn parse_command_metadata(path: &Path) -> CommandMetadata {
-    let mut metadata = CommandMetadata::default();
-
-    if let Ok(contents) = fs::read_to_string(path) {
-        for line in contents.lines() {
-            let trimmed = line.trim();
-            if trimmed.is_empty() || trimmed.starts_with("#!") {
-                continue;
-            }
-            if let Some(rest) = trimmed.strip_prefix("#@") {
-                if let Some(parsed) = parse_line(rest.trim()) {
-                    match parsed {
-                        LineType::Description(desc) => metadata.description = desc,
-                        _ => metadata.arguments.push(parsed),
-                    }
-                }
-            } else if trimmed.starts_with('#') {
-                // Regular comment — skip but keep parsing
-                continue;
+impl Config {
+    /// Checks whether the argument must be given: `[required]` without a default. With
+    /// both, the default is used and `shutl validate` warns about the `required`.
+    pub fn is_required(&self) -> bool {
+        self.required && self.default.is_none()
+    }
+
+    /// Gets the default of a `bool` flag, `None` if it has none or it is neither `true`
+    /// nor `false`
+    pub fn bool_default(&self) -> Option<bool> {
+        self.default.as_deref()?.parse().ok()
+    }
+
+    /// Checks whether the argument takes several values: a catch-all, or one with counts
+    pub fn is_multiple(&self) -> bool {
+        self.arg_type == Some(ArgType::CatchAll) || self.min.is_some() || self.max.is_some()
+    }
+
+    /// Gets the fewest values a positional must be given. Without `min`, catch-alls and
+    /// arguments with a default take none, others one.
+    pub fn min_values(&self) -> usize {
+        self.min.unwrap_or(
+            if self.arg_type == Some(ArgType::CatchAll) || self.default.is_some() {
+                0
             } else {
-                // First non-comment line — stop parsing
+                1
+            },
+        )
+    }
+}
+
+/// Gets the argument names a default refers to with `{{name}}`, e.g. `input` in
+/// `[default:{{input}}.out]`
+pub fn template_refs(template: &str) -> Vec<&str> {
+    let mut refs = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start..].find("}}") else {
+            break;
+        };
+        refs.push(rest[start + 2..start + end].trim());
+        rest = &rest[start + end + 2..];
+    }
+    refs
+}
+
+/// Replaces each `{{name}}` in `template` by `lookup(name)`; references `lookup` doesn't
+/// know are kept as they are
+pub fn fill_template(template: &str, lookup: impl Fn(&str) -> Option<String>) -> String {
+    let mut filled = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start..].find("}}") else {
+            break;
+        };
+        filled.push_str(&rest[..start]);
+        let reference = &rest[start..start + end + 2];
+        match lookup(reference[2..reference.len() - 2].trim()) {
+            Some(value) => filled.push_str(&value),
+            None => filled.push_str(reference),
+        }
+        rest = &rest[start + end + 2..];
+    }
+    filled.push_str(rest);
+    filled
+}
+
+/// How script files are named as commands
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CommandNames {
+    /// File name without extension. Files sharing a stem with each other or with a
+    /// directory keep their full file name.
+    #[default]
+    Stem,
+    /// Full file name, including the extension
+    Filename,
+}
+
+impl CommandNames {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim() {
+            "stem" => Some(CommandNames::Stem),
+            "filename" => Some(CommandNames::Filename),
+            _ => None,
+        }
+    }
+}
+
+/// Metadata for a directory command, parsed from the `.shutl` file inside it.
+/// Lines starting with `#@` are directives, all other lines form the description.
+/// A directory with a `README.md` but no `.shutl` takes its description from the README.
+#[derive(Default, Debug, PartialEq)]
+pub struct DirMetadata {
+    pub description: String,
+    /// Child command names shown first, in this order (`#@order: a, b, c`)
+    pub order: Vec<String>,
+    /// Naming of the scripts in this directory (`#@command-names: filename`)
+    pub command_names: Option<CommandNames>,
+    /// Script metadata lines (`#@flag:`, `#@interpreter:`, `#@env-allow:`, ...) that every
+    /// script below the directory inherits, see [`CommandMetadata::inherit`]
+    pub defaults: Vec<SpannedElement>,
+    /// The `README.md` the description comes from, for a directory without `.shutl`
+    pub readme: Option<PathBuf>,
+    /// Translations of the description by language (`#@description[de]:`)
+    pub descriptions: BTreeMap<String, String>,
+}
+
+/// The name of the per-directory metadata file
+pub const DIR_METADATA_FILE: &str = ".shutl";
+
+/// The name of the file describing a directory that has no `.shutl` file
+pub const DIR_README_FILE: &str = "README.md";
+
+pub fn parse_dir_metadata(dir: &Path) -> DirMetadata {
+    FsSource.dir_metadata(dir)
+}
+
+/// Gets a group description from a README: its first heading, or the first paragraph if
+/// it has no heading. Code blocks, HTML and paragraphs of badges are skipped, links are
+/// reduced to their text.
+pub fn readme_description(contents: &str) -> String {
+    let mut paragraph: Vec<&str> = Vec::new();
+    let mut in_code = false;
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.starts_with("```") || line.starts_with("~~~") {
+            in_code = !in_code;
+            continue;
+        }
+        if in_code {
+            continue;
+        }
+        if line.starts_with('#') {
+            let heading = line.trim_start_matches('#');
+            if heading.is_empty() || heading.starts_with(' ') {
+                return strip_links(heading.trim().trim_end_matches('#').trim());
+            }
+        }
+        if line.is_empty() {
+            if !paragraph.is_empty() {
                 break;
             }
+            continue;
         }
+        if line.starts_with('<') || line.starts_with("[![") || line.starts_with("![") {
+            continue;
+        }
+        paragraph.push(line);
     }
+    strip_links(&paragraph.join(" "))
+}
+
+/// Replaces Markdown links `[text](target)` by their text
+fn strip_links(text: &str) -> String {
+    let mut out = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('[') {
+        let Some(close) = rest[start..].find("](").map(|close| start + close) else {
+            break;
+        };
+        let Some(end) = rest[close..].find(')').map(|end| close + end) else {
+            break;
+        };
+        out.push_str(&rest[..start]);
+        out.push_str(&rest[start + 1..close]);
+        rest = &rest[end + 1..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Parses the contents of the `.shutl` file of `dir`
+pub fn parse_dir_metadata_contents(dir: &Path, contents: &str) -> DirMetadata {
+    let mut metadata = DirMetadata::default();
+    let mut description = Vec::new();
+    for (index, line) in contents.lines().enumerate() {
+        let Some(directive) = line.trim().strip_prefix("#@") else {
+            description.push(line);
+            continue;
+        };
+        if let Some((language, description)) = localized_description(directive.trim()) {
+            metadata.descriptions.insert(language, description);
+            continue;
+        }
+        let (key, value) = split_once_or_all(directive.trim(), ':');
+        match key.trim() {
+            "order" => {
+                metadata.order = value
+                    .split(',')
+                    .map(|s| s.trim().to_string())
+                    .filter(|s| !s.is_empty())
+                    .collect()
+            }
+            "command-names" => {
+                metadata.command_names = CommandNames::parse(value);
+                if metadata.command_names.is_none() {
+                    log::warn!(
+                        "Ignoring invalid command-names '{}' in {}",
+                        value.trim(),
+                        dir.display()
+                    );
+                }
+            }
+            _ => match parse_element(directive) {
+                element if element.is_inheritable() => metadata.defaults.push(SpannedElement {
+                    element,
+                    span: Span {
+                        line: index + 1,
+                        column: line.len() - line.trim_start().len() + 1,
+                        end_column: line.trim_end().chars().count() + 1,
+                    },
+                }),
+                MetadataElement::Unknown(_) => {
+                    log::debug!("Ignoring unknown directory directive '{}'", key.trim())
+                }
+                _ => log::warn!(
+                    "Ignoring '#@{}' in {}: only flags and settings are inherited by scripts",
+                    directive.trim(),
+                    dir.join(DIR_METADATA_FILE).display()
+                ),
+            },
+        }
+    }
+    metadata.description = description.join("\n").trim().to_string();
 
     metadata
 }
 
+/// Reads the metadata of a script, including what it inherits from its directories
+pub fn parse_command_metadata(path: &Path) -> CommandMetadata {
+    FsSource.command_metadata(path)
+}
+
+/// Parses the metadata lines of a script's header into elements, in source order.
+/// `path` selects the comment syntax; the file itself is not read.
+pub fn parse_metadata_elements(path: &Path, contents: &str) -> Vec<SpannedElement> {
+    let mut elements = Vec::new();
+
+    // PowerShell scripts may keep their metadata in a `<# ... #>` block comment, where
+    // lines can start with `#@` or just `@`
+    let block_comments = path.extension().is_some_and(|ext| ext == "ps1");
+    let mut in_block = false;
+    let comment = comment_prefix(path);
+    let marker = format!("{}@", comment);
+
+    let mut push = |rest: &str, line_number: usize, line: &str, marker_start: usize| {
+        elements.push(SpannedElement {
+            element: parse_element(rest),
+            span: Span {
+                line: line_number,
+                column: line[..marker_start].chars().count() + 1,
+                end_column: line.trim_end().chars().count() + 1,
+            },
+        });
+    };
+    let indent = |line: &str| line.len() - line.trim_start().len();
+
+    for (index, line) in contents.lines().enumerate() {
+        let line_number = index + 1;
+        let trimmed = line.trim();
+        if in_block {
+            let (body, closed) = match trimmed.strip_suffix("#>") {
+                Some(body) => (body.trim(), true),
+                None => (trimmed, false),
+            };
+            if let Some(rest) = body.strip_prefix("#@").or_else(|| body.strip_prefix('@')) {
+                push(rest, line_number, line, indent(line));
+            }
+            in_block = !closed;
+            continue;
+        }
+        if trimmed.is_empty() || trimmed.starts_with("#!") {
+            continue;
+        }
+        if block_comments && let Some(rest) = trimmed.strip_prefix("<#") {
+            in_block = !rest.trim_end().ends_with("#>");
+            continue;
+        }
+        if let Some(rest) = trimmed.strip_prefix(marker.as_str()) {
+            push(rest, line_number, line, indent(line));
+        } else if trimmed.starts_with(comment) {
+            // Regular comment — skip but keep parsing
+            continue;
+        } else {
+            // First non-comment line — stop parsing
+            break;
+        }
+    }
+
+    elements
+}
+
+/// Parses the text of a metadata line after its `#@` marker
+fn parse_element(rest: &str) -> MetadataElement {
+    let text = rest.trim();
+    if let Some(interpreter) = text.strip_prefix("interpreter:") {
+        MetadataElement::Interpreter(interpreter.trim().to_string())
+    } else if let Some(build) = text.strip_prefix("build:") {
+        MetadataElement::Build(build.trim().to_string())
+    } else if let Some(example) = text.strip_prefix("example:") {
+        MetadataElement::Example(example.trim().to_string())
+    } else if let Some(size) = text.strip_prefix("max-memory:") {
+        MetadataElement::MaxMemory(size.trim().to_string())
+    } else if let Some(seconds) = text.strip_prefix("max-cpu-seconds:") {
+        MetadataElement::MaxCpuSeconds(seconds.trim().to_string())
+    } else if let Some(priority) = text.strip_prefix("priority:") {
+        MetadataElement::Priority(priority.trim().to_string())
+    } else if let Some(mode) = text.strip_prefix("stdin:") {
+        MetadataElement::Stdin(mode.trim().to_string())
+    } else if let Some(format) = text.strip_prefix("output:") {
+        MetadataElement::Output(format.trim().to_string())
+    } else if let Some(list) = text.strip_prefix("env-allow:") {
+        MetadataElement::EnvAllow(list.trim().to_string())
+    } else if let Some(list) = text.strip_prefix("env-deny:") {
+        MetadataElement::EnvDeny(list.trim().to_string())
+    } else if let Some(condition) = text.strip_prefix("enabled-if:") {
+        MetadataElement::EnabledIf(condition.trim().to_string())
+    } else if let Some(roles) = text.strip_prefix("roles:") {
+        MetadataElement::Roles(roles.trim().to_string())
+    } else if let Some(list) = text.strip_prefix("sandbox:") {
+        MetadataElement::Sandbox(list.trim().to_string())
+    } else if let Some(pod) = text.strip_prefix("k8s:") {
+        MetadataElement::K8s(pod.trim().to_string())
+    } else if let Some((language, description)) = localized_description(text) {
+        MetadataElement::LocalizedDescription(language, description)
+    } else {
+        match parse_line(text) {
+            Some(LineType::Description(description)) => MetadataElement::Description(description),
+            Some(arg) => MetadataElement::Argument(Box::new(arg)),
+            None => MetadataElement::Unknown(text.to_string()),
+        }
+    }
+}
+
+/// Splits `description[de]: Text` into the language and the text
+fn localized_description(text: &str) -> Option<(String, String)> {
+    let (language, description) = text.strip_prefix("description[")?.split_once("]:")?;
+    let valid = !language.is_empty()
+        && language
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    valid.then(|| (language.to_string(), description.trim().to_string()))
+}
+
+/// Normalizes a language tag for comparison: `de-AT` and `de_at` are the same
+fn language_key(language: &str) -> String {
+    language.replace('-', "_").to_ascii_lowercase()
+}
+
+/// Finds the translation into the first of `locales` that has one
+pub fn translation<'a>(
+    translations: &'a BTreeMap<String, String>,
+    locales: &[String],
+) -> Option<&'a str> {
+    locales.iter().find_map(|locale| {
+        let locale = language_key(locale);
+        translations
+            .iter()
+            .find(|(language, _)| language_key(language) == locale)
+            .map(|(_, description)| description.as_str())
+    })
+}
+
 fn parse_line(line: &str) -> Option<LineType> {
     if let Some(description) = line.strip_prefix("description:") {
         return Some(LineType::Description(description.trim().to_string()));
@@ -125,8 +886,19 @@ fn parse_annotations(annotations: Vec<String>) -> Option<Config> {
         default: None,
         arg_type: None,
         options: Vec::new(),
+        option_help: BTreeMap::new(),
         complete_options: None,
+        complete_command: None,
         required: false,
+        secret: false,
+        min: None,
+        max: None,
+        allow_hyphen: false,
+        tristate: false,
+        expand: false,
+        must_exist: false,
+        create: false,
+        short: None,
     };
 
     for annotation in annotations {
@@ -134,7 +906,28 @@ fn parse_annotations(annotations: Vec<String>) -> Option<Config> {
         match key.trim() {
             "default" => cfg.default = Some(value.trim().to_string()),
             "required" => cfg.required = true,
+            "secret" => cfg.secret = true,
+            "allow-hyphen" => cfg.allow_hyphen = true,
+            "expand" => cfg.expand = true,
+            "must-exist" => cfg.must_exist = true,
+            "create" => cfg.create = true,
             "bool" => cfg.arg_type = Some(ArgType::Bool),
+            "short" => {
+                let mut chars = value.trim().chars();
+                match (chars.next(), chars.next()) {
+                    (Some(short), None) if short.is_ascii_alphanumeric() => cfg.short = Some(short),
+                    _ => log::warn!("Ignoring invalid short flag '{}'", annotation.trim()),
+                }
+            }
+            "tristate" => {
+                cfg.arg_type = Some(ArgType::Bool);
+                cfg.tristate = true;
+            }
+            "min" | "max" => match value.trim().parse() {
+                Ok(count) if key.trim() == "min" => cfg.min = Some(count),
+                Ok(count) => cfg.max = Some(count),
+                Err(_) => log::warn!("Ignoring invalid value count '{}'", annotation.trim()),
+            },
             "dir" | "file" | "path" => {
                 let arg_type = match key {
                     "dir" => ArgType::Dir,
@@ -151,36 +944,71 @@ fn parse_annotations(annotations: Vec<String>) -> Option<Config> {
                     cfg.complete_options = Some(CompleteOptions { path, env_var });
                 }
             }
+            "complete-self" => {
+                cfg.complete_command = Some(CompleteCommand {
+                    script: CompleterScript::Own,
+                    args: value.split_whitespace().map(|s| s.to_string()).collect(),
+                });
+            }
+            "complete-script" => {
+                let mut words = value.split_whitespace().map(|s| s.to_string());
+                cfg.complete_command = words.next().map(|script| CompleteCommand {
+                    script: CompleterScript::Sibling(script),
+                    args: words.collect(),
+                });
+            }
+            "complete" => {
+                let mut words = value.split_whitespace().map(|s| s.to_string());
+                cfg.complete_command = words.next().map(|name| CompleteCommand {
+                    script: CompleterScript::External(name),
+                    args: words.collect(),
+                });
+            }
             "options" => {
-                let options: Vec<String> = value.split('|').map(|s| s.trim().to_string()).collect();
-                if let Some(default) = options
-                    .iter()
-                    .find(|s| s.starts_with('!') && s.ends_with('!'))
-                {
-                    cfg.default = Some(default.trim_matches('!').to_string());
+                // Each option is `name` or `name(description)`, marked `!...!` as the
+                // default either around the name or around all of it
+                let marked = |s: &str| s.starts_with('!') && s.ends_with('!');
+                let mut default = None;
+                for option in value.split('|').map(str::trim) {
+                    let mut is_default = marked(option);
+                    let option = if is_default {
+                        option.trim_matches('!')
+                    } else {
+                        option
+                    };
+                    let (name, help) =
+                        match option.strip_suffix(')').and_then(|s| s.split_once('(')) {
+                            Some((name, help)) => (name.trim(), help.trim()),
+                            None => (option, ""),
+                        };
+                    let name = if marked(name) {
+                        is_default = true;
+                        name.trim_matches('!').to_string()
+                    } else {
+                        name.to_string()
+                    };
+                    if is_default && default.is_none() {
+                        default = Some(name.clone());
+                    }
+                    if !help.is_empty() {
+                        cfg.option_help.insert(name.clone(), help.to_string());
+                    }
+                    cfg.options.push(name);
+                }
+                if default.is_some() {
+                    cfg.default = default;
                 }
-
-                cfg.options = options
-                    .into_iter()
-                    .map(|s| {
-                        if s.starts_with('!') && s.ends_with('!') {
-                            s.trim_matches('!').to_string()
-                        } else {
-                            s
-                        }
-                    })
-                    .collect();
+            }
+            name if crate::argtype::lookup(name).is_some() => {
+                cfg.arg_type = Some(ArgType::Custom(Box::new(CustomType {
+                    name: name.to_string(),
+                    option: value.trim().to_string(),
+                })));
             }
             _ => {}
         }
     }
 
-    // Warn if both required and default are set (contradictory)
-    if cfg.required && cfg.default.is_some() {
-        log::warn!("Argument has both 'required' and 'default' set - 'required' will be ignored");
-        cfg.required = false;
-    }
-
     Some(cfg)
 }
 
@@ -209,7 +1037,7 @@ fn split_once_or_all(s: &str, delim: char) -> (&str, &str) {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::fs::File;
+    use std::fs::{self, File};
     use std::io::Write;
     use tempfile::tempdir;
 
@@ -235,7 +1063,7 @@ mod tests {
 "#;
 
         let dir = tempdir().unwrap();
-        let script_path = create_test_script(&dir.path(), "test.sh", script_content);
+        let script_path = create_test_script(dir.path(), "test.sh", script_content);
         let metadata = parse_command_metadata(&script_path);
 
         // Test description
@@ -312,7 +1140,7 @@ mod tests {
             )
         );
 
-        // Test output-dir flag (required is ignored because default is set)
+        // Test output-dir flag (required is kept, the builder ignores it as default is set)
         let output_dir_flag = &metadata.arguments[5];
         assert_eq!(
             output_dir_flag,
@@ -322,7 +1150,7 @@ mod tests {
                 Config {
                     default: Some("./output".to_string()),
                     arg_type: Some(ArgType::Dir),
-                    required: false, // required is ignored when default is set
+                    required: true,
                     ..Default::default()
                 }
             )
@@ -366,7 +1194,7 @@ mod tests {
 "#;
 
         let dir = tempdir().unwrap();
-        let script_path = create_test_script(&dir.path(), "test.sh", script_content);
+        let script_path = create_test_script(dir.path(), "test.sh", script_content);
         let metadata = parse_command_metadata(&script_path);
 
         let flag = &metadata.arguments[0];
@@ -377,11 +1205,42 @@ mod tests {
                 "Test flag".to_string(),
                 Config {
                     default: Some("value".to_string()),
-                    required: false, // should be false because default is set
+                    required: true, // kept as written, so `shutl validate` can warn
                     ..Default::default()
                 }
             )
         );
+        let LineType::Flag(_, _, cfg) = flag else {
+            unreachable!()
+        };
+        assert!(!cfg.is_required());
+    }
+
+    #[test]
+    fn test_option_descriptions() {
+        let line = "flag:mode - Build mode [options:fast(quick but risky)|!safe!( slow )|plain]";
+        let Some(LineType::Flag(_, _, cfg)) = parse_line(line) else {
+            panic!("not a flag");
+        };
+        assert_eq!(cfg.options, vec!["fast", "safe", "plain"]);
+        assert_eq!(cfg.default.as_deref(), Some("safe"));
+        assert_eq!(
+            cfg.option_help,
+            BTreeMap::from([
+                ("fast".to_string(), "quick but risky".to_string()),
+                ("safe".to_string(), "slow".to_string()),
+            ])
+        );
+
+        // The default marker may also surround the description
+        let Some(LineType::Positional(_, _, cfg)) =
+            parse_line("arg:env - Env [options:dev|!prod(careful)!]")
+        else {
+            panic!("not a positional");
+        };
+        assert_eq!(cfg.options, vec!["dev", "prod"]);
+        assert_eq!(cfg.default.as_deref(), Some("prod"));
+        assert_eq!(cfg.option_help["prod"], "careful");
     }
 
     #[test]
@@ -393,7 +1252,7 @@ mod tests {
 "#;
 
         let dir = tempdir().unwrap();
-        let script_path = create_test_script(&dir.path(), "test.sh", script_content);
+        let script_path = create_test_script(dir.path(), "test.sh", script_content);
         let metadata = parse_command_metadata(&script_path);
 
         // Test flag with file and start directory
@@ -442,7 +1301,7 @@ mod tests {
 "#;
 
         let dir = tempdir().unwrap();
-        let script_path = create_test_script(&dir.path(), "test.sh", script_content);
+        let script_path = create_test_script(dir.path(), "test.sh", script_content);
         let metadata = parse_command_metadata(&script_path);
 
         // Test flag with file, start directory, and env var
@@ -482,6 +1341,93 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_complete_command_annotations() {
+        let script_content = r#"#!/bin/bash
+#@arg:env - Environment [complete-self:--list-envs]
+#@flag:region - Region [complete-script:regions --all --json]
+#@flag:context - Context [complete:kube-contexts --current]
+"#;
+
+        let dir = tempdir().unwrap();
+        let script_path = create_test_script(dir.path(), "test.sh", script_content);
+        let metadata = parse_command_metadata(&script_path);
+
+        assert_eq!(
+            metadata.arguments[0],
+            LineType::Positional(
+                "env".to_string(),
+                "Environment".to_string(),
+                Config {
+                    complete_command: Some(CompleteCommand {
+                        script: CompleterScript::Own,
+                        args: vec!["--list-envs".to_string()],
+                    }),
+                    ..Default::default()
+                }
+            )
+        );
+        assert_eq!(
+            metadata.arguments[1],
+            LineType::Flag(
+                "region".to_string(),
+                "Region".to_string(),
+                Config {
+                    complete_command: Some(CompleteCommand {
+                        script: CompleterScript::Sibling("regions".to_string()),
+                        args: vec!["--all".to_string(), "--json".to_string()],
+                    }),
+                    ..Default::default()
+                }
+            )
+        );
+        assert_eq!(
+            metadata.arguments[2],
+            LineType::Flag(
+                "context".to_string(),
+                "Context".to_string(),
+                Config {
+                    complete_command: Some(CompleteCommand {
+                        script: CompleterScript::External("kube-contexts".to_string()),
+                        args: vec!["--current".to_string()],
+                    }),
+                    ..Default::default()
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn test_registered_arg_type() {
+        let script_content = r#"#!/bin/bash
+#@flag:branch - Branch [git-branch:all]
+#@flag:other - Unknown types are ignored [no-such-type]
+"#;
+
+        let dir = tempdir().unwrap();
+        let script_path = create_test_script(dir.path(), "test.sh", script_content);
+        let metadata = parse_command_metadata(&script_path);
+
+        assert_eq!(
+            metadata.arguments[0],
+            LineType::Flag(
+                "branch".to_string(),
+                "Branch".to_string(),
+                Config {
+                    arg_type: Some(ArgType::Custom(Box::new(CustomType {
+                        name: "git-branch".to_string(),
+                        option: "all".to_string(),
+                    }))),
+                    ..Default::default()
+                }
+            )
+        );
+        let LineType::Flag(_, _, other) = &metadata.arguments[1] else {
+            panic!("expected a flag");
+        };
+        assert_eq!(other.arg_type, None);
+    }
+
     #[test]
     fn test_named_catchall_arg() {
         let script_content = r#"#!/bin/bash
@@ -490,7 +1436,7 @@ mod tests {
 "#;
 
         let dir = tempdir().unwrap();
-        let script_path = create_test_script(&dir.path(), "test.sh", script_content);
+        let script_path = create_test_script(dir.path(), "test.sh", script_content);
         let metadata = parse_command_metadata(&script_path);
 
         assert_eq!(metadata.arguments.len(), 1);
@@ -516,7 +1462,7 @@ mod tests {
 "#;
 
         let dir = tempdir().unwrap();
-        let script_path = create_test_script(&dir.path(), "test.sh", script_content);
+        let script_path = create_test_script(dir.path(), "test.sh", script_content);
         let metadata = parse_command_metadata(&script_path);
 
         assert_eq!(metadata.arguments.len(), 1);
@@ -533,6 +1479,362 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_value_counts() {
+        let script_content = r#"#!/bin/bash
+#@arg:files - Files [min:1,max:10]
+#@arg:...rest - Rest [max:3]
+#@arg:tags - Tags [min:0, max:x]
+"#;
+
+        let dir = tempdir().unwrap();
+        let script_path = create_test_script(dir.path(), "test.sh", script_content);
+        let metadata = parse_command_metadata(&script_path);
+
+        let configs: Vec<_> = metadata
+            .arguments
+            .iter()
+            .map(|arg| match arg {
+                LineType::Positional(_, _, cfg) => cfg.clone(),
+                other => panic!("unexpected {:?}", other),
+            })
+            .collect();
+        assert_eq!((configs[0].min, configs[0].max), (Some(1), Some(10)));
+        assert_eq!(configs[1].max, Some(3));
+        assert_eq!((configs[2].min, configs[2].max), (Some(0), None));
+        assert!(configs.iter().all(Config::is_multiple));
+        assert_eq!(
+            configs.iter().map(Config::min_values).collect::<Vec<_>>(),
+            vec![1, 0, 0]
+        );
+        assert!(!Config::default().is_multiple());
+        assert_eq!(Config::default().min_values(), 1);
+    }
+
+    #[test]
+    fn test_elements_keep_order_and_spans() {
+        let contents = "#!/bin/bash\n# plain comment\n#@flag:b - B\n  #@description: Test\n#@flg:typo - x\n#@arg:a - A\necho\n#@arg:late - ignored\n";
+        let elements = parse_metadata_elements(Path::new("x.sh"), contents);
+
+        let kinds: Vec<_> = elements
+            .iter()
+            .map(|e| match &e.element {
+                MetadataElement::Argument(arg) => match &**arg {
+                    LineType::Flag(name, _, _) | LineType::Positional(name, _, _) => name.clone(),
+                    other => format!("{:?}", other),
+                },
+                MetadataElement::Description(d) => format!("description {}", d),
+                MetadataElement::Unknown(text) => format!("unknown {}", text),
+                other => format!("{:?}", other),
+            })
+            .collect();
+        assert_eq!(
+            kinds,
+            vec!["b", "description Test", "unknown flg:typo - x", "a"]
+        );
+        assert_eq!(
+            elements[1].span,
+            Span {
+                line: 4,
+                column: 3,
+                end_column: 22
+            }
+        );
+
+        let metadata = CommandMetadata::from_elements(elements);
+        assert_eq!(metadata.description, "Test");
+        assert_eq!(metadata.argument_span(1).map(|s| s.line), Some(6));
+        assert_eq!(metadata.argument_span(2), None);
+    }
+
+    #[test]
+    fn test_examples() {
+        let script_content = r#"#!/bin/bash
+#@description: Deploy
+#@example: shutl deploy prod --region eu
+#@arg:env - Target
+#@example: shutl deploy staging
+"#;
+
+        let dir = tempdir().unwrap();
+        let script_path = create_test_script(dir.path(), "deploy.sh", script_content);
+        let metadata = parse_command_metadata(&script_path);
+
+        assert_eq!(
+            metadata.examples,
+            vec!["shutl deploy prod --region eu", "shutl deploy staging"]
+        );
+        assert_eq!(metadata.arguments.len(), 1);
+    }
+
+    #[test]
+    fn test_stdin_mode() {
+        let dir = tempdir().unwrap();
+        let script_path =
+            create_test_script(dir.path(), "count.sh", "#!/bin/bash\n#@stdin: required\n");
+        assert_eq!(
+            parse_command_metadata(&script_path).stdin,
+            Some(StdinMode::Required)
+        );
+
+        let script_path =
+            create_test_script(dir.path(), "odd.sh", "#!/bin/bash\n#@stdin: sometimes\n");
+        assert_eq!(parse_command_metadata(&script_path).stdin, None);
+        assert!("sometimes".parse::<StdinMode>().is_err());
+    }
+
+    #[test]
+    fn test_output_format() {
+        let dir = tempdir().unwrap();
+        let script_path =
+            create_test_script(dir.path(), "pods.sh", "#!/bin/bash\n#@output: json\n");
+        assert_eq!(
+            parse_command_metadata(&script_path).output,
+            OutputFormat::Json
+        );
+        let script_path = create_test_script(dir.path(), "log.sh", "#!/bin/bash\n");
+        assert_eq!(
+            parse_command_metadata(&script_path).output,
+            OutputFormat::Text
+        );
+        assert!("yaml".parse::<OutputFormat>().is_err());
+    }
+
+    #[test]
+    fn test_env_filter_metadata() {
+        let dir = tempdir().unwrap();
+        let script_path = create_test_script(
+            dir.path(),
+            "aws.sh",
+            "#!/bin/bash\n#@env-allow: AWS_*, HOME\n#@env-allow: TERM\n#@env-deny: AWS_SECRET_*\n",
+        );
+        let env = parse_command_metadata(&script_path).env;
+        assert_eq!(
+            env.allow,
+            Some(vec![
+                "AWS_*".to_string(),
+                "HOME".to_string(),
+                "TERM".to_string()
+            ])
+        );
+        assert_eq!(env.deny, vec!["AWS_SECRET_*"]);
+    }
+
+    #[test]
+    fn test_typescript_metadata_and_interpreter() {
+        let script_content = r#"#!/usr/bin/env -S deno run -A
+//@description: Fetch the changelog
+//@interpreter: bun
+// regular comment
+//@flag:since - Start date [default:yesterday]
+#@flag:ignored - Not a TypeScript comment
+console.log(Deno.env.get("SHUTL_SINCE"));
+"#;
+
+        let dir = tempdir().unwrap();
+        let script_path = create_test_script(dir.path(), "changelog.ts", script_content);
+        let metadata = parse_command_metadata(&script_path);
+
+        assert_eq!(metadata.description, "Fetch the changelog");
+        assert_eq!(metadata.interpreter.as_deref(), Some("bun"));
+        assert!(metadata.build.is_none());
+        assert_eq!(metadata.arguments.len(), 1);
+        assert_eq!(comment_prefix(&script_path), "//");
+        assert_eq!(comment_prefix(Path::new("x.sh")), "#");
+    }
+
+    #[test]
+    fn test_powershell_block_comment_metadata() {
+        let script_content = r#"<#
+@description: Generate a report
+#@arg:month - Month to report on
+.SYNOPSIS
+Not metadata
+@flag:format - Output format [options:csv|json] #>
+#@flag:quiet - Less output [bool]
+Write-Output "$env:SHUTL_MONTH"
+#@flag:ignored - After code
+"#;
+
+        let dir = tempdir().unwrap();
+        let script_path = create_test_script(dir.path(), "report.ps1", script_content);
+        let metadata = parse_command_metadata(&script_path);
+
+        assert_eq!(metadata.description, "Generate a report");
+        let names: Vec<_> = metadata
+            .arguments
+            .iter()
+            .map(|a| match a {
+                LineType::Positional(name, _, _) | LineType::Flag(name, _, _) => name.as_str(),
+                LineType::Description(_) => "",
+            })
+            .collect();
+        assert_eq!(names, vec!["month", "format", "quiet"]);
+
+        // Outside of .ps1 files `<#` is not a comment
+        let sh_path = create_test_script(dir.path(), "report.sh", script_content);
+        assert!(parse_command_metadata(&sh_path).arguments.is_empty());
+    }
+
+    #[test]
+    fn test_fill_template() {
+        assert_eq!(
+            template_refs("{{input}}.{{ format }}"),
+            vec!["input", "format"]
+        );
+        assert!(template_refs("plain {{ unclosed").is_empty());
+        let lookup = |name: &str| (name == "input").then(|| "report".to_string());
+        assert_eq!(fill_template("{{input}}.out", lookup), "report.out");
+        assert_eq!(
+            fill_template("{{other}}-{{ input }}", lookup),
+            "{{other}}-report"
+        );
+        assert_eq!(fill_template("no refs", lookup), "no refs");
+    }
+
+    #[test]
+    fn test_enabled_if() {
+        let dir = tempdir().unwrap();
+        let script_path = create_test_script(
+            dir.path(),
+            "k.sh",
+            "#!/bin/bash\n#@enabled-if: env:PATH\n#@enabled-if: exec:true\n",
+        );
+        let metadata = parse_command_metadata(&script_path);
+        assert_eq!(
+            metadata.enabled_if,
+            vec![
+                Condition::Env("PATH".to_string()),
+                Condition::Exec("true".to_string())
+            ]
+        );
+        assert!(metadata.is_enabled());
+
+        assert!(!Condition::Exec("false".to_string()).holds());
+        assert!(!Condition::Env("SHUTL_TEST_UNSET_VARIABLE".to_string()).holds());
+        assert!("exec:".parse::<Condition>().is_err());
+        assert!("file:/etc".parse::<Condition>().is_err());
+    }
+
+    #[test]
+    fn test_roles() {
+        let dir = tempdir().unwrap();
+        let script_path = create_test_script(
+            dir.path(),
+            "deploy.sh",
+            "#!/bin/bash\n#@roles: ci, laptop\n",
+        );
+        let metadata = parse_command_metadata(&script_path);
+        assert_eq!(metadata.roles, vec!["ci", "laptop"]);
+        let roles = |list: &[&str]| list.iter().map(|r| r.to_string()).collect::<Vec<_>>();
+        assert!(metadata.is_for_roles(&roles(&["laptop", "work"])));
+        assert!(!metadata.is_for_roles(&roles(&["server"])));
+        assert!(metadata.is_for_roles(&[]));
+        assert!(CommandMetadata::default().is_for_roles(&roles(&["server"])));
+    }
+
+    #[test]
+    fn test_sandbox() {
+        let dir = tempdir().unwrap();
+        let script_path = create_test_script(
+            dir.path(),
+            "import.sh",
+            "#!/bin/bash\n#@sandbox: ro-home, net-off\n",
+        );
+        let sandbox = parse_command_metadata(&script_path).sandbox;
+        assert_eq!(sandbox.describe(), "ro-home, net-off");
+
+        // A typo must not drop the restrictions
+        let script_path =
+            create_test_script(dir.path(), "typo.sh", "#!/bin/bash\n#@sandbox: net-of\n");
+        assert_eq!(
+            parse_command_metadata(&script_path).sandbox,
+            Sandbox::strictest()
+        );
+    }
+
+    #[test]
+    fn test_parse_dir_metadata() {
+        let dir = tempdir().unwrap();
+        assert_eq!(parse_dir_metadata(dir.path()), DirMetadata::default());
+
+        fs::write(
+            dir.path().join(DIR_METADATA_FILE),
+            "Deployment scripts\n#@order: prod, staging\n#@command-names: filename\n",
+        )
+        .unwrap();
+        let metadata = parse_dir_metadata(dir.path());
+        assert_eq!(metadata.description, "Deployment scripts");
+        assert_eq!(metadata.order, vec!["prod", "staging"]);
+        assert_eq!(metadata.command_names, Some(CommandNames::Filename));
+        assert!(metadata.defaults.is_empty());
+
+        fs::write(
+            dir.path().join(DIR_METADATA_FILE),
+            "AWS tools\n#@flag:profile - AWS profile\n#@arg:name - Not inherited\n",
+        )
+        .unwrap();
+        let metadata = parse_dir_metadata(dir.path());
+        assert_eq!(metadata.description, "AWS tools");
+        assert_eq!(metadata.defaults.len(), 1);
+        assert_eq!(metadata.defaults[0].span.line, 2);
+
+        // A README describes the directory only as long as there is no `.shutl`
+        fs::write(dir.path().join(DIR_README_FILE), "# Cloud tooling\n").unwrap();
+        assert_eq!(parse_dir_metadata(dir.path()).readme, None);
+        fs::remove_file(dir.path().join(DIR_METADATA_FILE)).unwrap();
+        let metadata = parse_dir_metadata(dir.path());
+        assert_eq!(metadata.description, "Cloud tooling");
+        assert_eq!(metadata.readme, Some(dir.path().join(DIR_README_FILE)));
+    }
+
+    #[test]
+    fn test_localized_descriptions() {
+        let contents = "#!/bin/bash\n#@description: Deploy\n#@description[de]: Ausrollen\n#@description[pt-BR]: Implantar\n#@description[]: ignored\n";
+        let mut metadata =
+            CommandMetadata::from_elements(parse_metadata_elements(Path::new("x.sh"), contents));
+        assert_eq!(metadata.description, "Deploy");
+        assert_eq!(metadata.descriptions.len(), 2);
+        assert!(matches!(
+            metadata.elements[3].element,
+            MetadataElement::Unknown(_)
+        ));
+
+        let locales = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+        metadata.localize(&locales(&["fr_FR", "fr"]));
+        assert_eq!(metadata.description, "Deploy");
+        metadata.localize(&locales(&["pt_BR", "pt"]));
+        assert_eq!(metadata.description, "Implantar");
+        metadata.localize(&locales(&["de_AT", "de"]));
+        assert_eq!(metadata.description, "Ausrollen");
+
+        let dir = parse_dir_metadata_contents(
+            Path::new("/scripts/deploy"),
+            "Deployments\n#@description[de]: Bereitstellungen\n",
+        );
+        assert_eq!(dir.description, "Deployments");
+        assert_eq!(
+            translation(&dir.descriptions, &locales(&["DE"])),
+            Some("Bereitstellungen")
+        );
+    }
+
+    #[test]
+    fn test_readme_description() {
+        assert_eq!(
+            readme_description("# Database [scripts](./db)\n\nBackups and restores.\n"),
+            "Database scripts"
+        );
+        assert_eq!(
+            readme_description(
+                "[![CI](https://ci/badge.svg)](https://ci)\n\n<p>logo</p>\n```\n# not a heading\n```\nScripts for the\n[staging](x) cluster.\n\nMore text.\n"
+            ),
+            "Scripts for the staging cluster."
+        );
+        assert_eq!(readme_description("#hashtag only\n"), "#hashtag only");
+        assert_eq!(readme_description(""), "");
+    }
+
     #[test]
     fn test_parse_metadata_stops_at_code() {
         // Metadata after a non-comment line should be ignored
@@ -546,7 +1848,7 @@ echo "some code"
 "#;
 
         let dir = tempdir().unwrap();
-        let script_path = create_test_script(&dir.path(), "test.sh", script_content);
+        let script_path = create_test_script(dir.path(), "test.sh", script_content);
         let metadata = parse_command_metadata(&script_path);
 
         assert_eq!(metadata.description, "My tool");
@@ -574,7 +1876,7 @@ echo "some code"
 "#;
 
         let dir = tempdir().unwrap();
-        let script_path = create_test_script(&dir.path(), "test.sh", script_content);
+        let script_path = create_test_script(dir.path(), "test.sh", script_content);
         let metadata = parse_command_metadata(&script_path);
 
         assert_eq!(metadata.description, "My tool");
@@ -599,4 +1901,210 @@ echo "some code"
             )
         );
     }
+
+    mod properties {
+        use super::*;
+        use proptest::prelude::*;
+
+        /// A value that survives the annotation syntax: no `,`, `[`, `]`, `|`, `!` or
+        /// surrounding whitespace
+        fn value() -> impl Strategy<Value = String> {
+            "[A-Za-z0-9._/~-]{1,12}"
+        }
+
+        /// A description of an option: no `,`, `(`, `)`, `[`, `]` or `|`
+        fn help() -> impl Strategy<Value = String> {
+            "[A-Za-z0-9]([A-Za-z0-9 .]{0,10}[A-Za-z0-9.])?"
+        }
+
+        fn config() -> impl Strategy<Value = Config> {
+            let arg_type = prop_oneof![
+                Just(None),
+                Just(Some(ArgType::Bool)),
+                Just(Some(ArgType::File)),
+                Just(Some(ArgType::Dir)),
+                Just(Some(ArgType::Path)),
+            ];
+            let complete_options =
+                proptest::option::of((value(), proptest::option::of("[A-Z_]{1,8}")));
+            let flags = proptest::array::uniform7(any::<bool>());
+            let counts = (
+                proptest::option::of(0usize..5),
+                proptest::option::of(0usize..5),
+                proptest::option::of(proptest::char::range('a', 'z')),
+            );
+            (
+                proptest::option::of(value()),
+                arg_type,
+                complete_options,
+                proptest::collection::vec((value(), proptest::option::of(help())), 0..4),
+                flags,
+                counts,
+            )
+                .prop_map(|(default, arg_type, complete, options, flags, counts)| {
+                    let [
+                        required,
+                        secret,
+                        allow_hyphen,
+                        expand,
+                        must_exist,
+                        create,
+                        tristate,
+                    ] = flags;
+                    let is_path =
+                        matches!(arg_type, Some(ArgType::File | ArgType::Dir | ArgType::Path));
+                    // `tristate` implies `bool`
+                    let tristate = tristate && arg_type == Some(ArgType::Bool);
+                    Config {
+                        // Only one default, and a default makes `required` moot
+                        required: required && default.is_none(),
+                        default: default.filter(|_| options.is_empty()),
+                        complete_options: complete.filter(|_| is_path).map(|(path, env_var)| {
+                            CompleteOptions {
+                                path: PathBuf::from(path),
+                                env_var,
+                            }
+                        }),
+                        arg_type,
+                        option_help: options
+                            .iter()
+                            .filter_map(|(option, help)| Some((option.clone(), help.clone()?)))
+                            .collect(),
+                        options: options.into_iter().map(|(option, _)| option).collect(),
+                        complete_command: None,
+                        secret,
+                        min: counts.0,
+                        max: counts.1,
+                        allow_hyphen,
+                        tristate,
+                        expand,
+                        must_exist,
+                        create,
+                        short: counts.2,
+                    }
+                })
+        }
+
+        /// Writes `cfg` back as annotations, the way a formatter of headers would
+        fn format_annotations(cfg: &Config) -> Vec<String> {
+            let mut annotations = Vec::new();
+            let key = match cfg.arg_type {
+                Some(ArgType::Bool) => Some("bool"),
+                Some(ArgType::File) => Some("file"),
+                Some(ArgType::Dir) => Some("dir"),
+                Some(ArgType::Path) => Some("path"),
+                _ => None,
+            };
+            if let Some(key) = key {
+                annotations.push(match &cfg.complete_options {
+                    Some(CompleteOptions { path, env_var }) => match env_var {
+                        Some(var) => format!("{}:{}:{}", key, path.display(), var),
+                        None => format!("{}:{}", key, path.display()),
+                    },
+                    None => key.to_string(),
+                });
+            }
+            if let Some(default) = &cfg.default {
+                annotations.push(format!("default:{}", default));
+            }
+            if !cfg.options.is_empty() {
+                let options: Vec<String> = cfg
+                    .options
+                    .iter()
+                    .map(|option| match cfg.option_help.get(option) {
+                        Some(help) => format!("{}({})", option, help),
+                        None => option.clone(),
+                    })
+                    .collect();
+                annotations.push(format!("options:{}", options.join("|")));
+            }
+            let flags = [
+                (cfg.required, "required"),
+                (cfg.secret, "secret"),
+                (cfg.allow_hyphen, "allow-hyphen"),
+                (cfg.tristate, "tristate"),
+                (cfg.expand, "expand"),
+                (cfg.must_exist, "must-exist"),
+                (cfg.create, "create"),
+            ];
+            annotations.extend(
+                flags
+                    .iter()
+                    .filter(|(set, _)| *set)
+                    .map(|(_, name)| name.to_string()),
+            );
+            if let Some(min) = cfg.min {
+                annotations.push(format!("min:{}", min));
+            }
+            if let Some(max) = cfg.max {
+                annotations.push(format!("max:{}", max));
+            }
+            if let Some(short) = cfg.short {
+                annotations.push(format!("short:{}", short));
+            }
+            annotations
+        }
+
+        proptest! {
+            #[test]
+            fn parse_line_never_panics(line in any::<String>()) {
+                let _ = parse_line(&line);
+                let _ = parse_element(&line);
+            }
+
+            #[test]
+            fn extract_annotations_never_panics(text in any::<String>()) {
+                let (description, annotations) = extract_annotations(&text);
+                if !annotations.is_empty() {
+                    prop_assert!(!description.contains('['));
+                }
+                let _ = parse_annotations(annotations);
+            }
+
+            #[test]
+            fn parse_annotations_never_panics(
+                annotations in proptest::collection::vec(any::<String>(), 0..8),
+            ) {
+                let _ = parse_annotations(annotations);
+            }
+
+            /// Whole headers, mixing metadata lines, comments and PowerShell block comments
+            #[test]
+            fn malformed_headers_never_panic(
+                lines in proptest::collection::vec(("#@|# |<#|#>|@|", any::<String>()), 0..10),
+                powershell in any::<bool>(),
+            ) {
+                let header: String = lines
+                    .iter()
+                    .map(|(marker, text)| format!("{}{}\n", marker, text))
+                    .collect();
+                let path = Path::new(if powershell { "tool.ps1" } else { "tool.sh" });
+                let metadata = CommandMetadata::from_elements(parse_metadata_elements(path, &header));
+                for argument in &metadata.arguments {
+                    if let LineType::Positional(_, _, cfg) | LineType::Flag(_, _, cfg) = argument {
+                        let _ = (cfg.is_multiple(), cfg.min_values());
+                    }
+                }
+                let _ = parse_dir_metadata_contents(Path::new("/scripts/dir"), &header);
+            }
+
+            #[test]
+            fn annotations_round_trip(
+                name in "[a-z][a-z0-9-]{0,10}",
+                description in "[A-Za-z][A-Za-z0-9 .,'-]{0,30}[A-Za-z0-9.]",
+                cfg in config(),
+            ) {
+                let annotations = format_annotations(&cfg);
+                let line = if annotations.is_empty() {
+                    format!("flag:{} - {}", name, description)
+                } else {
+                    format!("flag:{} - {} [{}]", name, description, annotations.join(", "))
+                };
+                prop_assert_eq!(
+                    parse_line(&line),
+                    Some(LineType::Flag(name, description, cfg))
+                );
+            }
+        }
+    }
 }
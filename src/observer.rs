@@ -0,0 +1,97 @@
+//! Callbacks around script runs. Subsystems that react to runs (history, the audit log,
+//! and library users' own metrics or notifications) implement [`ExecutionObserver`] and
+//! [`register`] it once; `script::execute_script_as` notifies every registered observer.
+
+use std::path::PathBuf;
+use std::process::ExitStatus;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// A script run as observers see it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Run {
+    /// Command path the script was invoked by, e.g. `["deploy", "prod"]`
+    pub components: Vec<String>,
+    pub script_path: PathBuf,
+    /// Arguments after the command path, with secret values masked
+    pub args: Vec<String>,
+}
+
+/// Receives the stages of a script run. Every method does nothing by default.
+pub trait ExecutionObserver: Send + Sync {
+    /// The command path was resolved to a script. Also called for `--shutl-noexec`.
+    fn on_resolve(&self, _run: &Run) {}
+
+    /// The environment shutl sets for the script, with secret values masked
+    fn on_env(&self, _run: &Run, _envs: &[(String, String)]) {}
+
+    /// The script is about to start (after a compiled script was built)
+    fn on_start(&self, _run: &Run) {}
+
+    /// The script exited. Not called if it could not be started.
+    fn on_exit(&self, _run: &Run, _status: ExitStatus, _elapsed: Duration) {}
+}
+
+static OBSERVERS: Mutex<Vec<Arc<dyn ExecutionObserver>>> = Mutex::new(Vec::new());
+
+/// Registers an observer for all following runs in this process
+pub fn register(observer: impl ExecutionObserver + 'static) {
+    OBSERVERS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .push(Arc::new(observer));
+}
+
+/// Calls `f` for every registered observer, in registration order. The lock is not held
+/// while observers run, so they may register others.
+pub(crate) fn notify(f: impl Fn(&dyn ExecutionObserver)) {
+    let observers = OBSERVERS.lock().unwrap_or_else(|e| e.into_inner()).clone();
+    for observer in &observers {
+        f(observer.as_ref());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Recorder {
+        script: PathBuf,
+        events: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl ExecutionObserver for Recorder {
+        fn on_resolve(&self, run: &Run) {
+            if run.script_path == self.script {
+                self.events.lock().unwrap().push("resolve".to_string());
+            }
+        }
+
+        fn on_start(&self, run: &Run) {
+            if run.script_path == self.script {
+                self.events.lock().unwrap().push("start".to_string());
+            }
+        }
+    }
+
+    #[test]
+    fn test_notify_registered_observers() {
+        // Observers are process-wide, so the recorder only counts its own script
+        let script = PathBuf::from("/observer/test.sh");
+        let events = Arc::new(Mutex::new(Vec::new()));
+        register(Recorder {
+            script: script.clone(),
+            events: events.clone(),
+        });
+
+        let run = Run {
+            components: vec!["test".to_string()],
+            script_path: script,
+            args: Vec::new(),
+        };
+        notify(|o| o.on_resolve(&run));
+        notify(|o| o.on_env(&run, &[]));
+        notify(|o| o.on_start(&run));
+        assert_eq!(*events.lock().unwrap(), vec!["resolve", "start"]);
+    }
+}
@@ -0,0 +1,106 @@
+//! Paging long output (help, `shutl list`, `shutl docs`) like git: output that doesn't fit
+//! the terminal goes through `pager` from the config, `$PAGER` or `less`. Output that is
+//! not a terminal, `--no-pager` and an empty `pager` setting print it directly.
+
+use crate::config;
+use std::io::{IsTerminal, Write};
+use std::process::{Command, Stdio};
+
+/// Options for `less` unless `$LESS` is set: quit if the text fits after all, keep colors
+/// and don't clear the screen
+const LESS_DEFAULT: &str = "FRX";
+
+/// Picks the pager from the `pager` setting, `$PAGER` or `less`. `None` if paging is
+/// turned off by an empty command.
+pub fn pager_command(setting: Option<&str>, pager_var: Option<&str>) -> Option<String> {
+    let command = setting.or(pager_var).unwrap_or("less").trim();
+    (!command.is_empty() && command != "cat").then(|| command.to_string())
+}
+
+/// Checks whether `text` is too long for a terminal of `height` rows
+pub fn exceeds(text: &str, height: usize) -> bool {
+    // The prompt takes the last row
+    text.lines().count() >= height
+}
+
+/// Gets the number of rows of the terminal on stdout, or `$LINES`
+fn terminal_height() -> Option<usize> {
+    // SAFETY: TIOCGWINSZ only writes the winsize struct passed to it
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    if unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } == 0
+        && size.ws_row > 0
+    {
+        return Some(size.ws_row.into());
+    }
+    std::env::var("LINES").ok()?.parse().ok()
+}
+
+/// Gets the pager to show `text` in, if it should be paged
+pub fn pager_for(text: &str) -> Option<String> {
+    if config::no_pager() || !std::io::stdout().is_terminal() {
+        return None;
+    }
+    let pager = pager_command(
+        config::get().pager.as_deref(),
+        std::env::var("PAGER").ok().as_deref(),
+    )?;
+    exceeds(text, terminal_height()?).then_some(pager)
+}
+
+/// Shows `text` in `pager` (run by `sh`) and waits for it to be closed. Returns false if
+/// the pager could not be started, so the caller prints the text itself.
+pub fn show(pager: &str, text: &str) -> bool {
+    let mut command = Command::new("sh");
+    command.arg("-c").arg(pager).stdin(Stdio::piped());
+    if std::env::var_os("LESS").is_none() {
+        command.env("LESS", LESS_DEFAULT);
+    }
+    let Ok(mut child) = command.spawn() else {
+        return false;
+    };
+    // Quitting the pager early closes the pipe, which is not an error
+    let _ = child
+        .stdin
+        .take()
+        .expect("stdin is piped")
+        .write_all(text.as_bytes());
+    match child.wait() {
+        // `sh` exits with 127 when the pager is not installed
+        Ok(status) => status.code() != Some(127),
+        Err(_) => false,
+    }
+}
+
+/// Prints `text`, through the pager if it doesn't fit the terminal
+pub fn page(text: &str) {
+    if let Some(pager) = pager_for(text)
+        && show(&pager, text)
+    {
+        return;
+    }
+    print!("{}", text);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pager_command() {
+        assert_eq!(pager_command(None, None), Some("less".to_string()));
+        assert_eq!(pager_command(None, Some("most")), Some("most".to_string()));
+        assert_eq!(
+            pager_command(Some("less -S"), Some("most")),
+            Some("less -S".to_string())
+        );
+        assert_eq!(pager_command(Some(""), Some("most")), None);
+        assert_eq!(pager_command(None, Some("cat")), None);
+    }
+
+    #[test]
+    fn test_exceeds() {
+        assert!(!exceeds("a\nb\n", 24));
+        assert!(exceeds(&"line\n".repeat(24), 24));
+        assert!(!exceeds(&"line\n".repeat(23), 24));
+    }
+}
@@ -0,0 +1,148 @@
+//! Platform directories and `~`/`$VAR` expansion. With the `binary` feature the directories
+//! come from the `dirs` crate; without it they follow `$HOME` and the XDG base directory
+//! variables, so library users don't need `dirs`.
+
+use std::borrow::Cow;
+use std::env::VarError;
+use std::path::{Path, PathBuf};
+
+/// Gets the home directory of the current user
+#[cfg(feature = "binary")]
+pub fn home_dir() -> Option<PathBuf> {
+    dirs::home_dir()
+}
+
+/// Gets the home directory of the current user
+#[cfg(not(feature = "binary"))]
+pub fn home_dir() -> Option<PathBuf> {
+    std::env::home_dir()
+}
+
+/// Gets the directory for config files, e.g. `~/.config`
+#[cfg(feature = "binary")]
+pub fn config_dir() -> Option<PathBuf> {
+    dirs::config_dir()
+}
+
+/// Gets the directory for config files, e.g. `~/.config`
+#[cfg(not(feature = "binary"))]
+pub fn config_dir() -> Option<PathBuf> {
+    xdg_dir("XDG_CONFIG_HOME", ".config")
+}
+
+/// Gets the directory for cached data, e.g. `~/.cache`
+#[cfg(feature = "binary")]
+pub fn cache_dir() -> Option<PathBuf> {
+    dirs::cache_dir()
+}
+
+/// Gets the directory for cached data, e.g. `~/.cache`
+#[cfg(not(feature = "binary"))]
+pub fn cache_dir() -> Option<PathBuf> {
+    xdg_dir("XDG_CACHE_HOME", ".cache")
+}
+
+/// Gets the directory for local application data, e.g. `~/.local/share`
+#[cfg(feature = "binary")]
+pub fn data_local_dir() -> Option<PathBuf> {
+    dirs::data_local_dir()
+}
+
+/// Gets the directory for local application data, e.g. `~/.local/share`
+#[cfg(not(feature = "binary"))]
+pub fn data_local_dir() -> Option<PathBuf> {
+    xdg_dir("XDG_DATA_HOME", ".local/share")
+}
+
+/// Reads an XDG directory variable, falling back to `fallback` under the home directory.
+/// Relative values are ignored, as the XDG spec requires.
+#[cfg(not(feature = "binary"))]
+fn xdg_dir(var: &str, fallback: &str) -> Option<PathBuf> {
+    std::env::var_os(var)
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| home_dir().map(|home| home.join(fallback)))
+}
+
+/// Expands a leading `~` and `$VAR`/`${VAR}` references. Unknown variables are an error.
+pub fn expand(input: &str) -> Result<Cow<'_, str>, shellexpand::LookupError<VarError>> {
+    shellexpand::full_with_context(
+        input,
+        || home_dir().map(|home| home.to_string_lossy().into_owned()),
+        |var| std::env::var(var).map(Some),
+    )
+}
+
+/// Moves the directory `from` to `to`, which must not exist. Across filesystems the tree
+/// is copied, keeping symlinks and permissions, and `from` is only removed once the copy
+/// is complete.
+pub fn move_dir(from: &Path, to: &Path) -> std::io::Result<()> {
+    match std::fs::rename(from, to) {
+        Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => {
+            if let Err(e) = copy_dir(from, to) {
+                let _ = std::fs::remove_dir_all(to);
+                return Err(e);
+            }
+            std::fs::remove_dir_all(from)
+        }
+        result => result,
+    }
+}
+
+fn copy_dir(from: &Path, to: &Path) -> std::io::Result<()> {
+    std::fs::create_dir(to)?;
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        let file_type = entry.file_type()?;
+        if file_type.is_symlink() {
+            std::os::unix::fs::symlink(std::fs::read_link(entry.path())?, &target)?;
+        } else if file_type.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            std::fs::copy(entry.path(), &target)?;
+        }
+    }
+    std::fs::set_permissions(to, std::fs::metadata(from)?.permissions())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand() {
+        let home = home_dir().unwrap();
+        assert_eq!(
+            expand("~/scripts").unwrap(),
+            home.join("scripts").to_string_lossy()
+        );
+        assert_eq!(expand("/opt/$PATH_THAT_IS_UNSET").ok(), None);
+        assert_eq!(expand("plain").unwrap(), "plain");
+    }
+
+    #[test]
+    fn test_copy_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let from = dir.path().join("from");
+        std::fs::create_dir_all(from.join("tools")).unwrap();
+        std::fs::write(from.join("tools/lint.sh"), "#!/bin/bash\n").unwrap();
+        std::os::unix::fs::symlink("tools/lint.sh", from.join("lint")).unwrap();
+
+        let to = dir.path().join("to");
+        copy_dir(&from, &to).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(to.join("tools/lint.sh")).unwrap(),
+            "#!/bin/bash\n"
+        );
+        assert_eq!(
+            std::fs::read_link(to.join("lint")).unwrap(),
+            PathBuf::from("tools/lint.sh")
+        );
+
+        let moved = dir.path().join("moved");
+        move_dir(&to, &moved).unwrap();
+        assert!(!to.exists());
+        assert!(moved.join("tools/lint.sh").exists());
+    }
+}
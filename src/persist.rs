@@ -0,0 +1,164 @@
+//! Writing shutl's own files safely while other shutl processes use them, e.g. completions
+//! firing while a script runs. Logs (history, audit log) are appended under an exclusive
+//! `flock`; caches and state files are written to a temporary file and renamed into place,
+//! so readers see the old or the new content but never half of it. Read-modify-write
+//! sequences (presets, the imports lockfile) hold a lock on the file's directory.
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::ops::{Deref, DerefMut};
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+/// An open file or directory with an exclusive advisory lock, released when dropped
+pub struct Locked(File);
+
+impl Deref for Locked {
+    type Target = File;
+
+    fn deref(&self) -> &File {
+        &self.0
+    }
+}
+
+impl DerefMut for Locked {
+    fn deref_mut(&mut self) -> &mut File {
+        &mut self.0
+    }
+}
+
+/// Waits for an exclusive lock on `file`
+fn lock(file: File) -> std::io::Result<Locked> {
+    loop {
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } == 0 {
+            return Ok(Locked(file));
+        }
+        let error = std::io::Error::last_os_error();
+        if error.kind() != std::io::ErrorKind::Interrupted {
+            return Err(error);
+        }
+    }
+}
+
+/// Opens `path` for appending, creating it and its directory if needed, and locks it. If
+/// the file was renamed away (rotated) while waiting for the lock, the new one is opened.
+pub fn open_append_locked(path: &Path) -> std::io::Result<Locked> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    loop {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let locked = lock(file)?;
+        let opened = locked.metadata()?;
+        let current = std::fs::metadata(path).ok();
+        if current.is_some_and(|m| m.dev() == opened.dev() && m.ino() == opened.ino()) {
+            return Ok(locked);
+        }
+    }
+}
+
+/// Appends `data` to `path` in one write under the lock, so concurrent appends never
+/// interleave
+pub fn append(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    open_append_locked(path)?.write_all(data)
+}
+
+/// Locks the directory of `path` (creating it if needed) for a read-modify-write of `path`
+/// with [`write_atomic`]. The file itself cannot hold the lock as it is replaced.
+pub fn lock_dir_of(path: &Path) -> std::io::Result<Locked> {
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    std::fs::create_dir_all(dir)?;
+    lock(File::open(dir)?)
+}
+
+/// Replaces `path` with `data` through a hidden temporary file in the same directory,
+/// creating the directory if needed
+pub fn write_atomic(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    write_atomic_impl(path, data, None)
+}
+
+/// Like [`write_atomic`], but the file has `mode` (regardless of the umask) as soon as it
+/// appears under its name, e.g. so an executable is never seen without its `x` bits
+pub fn write_atomic_with_mode(path: &Path, data: &[u8], mode: u32) -> std::io::Result<()> {
+    write_atomic_impl(path, data, Some(mode))
+}
+
+fn write_atomic_impl(path: &Path, data: &[u8], mode: Option<u32>) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let temporary = temporary_path(path);
+    let written = File::create(&temporary)
+        .and_then(|mut file| {
+            if let Some(mode) = mode {
+                file.set_permissions(std::fs::Permissions::from_mode(mode))?;
+            }
+            file.write_all(data).and_then(|_| file.sync_all())
+        })
+        .and_then(|_| std::fs::rename(&temporary, path));
+    if written.is_err() {
+        let _ = std::fs::remove_file(&temporary);
+    }
+    written
+}
+
+/// Gets the temporary file [`write_atomic`] writes `path` to, e.g. `.history.jsonl.123.tmp`
+fn temporary_path(path: &Path) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{}.{}.tmp", name, std::process::id()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_concurrent_appends() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("logs/history.jsonl");
+        let threads: Vec<_> = (0..8)
+            .map(|n| {
+                let path = path.clone();
+                std::thread::spawn(move || {
+                    let line = format!("{}\n", n.to_string().repeat(5000));
+                    for _ in 0..20 {
+                        append(&path, line.as_bytes()).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(contents.lines().count(), 160);
+        for line in contents.lines() {
+            assert_eq!(line.len(), 5000);
+            assert!(line.chars().all(|c| c == line.chars().next().unwrap()));
+        }
+    }
+
+    #[test]
+    fn test_write_atomic() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("cache/index.json");
+        let _lock = lock_dir_of(&path).unwrap();
+        write_atomic(&path, b"old").unwrap();
+        write_atomic(&path, b"new").unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"new");
+        // Only the file itself is left behind
+        let names: Vec<_> = std::fs::read_dir(dir.path().join("cache"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(names, vec!["index.json"]);
+    }
+}
@@ -0,0 +1,131 @@
+//! Named argument sets per command (`shutl preset save deploy prod-eu -- --region eu`).
+//! They are stored as TOML below the scripts directory, one file per command, so they are
+//! shared along with the scripts.
+
+use crate::persist;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Directory below the scripts directory holding the presets
+pub const PRESETS_DIR: &str = ".presets";
+
+/// Runs a script with a preset: `shutl deploy --shutl-preset prod-eu`
+pub const PRESET_FLAG: &str = "--shutl-preset";
+
+/// Suffix of the short form `shutl deploy prod-eu@preset`
+pub const PRESET_SUFFIX: &str = "@preset";
+
+/// Gets the file with the presets of the command `components` below `scripts_dir`,
+/// e.g. `.presets/deploy/prod.toml`
+pub fn presets_file(scripts_dir: &Path, components: &[String]) -> PathBuf {
+    let mut path = scripts_dir.join(PRESETS_DIR);
+    path.extend(components);
+    path.set_extension("toml");
+    path
+}
+
+/// Reads the presets of a file, by name. A missing or invalid file has none.
+pub fn load(path: &Path) -> BTreeMap<String, Vec<String>> {
+    let Ok(content) = std::fs::read_to_string(path) else {
+        return BTreeMap::new();
+    };
+    toml::from_str(&content).unwrap_or_else(|e| {
+        log::warn!("Ignoring invalid presets file {}: {}", path.display(), e);
+        BTreeMap::new()
+    })
+}
+
+/// Reads the presets of every command below `scripts_dir`, by command path
+/// (e.g. `deploy/prod`)
+pub fn load_all(scripts_dir: &Path) -> BTreeMap<String, BTreeMap<String, Vec<String>>> {
+    let mut all = BTreeMap::new();
+    let root = scripts_dir.join(PRESETS_DIR);
+    let mut dirs = vec![root.clone()];
+    while let Some(dir) = dirs.pop() {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if path.extension().is_some_and(|ext| ext == "toml")
+                && let Ok(relative) = path.with_extension("").strip_prefix(&root)
+            {
+                let command = relative.to_string_lossy().replace('\\', "/");
+                all.insert(command, load(&path));
+            }
+        }
+    }
+    all
+}
+
+/// Adds or replaces the preset `name`
+pub fn save(path: &Path, name: &str, args: &[String]) -> std::io::Result<()> {
+    let _lock = persist::lock_dir_of(path)?;
+    let mut presets = load(path);
+    presets.insert(name.to_string(), args.to_vec());
+    write(path, &presets)
+}
+
+/// Removes the preset `name`; returns whether it existed. The file is removed with the
+/// last preset.
+pub fn remove(path: &Path, name: &str) -> std::io::Result<bool> {
+    let _lock = persist::lock_dir_of(path)?;
+    let mut presets = load(path);
+    if presets.remove(name).is_none() {
+        return Ok(false);
+    }
+    if presets.is_empty() {
+        std::fs::remove_file(path)?;
+    } else {
+        write(path, &presets)?;
+    }
+    Ok(true)
+}
+
+/// Checks a preset name: not empty, no leading `-`, no whitespace, `/` or `@`
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('-')
+        && !name.contains(|c: char| c.is_whitespace() || c == '/' || c == '@')
+}
+
+fn write(path: &Path, presets: &BTreeMap<String, Vec<String>>) -> std::io::Result<()> {
+    let content = toml::to_string(presets).map_err(std::io::Error::other)?;
+    persist::write_atomic(path, content.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_save_and_remove_presets() {
+        let dir = tempdir().unwrap();
+        let components = vec!["deploy".to_string(), "prod".to_string()];
+        let path = presets_file(dir.path(), &components);
+        assert_eq!(path, dir.path().join(".presets/deploy/prod.toml"));
+        assert!(load(&path).is_empty());
+
+        let args = vec!["--region".to_string(), "eu".to_string()];
+        save(&path, "prod-eu", &args).unwrap();
+        save(&path, "prod-us", &[]).unwrap();
+        assert_eq!(load(&path).get("prod-eu"), Some(&args));
+        assert_eq!(load(&path).len(), 2);
+
+        assert!(remove(&path, "prod-us").unwrap());
+        assert!(!remove(&path, "prod-us").unwrap());
+        assert!(remove(&path, "prod-eu").unwrap());
+        assert!(!path.exists());
+
+        save(&path, "prod-eu", &args).unwrap();
+        let all = load_all(dir.path());
+        assert_eq!(all.keys().collect::<Vec<_>>(), vec!["deploy/prod"]);
+
+        assert!(is_valid_name("prod-eu"));
+        assert!(!is_valid_name("prod@eu"));
+        assert!(!is_valid_name("--region"));
+    }
+}
@@ -0,0 +1,202 @@
+//! Housekeeping for long-lived trees (`shutl prune`): broken symlinks, empty directories
+//! and presets of removed commands in the scripts directory, and old files in the cache.
+
+use crate::preset;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Why an entry is no longer needed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reason {
+    BrokenSymlink,
+    /// A directory holding nothing but (possibly nested) empty directories
+    EmptyDir,
+    /// Presets of a command that no longer exists
+    OrphanedPresets,
+    /// A cached build or completer output that has not been refreshed for a while
+    StaleCache,
+    /// Output of an old `run-all`
+    OldLog,
+}
+
+impl Reason {
+    pub fn describe(&self) -> &'static str {
+        match self {
+            Reason::BrokenSymlink => "broken symlink",
+            Reason::EmptyDir => "empty directory",
+            Reason::OrphanedPresets => "presets of a removed command",
+            Reason::StaleCache => "stale cache entry",
+            Reason::OldLog => "old log",
+        }
+    }
+}
+
+/// An entry `shutl prune` removes
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stale {
+    pub reason: Reason,
+    pub path: PathBuf,
+}
+
+/// Finds broken symlinks, empty directories and orphaned presets in `scripts_dir`.
+/// `command_exists` tells whether a command path (e.g. `["deploy", "prod"]`) still
+/// resolves to a script. Hidden entries other than the presets are left alone.
+pub fn find_in_scripts_dir(
+    scripts_dir: &Path,
+    command_exists: impl Fn(&[String]) -> bool,
+) -> Vec<Stale> {
+    let mut stale = Vec::new();
+    let mut dirs = vec![scripts_dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        let mut entries: Vec<_> = entries.flatten().map(|entry| entry.path()).collect();
+        entries.sort();
+        for path in entries {
+            if path
+                .file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with('.'))
+            {
+                continue;
+            }
+            if path.is_symlink() {
+                if !path.exists() {
+                    stale.push(Stale {
+                        reason: Reason::BrokenSymlink,
+                        path,
+                    });
+                }
+            } else if path.is_dir() {
+                if is_empty_tree(&path) {
+                    stale.push(Stale {
+                        reason: Reason::EmptyDir,
+                        path,
+                    });
+                } else {
+                    dirs.push(path);
+                }
+            }
+        }
+    }
+
+    for command in preset::load_all(scripts_dir).into_keys() {
+        let components: Vec<String> = command.split('/').map(String::from).collect();
+        if !command_exists(&components) {
+            stale.push(Stale {
+                reason: Reason::OrphanedPresets,
+                path: preset::presets_file(scripts_dir, &components),
+            });
+        }
+    }
+    stale
+}
+
+/// Finds the files below `dir` last modified more than `max_age` ago
+pub fn find_old_files(dir: &Path, reason: Reason, max_age: Duration) -> Vec<Stale> {
+    let Some(cutoff) = SystemTime::now().checked_sub(max_age) else {
+        return Vec::new();
+    };
+    let mut stale = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            if metadata.is_dir() {
+                dirs.push(entry.path());
+            } else if metadata.modified().is_ok_and(|modified| modified < cutoff) {
+                stale.push(Stale {
+                    reason,
+                    path: entry.path(),
+                });
+            }
+        }
+    }
+    stale.sort_by(|a, b| a.path.cmp(&b.path));
+    stale
+}
+
+/// Removes a stale entry
+pub fn remove(stale: &Stale) -> std::io::Result<()> {
+    if stale.reason == Reason::EmptyDir {
+        std::fs::remove_dir_all(&stale.path)
+    } else {
+        std::fs::remove_file(&stale.path)
+    }
+}
+
+/// Checks whether `dir` contains nothing but empty directories
+fn is_empty_tree(dir: &Path) -> bool {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return false;
+    };
+    entries.flatten().all(|entry| {
+        let path = entry.path();
+        !path.is_symlink() && path.is_dir() && is_empty_tree(&path)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_find_stale_entries() {
+        let dir = tempdir().unwrap();
+        let scripts = dir.path();
+        std::fs::create_dir_all(scripts.join("old/empty")).unwrap();
+        std::fs::create_dir_all(scripts.join("tools")).unwrap();
+        std::fs::write(scripts.join("tools/lint.sh"), "").unwrap();
+        std::os::unix::fs::symlink(scripts.join("gone.sh"), scripts.join("tools/gone")).unwrap();
+        std::fs::create_dir(scripts.join(".git")).unwrap();
+        preset::save(
+            &preset::presets_file(scripts, &["tools".to_string(), "lint".to_string()]),
+            "fix",
+            &[],
+        )
+        .unwrap();
+        preset::save(
+            &preset::presets_file(scripts, &["deploy".to_string()]),
+            "prod",
+            &[],
+        )
+        .unwrap();
+
+        let stale = find_in_scripts_dir(scripts, |components| components[0] == "tools");
+        assert_eq!(
+            stale,
+            vec![
+                Stale {
+                    reason: Reason::EmptyDir,
+                    path: scripts.join("old"),
+                },
+                Stale {
+                    reason: Reason::BrokenSymlink,
+                    path: scripts.join("tools/gone"),
+                },
+                Stale {
+                    reason: Reason::OrphanedPresets,
+                    path: scripts.join(".presets/deploy.toml"),
+                },
+            ]
+        );
+        stale.iter().for_each(|s| remove(s).unwrap());
+        assert!(!scripts.join("old").exists());
+
+        let logs = dir.path().join("logs");
+        std::fs::create_dir(&logs).unwrap();
+        let old = std::fs::File::create(logs.join("old.log")).unwrap();
+        old.set_modified(SystemTime::now() - Duration::from_secs(3 * 86400))
+            .unwrap();
+        std::fs::write(logs.join("new.log"), "").unwrap();
+        let stale = find_old_files(&logs, Reason::OldLog, Duration::from_secs(86400));
+        assert_eq!(stale.len(), 1);
+        assert_eq!(stale[0].path, logs.join("old.log"));
+    }
+}
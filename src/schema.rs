@@ -0,0 +1,220 @@
+//! JSON Schema of a command's arguments (`shutl schema <command>`), for tools that build
+//! forms for scripts or validate their input before running them. Properties are named
+//! like the arguments; `x-shutl-kind`, `x-shutl-position` and `x-shutl-short` tell how
+//! each becomes part of the command line.
+
+use crate::metadata::{ArgType, CommandMetadata, Config, LineType, template_refs};
+use serde_json::{Map, Value, json};
+
+/// The JSON Schema dialect of the generated schemas
+pub const DIALECT: &str = "https://json-schema.org/draft/2020-12/schema";
+
+/// Describes a single value of an argument: its type, allowed values and their help
+fn value_schema(cfg: &Config) -> Map<String, Value> {
+    let mut schema = Map::new();
+    let json_type = match &cfg.arg_type {
+        Some(ArgType::Bool) => "boolean",
+        _ => "string",
+    };
+    schema.insert("type".into(), json!(json_type));
+    match &cfg.arg_type {
+        Some(ArgType::File) => {
+            schema.insert("x-shutl-type".into(), json!("file"));
+        }
+        Some(ArgType::Dir) => {
+            schema.insert("x-shutl-type".into(), json!("dir"));
+        }
+        Some(ArgType::Path) => {
+            schema.insert("x-shutl-type".into(), json!("path"));
+        }
+        Some(ArgType::Custom(custom)) => {
+            schema.insert("x-shutl-type".into(), json!(custom.name));
+        }
+        _ => {}
+    }
+    if cfg.options.is_empty() {
+        return schema;
+    }
+    if cfg.option_help.is_empty() {
+        schema.insert("enum".into(), json!(cfg.options));
+    } else {
+        // `oneOf` with titles is how form generators label the choices
+        let choices: Vec<Value> = cfg
+            .options
+            .iter()
+            .map(|option| match cfg.option_help.get(option) {
+                Some(help) => json!({ "const": option, "title": help }),
+                None => json!({ "const": option }),
+            })
+            .collect();
+        schema.insert("oneOf".into(), Value::Array(choices));
+    }
+    schema
+}
+
+/// Gets the default as the schema states it. Secret defaults are left out, as are
+/// defaults built from other arguments, which only exist once those are known.
+fn default_value(cfg: &Config) -> Option<Value> {
+    if cfg.arg_type == Some(ArgType::Bool) {
+        return match cfg.bool_default() {
+            Some(default) => Some(json!(default)),
+            None if cfg.tristate => None,
+            None => Some(json!(false)),
+        };
+    }
+    let default = cfg.default.as_ref()?;
+    if cfg.secret || !template_refs(default).is_empty() {
+        return None;
+    }
+    Some(if cfg.is_multiple() {
+        json!([default])
+    } else {
+        json!(default)
+    })
+}
+
+/// Describes one argument as a property
+fn property(description: &str, cfg: &Config, kind: &str, position: Option<usize>) -> Value {
+    let value = value_schema(cfg);
+    let mut schema = if cfg.is_multiple() {
+        let mut array = Map::new();
+        array.insert("type".into(), json!("array"));
+        array.insert("items".into(), Value::Object(value));
+        let min = cfg.min_values();
+        if min > 0 {
+            array.insert("minItems".into(), json!(min));
+        }
+        if let Some(max) = cfg.max {
+            array.insert("maxItems".into(), json!(max));
+        }
+        array
+    } else {
+        value
+    };
+    if !description.is_empty() {
+        schema.insert("description".into(), json!(description));
+    }
+    if let Some(default) = default_value(cfg) {
+        schema.insert("default".into(), default);
+    }
+    if cfg.secret {
+        schema.insert("writeOnly".into(), json!(true));
+    }
+    schema.insert("x-shutl-kind".into(), json!(kind));
+    if let Some(short) = cfg.short {
+        schema.insert("x-shutl-short".into(), json!(short.to_string()));
+    }
+    if let Some(position) = position {
+        schema.insert("x-shutl-position".into(), json!(position));
+    }
+    Value::Object(schema)
+}
+
+/// Builds the JSON Schema of the arguments of `command` (e.g. `deploy prod`), an object
+/// with a property per positional argument and flag
+pub fn command_schema(command: &str, metadata: &CommandMetadata) -> Value {
+    let mut properties = Map::new();
+    let mut required = Vec::new();
+    let mut position = 0;
+    for arg in &metadata.arguments {
+        let (name, description, cfg, kind) = match arg {
+            LineType::Positional(name, description, cfg) => (name, description, cfg, "positional"),
+            LineType::Flag(name, description, cfg) => (name, description, cfg, "flag"),
+            LineType::Description(_) => continue,
+        };
+        let is_positional = kind == "positional";
+        let property = property(description, cfg, kind, is_positional.then_some(position));
+        if is_positional {
+            position += 1;
+        }
+        let needed = if cfg.is_multiple() {
+            cfg.is_required() || cfg.min_values() > 0
+        } else {
+            cfg.is_required() || (is_positional && cfg.default.is_none())
+        };
+        if needed {
+            required.push(name.clone());
+        }
+        properties.insert(name.clone(), property);
+    }
+
+    let mut schema = Map::new();
+    schema.insert("$schema".into(), json!(DIALECT));
+    schema.insert("title".into(), json!(command));
+    if !metadata.description.is_empty() {
+        schema.insert("description".into(), json!(metadata.description));
+    }
+    schema.insert("type".into(), json!("object"));
+    schema.insert("properties".into(), Value::Object(properties));
+    if !required.is_empty() {
+        schema.insert("required".into(), json!(required));
+    }
+    schema.insert("additionalProperties".into(), json!(false));
+    Value::Object(schema)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::parse_metadata_elements;
+    use std::path::Path;
+
+    fn schema(header: &str) -> Value {
+        let metadata =
+            CommandMetadata::from_elements(parse_metadata_elements(Path::new("x.sh"), header));
+        command_schema("deploy prod", &metadata)
+    }
+
+    #[test]
+    fn test_command_schema() {
+        let schema = schema(
+            "#!/bin/bash
+#@description: Deploy the service
+#@arg:env - Target [options:dev|prod]
+#@arg:...services - Services [min:1]
+#@flag:region - Region [default:eu,short:r]
+#@flag:mode - Mode [options:fast(quick)|safe]
+#@flag:dry-run - Only print [bool]
+#@flag:force - Force [tristate]
+#@flag:token - API token [secret,default:abc]
+#@flag:out - Output [file,default:{{env}}.log]
+#@flag:team - Team [required]
+",
+        );
+        assert_eq!(schema["$schema"], DIALECT);
+        assert_eq!(schema["title"], "deploy prod");
+        assert_eq!(schema["description"], "Deploy the service");
+        assert_eq!(schema["required"], json!(["env", "services", "team"]));
+        assert_eq!(schema["additionalProperties"], false);
+
+        let properties = &schema["properties"];
+        assert_eq!(
+            properties["env"],
+            json!({
+                "type": "string",
+                "description": "Target",
+                "enum": ["dev", "prod"],
+                "x-shutl-kind": "positional",
+                "x-shutl-position": 0,
+            })
+        );
+        assert_eq!(properties["services"]["type"], "array");
+        assert_eq!(properties["services"]["items"]["type"], "string");
+        assert_eq!(properties["services"]["minItems"], 1);
+        assert_eq!(properties["services"]["x-shutl-position"], 1);
+        assert_eq!(properties["region"]["default"], "eu");
+        assert_eq!(properties["region"]["x-shutl-kind"], "flag");
+        assert_eq!(properties["region"]["x-shutl-short"], "r");
+        assert_eq!(
+            properties["mode"]["oneOf"],
+            json!([{ "const": "fast", "title": "quick" }, { "const": "safe" }])
+        );
+        assert_eq!(properties["dry-run"]["type"], "boolean");
+        assert_eq!(properties["dry-run"]["default"], false);
+        assert_eq!(properties["force"].get("default"), None);
+        assert_eq!(properties["token"]["writeOnly"], true);
+        assert_eq!(properties["token"].get("default"), None);
+        assert_eq!(properties["out"]["x-shutl-type"], "file");
+        assert_eq!(properties["out"].get("default"), None);
+    }
+}
@@ -1,119 +1,1122 @@
+use crate::build;
+use crate::capture::{self, Input};
+use crate::clipboard;
+use crate::command::{build_script_command_for_help, command_names_from, command_names_in};
+use crate::config;
+use crate::env_filter::EnvFilter;
+use crate::errors::ShutlError;
 use crate::get_scripts_dir;
-use crate::metadata::{ArgType, LineType, parse_command_metadata};
+use crate::k8s::Pod;
+use crate::limits::ResourceLimits;
+use crate::metadata::{
+    ArgType, CommandMetadata, CommandNames, Config, LineType, OutputFormat, StdinMode,
+    fill_template, parse_command_metadata, roles_match, template_refs,
+};
+use crate::observer::{self, Run};
+use crate::paths;
+use crate::review::Review;
+use crate::roots::{self, Lookup};
+use crate::sandbox::{self, Sandbox};
+use crate::source::{EntryKind, FsSource, ScriptSource};
+use crate::tmux;
 use clap::ArgMatches;
-use log::debug;
-use std::path::Path;
-use std::process::Command as ProcessCommand;
+use clap::parser::ValueSource;
+use is_executable::IsExecutable;
+use log::{debug, warn};
+use std::collections::HashSet;
+use std::io::IsTerminal;
+use std::path::{Component, Path, PathBuf};
+use std::process::{Command as ProcessCommand, Stdio};
+use std::time::Instant;
+
+/// How a script is run, worked out without running anything (see `shutl explain`)
+pub struct ExecutionPlan {
+    /// Program, arguments and environment for the script
+    pub command: ProcessCommand,
+    /// Build command and cached binary of a compiled script; the binary may not exist yet
+    pub build: Option<(String, PathBuf)>,
+    /// Env vars of `[secret]` arguments
+    pub secret_vars: HashSet<String>,
+    /// Resource limits, already applied to `command`
+    pub limits: ResourceLimits,
+    /// Declared stdin expectation; `none` is already applied to `command`
+    pub stdin: Option<StdinMode>,
+    /// Declared format of the script's stdout
+    pub output: OutputFormat,
+    /// Which inherited variables the script gets, already applied to `command`
+    pub env_filter: EnvFilter,
+    /// Roles of the machines the script is meant for (`#@roles:`)
+    pub roles: Vec<String>,
+    /// Directories of `[create]` arguments, made right before the script runs
+    pub create_dirs: Vec<PathBuf>,
+    /// Restrictions of `#@sandbox:`, already applied to `command`
+    pub sandbox: Sandbox,
+    /// Pod of `#@k8s:`, already applied to `command` with the `k8s` feature
+    pub pod: Option<Pod>,
+    /// What `command` has to get on stdin first, for a pod
+    pub input: Option<Input>,
+}
+
+impl ExecutionPlan {
+    /// Gets the program and its arguments, for display. In a pod, the loader script is
+    /// shown as `LOADER`.
+    pub fn program_line(&self) -> String {
+        let mut program = vec![self.command.get_program().to_string_lossy().to_string()];
+        program.extend(
+            self.command
+                .get_args()
+                .map(|a| a.to_string_lossy().to_string()),
+        );
+        if self.pod.is_some()
+            && let Some(loader) = program.iter().position(|arg| arg == "-c")
+            && let Some(arg) = program.get_mut(loader + 1)
+        {
+            *arg = "LOADER".to_string();
+        }
+        program.join(" ")
+    }
+
+    /// Gets the environment shutl sets for the script, with secret values masked, for
+    /// display
+    pub fn redacted_envs(&self) -> Vec<(String, String)> {
+        self.command
+            .get_envs()
+            .filter_map(|(key, value)| {
+                let key = key.to_string_lossy().to_string();
+                let value = value?.to_string_lossy();
+                let value = redact(&key, &value, &self.secret_vars).to_string();
+                Some((key, value))
+            })
+            .collect()
+    }
+}
 
 /// Executes a script with the provided arguments
-pub fn execute_script(script_path: &Path, matches: &ArgMatches) -> std::io::Result<()> {
-    let mut command = ProcessCommand::new(script_path);
+pub fn execute_script(script_path: &Path, matches: &ArgMatches) -> Result<(), ShutlError> {
+    execute_script_as(&[], script_path, matches, &[])
+}
+
+/// Executes a script invoked as `components` with the raw `args` after the command path,
+/// notifying the registered [`observer`]s. Observers get the args with secrets masked;
+/// empty `components` are taken from `SHUTL_COMMAND_PATH`.
+pub fn execute_script_as(
+    components: &[String],
+    script_path: &Path,
+    matches: &ArgMatches,
+    args: &[String],
+) -> Result<(), ShutlError> {
+    let plan = plan_execution(script_path, matches)?;
+
+    let envs = plan.redacted_envs();
+    let components = if components.is_empty() {
+        envs.iter()
+            .find(|(key, _)| key == "SHUTL_COMMAND_PATH")
+            .map(|(_, path)| path.split('/').map(String::from).collect())
+            .unwrap_or_default()
+    } else {
+        components.to_vec()
+    };
+    let run = Run {
+        components,
+        script_path: script_path.to_path_buf(),
+        args: redact_args(script_path, matches, args),
+    };
+    observer::notify(|o| o.on_resolve(&run));
+
+    if matches.get_flag("shutlverboseid") || matches.get_flag("shutlnoexec") {
+        print!("{}", format_trace(&run.components, script_path, &plan));
+        println!("Environment variables:");
+        for (key, value) in &envs {
+            println!("{}: {}", key, value);
+        }
+
+        println!("Command: {:?}", plan.command.get_program());
+    }
+
+    // debug the command env
+    debug!("Command Envs: {:?}", envs);
+    observer::notify(|o| o.on_env(&run, &envs));
+    #[cfg(not(feature = "k8s"))]
+    if let Some(pod) = &plan.pod
+        && !matches.get_flag("shutlnoexec")
+    {
+        return Err(std::io::Error::other(crate::k8s::unsupported_message(
+            &run.components.join("/"),
+            pod,
+        ))
+        .into());
+    }
+    let ExecutionPlan {
+        mut command,
+        build,
+        stdin,
+        output,
+        roles,
+        create_dirs,
+        sandbox,
+        input,
+        ..
+    } = plan;
+    if matches.get_flag("shutlnoexec") {
+        //    println!("Command would be executed: {:?}", command);
+        return Ok(());
+    }
+    let machine_roles = &config::get().roles;
+    if !config::all_roles() && !roles_match(&roles, machine_roles) {
+        return Err(std::io::Error::other(format!(
+            "{} is meant for machines with the roles {} (this one: {}); run it with `shutl --all {}`",
+            run.components.join("/"),
+            roles.join(", "),
+            machine_roles.join(", "),
+            run.components.join(" ")
+        )).into());
+    }
+    #[cfg(feature = "remote")]
+    if config::frozen() {
+        crate::imports::check_frozen(&get_scripts_dir(), script_path)
+            .map_err(std::io::Error::other)?;
+    }
+    if config::get().require_review {
+        let review = Review::open(&get_scripts_dir())?;
+        if let Some(state) = review.state(script_path)
+            && !review.confirm(script_path, state)
+        {
+            return Err(std::io::Error::other(format!(
+                "{} is {} since you last reviewed it; run `shutl review` first",
+                run.components.join("/"),
+                state.describe()
+            ))
+            .into());
+        }
+    }
+    if !sandbox.is_empty() && sandbox::bwrap_path().is_none() {
+        return Err(std::io::Error::other(sandbox::unsupported_message(
+            &run.components.join("/"),
+            &sandbox,
+        ))
+        .into());
+    }
+    if matches.try_get_one::<bool>("shutltmux").ok().flatten() == Some(&true) {
+        return Ok(run_in_tmux(&run.components, args)?);
+    }
+    if stdin == Some(StdinMode::Required) && std::io::stdin().is_terminal() {
+        return Err(std::io::Error::other(format!(
+            "{} reads its input from stdin; pipe or redirect it, e.g. `... | shutl {}`",
+            run.components.join("/"),
+            run.components.join(" ")
+        ))
+        .into());
+    }
+    for dir in &create_dirs {
+        std::fs::create_dir_all(dir).map_err(|e| {
+            std::io::Error::new(e.kind(), format!("cannot create {}: {}", dir.display(), e))
+        })?;
+    }
+    if let Some((build, binary)) = &build {
+        build::ensure_built(script_path, build, binary).map_err(std::io::Error::other)?;
+    }
+    observer::notify(|o| o.on_start(&run));
+    let capture = matches
+        .try_get_one::<String>("shutlcapture")
+        .ok()
+        .flatten()
+        .map(PathBuf::from);
+    let json = matches.try_get_one::<bool>("shutljson").ok().flatten() == Some(&true);
+    let copy = matches.try_get_one::<bool>("shutlcopy").ok().flatten() == Some(&true);
+    let started = Instant::now();
+    let result = if capture.is_some() || json || copy {
+        capture::run_captured(
+            &mut command,
+            capture.as_deref(),
+            !json,
+            json || copy,
+            input.as_ref(),
+        )
+    } else {
+        capture::spawn(&mut command, input.as_ref())
+            .and_then(|mut child| child.wait())
+            .map(|status| (status, Vec::new()))
+    };
+    let (status, stdout) = result.map_err(|e| {
+        if e.kind() == std::io::ErrorKind::NotFound && command.get_program() != script_path {
+            std::io::Error::new(
+                e.kind(),
+                format!(
+                    "{} is not installed",
+                    command.get_program().to_string_lossy()
+                ),
+            )
+        } else {
+            e
+        }
+    })?;
+    let elapsed = started.elapsed();
+    if json {
+        let report =
+            capture::json_report(&run.components.join("/"), status, elapsed, &stdout, output);
+        println!("{}", report);
+    }
+    observer::notify(|o| o.on_exit(&run, status, elapsed));
+    if !status.success() {
+        return Err(ShutlError::ScriptFailed(status));
+    }
+    if copy {
+        let text = clipboard::clip_text(&stdout);
+        match clipboard::copy(text) {
+            Ok(tool) => eprintln!(
+                "Copied {} to the clipboard ({})",
+                match text.iter().filter(|&&b| b == b'\n').count() {
+                    0 if text.is_empty() => "nothing".to_string(),
+                    0 => "1 line".to_string(),
+                    n => format!("{} lines", n + 1),
+                },
+                tool
+            ),
+            Err(e) => eprintln!("Warning: not copied to the clipboard: {}", e),
+        }
+    }
+
+    Ok(())
+}
+
+/// Starts the command again in a new tmux window (`--shutl-tmux`), without the flag
+fn run_in_tmux(components: &[String], args: &[String]) -> std::io::Result<()> {
+    if components.is_empty() {
+        return Err(std::io::Error::other(
+            "--shutl-tmux needs the command path of the script",
+        ));
+    }
+    let mut command = vec![std::env::current_exe()?.display().to_string()];
+    command.extend(components.iter().cloned());
+    // Values after `--` are the script's, even if one is `--shutl-tmux`
+    let end = args
+        .iter()
+        .position(|arg| arg == "--")
+        .unwrap_or(args.len());
+    command.extend(
+        args.iter()
+            .enumerate()
+            .filter(|(i, arg)| *i >= end || *arg != "--shutl-tmux")
+            .map(|(_, arg)| arg.clone()),
+    );
+    let name = components.join(" ");
+    tmux::open_window(&name, &command)?;
+    eprintln!("Started {} in tmux window '{}'", name, name);
+    Ok(())
+}
+
+/// Describes how the command path was resolved, what runs the script and which config
+/// variables it gets, for `--shutl-verbose`
+fn format_trace(components: &[String], script_path: &Path, plan: &ExecutionPlan) -> String {
+    let mut out = String::new();
+    if !components.is_empty() {
+        out.push_str(&format!("Resolution of {}:\n", components.join("/")));
+        let mounts = roots::mounts();
+        let mut provided = false;
+        for (mount, lookup) in roots::trace(&mounts, components) {
+            let root = match &mount.prefix {
+                Some(prefix) => format!("{} (prefix '{}')", mount.location(), prefix),
+                None => mount.location(),
+            };
+            let outcome = match lookup {
+                Lookup::OtherPrefix => "skipped, other prefix".to_string(),
+                Lookup::Missing => "not found".to_string(),
+                Lookup::Found(path) if !provided => {
+                    provided = true;
+                    format!("{} (used)", path.display())
+                }
+                Lookup::Found(path) => format!("{} (shadowed)", path.display()),
+            };
+            out.push_str(&format!("  {}: {}\n", root, outcome));
+        }
+    }
+
+    let command = &plan.command;
+    if let Some(pod) = &plan.pod {
+        out.push_str(&format!("Pod: {}\n", pod.describe()));
+    }
+    out.push_str(&format!("Runs: {}\n", plan.program_line()));
+    let runner = match &plan.build {
+        Some((build, binary)) => Some(format!("{} (built with `{}`)", binary.display(), build)),
+        None if command.get_program() == script_path => {
+            shebang(script_path).map(|program| format!("{} (#! line)", program))
+        }
+        None => None,
+    };
+    if let Some(runner) = runner {
+        out.push_str(&format!("Interpreter: {}\n", runner));
+    }
+
+    // Config (or profile) variables, as far as the caller's environment didn't win
+    let config_env: Vec<&str> = config::get()
+        .env
+        .keys()
+        .filter(|key| {
+            command
+                .get_envs()
+                .any(|(k, v)| k == key.as_str() && v.is_some())
+        })
+        .map(String::as_str)
+        .collect();
+    if !config_env.is_empty() {
+        let source = match config::active_profile() {
+            Some(profile) => format!("config, profile '{}'", profile),
+            None => "config".to_string(),
+        };
+        out.push_str(&format!("From the {}: {}\n", source, config_env.join(", ")));
+    }
+    out
+}
+
+/// Works out the program, arguments and environment that run a script with the parsed
+/// arguments in `matches`
+pub fn plan_execution(script_path: &Path, matches: &ArgMatches) -> std::io::Result<ExecutionPlan> {
     let metadata = parse_command_metadata(script_path);
+    let build = build::build_command(script_path, metadata.build.as_deref());
+    let binary = match (&build, build::cache_dir()) {
+        (Some(build), Some(cache)) => Some(build::binary_path(script_path, build, &cache)?),
+        (Some(_), None) => return Err(std::io::Error::other("no cache directory to build in")),
+        _ => None,
+    };
+    let mut command = match (
+        &binary,
+        interpreter_for(script_path, metadata.interpreter.as_deref()),
+    ) {
+        (Some(binary), _) => ProcessCommand::new(binary),
+        (None, Some(interpreter)) => {
+            let mut command = ProcessCommand::new(&interpreter[0]);
+            command.args(&interpreter[1..]).arg(script_path);
+            command
+        }
+        (None, None) => ProcessCommand::new(script_path),
+    };
+    let pod = metadata.k8s.clone().transpose().map_err(|e| {
+        std::io::Error::other(format!(
+            "invalid #@k8s: line in {}: {}",
+            script_path.display(),
+            e
+        ))
+    })?;
+    if pod.is_some() && (build.is_some() || !metadata.sandbox.is_empty()) {
+        return Err(std::io::Error::other(
+            "#@k8s: cannot be combined with #@build: or #@sandbox:",
+        ));
+    }
+    if !metadata.sandbox.is_empty() {
+        let mut expose = vec![script_path];
+        expose.extend(binary.as_deref());
+        command = metadata.sandbox.wrap(&command, &expose);
+    }
+
+    // The script's allow list replaces the config's; deny lists add up
+    let config = config::get();
+    let env_filter = EnvFilter {
+        allow: metadata
+            .env
+            .allow
+            .clone()
+            .or_else(|| config.env_allow.clone()),
+        deny: config
+            .env_deny
+            .iter()
+            .chain(&metadata.env.deny)
+            .cloned()
+            .collect(),
+    };
+    env_filter.apply(&mut command);
+
+    // Config (or profile) env defaults never override the caller's environment
+    for (key, value) in &config.env {
+        if std::env::var_os(key).is_none() || !env_filter.passes(key) {
+            command.env(key, value);
+        }
+    }
 
+    let mut secret_vars = secret_arg_vars(&metadata);
+    let mut templated = Vec::new();
+    let mut path_args = Vec::new();
+    let mut create_dirs = Vec::new();
     for arg in metadata.arguments {
         match arg {
             LineType::Positional(name, _, config) => {
-                if let Some(ArgType::CatchAll) = config.arg_type {
-                    debug!("catch-all: {}", name);
-                    let env_name = format!("SHUTL_{}", name.replace('-', "_").to_uppercase());
+                if config.is_multiple() {
+                    debug!("multiple values: {}", name);
+                    let env_name = arg_env_name(&name);
                     if let Some(values) = matches.get_many::<String>(name.as_str()) {
-                        let env_value = values.map(|s| s.as_str()).collect::<Vec<_>>().join(" ");
-                        debug!("{}: {:?}", env_name, env_value);
+                        let mut expanded = Vec::new();
+                        for value in values {
+                            for value in expand_value(value)? {
+                                expanded.push(expand_arg(&name, &config, value)?);
+                            }
+                        }
+                        check_value_count(&name, &config, expanded.len())?;
+                        for value in &expanded {
+                            resolve_path(&name, &config, value, &mut create_dirs)?;
+                        }
+                        let env_value = expanded.join(" ");
+                        debug!(
+                            "{}: {:?}",
+                            env_name,
+                            redact(&env_name, &env_value, &secret_vars)
+                        );
                         command.env(&env_name, env_value);
+                        // Indexed copies keep values containing spaces intact
+                        command.env(format!("{}_COUNT", env_name), expanded.len().to_string());
+                        for (i, value) in expanded.iter().enumerate() {
+                            let indexed = format!("{}_{}", env_name, i + 1);
+                            if config.secret {
+                                secret_vars.insert(indexed.clone());
+                            }
+                            command.env(indexed, value);
+                        }
+                    } else {
+                        command.env(format!("{}_COUNT", env_name), "0");
                     }
                 } else {
-                    let env_name = format!("SHUTL_{}", name.replace('-', "_").to_uppercase());
-                    let value = matches
-                        .get_one::<String>(name.as_str())
-                        .map(|v| v.as_str())
-                        .unwrap_or_else(|| config.default.as_deref().unwrap_or(""));
-                    command.env(&env_name, value);
+                    let env_name = arg_env_name(&name);
+                    let value = match matches.get_one::<String>(name.as_str()) {
+                        Some(value) => expand_value(value)?.join(" "),
+                        None => config.default.clone().unwrap_or_default(),
+                    };
+                    templated.extend(templated_default(matches, &name, &config, &env_name)?);
+                    command.env(&env_name, expand_arg(&name, &config, value)?);
+                    path_args.push((name, env_name, config));
                 }
             }
             LineType::Flag(name, _, config) => {
-                let env_name = format!("SHUTL_{}", name.replace('-', "_").to_uppercase());
+                let env_name = arg_env_name(&name);
                 let value = if config.arg_type == Some(ArgType::Bool) {
                     let negated_name = format!("no-{}", name);
                     if matches.get_flag(&negated_name) {
-                        "false"
+                        "false".to_string()
                     } else if matches.get_flag(name.as_str()) {
-                        "true"
+                        "true".to_string()
+                    } else if let Some(default) = &config.default {
+                        default.clone()
+                    } else if config.tristate {
+                        // Neither given: the script sees the variable unset, even if
+                        // shutl itself was started with it
+                        command.env_remove(&env_name);
+                        continue;
                     } else {
-                        config.default.as_deref().unwrap_or("false")
+                        "false".to_string()
                     }
                 } else {
-                    matches
-                        .get_one::<String>(name.as_str())
-                        .map(|v| v.as_str())
-                        .unwrap_or_else(|| config.default.as_deref().unwrap_or(""))
+                    templated.extend(templated_default(matches, &name, &config, &env_name)?);
+                    let value = match matches.get_one::<String>(name.as_str()) {
+                        Some(value) => expand_value(value)?.join(" "),
+                        None => config.default.clone().unwrap_or_default(),
+                    };
+                    expand_arg(&name, &config, value)?
                 };
                 command.env(&env_name, value);
+                path_args.push((name, env_name, config));
             }
             _ => {}
         }
     }
 
-    if matches.get_flag("shutlverboseid") || matches.get_flag("shutlnoexec") {
-        println!("Environment variables:");
-        for (key, value) in command.get_envs() {
-            println!(
-                "{}: {}",
-                key.to_str().unwrap(),
-                value.unwrap().to_str().unwrap()
-            );
+    // Defaults such as `{{input}}.out` are filled in once all values are known, in
+    // declaration order, so they can build on templated defaults declared before them
+    for (env_name, template) in templated {
+        if template_refs(&template)
+            .iter()
+            .any(|name| secret_vars.contains(&arg_env_name(name)))
+        {
+            secret_vars.insert(env_name.clone());
         }
+        let value = fill_template(&template, |name| env_value(&command, &arg_env_name(name)));
+        command.env(&env_name, value);
+    }
 
-        println!("Command: {:?}", command.get_program());
+    // `file`/`dir`/`path` arguments also get their absolute path, from the final values
+    for (name, env_name, config) in path_args {
+        let value = env_value(&command, &env_name).unwrap_or_default();
+        if let Some(absolute) = resolve_path(&name, &config, &value, &mut create_dirs)? {
+            let abs_name = format!("{}_ABS", env_name);
+            if config.secret {
+                secret_vars.insert(abs_name.clone());
+            }
+            command.env(abs_name, absolute);
+        }
     }
 
-    // debug the command env
-    debug!("Command Envs: {:?}", command.get_envs());
-    if matches.get_flag("shutlnoexec") {
-        //    println!("Command would be executed: {:?}", command);
-        return Ok(());
+    for (key, value) in script_context_env(script_path) {
+        command.env(key, value);
     }
-    let status = command.status()?;
-    if !status.success() {
-        std::process::exit(status.code().unwrap_or(1));
+    #[cfg(feature = "k8s")]
+    let input = match &pod {
+        Some(pod) => {
+            let (wrapped, prefix) = pod.wrap(&command, script_path)?;
+            command = wrapped;
+            Some(Input {
+                prefix,
+                forward: metadata.stdin != Some(StdinMode::None),
+            })
+        }
+        None => None,
+    };
+    #[cfg(not(feature = "k8s"))]
+    let input = None;
+    let mut limits = metadata.limits;
+    if let Ok(Some(priority)) = matches.try_get_one::<String>("shutlpriority") {
+        limits.priority = priority.parse().ok();
+    }
+    limits.apply(&mut command);
+    if metadata.stdin == Some(StdinMode::None) {
+        command.stdin(Stdio::null());
+    }
+
+    Ok(ExecutionPlan {
+        command,
+        build: build.zip(binary),
+        secret_vars,
+        limits,
+        stdin: metadata.stdin,
+        output: metadata.output,
+        roles: metadata.roles,
+        create_dirs,
+        env_filter,
+        sandbox: metadata.sandbox,
+        pod,
+        input,
+    })
+}
+
+/// Gets a variable set on `command`
+fn env_value(command: &ProcessCommand, key: &str) -> Option<String> {
+    command
+        .get_envs()
+        .find(|(name, _)| *name == key)
+        .and_then(|(_, value)| value)
+        .map(|value| value.to_string_lossy().to_string())
+}
+
+/// Gets the absolute path of a value of a `file`/`dir`/`path` argument, with symlinks
+/// resolved if it exists. Checks `[must-exist]` and adds the directory `[create]` needs
+/// to `create_dirs`. Other arguments and empty values have no path.
+fn resolve_path(
+    name: &str,
+    config: &Config,
+    value: &str,
+    create_dirs: &mut Vec<PathBuf>,
+) -> std::io::Result<Option<PathBuf>> {
+    let Some(arg_type @ (ArgType::File | ArgType::Dir | ArgType::Path)) = &config.arg_type else {
+        return Ok(None);
+    };
+    if value.is_empty() {
+        return Ok(None);
+    }
+    let path = Path::new(value);
+    let absolute = std::fs::canonicalize(path).or_else(|_| std::path::absolute(path))?;
+    if config.must_exist {
+        let (found, kind) = match arg_type {
+            ArgType::File => (absolute.is_file(), "file"),
+            ArgType::Dir => (absolute.is_dir(), "directory"),
+            _ => (absolute.exists(), "path"),
+        };
+        if !found {
+            return Err(std::io::Error::other(format!(
+                "<{}>: {} is not an existing {}",
+                name, value, kind
+            )));
+        }
+    }
+    if config.create {
+        let dir = match arg_type {
+            ArgType::Dir => Some(absolute.as_path()),
+            _ => absolute.parent(),
+        };
+        create_dirs.extend(dir.map(Path::to_path_buf));
+    }
+    Ok(Some(absolute))
+}
+
+/// Expands `~` and `$VAR` in a value of an argument annotated with `[expand]`
+fn expand_arg(name: &str, config: &Config, value: String) -> std::io::Result<String> {
+    if !config.expand {
+        return Ok(value);
+    }
+    paths::expand(&value)
+        .map(|expanded| expanded.into_owned())
+        .map_err(|e| std::io::Error::other(format!("cannot expand the value of {}: {}", name, e)))
+}
+
+/// Gets the env var and template of an argument whose default refers to other arguments
+/// (`[default:{{input}}.out]`), if the default is used. With `[expand]`, `~` and `$VAR` in
+/// the template are already expanded.
+fn templated_default(
+    matches: &ArgMatches,
+    name: &str,
+    config: &Config,
+    env_name: &str,
+) -> std::io::Result<Option<(String, String)>> {
+    let Some(template) = config.default.as_ref().filter(|d| d.contains("{{")) else {
+        return Ok(None);
+    };
+    if matches.value_source(name) == Some(clap::parser::ValueSource::CommandLine) {
+        return Ok(None);
     }
+    Ok(Some((
+        env_name.to_string(),
+        expand_arg(name, config, template.clone())?,
+    )))
+}
 
+/// Checks the number of values of an argument with `[min:..]`/`[max:..]` after `@file`
+/// expansion; clap only sees the values as typed.
+fn check_value_count(name: &str, config: &Config, count: usize) -> std::io::Result<()> {
+    let min = config.min.unwrap_or_default();
+    if count < min || config.max.is_some_and(|max| count > max) {
+        let expected = match config.max {
+            Some(max) => format!("{} to {}", min, max),
+            None => format!("at least {}", min),
+        };
+        return Err(std::io::Error::other(format!(
+            "<{}> takes {} values, got {}",
+            name, expected, count
+        )));
+    }
     Ok(())
 }
-/// Recursively finds a script file in the scripts directory
+
+/// Expands an argument value read from a file: `@hosts.txt` becomes the lines of the
+/// file (blank lines and `#` comments skipped), `@@text` the literal `@text`. Other
+/// values are returned as they are.
+pub fn expand_value(value: &str) -> std::io::Result<Vec<String>> {
+    if let Some(literal) = value.strip_prefix("@@") {
+        return Ok(vec![format!("@{}", literal)]);
+    }
+    let Some(file) = value.strip_prefix('@') else {
+        return Ok(vec![value.to_string()]);
+    };
+    let path = paths::expand(file)
+        .map(|p| PathBuf::from(p.as_ref()))
+        .unwrap_or_else(|_| PathBuf::from(file));
+    let content = std::fs::read_to_string(&path).map_err(|e| {
+        std::io::Error::new(
+            e.kind(),
+            format!(
+                "cannot read values from {} ({}); pass a literal '@' as '@@'",
+                path.display(),
+                e
+            ),
+        )
+    })?;
+    Ok(content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect())
+}
+
+/// Placeholder shown instead of secret values
+pub const REDACTED: &str = "********";
+
+/// Env var name fragments that mark a value as secret unless `secret-patterns` is set
+pub const DEFAULT_SECRET_PATTERNS: [&str; 4] = ["TOKEN", "PASSWORD", "SECRET", "API_KEY"];
+
+fn arg_env_name(name: &str) -> String {
+    format!("SHUTL_{}", name.replace('-', "_").to_uppercase())
+}
+
+/// Gets the env vars of a script's `[secret]` arguments
+pub fn secret_arg_vars(metadata: &CommandMetadata) -> HashSet<String> {
+    metadata
+        .arguments
+        .iter()
+        .filter_map(|arg| match arg {
+            LineType::Positional(name, _, config) | LineType::Flag(name, _, config)
+                if config.secret =>
+            {
+                Some(arg_env_name(name))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Returns true if the env var `name` holds a secret: it belongs to a `[secret]` argument
+/// (`secret_vars`) or contains one of the configured secret patterns
+pub fn is_secret_var(name: &str, secret_vars: &HashSet<String>) -> bool {
+    if secret_vars.contains(name) {
+        return true;
+    }
+    let name = name.to_uppercase();
+    match &config::get().secret_patterns {
+        Some(patterns) => patterns.iter().any(|p| name.contains(&p.to_uppercase())),
+        None => DEFAULT_SECRET_PATTERNS.iter().any(|p| name.contains(p)),
+    }
+}
+
+/// Masks `value` if the env var `name` holds a secret (see [`is_secret_var`])
+pub fn redact<'a>(name: &str, value: &'a str, secret_vars: &HashSet<String>) -> &'a str {
+    if is_secret_var(name, secret_vars) && !value.is_empty() {
+        REDACTED
+    } else {
+        value
+    }
+}
+
+/// Masks the secret argument values in a script's command line, e.g. before it is
+/// recorded in the history. `matches` are the parsed `args`.
+pub fn redact_args(script_path: &Path, matches: &ArgMatches, args: &[String]) -> Vec<String> {
+    let metadata = parse_command_metadata(script_path);
+    let secret_vars = secret_arg_vars(&metadata);
+    let mut secrets: Vec<String> = Vec::new();
+    for arg in &metadata.arguments {
+        let (LineType::Positional(name, _, config) | LineType::Flag(name, _, config)) = arg else {
+            continue;
+        };
+        if config.arg_type == Some(ArgType::Bool)
+            || !is_secret_var(&arg_env_name(name), &secret_vars)
+        {
+            continue;
+        }
+        if let Ok(Some(values)) = matches.try_get_many::<String>(name) {
+            secrets.extend(values.filter(|v| !v.is_empty()).cloned());
+        }
+    }
+    mask_values(args, &secrets)
+}
+
+/// Builds the arguments for `--shutl-again` and presets: values given in `current` win,
+/// every other argument keeps its value from `previous` (the last run in the history or
+/// a preset). Masked secret values must be given again. Hidden `--shutl-*` options only come from `current`.
+pub fn merge_args(
+    script_path: &Path,
+    previous: &[String],
+    current: &[String],
+) -> Result<Vec<String>, String> {
+    let metadata = parse_command_metadata(script_path);
+    // Previous arguments may stem from an older version of the script, and current ones
+    // are completed by them, so neither has to be complete on its own
+    let lenient = build_script_command_for_help("again".to_string(), script_path)
+        .mut_args(|arg| arg.required(false));
+    let parse = |args: &[String]| {
+        lenient
+            .clone()
+            .try_get_matches_from(std::iter::once("again").chain(args.iter().map(String::as_str)))
+    };
+    let previous = parse(previous)
+        .map_err(|e| format!("the arguments of the last run no longer fit: {}", e.kind()))?;
+    let current = parse(current).map_err(|e| e.to_string())?;
+    let given =
+        |matches: &ArgMatches, id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+    let values = |matches: &ArgMatches, id: &str| -> Result<Vec<String>, String> {
+        let values: Vec<String> = matches
+            .get_many::<String>(id)
+            .map(|values| values.cloned().collect())
+            .unwrap_or_default();
+        if values.iter().any(|v| v == REDACTED) {
+            return Err(format!("'{}' is secret and not stored; pass it again", id));
+        }
+        Ok(values)
+    };
+
+    let mut merged = Vec::new();
+    let mut positionals = Vec::new();
+    for arg in &metadata.arguments {
+        match arg {
+            LineType::Flag(name, _, config) if config.arg_type == Some(ArgType::Bool) => {
+                let negated = format!("no-{}", name);
+                let source = if given(&current, name) || given(&current, &negated) {
+                    &current
+                } else {
+                    &previous
+                };
+                if source.get_flag(name) {
+                    merged.push(format!("--{}", name));
+                } else if source.get_flag(&negated) {
+                    merged.push(format!("--{}", negated));
+                }
+            }
+            LineType::Flag(name, _, _) | LineType::Positional(name, _, _) => {
+                let source = if given(&current, name) {
+                    &current
+                } else if given(&previous, name) {
+                    &previous
+                } else {
+                    continue;
+                };
+                let values = values(source, name)?;
+                if matches!(arg, LineType::Flag(..)) {
+                    merged.extend(values.iter().map(|v| format!("--{}={}", name, v)));
+                } else {
+                    positionals.extend(values);
+                }
+            }
+            LineType::Description(_) => {}
+        }
+    }
+
+    if current.get_flag("shutlverboseid") {
+        merged.push("--shutl-verbose".to_string());
+    }
+    if current.get_flag("shutlnoexec") {
+        merged.push("--shutl-noexec".to_string());
+    }
+    if let Some(priority) = current.get_one::<String>("shutlpriority") {
+        merged.push(format!("--shutl-priority={}", priority));
+    }
+    if let Some(file) = current.get_one::<String>("shutlcapture") {
+        merged.push(format!("--shutl-capture={}", file));
+    }
+    if current.get_flag("shutljson") {
+        merged.push("--shutl-json".to_string());
+    }
+    if current.get_flag("shutlcopy") {
+        merged.push("--shutl-copy".to_string());
+    }
+    if current.get_flag("shutltmux") {
+        merged.push("--shutl-tmux".to_string());
+    }
+    if positionals.iter().any(|v| v.starts_with('-')) {
+        merged.push("--".to_string());
+    }
+    merged.extend(positionals);
+    Ok(merged)
+}
+
+/// Replaces every argument equal to one of `secrets` (or `--flag=<secret>`, or a short
+/// flag with the secret attached, `-t<secret>`) with [`REDACTED`]
+pub fn mask_values(args: &[String], secrets: &[String]) -> Vec<String> {
+    args.iter()
+        .map(|arg| {
+            if secrets.contains(arg) {
+                return REDACTED.to_string();
+            }
+            match arg.split_once('=') {
+                Some((flag, value))
+                    if flag.starts_with('-') && secrets.iter().any(|s| s == value) =>
+                {
+                    format!("{}={}", flag, REDACTED)
+                }
+                _ if !arg.starts_with("--")
+                    && arg.len() > 2
+                    && arg.starts_with('-')
+                    && arg.is_char_boundary(2)
+                    && secrets.iter().any(|s| *s == arg[2..]) =>
+                {
+                    format!("{}{}", &arg[..2], REDACTED)
+                }
+                _ => arg.clone(),
+            }
+        })
+        .collect()
+}
+
+/// Masks the values of options whose name looks secret (`--api-token x`,
+/// `--password=x`) in a raw command line, for logging before any script is resolved
+pub fn redact_secret_flags(args: &[String]) -> Vec<String> {
+    let secret_flag = |flag: &str| {
+        flag.starts_with("--")
+            && is_secret_var(&arg_env_name(flag.trim_start_matches('-')), &HashSet::new())
+    };
+    let mut redacted = Vec::with_capacity(args.len());
+    let mut mask_next = false;
+    for arg in args {
+        if mask_next {
+            redacted.push(REDACTED.to_string());
+            mask_next = false;
+        } else if let Some((flag, _)) = arg.split_once('=').filter(|(f, _)| secret_flag(f)) {
+            redacted.push(format!("{}={}", flag, REDACTED));
+        } else {
+            mask_next = secret_flag(arg);
+            redacted.push(arg.clone());
+        }
+    }
+    redacted
+}
+
+/// File types that are run through an interpreter or compiled, and need no executable bit
+const INTERPRETED_EXTENSIONS: [&str; 5] = ["ps1", "ts", "mts", "go", "rs"];
+
+/// Gets the program and arguments that run a script, or `None` to execute it directly.
+/// The script path is appended last. In order of precedence:
+/// - the `#@interpreter:` metadata (`declared`), e.g. `bun`
+/// - `.ps1`: `pwsh -NoLogo -NoProfile -File`
+/// - `.ts`/`.mts`: directly if executable with a shebang, else the shebang's program,
+///   else `deno run -A`
+pub fn interpreter_for(script_path: &Path, declared: Option<&str>) -> Option<Vec<String>> {
+    let words = |s: &str| s.split_whitespace().map(String::from).collect::<Vec<_>>();
+    if let Some(declared) = declared.filter(|d| !d.trim().is_empty()) {
+        return Some(words(declared));
+    }
+    match script_path.extension()?.to_str()? {
+        "ps1" => Some(words("pwsh -NoLogo -NoProfile -File")),
+        "ts" | "mts" => match shebang(script_path) {
+            Some(_) if script_path.is_executable() => None,
+            Some(program) => Some(words(&program)),
+            None => Some(words("deno run -A")),
+        },
+        _ => None,
+    }
+}
+
+/// Reads the program from a script's `#!` line
+fn shebang(script_path: &Path) -> Option<String> {
+    let file = std::fs::File::open(script_path).ok()?;
+    let mut first_line = String::new();
+    std::io::BufRead::read_line(&mut std::io::BufReader::new(file), &mut first_line).ok()?;
+    let program = first_line.strip_prefix("#!")?.trim();
+    (!program.is_empty()).then(|| program.to_string())
+}
+
+/// Returns true if `path` is a script shutl can run: an executable file, or a file type
+/// run through an interpreter (which needs no executable bit)
+pub fn is_runnable(path: &Path) -> bool {
+    is_runnable_from(&FsSource, path)
+}
+
+/// Returns true if `path` is a script of `source` shutl can run (see [`is_runnable`])
+pub fn is_runnable_from(source: &dyn ScriptSource, path: &Path) -> bool {
+    source.is_file(path)
+        && (source.is_executable(path)
+            || path
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| INTERPRETED_EXTENSIONS.contains(&ext)))
+}
+
+/// Environment variables describing the invocation, set for every script run.
+/// They take precedence over arguments with the same env var name.
+pub const CONTEXT_ENV_VARS: [&str; 4] = [
+    "SHUTL_COMMAND_PATH",
+    "SHUTL_SCRIPT_PATH",
+    "SHUTL_ROOT",
+    "SHUTL_INVOCATION_ID",
+];
+
+/// Builds the context env vars (see [`CONTEXT_ENV_VARS`]) for a script below `root`,
+/// which is mounted under `prefix` if given
+pub fn context_env(
+    script_path: &Path,
+    root: &Path,
+    prefix: Option<&str>,
+) -> Vec<(&'static str, String)> {
+    let command_path = match prefix {
+        Some(prefix) => format!("{}/{}", prefix, command_path(script_path, root)),
+        None => command_path(script_path, root),
+    };
+    vec![
+        ("SHUTL_COMMAND_PATH", command_path),
+        ("SHUTL_SCRIPT_PATH", script_path.display().to_string()),
+        ("SHUTL_ROOT", root.display().to_string()),
+        ("SHUTL_INVOCATION_ID", invocation_id()),
+    ]
+}
+
+/// Builds the context env vars for a script, below the root it is mounted from
+pub fn script_context_env(script_path: &Path) -> Vec<(&'static str, String)> {
+    let mounts = roots::mounts();
+    match roots::mount_for(&mounts, script_path) {
+        Some(mount) => context_env(script_path, &mount.path, mount.prefix.as_deref()),
+        None => context_env(script_path, &get_scripts_dir(), None),
+    }
+}
+
+/// Gets the `/`-separated command path of a script, e.g. `deploy/prod` for
+/// `<root>/deploy/prod.sh`. Like the command tree, the extension is kept when a
+/// directory with the same stem exists next to the script or the directory names
+/// commands by file name.
+pub fn command_path(script_path: &Path, root: &Path) -> String {
+    let relative = script_path.strip_prefix(root).unwrap_or(script_path);
+    let mut parts: Vec<String> = relative
+        .parent()
+        .into_iter()
+        .flat_map(|p| p.components())
+        .map(|c| c.as_os_str().to_string_lossy().to_string())
+        .collect();
+
+    let stem = script_path.file_stem().unwrap_or_default();
+    let name = match script_path.parent() {
+        Some(parent)
+            if command_names_in(parent) == CommandNames::Stem && !parent.join(stem).is_dir() =>
+        {
+            stem
+        }
+        _ => script_path.file_name().unwrap_or_default(),
+    };
+    parts.push(name.to_string_lossy().to_string());
+    parts.join("/")
+}
+
+/// Generates an identifier that is unique per run, for correlating logs
+fn invocation_id() -> String {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+    format!("{:x}-{:x}", nanos, std::process::id())
+}
+
+/// Returns true if a command path component can be joined onto the scripts directory
+/// without escaping it: empty components, `.`, `..`, absolute paths and anything
+/// containing a path separator are rejected.
+pub fn is_safe_component(component: &str) -> bool {
+    let mut parts = Path::new(component).components();
+    matches!(
+        (parts.next(), parts.next()),
+        (Some(Component::Normal(_)), None)
+    )
+}
+
+/// Joins command path components onto `base_dir`, refusing any component that would
+/// resolve outside of it.
+pub fn resolve_command_path(base_dir: &Path, components: &[String]) -> Option<PathBuf> {
+    if let Some(bad) = components.iter().find(|c| !is_safe_component(c)) {
+        warn!(
+            "Refusing to resolve command component {:?} outside the scripts directory",
+            bad
+        );
+        return None;
+    }
+    let mut path = base_dir.to_path_buf();
+    path.extend(components);
+    Some(path)
+}
+
+/// Finds a script (or directory) by command path in the scripts directory and the
+/// configured roots
 pub fn find_script_file(components: &[String]) -> Option<std::path::PathBuf> {
-    find_script_file_in_dir(components, &get_scripts_dir())
+    roots::resolve(&roots::mounts(), components)
 }
 
 pub fn find_script_file_in_dir(
     components: &[String],
     base_dir: &Path,
 ) -> Option<std::path::PathBuf> {
-    let mut path = base_dir.to_path_buf();
+    find_script_file_from(&FsSource, components, base_dir)
+}
 
-    // Build the path using all components except the last one
-    for component in &components[..components.len() - 1] {
-        path.push(component);
-    }
-    path.push(components.last()?);
+/// Finds a script (or directory) by command path below `base_dir` of `source`
+pub fn find_script_file_from(
+    source: &dyn ScriptSource,
+    components: &[String],
+    base_dir: &Path,
+) -> Option<PathBuf> {
+    let last = components.last()?;
+    let mut path = resolve_command_path(base_dir, components)?;
 
     // Check for an exact match
-    if path.exists() {
+    if source.is_dir(&path) || source.is_file(&path) {
         return Some(path);
     }
 
     // Check for files with the same stem in the parent directory
     path.pop();
-    let last = components.last()?;
-    std::fs::read_dir(&path)
-        .ok()?
-        .filter_map(Result::ok)
-        .find_map(|entry| {
-            if entry.path().is_dir() {
-                return None;
-            }
-            let file_stem = entry.path().file_stem()?.to_str()?.to_string();
-            (file_stem == *last).then_some(entry.path())
-        })
+    if command_names_from(source, &path) == CommandNames::Filename {
+        return None;
+    }
+    source.list_dir(&path).into_iter().find_map(|entry| {
+        if entry.kind != EntryKind::File {
+            return None;
+        }
+        let file_stem = entry.path.file_stem()?.to_str()?.to_string();
+        (file_stem == *last).then_some(entry.path)
+    })
 }
 
 #[cfg(test)]
@@ -143,6 +1146,365 @@ mod tests {
         script_path
     }
 
+    #[test]
+    fn test_redact_args() {
+        let dir = tempdir().unwrap();
+        let script = create_test_script(
+            dir.path(),
+            "login.sh",
+            "#!/bin/bash\n#@arg:user - User\n#@arg:pin - PIN [secret]\n#@flag:api-token - Token\n#@flag:force - Force [bool]\n#@flag:key - Key [secret,short:k]\n",
+        );
+        let args: Vec<String> = ["alice", "1234", "--api-token=abc", "--force", "-kxyz"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let matches = crate::command::build_script_command_for_help("login".into(), &script)
+            .get_matches_from(std::iter::once("login".to_string()).chain(args.clone()));
+
+        assert_eq!(
+            redact_args(&script, &matches, &args),
+            vec![
+                "alice",
+                REDACTED,
+                "--api-token=********",
+                "--force",
+                "-k********"
+            ]
+        );
+        let secret_vars = secret_arg_vars(&parse_command_metadata(&script));
+        assert_eq!(
+            secret_vars,
+            HashSet::from(["SHUTL_PIN".to_string(), "SHUTL_KEY".to_string()])
+        );
+        assert_eq!(redact("SHUTL_USER", "alice", &secret_vars), "alice");
+        assert_eq!(redact("GITHUB_TOKEN", "ghp_x", &secret_vars), REDACTED);
+        assert_eq!(redact("SHUTL_PIN", "", &secret_vars), "");
+    }
+
+    #[test]
+    fn test_templated_defaults() {
+        let dir = tempdir().unwrap();
+        let script = create_test_script(
+            dir.path(),
+            "convert.sh",
+            "#!/bin/bash\n#@arg:input - Input\n#@flag:output - Output [default:{{input}}.out]\n#@flag:log - Log [default:{{output}}.log]\n#@flag:token - Token [secret]\n#@flag:url - URL [default:https://x/?t={{token}}]\n",
+        );
+        let plan = |args: &[&str]| {
+            let matches = crate::command::build_script_command_for_help("convert".into(), &script)
+                .get_matches_from(std::iter::once("convert").chain(args.iter().copied()));
+            plan_execution(&script, &matches).unwrap()
+        };
+        let env = |plan: &ExecutionPlan, key: &str| {
+            plan.command
+                .get_envs()
+                .find(|(k, _)| *k == key)
+                .and_then(|(_, v)| v)
+                .map(|v| v.to_string_lossy().to_string())
+        };
+
+        let derived = plan(&["report", "--token", "abc"]);
+        assert_eq!(env(&derived, "SHUTL_OUTPUT").as_deref(), Some("report.out"));
+        assert_eq!(
+            env(&derived, "SHUTL_LOG").as_deref(),
+            Some("report.out.log")
+        );
+        assert!(derived.secret_vars.contains("SHUTL_URL"));
+
+        let given = plan(&["report", "--output", "{{input}}"]);
+        assert_eq!(env(&given, "SHUTL_OUTPUT").as_deref(), Some("{{input}}"));
+        assert_eq!(env(&given, "SHUTL_LOG").as_deref(), Some("{{input}}.log"));
+    }
+
+    #[test]
+    fn test_tristate_flags() {
+        let dir = tempdir().unwrap();
+        let script = create_test_script(
+            dir.path(),
+            "deploy.sh",
+            "#!/bin/bash\n#@flag:force - Force [bool]\n#@flag:cache - Cache [tristate]\n#@flag:color - Color [tristate, default:true]\n",
+        );
+        let plan = |args: &[&str]| {
+            let matches = crate::command::build_script_command_for_help("deploy".into(), &script)
+                .get_matches_from(std::iter::once("deploy").chain(args.iter().copied()));
+            plan_execution(&script, &matches).unwrap()
+        };
+        // `Some(None)` for a variable that is removed
+        let env = |plan: &ExecutionPlan, key: &str| {
+            plan.command
+                .get_envs()
+                .find(|(k, _)| *k == key)
+                .map(|(_, v)| v.map(|v| v.to_string_lossy().to_string()))
+        };
+
+        let unset = plan(&[]);
+        assert_eq!(env(&unset, "SHUTL_FORCE"), Some(Some("false".into())));
+        assert_eq!(env(&unset, "SHUTL_CACHE"), Some(None));
+        assert_eq!(env(&unset, "SHUTL_COLOR"), Some(Some("true".into())));
+        assert_eq!(
+            env(&plan(&["--cache"]), "SHUTL_CACHE"),
+            Some(Some("true".into()))
+        );
+        assert_eq!(
+            env(&plan(&["--no-cache"]), "SHUTL_CACHE"),
+            Some(Some("false".into()))
+        );
+    }
+
+    #[test]
+    fn test_expand_annotation() {
+        let dir = tempdir().unwrap();
+        let script = create_test_script(
+            dir.path(),
+            "sync.sh",
+            "#!/bin/bash\n#@arg:name - Name\n#@arg:...files - Files [expand]\n#@flag:out - Output [dir,expand,default:~/out]\n#@flag:log - Log [expand,default:~/logs/{{name}}.log]\n#@flag:raw - Raw [default:~/raw]\n",
+        );
+        let matches = crate::command::build_script_command_for_help("sync".into(), &script)
+            .get_matches_from(["sync", "daily", "~/a", "b"]);
+        let plan = plan_execution(&script, &matches).unwrap();
+        let env = |key: &str| {
+            plan.command
+                .get_envs()
+                .find(|(k, _)| *k == key)
+                .and_then(|(_, v)| v)
+                .map(|v| v.to_string_lossy().to_string())
+                .unwrap()
+        };
+        let home = paths::home_dir().unwrap().to_string_lossy().to_string();
+        assert_eq!(env("SHUTL_OUT"), format!("{}/out", home));
+        assert_eq!(env("SHUTL_LOG"), format!("{}/logs/daily.log", home));
+        assert_eq!(env("SHUTL_FILES_1"), format!("{}/a", home));
+        assert_eq!(env("SHUTL_FILES_2"), "b");
+        assert_eq!(env("SHUTL_RAW"), "~/raw");
+
+        let matches = crate::command::build_script_command_for_help("sync".into(), &script)
+            .get_matches_from(["sync", "daily", "--out", "$SHUTL_TEST_UNSET_VARIABLE/x"]);
+        assert!(plan_execution(&script, &matches).is_err());
+    }
+
+    #[test]
+    fn test_absolute_paths() {
+        let dir = tempdir().unwrap();
+        let root = std::fs::canonicalize(dir.path()).unwrap();
+        let script = create_test_script(
+            &root,
+            "copy.sh",
+            "#!/bin/bash\n#@arg:input - Input [file,must-exist]\n#@flag:out - Output [dir,create]\n#@flag:note - Note\n",
+        );
+        let input = root.join("input.txt");
+        std::fs::write(&input, "").unwrap();
+        let out = root.join("out/nested");
+        let plan = |args: &[&str]| {
+            let matches = crate::command::build_script_command_for_help("copy".into(), &script)
+                .get_matches_from(std::iter::once("copy").chain(args.iter().copied()));
+            plan_execution(&script, &matches)
+        };
+
+        let input_arg = input.to_string_lossy().to_string();
+        let out_arg = format!("{}/../out/nested", input.display());
+        let planned = plan(&[&input_arg, "--out", &out_arg, "--note", "x"]).unwrap();
+        assert_eq!(
+            env_value(&planned.command, "SHUTL_INPUT_ABS").as_deref(),
+            Some(input_arg.as_str())
+        );
+        assert_eq!(
+            env_value(&planned.command, "SHUTL_OUT").as_deref(),
+            Some(out_arg.as_str())
+        );
+        assert!(env_value(&planned.command, "SHUTL_OUT_ABS").is_some());
+        assert_eq!(env_value(&planned.command, "SHUTL_NOTE_ABS"), None);
+        assert_eq!(planned.create_dirs.len(), 1);
+        assert!(!out.exists());
+
+        let missing = root.join("missing.txt").to_string_lossy().to_string();
+        let Err(e) = plan(&[&missing]) else {
+            panic!("a missing input is accepted");
+        };
+        assert!(
+            e.to_string()
+                .ends_with("missing.txt is not an existing file")
+        );
+    }
+
+    #[test]
+    fn test_expand_value() {
+        let dir = tempdir().unwrap();
+        let hosts = dir.path().join("hosts.txt");
+        std::fs::write(&hosts, "# web\nweb1\n\n  web2  \n").unwrap();
+
+        let file_value = format!("@{}", hosts.display());
+        assert_eq!(expand_value(&file_value).unwrap(), vec!["web1", "web2"]);
+        assert_eq!(expand_value("@@channel").unwrap(), vec!["@channel"]);
+        assert_eq!(expand_value("plain").unwrap(), vec!["plain"]);
+        assert!(expand_value("@/nonexistent/hosts").is_err());
+
+        let counts = Config {
+            min: Some(1),
+            max: Some(2),
+            ..Default::default()
+        };
+        assert!(check_value_count("hosts", &counts, 2).is_ok());
+        assert_eq!(
+            check_value_count("hosts", &counts, 3)
+                .unwrap_err()
+                .to_string(),
+            "<hosts> takes 1 to 2 values, got 3"
+        );
+    }
+
+    #[test]
+    fn test_merge_args() {
+        let dir = tempdir().unwrap();
+        let script = create_test_script(
+            dir.path(),
+            "deploy.sh",
+            "#!/bin/bash\n#@arg:target - Target\n#@flag:region - Region [required]\n#@flag:cluster - Cluster\n#@flag:dry - Dry run [bool]\n#@flag:token - Token [secret]\n",
+        );
+        let strings = |args: &[&str]| args.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let previous = strings(&["app", "--region", "eu", "--cluster", "prod", "--dry"]);
+
+        assert_eq!(
+            merge_args(&script, &previous, &[]).unwrap(),
+            strings(&["--region=eu", "--cluster=prod", "--dry", "app"])
+        );
+        assert_eq!(
+            merge_args(
+                &script,
+                &previous,
+                &strings(&["--no-dry", "--region=us", "--", "-web"])
+            )
+            .unwrap(),
+            strings(&["--region=us", "--cluster=prod", "--no-dry", "--", "-web"])
+        );
+
+        let previous = strings(&["app", "--region", "eu", "--token", REDACTED]);
+        assert!(merge_args(&script, &previous, &[]).is_err());
+        assert_eq!(
+            merge_args(&script, &previous, &strings(&["--token", "t"])).unwrap(),
+            strings(&["--region=eu", "--token=t", "app"])
+        );
+    }
+
+    #[test]
+    fn test_redact_secret_flags() {
+        let args: Vec<String> = [
+            "shutl",
+            "login",
+            "--password",
+            "hunter2",
+            "--db-secret=x",
+            "--user",
+            "bob",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        assert_eq!(
+            redact_secret_flags(&args),
+            vec![
+                "shutl",
+                "login",
+                "--password",
+                REDACTED,
+                "--db-secret=********",
+                "--user",
+                "bob"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_powershell_scripts_are_runnable() {
+        let dir = tempdir().unwrap();
+        let ps1 = dir.path().join("report.ps1");
+        std::fs::write(&ps1, "Write-Output hi\n").unwrap();
+        let txt = dir.path().join("notes.txt");
+        std::fs::write(&txt, "notes\n").unwrap();
+
+        assert!(is_runnable(&ps1));
+        assert!(!is_runnable(&txt));
+        assert_eq!(
+            interpreter_for(&ps1, None).unwrap(),
+            vec!["pwsh", "-NoLogo", "-NoProfile", "-File"]
+        );
+        assert!(interpreter_for(&txt, None).is_none());
+    }
+
+    #[test]
+    fn test_typescript_interpreter() {
+        let dir = tempdir().unwrap();
+        let plain = dir.path().join("plain.ts");
+        std::fs::write(&plain, "console.log(1)\n").unwrap();
+        let shebang = dir.path().join("bun.ts");
+        std::fs::write(&shebang, "#!/usr/bin/env bun\nconsole.log(1)\n").unwrap();
+        let executable = create_test_script(dir.path(), "exec.ts", "#!/usr/bin/env bun\n");
+
+        assert!(is_runnable(&plain));
+        assert_eq!(
+            interpreter_for(&plain, None).unwrap(),
+            vec!["deno", "run", "-A"]
+        );
+        assert_eq!(
+            interpreter_for(&shebang, None).unwrap(),
+            vec!["/usr/bin/env", "bun"]
+        );
+        assert!(interpreter_for(&executable, None).is_none());
+        assert_eq!(
+            interpreter_for(&executable, Some("bun run")).unwrap(),
+            vec!["bun", "run"]
+        );
+    }
+
+    #[test]
+    fn test_context_env() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        let script = create_test_script(root, "deploy/prod.sh", "#!/bin/bash");
+        let clashing = create_test_script(root, "test.sh", "#!/bin/bash");
+        std::fs::create_dir(root.join("test")).unwrap();
+
+        assert_eq!(command_path(&script, root), "deploy/prod");
+        assert_eq!(command_path(&clashing, root), "test.sh");
+
+        let prefixed: std::collections::HashMap<_, _> = context_env(&script, root, Some("team"))
+            .into_iter()
+            .collect();
+        assert_eq!(prefixed["SHUTL_COMMAND_PATH"], "team/deploy/prod");
+
+        let env: std::collections::HashMap<_, _> =
+            context_env(&script, root, None).into_iter().collect();
+        assert_eq!(env.len(), CONTEXT_ENV_VARS.len());
+        assert_eq!(env["SHUTL_COMMAND_PATH"], "deploy/prod");
+        assert_eq!(env["SHUTL_SCRIPT_PATH"], script.display().to_string());
+        assert_eq!(env["SHUTL_ROOT"], root.display().to_string());
+        assert!(!env["SHUTL_INVOCATION_ID"].is_empty());
+    }
+
+    #[test]
+    fn test_find_script_file_from_memory_source() {
+        let mut source = crate::source::MemorySource::new();
+        source
+            .add_script("/s/deploy/prod.sh", "#!/bin/bash\n")
+            .add_file("/s/readme.md", "docs", false)
+            .add_file("/s/tool.ps1", "Write-Output hi\n", false);
+        let components = |path: &str| path.split('/').map(String::from).collect::<Vec<_>>();
+        let root = Path::new("/s");
+
+        assert_eq!(
+            find_script_file_from(&source, &components("deploy/prod"), root),
+            Some(PathBuf::from("/s/deploy/prod.sh"))
+        );
+        assert_eq!(
+            find_script_file_from(&source, &components("deploy"), root),
+            Some(PathBuf::from("/s/deploy"))
+        );
+        assert_eq!(
+            find_script_file_from(&source, &components("missing"), root),
+            None
+        );
+        assert!(is_runnable_from(&source, Path::new("/s/tool.ps1")));
+        assert!(!is_runnable_from(&source, Path::new("/s/readme.md")));
+    }
+
     #[test]
     fn test_find_script_file() {
         let dir = tempdir().unwrap();
@@ -200,13 +1562,60 @@ mod tests {
         assert!(find_script_file_in_dir(&components, &scripts_dir).is_none());
     }
 
+    #[test]
+    fn test_is_safe_component() {
+        assert!(is_safe_component("deploy"));
+        assert!(is_safe_component("deploy.sh"));
+        assert!(!is_safe_component(""));
+        assert!(!is_safe_component("."));
+        assert!(!is_safe_component(".."));
+        assert!(!is_safe_component("/etc"));
+        assert!(!is_safe_component("a/b"));
+    }
+
+    #[test]
+    fn test_find_script_file_rejects_traversal() {
+        let dir = tempdir().unwrap();
+        let scripts_dir = dir.path().join(".shutl");
+        std::fs::create_dir(&scripts_dir).unwrap();
+        create_test_script(dir.path(), "outside.sh", "#!/bin/bash");
+        create_test_script(&scripts_dir, "inside.sh", "#!/bin/bash");
+
+        let components = vec!["..".to_string(), "outside".to_string()];
+        assert!(find_script_file_in_dir(&components, &scripts_dir).is_none());
+
+        let components = vec!["../outside".to_string()];
+        assert!(find_script_file_in_dir(&components, &scripts_dir).is_none());
+
+        assert!(find_script_file_in_dir(&[], &scripts_dir).is_none());
+        assert!(resolve_command_path(&scripts_dir, &["inside".to_string()]).is_some());
+    }
+
+    #[test]
+    fn test_find_script_file_skips_broken_symlinks() {
+        let dir = tempdir().unwrap();
+        let scripts_dir = dir.path().join(".shutl");
+        std::fs::create_dir(&scripts_dir).unwrap();
+
+        std::os::unix::fs::symlink(dir.path().join("missing.sh"), scripts_dir.join("gone.sh"))
+            .unwrap();
+        let components = vec!["gone".to_string()];
+        assert!(find_script_file_in_dir(&components, &scripts_dir).is_none());
+
+        let target = create_test_script(dir.path(), "real.sh", "#!/bin/bash");
+        std::os::unix::fs::symlink(&target, scripts_dir.join("linked.sh")).unwrap();
+        let components = vec!["linked".to_string()];
+        let found = find_script_file_in_dir(&components, &scripts_dir).unwrap();
+        assert_eq!(found.file_name().unwrap(), "linked.sh");
+    }
+
     #[test]
     fn test_execute_script_with_different_extensions() {
         let dir = tempdir().unwrap();
 
         // Create test scripts with different extensions
         let sh_script = create_test_script(
-            &dir.path(),
+            dir.path(),
             "test.sh",
             r#"#!/bin/bash
 #@description: Test shell script
@@ -216,7 +1625,7 @@ echo "Shell script executed with input: $SHUTL_INPUT"
         );
 
         let py_script = create_test_script(
-            &dir.path(),
+            dir.path(),
             "test.py",
             r#"#!/usr/bin/env python3
 import os
@@ -227,7 +1636,7 @@ print(f"Python script executed with input: {os.environ.get('SHUTL_INPUT', '')}")
         );
 
         let rb_script = create_test_script(
-            &dir.path(),
+            dir.path(),
             "test.rb",
             r#"#!/usr/bin/env ruby
 #@description: Test Ruby script
@@ -0,0 +1,229 @@
+//! Signatures of shared scripts. `shutl sign` signs files with `ssh-keygen -Y sign` or
+//! `minisign -S`; `shutl import` checks the signature published next to a script
+//! (`<url>.sig` or `<url>.minisig`) against the keys the config trusts.
+
+use crate::config;
+use crate::paths;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Namespace of SSH signatures, so signatures made for other purposes don't verify
+pub const NAMESPACE: &str = "shutl";
+
+/// Signature tool
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scheme {
+    /// `ssh-keygen -Y`, trusting the signers of an allowed-signers file
+    Ssh,
+    /// `minisign`, trusting one public key
+    Minisign,
+}
+
+impl Scheme {
+    /// Picks the tool for a secret key: minisign keys end in `.key`, anything else is taken
+    /// for an SSH key
+    pub fn for_key(key: &Path) -> Self {
+        if key.extension().is_some_and(|ext| ext == "key") {
+            Scheme::Minisign
+        } else {
+            Scheme::Ssh
+        }
+    }
+
+    /// Extension of detached signatures, appended to the name of the signed file
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Scheme::Ssh => "sig",
+            Scheme::Minisign => "minisig",
+        }
+    }
+
+    /// Gets the path of the signature of `file`, e.g. `deploy.sh.sig`
+    pub fn signature_path(&self, file: &Path) -> PathBuf {
+        let mut path = file.as_os_str().to_owned();
+        path.push(".");
+        path.push(self.extension());
+        PathBuf::from(path)
+    }
+}
+
+/// Keys imported scripts are checked against (`allowed-signers`, `minisign-public-key`
+/// and `require-signatures` in the config)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Trust {
+    pub allowed_signers: Option<PathBuf>,
+    pub minisign_key: Option<PathBuf>,
+    /// Refuse scripts without a valid signature
+    pub required: bool,
+}
+
+impl Trust {
+    pub fn from_config() -> Self {
+        let config = config::get();
+        let expand = |path: &String| {
+            PathBuf::from(
+                paths::expand(path)
+                    .map(|p| p.to_string())
+                    .unwrap_or_else(|_| path.clone()),
+            )
+        };
+        Trust {
+            allowed_signers: config.allowed_signers.as_ref().map(expand),
+            minisign_key: config.minisign_public_key.as_ref().map(expand),
+            required: config.require_signatures,
+        }
+    }
+
+    /// Gets the schemes with a trusted key, in the order signatures are looked for
+    pub fn schemes(&self) -> Vec<Scheme> {
+        let mut schemes = Vec::new();
+        if self.allowed_signers.is_some() {
+            schemes.push(Scheme::Ssh);
+        }
+        if self.minisign_key.is_some() {
+            schemes.push(Scheme::Minisign);
+        }
+        schemes
+    }
+}
+
+/// Signs `file` with the secret `key`, writing the signature next to it. The tool may
+/// ask for the key's passphrase.
+pub fn sign(key: &Path, file: &Path) -> Result<PathBuf, String> {
+    let scheme = Scheme::for_key(key);
+    let signature = scheme.signature_path(file);
+    // ssh-keygen does not replace an existing signature
+    if signature.exists() {
+        std::fs::remove_file(&signature).map_err(|e| format!("{}: {}", signature.display(), e))?;
+    }
+    let mut command = match scheme {
+        Scheme::Ssh => {
+            let mut command = Command::new("ssh-keygen");
+            command
+                .args(["-q", "-Y", "sign", "-n", NAMESPACE, "-f"])
+                .arg(key)
+                .arg(file);
+            command
+        }
+        Scheme::Minisign => {
+            let mut command = Command::new("minisign");
+            command.args(["-S", "-s"]).arg(key).arg("-m").arg(file);
+            command
+        }
+    };
+    run(&mut command, scheme)?;
+    Ok(signature)
+}
+
+/// Checks the `signature` of `file` against the trusted key of `scheme` and returns who
+/// signed it: the principal of the allowed-signers file, or the minisign key file
+pub fn verify(
+    trust: &Trust,
+    scheme: Scheme,
+    file: &Path,
+    signature: &Path,
+) -> Result<String, String> {
+    match scheme {
+        Scheme::Ssh => {
+            let allowed = trust
+                .allowed_signers
+                .as_deref()
+                .ok_or("no allowed-signers file is configured")?;
+            let output = Command::new("ssh-keygen")
+                .args(["-Y", "find-principals", "-s"])
+                .arg(signature)
+                .arg("-f")
+                .arg(allowed)
+                .output()
+                .map_err(|e| tool_error(scheme, e))?;
+            let principals = String::from_utf8_lossy(&output.stdout);
+            let Some(principal) = principals
+                .lines()
+                .next()
+                .filter(|_| output.status.success())
+            else {
+                return Err(format!(
+                    "{} was not made by a signer in {}",
+                    signature.display(),
+                    allowed.display()
+                ));
+            };
+            let file =
+                std::fs::File::open(file).map_err(|e| format!("{}: {}", file.display(), e))?;
+            let mut command = Command::new("ssh-keygen");
+            command
+                .args(["-Y", "verify", "-n", NAMESPACE, "-f"])
+                .arg(allowed)
+                .args(["-I", principal, "-s"])
+                .arg(signature)
+                .stdin(file);
+            run(&mut command, scheme)?;
+            Ok(principal.to_string())
+        }
+        Scheme::Minisign => {
+            let key = trust
+                .minisign_key
+                .as_deref()
+                .ok_or("no minisign-public-key is configured")?;
+            let mut command = Command::new("minisign");
+            command
+                .args(["-V", "-q", "-p"])
+                .arg(key)
+                .arg("-m")
+                .arg(file)
+                .arg("-x")
+                .arg(signature);
+            run(&mut command, scheme)?;
+            Ok(key.display().to_string())
+        }
+    }
+}
+
+/// Runs a signature tool without its output, turning failures into its error message
+fn run(command: &mut Command, scheme: Scheme) -> Result<(), String> {
+    let output = command
+        .stdout(Stdio::null())
+        .output()
+        .map_err(|e| tool_error(scheme, e))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(String::from_utf8_lossy(&output.stderr).trim().to_string())
+    }
+}
+
+fn tool_error(scheme: Scheme, e: std::io::Error) -> String {
+    let tool = match scheme {
+        Scheme::Ssh => "ssh-keygen",
+        Scheme::Minisign => "minisign",
+    };
+    format!("cannot run {}: {}", tool, e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_schemes() {
+        assert_eq!(
+            Scheme::for_key(Path::new("/home/me/.ssh/id_ed25519")),
+            Scheme::Ssh
+        );
+        assert_eq!(
+            Scheme::for_key(Path::new("/home/me/.minisign/minisign.key")),
+            Scheme::Minisign
+        );
+        assert_eq!(
+            Scheme::Minisign.signature_path(Path::new("/s/deploy.sh")),
+            PathBuf::from("/s/deploy.sh.minisig")
+        );
+
+        let trust = Trust {
+            minisign_key: Some(PathBuf::from("/k/minisign.pub")),
+            ..Default::default()
+        };
+        assert_eq!(trust.schemes(), vec![Scheme::Minisign]);
+        assert!(Trust::default().schemes().is_empty());
+    }
+}
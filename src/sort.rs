@@ -0,0 +1,179 @@
+//! Ordering of commands in help output and listings.
+
+use serde::Deserialize;
+use std::cmp::Ordering;
+
+/// How sibling commands are ordered
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SortOrder {
+    /// Case-insensitive, with digit runs compared by value (`task2` < `task10`)
+    #[default]
+    Natural,
+    /// Plain byte-wise comparison
+    Alphabetical,
+    /// Keep the order in which the filesystem returned the entries
+    Filesystem,
+}
+
+impl SortOrder {
+    /// Compares two command names according to this order.
+    /// `Filesystem` treats all names as equal so a stable sort keeps the original order.
+    pub fn compare(self, a: &str, b: &str) -> Ordering {
+        match self {
+            SortOrder::Natural => natural_cmp(a, b),
+            SortOrder::Alphabetical => a.cmp(b),
+            SortOrder::Filesystem => Ordering::Equal,
+        }
+    }
+}
+
+/// Sorts items by name, placing names listed in `manual_order` first (in that order)
+/// and the remaining ones according to `order`.
+pub fn sort_by_name<T>(
+    items: &mut [T],
+    manual_order: &[String],
+    order: SortOrder,
+    name: impl Fn(&T) -> &str,
+) {
+    let rank = |item: &T| {
+        manual_order
+            .iter()
+            .position(|n| n == name(item))
+            .unwrap_or(usize::MAX)
+    };
+    items.sort_by(|a, b| {
+        rank(a)
+            .cmp(&rank(b))
+            .then_with(|| order.compare(name(a), name(b)))
+    });
+}
+
+/// Compares `/`-separated command paths component by component, so entries of the
+/// same directory stay grouped together.
+pub fn compare_paths(order: SortOrder, a: &str, b: &str) -> Ordering {
+    let mut left = a.split('/');
+    let mut right = b.split('/');
+    loop {
+        match (left.next(), right.next()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(l), Some(r)) => {
+                let ord = order.compare(l, r);
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+            }
+        }
+    }
+}
+
+/// Natural, case-insensitive comparison. Ties are broken byte-wise so the result is total.
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut left = chunks(a);
+    let mut right = chunks(b);
+    loop {
+        match (left.next(), right.next()) {
+            (None, None) => return a.cmp(b),
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(l), Some(r)) => {
+                let ord = match (is_digits(l), is_digits(r)) {
+                    (true, true) => compare_numeric(l, r),
+                    _ => l.to_lowercase().cmp(&r.to_lowercase()),
+                };
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+            }
+        }
+    }
+}
+
+fn is_digits(s: &str) -> bool {
+    s.starts_with(|c: char| c.is_ascii_digit())
+}
+
+/// Compares two runs of ASCII digits by value without overflowing on long runs
+fn compare_numeric(a: &str, b: &str) -> Ordering {
+    let a = a.trim_start_matches('0');
+    let b = b.trim_start_matches('0');
+    a.len().cmp(&b.len()).then_with(|| a.cmp(b))
+}
+
+/// Splits a string into alternating runs of ASCII digits and non-digits
+fn chunks(s: &str) -> impl Iterator<Item = &str> {
+    let mut rest = s;
+    std::iter::from_fn(move || {
+        let first = rest.chars().next()?;
+        let digit = first.is_ascii_digit();
+        let end = rest
+            .find(|c: char| c.is_ascii_digit() != digit)
+            .unwrap_or(rest.len());
+        let (chunk, tail) = rest.split_at(end);
+        rest = tail;
+        Some(chunk)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_natural_cmp_numbers() {
+        assert_eq!(natural_cmp("task2", "task10"), Ordering::Less);
+        assert_eq!(natural_cmp("task10", "task2"), Ordering::Greater);
+        assert_eq!(natural_cmp("task02", "task2"), Ordering::Less);
+        assert_eq!(natural_cmp("v1.9", "v1.10"), Ordering::Less);
+    }
+
+    #[test]
+    fn test_natural_cmp_case_insensitive() {
+        assert_eq!(natural_cmp("Build", "apply"), Ordering::Greater);
+        assert_eq!(natural_cmp("build", "Build"), Ordering::Greater);
+        assert_eq!(natural_cmp("a", "ab"), Ordering::Less);
+    }
+
+    #[test]
+    fn test_compare_paths_groups_directories() {
+        let mut paths = vec![
+            "hello",
+            "docker/push",
+            "docker10/x",
+            "docker/build",
+            "docker2/x",
+        ];
+        paths.sort_by(|a, b| compare_paths(SortOrder::Natural, a, b));
+        assert_eq!(
+            paths,
+            vec![
+                "docker/build",
+                "docker/push",
+                "docker2/x",
+                "docker10/x",
+                "hello"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sort_by_name_with_manual_order() {
+        let mut names = vec!["task10", "deploy", "task2", "build", "zeta"];
+        let manual = vec!["zeta".to_string(), "deploy".to_string()];
+        sort_by_name(&mut names, &manual, SortOrder::Natural, |n| n);
+        assert_eq!(names, vec!["zeta", "deploy", "build", "task2", "task10"]);
+    }
+
+    #[test]
+    fn test_sort_by_name_filesystem_keeps_order() {
+        let mut names = vec!["b", "c", "a"];
+        sort_by_name(&mut names, &["a".to_string()], SortOrder::Filesystem, |n| n);
+        assert_eq!(names, vec!["a", "b", "c"]);
+
+        let mut names = vec!["b", "c", "a"];
+        sort_by_name(&mut names, &[], SortOrder::Filesystem, |n| n);
+        assert_eq!(names, vec!["b", "c", "a"]);
+    }
+}
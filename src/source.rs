@@ -0,0 +1,425 @@
+//! Where the command tree is read from. [`ScriptSource`] is the view of a script root that
+//! `command.rs` and `script.rs` need: directory listings, file contents (for metadata)
+//! and the executable bit. [`FsSource`] is the real filesystem; [`MemorySource`] holds a
+//! tree in memory, for tests and roots that don't live on disk.
+
+use crate::config::locales;
+use crate::metadata::{
+    CommandMetadata, DIR_METADATA_FILE, DIR_README_FILE, DirMetadata, SpannedElement,
+    parse_dir_metadata_contents, parse_metadata_elements, readme_description, translation,
+};
+use crate::persist;
+use is_executable::IsExecutable;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Whether a directory entry is a directory or a file. Symlinks are resolved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    Dir,
+    File,
+}
+
+/// A directory entry of a [`ScriptSource`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceEntry {
+    /// File name, always valid UTF-8 since it becomes a command name
+    pub name: String,
+    pub path: PathBuf,
+    pub kind: EntryKind,
+}
+
+/// A tree of scripts and directories
+pub trait ScriptSource {
+    /// Lists the entries of `dir`, in no particular order. Entries that are neither a
+    /// directory nor a file (e.g. broken links) are left out; a missing directory is empty.
+    fn list_dir(&self, dir: &Path) -> Vec<SourceEntry>;
+
+    /// Reads a file, or `None` if it does not exist or cannot be read
+    fn read_to_string(&self, path: &Path) -> Option<String>;
+
+    fn is_dir(&self, path: &Path) -> bool;
+
+    fn is_file(&self, path: &Path) -> bool;
+
+    fn is_executable(&self, path: &Path) -> bool;
+
+    /// Gets the identity of a path, used to visit a directory or script reachable
+    /// through several links only once
+    fn canonicalize(&self, path: &Path) -> PathBuf {
+        path.to_path_buf()
+    }
+
+    /// Gets a path on the local filesystem for `path`, e.g. to run the script. Sources
+    /// that are not the filesystem may download it first; `None` if that fails.
+    fn local_path(&self, path: &Path) -> Option<PathBuf> {
+        Some(path.to_path_buf())
+    }
+
+    /// Reads the `#@` metadata of a script, with the defaults it inherits from the
+    /// `.shutl` files of its directories up to its root
+    fn command_metadata(&self, path: &Path) -> CommandMetadata {
+        let contents = self.read_to_string(path).unwrap_or_default();
+        let mut metadata = CommandMetadata::from_elements(parse_metadata_elements(path, &contents));
+        let mounts = crate::roots::mounts();
+        let root = crate::roots::mount_for(&mounts, path)
+            .map(|mount| mount.path.as_path())
+            .or(path.parent());
+        if let Some(root) = root {
+            metadata.inherit(&self.inherited_defaults(root, path));
+        }
+        metadata.localize(&crate::config::locales());
+        metadata
+    }
+
+    /// Collects the defaults of the `.shutl` files from `root` down to the directory of
+    /// `path`, outermost first
+    fn inherited_defaults(&self, root: &Path, path: &Path) -> Vec<SpannedElement> {
+        let mut dirs: Vec<&Path> = path
+            .ancestors()
+            .skip(1)
+            .take_while(|dir| dir.starts_with(root))
+            .collect();
+        dirs.reverse();
+        dirs.into_iter()
+            .flat_map(|dir| self.dir_metadata(dir).defaults)
+            .collect()
+    }
+
+    /// Reads the `.shutl` file of a directory, or its `README.md` if it has none
+    fn dir_metadata(&self, dir: &Path) -> DirMetadata {
+        if let Some(contents) = self.read_to_string(&dir.join(DIR_METADATA_FILE)) {
+            let mut metadata = parse_dir_metadata_contents(dir, &contents);
+            if let Some(description) = translation(&metadata.descriptions, &locales()) {
+                metadata.description = description.to_string();
+            }
+            return metadata;
+        }
+        let readme = dir.join(DIR_README_FILE);
+        match self.read_to_string(&readme) {
+            Some(contents) => DirMetadata {
+                description: readme_description(&contents),
+                readme: Some(readme),
+                ..Default::default()
+            },
+            None => DirMetadata::default(),
+        }
+    }
+}
+
+/// The real filesystem
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FsSource;
+
+impl ScriptSource for FsSource {
+    fn list_dir(&self, dir: &Path) -> Vec<SourceEntry> {
+        let Ok(read_dir) = std::fs::read_dir(dir) else {
+            return Vec::new();
+        };
+        read_dir
+            .filter_map(Result::ok)
+            .filter_map(|entry| {
+                let path = entry.path();
+                // Command names must be valid UTF-8, so entries whose file name is not
+                // cannot be addressed from the command line. They are skipped instead of
+                // being registered under a lossy name; `shutl doctor` lists them.
+                let Ok(name) = entry.file_name().into_string() else {
+                    log::warn!("Skipping {}: file name is not valid UTF-8", path.display());
+                    return None;
+                };
+                // `is_dir`/`is_file` follow symlinks, so broken links are neither
+                let kind = if path.is_dir() {
+                    EntryKind::Dir
+                } else if path.is_file() {
+                    EntryKind::File
+                } else {
+                    return None;
+                };
+                Some(SourceEntry { name, path, kind })
+            })
+            .collect()
+    }
+
+    fn read_to_string(&self, path: &Path) -> Option<String> {
+        std::fs::read_to_string(path).ok()
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        path.is_dir()
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        path.is_file()
+    }
+
+    fn is_executable(&self, path: &Path) -> bool {
+        path.is_executable()
+    }
+
+    fn canonicalize(&self, path: &Path) -> PathBuf {
+        std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+    }
+}
+
+/// Finds the entries below `dir` that [`FsSource::list_dir`] skips because their file
+/// name is not valid UTF-8, without descending into them
+pub fn non_utf8_entries(dir: &Path) -> Vec<PathBuf> {
+    let mut found = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let Ok(read_dir) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in read_dir.filter_map(Result::ok) {
+            let path = entry.path();
+            if entry.file_name().to_str().is_none() {
+                found.push(path);
+            } else if path.is_dir() {
+                dirs.push(path);
+            }
+        }
+    }
+    found.sort();
+    found
+}
+
+/// Writes the local copy of a file of a source that is not the filesystem (see
+/// [`ScriptSource::local_path`]). It has its mode before it appears under its name, as
+/// other shutl processes may run it as soon as its content matches.
+pub(crate) fn write_local_copy(path: &Path, data: &[u8], executable: bool) -> std::io::Result<()> {
+    let mode = if executable { 0o755 } else { 0o644 };
+    persist::write_atomic_with_mode(path, data, mode)
+}
+
+#[derive(Debug, Clone)]
+enum MemoryEntry {
+    Dir,
+    File { contents: String, executable: bool },
+}
+
+/// A tree of files held in memory. Parent directories are created implicitly.
+#[derive(Debug, Clone, Default)]
+pub struct MemorySource {
+    entries: BTreeMap<PathBuf, MemoryEntry>,
+}
+
+impl MemorySource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a directory (and its parents)
+    pub fn add_dir(&mut self, path: impl AsRef<Path>) -> &mut Self {
+        for dir in path.as_ref().ancestors() {
+            if dir.as_os_str().is_empty() || dir.parent().is_none() {
+                break;
+            }
+            self.entries.insert(dir.to_path_buf(), MemoryEntry::Dir);
+        }
+        self
+    }
+
+    /// Adds a file, replacing an existing one
+    pub fn add_file(
+        &mut self,
+        path: impl AsRef<Path>,
+        contents: &str,
+        executable: bool,
+    ) -> &mut Self {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            self.add_dir(parent);
+        }
+        self.entries.insert(
+            path.to_path_buf(),
+            MemoryEntry::File {
+                contents: contents.to_string(),
+                executable,
+            },
+        );
+        self
+    }
+
+    /// Adds an executable script
+    pub fn add_script(&mut self, path: impl AsRef<Path>, contents: &str) -> &mut Self {
+        self.add_file(path, contents, true)
+    }
+}
+
+impl ScriptSource for MemorySource {
+    fn list_dir(&self, dir: &Path) -> Vec<SourceEntry> {
+        self.entries
+            .iter()
+            .filter(|(path, _)| path.parent() == Some(dir))
+            .filter_map(|(path, entry)| {
+                Some(SourceEntry {
+                    name: path.file_name()?.to_str()?.to_string(),
+                    path: path.clone(),
+                    kind: match entry {
+                        MemoryEntry::Dir => EntryKind::Dir,
+                        MemoryEntry::File { .. } => EntryKind::File,
+                    },
+                })
+            })
+            .collect()
+    }
+
+    fn read_to_string(&self, path: &Path) -> Option<String> {
+        match self.entries.get(path)? {
+            MemoryEntry::File { contents, .. } => Some(contents.clone()),
+            MemoryEntry::Dir => None,
+        }
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        matches!(self.entries.get(path), Some(MemoryEntry::Dir))
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        matches!(self.entries.get(path), Some(MemoryEntry::File { .. }))
+    }
+
+    fn is_executable(&self, path: &Path) -> bool {
+        matches!(
+            self.entries.get(path),
+            Some(MemoryEntry::File {
+                executable: true,
+                ..
+            })
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_source() {
+        let mut source = MemorySource::new();
+        source
+            .add_script(
+                "/scripts/deploy/prod.sh",
+                "#!/bin/bash\n#@description: Prod\n",
+            )
+            .add_file("/scripts/deploy/.shutl", "Deployments\n", false)
+            .add_file("/scripts/notes.txt", "notes", false);
+
+        let mut names: Vec<_> = source
+            .list_dir(Path::new("/scripts"))
+            .into_iter()
+            .map(|e| (e.name, e.kind))
+            .collect();
+        names.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            names,
+            vec![
+                ("deploy".to_string(), EntryKind::Dir),
+                ("notes.txt".to_string(), EntryKind::File),
+            ]
+        );
+        assert!(source.is_dir(Path::new("/scripts/deploy")));
+        assert!(source.is_executable(Path::new("/scripts/deploy/prod.sh")));
+        assert!(!source.is_executable(Path::new("/scripts/notes.txt")));
+        assert!(source.list_dir(Path::new("/missing")).is_empty());
+
+        let metadata = source.command_metadata(Path::new("/scripts/deploy/prod.sh"));
+        assert_eq!(metadata.description, "Prod");
+        let dir = source.dir_metadata(Path::new("/scripts/deploy"));
+        assert_eq!(dir.description, "Deployments");
+    }
+
+    #[test]
+    fn test_inherited_defaults() {
+        let mut source = MemorySource::new();
+        source
+            .add_file(
+                "/scripts/aws/.shutl",
+                "AWS\n#@flag:profile - AWS profile [options:dev|prod]\n#@env-allow: AWS_*\n#@interpreter: bash\n",
+                false,
+            )
+            .add_file(
+                "/scripts/aws/ec2/.shutl",
+                "#@flag:region - Region [default:eu-west-1]\n#@interpreter: zsh\n",
+                false,
+            )
+            .add_script(
+                "/scripts/aws/ec2/list.sh",
+                "#!/bin/bash\n#@flag:region - Region [default:us-east-1]\n",
+            );
+        let root = Path::new("/scripts");
+        let script = Path::new("/scripts/aws/ec2/list.sh");
+        assert_eq!(source.inherited_defaults(root, script).len(), 5);
+        assert!(
+            source
+                .inherited_defaults(Path::new("/scripts/aws/ec2"), script)
+                .iter()
+                .all(|d| d.span.line <= 2)
+        );
+
+        let contents = source.read_to_string(script).unwrap();
+        let mut metadata =
+            CommandMetadata::from_elements(parse_metadata_elements(script, &contents));
+        metadata.inherit(&source.inherited_defaults(root, script));
+        let names: Vec<_> = metadata
+            .arguments
+            .iter()
+            .map(|arg| match arg {
+                crate::metadata::LineType::Flag(name, _, cfg) => {
+                    (name.as_str(), cfg.default.clone())
+                }
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(
+            names,
+            vec![("region", Some("us-east-1".to_string())), ("profile", None),]
+        );
+        assert_eq!(metadata.interpreter.as_deref(), Some("zsh"));
+        assert_eq!(metadata.env.allow, Some(vec!["AWS_*".to_string()]));
+    }
+
+    #[test]
+    fn test_non_utf8_entries() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let bad = std::ffi::OsStr::from_bytes(b"caf\xe9.sh");
+        std::fs::create_dir_all(dir.path().join("tools")).unwrap();
+        std::fs::write(dir.path().join("tools").join(bad), "").unwrap();
+        std::fs::write(dir.path().join("ok.sh"), "").unwrap();
+
+        assert_eq!(
+            non_utf8_entries(dir.path()),
+            vec![dir.path().join("tools").join(bad)]
+        );
+        assert_eq!(FsSource.list_dir(&dir.path().join("tools")), Vec::new());
+    }
+
+    #[test]
+    fn test_write_local_copy_is_executable_when_it_appears() {
+        use std::os::unix::fs::PermissionsExt;
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("remote/deploy.sh");
+        let done = std::sync::Arc::new(AtomicBool::new(false));
+        let writer = {
+            let (path, done) = (path.clone(), done.clone());
+            std::thread::spawn(move || {
+                for _ in 0..2000 {
+                    let _ = std::fs::remove_file(&path);
+                    write_local_copy(&path, b"#!/bin/sh\necho hi\n", true).unwrap();
+                }
+                done.store(true, Ordering::SeqCst);
+            })
+        };
+        // Whenever another process could find the copy, it can run it
+        while !done.load(Ordering::SeqCst) {
+            if let Ok(metadata) = std::fs::metadata(&path) {
+                assert_eq!(metadata.permissions().mode() & 0o777, 0o755);
+            }
+        }
+        writer.join().unwrap();
+    }
+}
@@ -0,0 +1,138 @@
+//! Summaries of running several scripts in one go (`shutl run-all`): a table with the
+//! status, duration and log of every command, and the policy for failures.
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// What to do after a command failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExitPolicy {
+    /// Skip the remaining commands
+    FailFast,
+    /// Run the remaining commands anyway
+    #[default]
+    KeepGoing,
+}
+
+/// How a command of the batch ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Ok,
+    /// Exited non-zero, or killed by a signal (no code)
+    Failed(Option<i32>),
+    /// Not run because an earlier command failed with [`ExitPolicy::FailFast`]
+    Skipped,
+}
+
+/// One command of the batch
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunSummary {
+    /// Command path, e.g. `checks/lint`
+    pub command: String,
+    pub outcome: Outcome,
+    pub duration: Option<Duration>,
+    /// File with the command's output
+    pub log: Option<PathBuf>,
+}
+
+/// Gets the directory the output of batch runs is logged to
+pub fn logs_dir() -> Option<PathBuf> {
+    crate::paths::cache_dir().map(|dir| dir.join("shutl").join("logs"))
+}
+
+/// Renders the summary as an aligned table with a header line
+pub fn render_table(runs: &[RunSummary]) -> String {
+    let rows: Vec<[String; 4]> = runs
+        .iter()
+        .map(|run| {
+            let status = match run.outcome {
+                Outcome::Ok => "ok".to_string(),
+                Outcome::Failed(Some(code)) => format!("failed ({})", code),
+                Outcome::Failed(None) => "failed (signal)".to_string(),
+                Outcome::Skipped => "skipped".to_string(),
+            };
+            let duration = run
+                .duration
+                .map(|d| format!("{:.1}s", d.as_secs_f64()))
+                .unwrap_or_default();
+            let log = run
+                .log
+                .as_ref()
+                .map(|log| log.display().to_string())
+                .unwrap_or_default();
+            [run.command.clone(), status, duration, log]
+        })
+        .collect();
+
+    let header = ["COMMAND", "STATUS", "DURATION", "LOG"].map(String::from);
+    let mut widths = header.clone().map(|h| h.len());
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    std::iter::once(&header)
+        .chain(&rows)
+        .map(|row| {
+            let line = format!(
+                "{:<w0$}  {:<w1$}  {:>w2$}  {}",
+                row[0],
+                row[1],
+                row[2],
+                row[3],
+                w0 = widths[0],
+                w1 = widths[1],
+                w2 = widths[2]
+            );
+            line.trim_end().to_string()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Gets the exit code of the batch: 0 if every command succeeded, else 1
+pub fn exit_code(runs: &[RunSummary]) -> i32 {
+    if runs.iter().all(|run| run.outcome == Outcome::Ok) {
+        0
+    } else {
+        1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_table() {
+        let runs = vec![
+            RunSummary {
+                command: "checks/lint".to_string(),
+                outcome: Outcome::Ok,
+                duration: Some(Duration::from_millis(420)),
+                log: Some(PathBuf::from("/logs/lint.log")),
+            },
+            RunSummary {
+                command: "checks/test".to_string(),
+                outcome: Outcome::Failed(Some(2)),
+                duration: Some(Duration::from_secs(12)),
+                log: Some(PathBuf::from("/logs/test.log")),
+            },
+            RunSummary {
+                command: "checks/e2e".to_string(),
+                outcome: Outcome::Skipped,
+                duration: None,
+                log: None,
+            },
+        ];
+        assert_eq!(
+            render_table(&runs),
+            "COMMAND      STATUS      DURATION  LOG\n\
+             checks/lint  ok              0.4s  /logs/lint.log\n\
+             checks/test  failed (2)     12.0s  /logs/test.log\n\
+             checks/e2e   skipped"
+        );
+        assert_eq!(exit_code(&runs), 1);
+        assert_eq!(exit_code(&runs[..1]), 0);
+    }
+}
@@ -0,0 +1,162 @@
+//! Black-box tests of the `shutl` binary (`test-support` feature). A [`FakeHome`] is a
+//! temporary home directory with its own scripts directory, config and data directories;
+//! tests write scripts into it and run the CLI against it, nothing of the machine's own
+//! setup leaking in. In an integration test (`tests/*.rs`):
+//!
+//! ```ignore
+//! use shutl::testing::FakeHome;
+//!
+//! let home = FakeHome::new(env!("CARGO_BIN_EXE_shutl"));
+//! home.script("deploy/prod.sh", "#!/bin/bash\n#@arg:region - Region\necho \"$SHUTL_REGION\"\n");
+//! let output = home.run(&["deploy", "prod", "eu"]);
+//! assert!(output.success());
+//! assert_eq!(output.stdout, "eu\n");
+//! ```
+
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tempfile::TempDir;
+
+/// A temporary home for running shutl, removed when dropped
+pub struct FakeHome {
+    dir: TempDir,
+    binary: PathBuf,
+}
+
+/// What a run of shutl printed and how it exited
+#[derive(Debug, Clone, PartialEq)]
+pub struct Output {
+    /// Exit code; `None` if shutl was killed by a signal
+    pub code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl Output {
+    pub fn success(&self) -> bool {
+        self.code == Some(0)
+    }
+}
+
+impl FakeHome {
+    /// Creates an empty home for the shutl executable at `binary`, e.g.
+    /// `env!("CARGO_BIN_EXE_shutl")` in the tests of a crate building it
+    pub fn new(binary: impl Into<PathBuf>) -> Self {
+        let dir = tempfile::tempdir().expect("Failed to create a temporary home");
+        std::fs::create_dir_all(dir.path().join("scripts"))
+            .expect("Failed to create the scripts directory");
+        FakeHome {
+            dir,
+            binary: binary.into(),
+        }
+    }
+
+    /// The home directory (`$HOME` of the runs)
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    /// The scripts directory (`$SHUTL_DIR` of the runs)
+    pub fn scripts_dir(&self) -> PathBuf {
+        self.path().join("scripts")
+    }
+
+    /// The config file (`$SHUTL_CONFIG` of the runs); it does not exist until
+    /// [`FakeHome::config`] writes it
+    pub fn config_path(&self) -> PathBuf {
+        self.path().join(".config/shutl/config.toml")
+    }
+
+    /// Writes an executable script at `path` below the scripts directory, e.g.
+    /// `deploy/prod.sh`
+    pub fn script(&self, path: &str, contents: &str) -> &Self {
+        let path = self.write(&self.scripts_dir().join(path), contents);
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))
+            .expect("Failed to make the script executable");
+        self
+    }
+
+    /// Writes a file that is not executable below the scripts directory, such as a
+    /// directory's `.shutl` file
+    pub fn file(&self, path: &str, contents: &str) -> &Self {
+        self.write(&self.scripts_dir().join(path), contents);
+        self
+    }
+
+    /// Writes the config file
+    pub fn config(&self, contents: &str) -> &Self {
+        self.write(&self.config_path(), contents);
+        self
+    }
+
+    /// Copies the files below `fixtures` into the scripts directory, keeping their
+    /// permissions
+    pub fn fixtures(&self, fixtures: &Path) -> &Self {
+        let mut dirs = vec![fixtures.to_path_buf()];
+        while let Some(dir) = dirs.pop() {
+            let entries = std::fs::read_dir(&dir)
+                .unwrap_or_else(|e| panic!("Failed to read fixtures {}: {}", dir.display(), e));
+            for entry in entries.flatten() {
+                let source = entry.path();
+                let relative = source.strip_prefix(fixtures).unwrap_or(&source);
+                let target = self.scripts_dir().join(relative);
+                if source.is_dir() {
+                    std::fs::create_dir_all(&target).expect("Failed to create a directory");
+                    dirs.push(source);
+                } else {
+                    std::fs::copy(&source, &target).unwrap_or_else(|e| {
+                        panic!("Failed to copy fixture {}: {}", source.display(), e)
+                    });
+                }
+            }
+        }
+        self
+    }
+
+    fn write(&self, path: &Path, contents: &str) -> PathBuf {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).expect("Failed to create a directory");
+        }
+        std::fs::write(path, contents)
+            .unwrap_or_else(|e| panic!("Failed to write {}: {}", path.display(), e));
+        path.to_path_buf()
+    }
+
+    /// Builds the command running shutl with `args` in this home: a clean environment
+    /// with only `PATH` from the test's, and the home as working directory. Tests add
+    /// their own variables, stdin or working directory before running it.
+    pub fn command(&self, args: &[&str]) -> Command {
+        let home = self.path();
+        let mut command = Command::new(&self.binary);
+        command
+            .args(args)
+            .env_clear()
+            .env("PATH", std::env::var_os("PATH").unwrap_or_default())
+            .env("HOME", home)
+            .env("XDG_CONFIG_HOME", home.join(".config"))
+            .env("XDG_DATA_HOME", home.join(".local/share"))
+            .env("XDG_CACHE_HOME", home.join(".cache"))
+            .env("SHUTL_DIR", self.scripts_dir())
+            .env("SHUTL_CONFIG", self.config_path())
+            .current_dir(home);
+        command
+    }
+
+    /// Runs shutl with `args`, without input
+    pub fn run(&self, args: &[&str]) -> Output {
+        Self::output(self.command(args).stdin(std::process::Stdio::null()))
+    }
+
+    /// Runs a prepared [`FakeHome::command`] and collects its output
+    pub fn output(command: &mut Command) -> Output {
+        let output = command
+            .output()
+            .unwrap_or_else(|e| panic!("Failed to run {:?}: {}", command.get_program(), e));
+        Output {
+            code: output.status.code(),
+            stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        }
+    }
+}
@@ -0,0 +1,107 @@
+//! Starting a script in a new tmux window for `--shutl-tmux`, so long interactive scripts
+//! don't hold up the shell they were started from. The window runs shutl again with the
+//! same command line and environment; that run does the checks, the history and the
+//! audit log. If the script fails, the window stays open until Enter is pressed so its
+//! output can be read.
+
+use std::path::Path;
+use std::process::Command;
+
+/// Runs the command given after `sh -c SCRIPT NAME`, keeping the window open if it fails
+const KEEP_ON_FAILURE: &str = r#""$@"
+status=$?
+if [ "$status" -ne 0 ]; then
+  printf '\n%s exited with %d; press Enter to close\n' "$0" "$status"
+  read -r _
+fi
+exit "$status""#;
+
+/// Variables tmux sets in its windows itself
+const TMUX_VARS: [&str; 2] = ["TMUX", "TMUX_PANE"];
+
+/// Gets the arguments of `tmux` that open a window named `name` in `cwd`, running
+/// `command` with the variables `envs`
+pub fn new_window_args(
+    name: &str,
+    cwd: &Path,
+    envs: &[(String, String)],
+    command: &[String],
+) -> Vec<String> {
+    let mut args = vec![
+        "new-window".to_string(),
+        "-n".to_string(),
+        name.to_string(),
+        "-c".to_string(),
+        cwd.display().to_string(),
+    ];
+    for (key, value) in envs {
+        if !TMUX_VARS.contains(&key.as_str()) {
+            args.push("-e".to_string());
+            args.push(format!("{}={}", key, value));
+        }
+    }
+    args.extend(["--", "sh", "-c", KEEP_ON_FAILURE, name].map(String::from));
+    args.extend(command.iter().cloned());
+    args
+}
+
+/// Opens a tmux window named `name` running `command` with the current directory and
+/// environment, and returns once it is open
+pub fn open_window(name: &str, command: &[String]) -> std::io::Result<()> {
+    if std::env::var_os("TMUX").is_none() {
+        return Err(std::io::Error::other(
+            "--shutl-tmux opens a window in the current tmux session; run it inside tmux",
+        ));
+    }
+    // Windows get the environment of the tmux server, not of this shell, so it is passed
+    let envs: Vec<(String, String)> = std::env::vars().collect();
+    let cwd = std::env::current_dir()?;
+    let status = Command::new("tmux")
+        .args(new_window_args(name, &cwd, &envs, command))
+        .status()
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => std::io::Error::new(e.kind(), "tmux is not installed"),
+            _ => e,
+        })?;
+    if !status.success() {
+        return Err(std::io::Error::other(format!(
+            "tmux could not open a window ({})",
+            status
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new_window_args() {
+        let envs = vec![
+            ("AWS_PROFILE".to_string(), "prod".to_string()),
+            ("TMUX".to_string(), "/tmp/tmux-1000/default,1,0".to_string()),
+        ];
+        let command: Vec<String> = ["/usr/bin/shutl", "db", "migrate", "--dry-run"]
+            .map(String::from)
+            .to_vec();
+        let args = new_window_args("db migrate", Path::new("/work"), &envs, &command);
+        assert_eq!(
+            args[..7],
+            [
+                "new-window",
+                "-n",
+                "db migrate",
+                "-c",
+                "/work",
+                "-e",
+                "AWS_PROFILE=prod"
+            ]
+        );
+        assert_eq!(args[7..10], ["--", "sh", "-c"]);
+        assert_eq!(
+            args[11..],
+            ["db migrate", "/usr/bin/shutl", "db", "migrate", "--dry-run"]
+        );
+    }
+}
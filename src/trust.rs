@@ -0,0 +1,134 @@
+//! Project roots the user allowed with `shutl hook allow`. A `.shutl` directory that comes
+//! with a cloned repository is code from someone else, so the shell hook only loads it once
+//! it was allowed, and again only after its scripts changed and were allowed anew. The
+//! allowed roots are stored by canonical path with a hash of their content.
+
+use crate::paths;
+use crate::persist;
+use crate::remote::sha256_hex;
+use std::collections::{BTreeMap, HashSet};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+/// Gets the file listing the allowed project roots
+pub fn allowed_file() -> Option<PathBuf> {
+    paths::data_local_dir().map(|dir| dir.join("shutl").join("allowed-projects.toml"))
+}
+
+/// Reads the allowed roots: the hash of their content, by canonical path. A missing or
+/// invalid file allows nothing.
+pub fn load(file: &Path) -> BTreeMap<String, String> {
+    let Ok(content) = std::fs::read_to_string(file) else {
+        return BTreeMap::new();
+    };
+    toml::from_str(&content).unwrap_or_else(|e| {
+        log::warn!("Ignoring invalid allow list {}: {}", file.display(), e);
+        BTreeMap::new()
+    })
+}
+
+/// Allows `root` as it is now; returns its canonical path
+pub fn allow(file: &Path, root: &Path) -> std::io::Result<PathBuf> {
+    let root = root.canonicalize()?;
+    let _lock = persist::lock_dir_of(file)?;
+    let mut allowed = load(file);
+    allowed.insert(root.to_string_lossy().into_owned(), content_hash(&root));
+    write(file, &allowed)?;
+    Ok(root)
+}
+
+/// Takes back the permission for `root`; returns whether it was allowed
+pub fn deny(file: &Path, root: &Path) -> std::io::Result<bool> {
+    let root = root.canonicalize().unwrap_or_else(|_| root.to_path_buf());
+    let _lock = persist::lock_dir_of(file)?;
+    let mut allowed = load(file);
+    if allowed.remove(&*root.to_string_lossy()).is_none() {
+        return Ok(false);
+    }
+    write(file, &allowed)?;
+    Ok(true)
+}
+
+/// Checks that `root` was allowed and has not changed since
+pub fn is_allowed(file: &Path, root: &Path) -> bool {
+    let Ok(root) = root.canonicalize() else {
+        return false;
+    };
+    load(file)
+        .get(&*root.to_string_lossy())
+        .is_some_and(|hash| *hash == content_hash(&root))
+}
+
+fn write(file: &Path, allowed: &BTreeMap<String, String>) -> std::io::Result<()> {
+    let content = toml::to_string(allowed).map_err(std::io::Error::other)?;
+    persist::write_atomic(file, content.as_bytes())
+}
+
+/// Hashes the names, executable bits and contents of every file below `root`, following
+/// symlinks (each directory once), so any change to what shutl would run changes it
+pub fn content_hash(root: &Path) -> String {
+    let mut data = Vec::new();
+    let mut visited = HashSet::new();
+    hash_dir(root, "", &mut data, &mut visited);
+    sha256_hex(&data)
+}
+
+fn hash_dir(dir: &Path, prefix: &str, data: &mut Vec<u8>, visited: &mut HashSet<PathBuf>) {
+    if !dir.canonicalize().is_ok_and(|dir| visited.insert(dir)) {
+        return;
+    }
+    let Ok(read_dir) = std::fs::read_dir(dir) else {
+        return;
+    };
+    let mut entries: Vec<_> = read_dir.filter_map(Result::ok).collect();
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let path = entry.path();
+        let name = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+        let Ok(metadata) = std::fs::metadata(&path) else {
+            continue;
+        };
+        if metadata.is_dir() {
+            hash_dir(&path, &format!("{}/", name), data, visited);
+            continue;
+        }
+        let contents = std::fs::read(&path).unwrap_or_default();
+        let executable = metadata.permissions().mode() & 0o111 != 0;
+        data.extend_from_slice(
+            format!("{}\0{}\0{}\0", name, executable, contents.len()).as_bytes(),
+        );
+        data.extend_from_slice(&contents);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_allow_and_deny() {
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("data/allowed-projects.toml");
+        let root = dir.path().join("app/.shutl");
+        std::fs::create_dir_all(root.join("db")).unwrap();
+        std::fs::write(root.join("deploy.sh"), "#!/bin/sh\necho deploy\n").unwrap();
+        assert!(!is_allowed(&file, &root));
+
+        allow(&file, &root).unwrap();
+        assert!(is_allowed(&file, &root));
+
+        // A changed or new script needs a new allow
+        std::fs::write(root.join("db/dump.sh"), "#!/bin/sh\n").unwrap();
+        assert!(!is_allowed(&file, &root));
+        allow(&file, &root).unwrap();
+        assert!(is_allowed(&file, &root));
+        std::fs::set_permissions(root.join("db/dump.sh"), PermissionsExt::from_mode(0o755))
+            .unwrap();
+        assert!(!is_allowed(&file, &root));
+
+        assert!(deny(&file, &root).unwrap());
+        assert!(!deny(&file, &root).unwrap());
+        assert!(load(&file).is_empty());
+    }
+}
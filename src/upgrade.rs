@@ -0,0 +1,241 @@
+//! Self-update from GitHub releases: finds the archive built for this platform,
+//! verifies it against the published SHA-256 checksum and replaces the running binary.
+
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::io::Read;
+use std::path::Path;
+
+/// Endpoint of the latest published release
+const LATEST_RELEASE_URL: &str = "https://api.github.com/repos/k15r/shutl/releases/latest";
+
+/// Release archives can be a few MB; anything above this is not ours
+const MAX_DOWNLOAD_BYTES: u64 = 100 * 1024 * 1024;
+
+/// A GitHub release, as far as `upgrade` needs it
+#[derive(Debug, Deserialize)]
+pub struct Release {
+    pub tag_name: String,
+    #[serde(default)]
+    pub assets: Vec<Asset>,
+}
+
+/// A file attached to a release
+#[derive(Debug, Deserialize)]
+pub struct Asset {
+    pub name: String,
+    pub browser_download_url: String,
+}
+
+/// Gets the release target this binary was built for, matching the archive names
+/// (`<target>.tar.gz`) of the release workflow
+pub fn platform_target() -> Option<&'static str> {
+    match (std::env::consts::OS, std::env::consts::ARCH) {
+        ("linux", "x86_64") => Some("x86_64-unknown-linux-gnu"),
+        ("macos", "x86_64") => Some("x86_64-apple-darwin"),
+        ("macos", "aarch64") => Some("aarch64-apple-darwin"),
+        _ => None,
+    }
+}
+
+/// Parses a version such as `v0.3.0` or `0.3.0` into its numeric parts
+pub fn parse_version(version: &str) -> Option<(u64, u64, u64)> {
+    let mut parts = version.trim().trim_start_matches('v').splitn(3, '.');
+    let mut next = || parts.next()?.parse().ok();
+    Some((next()?, next()?, next()?))
+}
+
+/// Returns true if `latest` is a newer version than `current`
+pub fn is_newer(latest: &str, current: &str) -> bool {
+    match (parse_version(latest), parse_version(current)) {
+        (Some(latest), Some(current)) => latest > current,
+        _ => false,
+    }
+}
+
+impl Release {
+    /// Finds the archive for `target` and its `.sha256` checksum file
+    pub fn assets_for(&self, target: &str) -> Result<(&Asset, &Asset), String> {
+        let archive_name = format!("{}.tar.gz", target);
+        let checksum_name = format!("{}.sha256", archive_name);
+        let find = |name: &str| self.assets.iter().find(|a| a.name == name);
+        let archive = find(&archive_name)
+            .ok_or_else(|| format!("release {} has no {}", self.tag_name, archive_name))?;
+        let checksum = find(&checksum_name).ok_or_else(|| {
+            format!(
+                "release {} has no {}, refusing to install an unverified binary",
+                self.tag_name, checksum_name
+            )
+        })?;
+        Ok((archive, checksum))
+    }
+}
+
+fn get(url: &str) -> Result<ureq::http::Response<ureq::Body>, String> {
+    ureq::get(url)
+        .header("User-Agent", concat!("shutl/", env!("CARGO_PKG_VERSION")))
+        .header("Accept", "application/vnd.github+json")
+        .call()
+        .map_err(|e| format!("{}: {}", url, e))
+}
+
+fn download(url: &str) -> Result<Vec<u8>, String> {
+    get(url)?
+        .body_mut()
+        .with_config()
+        .limit(MAX_DOWNLOAD_BYTES)
+        .read_to_vec()
+        .map_err(|e| format!("{}: {}", url, e))
+}
+
+/// Fetches the latest release from GitHub
+pub fn latest_release() -> Result<Release, String> {
+    let body = get(LATEST_RELEASE_URL)?
+        .body_mut()
+        .read_to_string()
+        .map_err(|e| e.to_string())?;
+    serde_json::from_str(&body).map_err(|e| format!("unexpected release data: {}", e))
+}
+
+/// Checks `data` against a checksum file (`<hex digest>  <file name>`, as written by
+/// `shasum -a 256`)
+pub fn verify_checksum(data: &[u8], checksum_file: &str) -> Result<(), String> {
+    let expected = checksum_file
+        .split_whitespace()
+        .next()
+        .filter(|digest| digest.len() == 64)
+        .ok_or("malformed checksum file")?
+        .to_lowercase();
+    let actual: String = Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    if actual == expected {
+        Ok(())
+    } else {
+        Err(format!(
+            "checksum mismatch: expected {}, got {}",
+            expected, actual
+        ))
+    }
+}
+
+/// Extracts the `shutl` binary from a release archive
+pub fn extract_binary(archive: &[u8]) -> Result<Vec<u8>, String> {
+    let mut tar = tar::Archive::new(flate2::read::GzDecoder::new(archive));
+    for entry in tar.entries().map_err(|e| e.to_string())? {
+        let mut entry = entry.map_err(|e| e.to_string())?;
+        let path = entry.path().map_err(|e| e.to_string())?;
+        if path.file_name().is_some_and(|name| name == "shutl") {
+            let mut binary = Vec::new();
+            entry.read_to_end(&mut binary).map_err(|e| e.to_string())?;
+            return Ok(binary);
+        }
+    }
+    Err("archive does not contain a shutl binary".to_string())
+}
+
+/// Replaces the binary at `target` with `binary`. The new file is written next to it
+/// and renamed into place, so an interrupted upgrade leaves the old binary intact.
+pub fn replace_binary(target: &Path, binary: &[u8]) -> Result<(), String> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let staged = target.with_extension("upgrade");
+    let written = std::fs::write(&staged, binary)
+        .and_then(|_| std::fs::set_permissions(&staged, std::fs::Permissions::from_mode(0o755)))
+        .and_then(|_| std::fs::rename(&staged, target));
+    written.map_err(|e| {
+        let _ = std::fs::remove_file(&staged);
+        format!("cannot replace {}: {}", target.display(), e)
+    })
+}
+
+/// Downloads, verifies and installs `release` over the running binary
+pub fn install(release: &Release, target: &str) -> Result<(), String> {
+    let (archive_asset, checksum_asset) = release.assets_for(target)?;
+    let checksum = download(&checksum_asset.browser_download_url)?;
+    let archive = download(&archive_asset.browser_download_url)?;
+    verify_checksum(&archive, &String::from_utf8_lossy(&checksum))?;
+    let binary = extract_binary(&archive)?;
+    let current = std::env::current_exe()
+        .and_then(|p| p.canonicalize())
+        .map_err(|e| format!("cannot locate the running binary: {}", e))?;
+    replace_binary(&current, &binary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn asset(name: &str) -> Asset {
+        Asset {
+            name: name.to_string(),
+            browser_download_url: format!("https://example.com/{}", name),
+        }
+    }
+
+    #[test]
+    fn test_versions() {
+        assert_eq!(parse_version("v0.3.0"), Some((0, 3, 0)));
+        assert_eq!(parse_version("1.10.2"), Some((1, 10, 2)));
+        assert_eq!(parse_version("nightly"), None);
+        assert!(is_newer("v0.10.0", "0.9.1"));
+        assert!(!is_newer("v0.3.0", "0.3.0"));
+        assert!(!is_newer("garbage", "0.3.0"));
+    }
+
+    #[test]
+    fn test_assets_for_requires_checksum() {
+        let mut release = Release {
+            tag_name: "v1.0.0".to_string(),
+            assets: vec![asset("x86_64-apple-darwin.tar.gz")],
+        };
+        let err = release.assets_for("x86_64-apple-darwin").unwrap_err();
+        assert!(err.contains("unverified"));
+        assert!(release.assets_for("aarch64-apple-darwin").is_err());
+
+        release
+            .assets
+            .push(asset("x86_64-apple-darwin.tar.gz.sha256"));
+        let (archive, checksum) = release.assets_for("x86_64-apple-darwin").unwrap();
+        assert_eq!(archive.name, "x86_64-apple-darwin.tar.gz");
+        assert_eq!(checksum.name, "x86_64-apple-darwin.tar.gz.sha256");
+    }
+
+    #[test]
+    fn test_verify_checksum() {
+        // sha256("abc")
+        let digest = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        assert!(verify_checksum(b"abc", &format!("{}  x.tar.gz\n", digest)).is_ok());
+        assert!(verify_checksum(b"abd", digest).is_err());
+        assert!(verify_checksum(b"abc", "short").is_err());
+    }
+
+    #[test]
+    fn test_extract_and_replace_binary() {
+        let mut builder = tar::Builder::new(flate2::write::GzEncoder::new(
+            Vec::new(),
+            flate2::Compression::default(),
+        ));
+        let mut header = tar::Header::new_gnu();
+        header.set_size(4);
+        header.set_mode(0o755);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, "shutl", &b"new!"[..])
+            .unwrap();
+        let archive = builder.into_inner().unwrap().finish().unwrap();
+
+        let binary = extract_binary(&archive).unwrap();
+        assert_eq!(binary, b"new!");
+        assert!(extract_binary(b"not an archive").is_err());
+
+        let dir = tempdir().unwrap();
+        let target = dir.path().join("shutl");
+        std::fs::write(&target, "old").unwrap();
+        replace_binary(&target, &binary).unwrap();
+        assert_eq!(std::fs::read(&target).unwrap(), b"new!");
+        assert!(!dir.path().join("shutl.upgrade").exists());
+    }
+}
@@ -1,4 +1,12 @@
-use crate::metadata::{ArgType, CommandMetadata, LineType, parse_command_metadata};
+use crate::env_filter::{is_valid_pattern, parse_patterns};
+use crate::k8s::Pod;
+use crate::limits::{Priority, parse_memory, parse_seconds};
+use crate::metadata::{
+    ArgType, CommandMetadata, Condition, LineType, MetadataElement, OutputFormat, Span, StdinMode,
+    parse_command_metadata, template_refs,
+};
+use crate::sandbox::Sandbox;
+use crate::script::CONTEXT_ENV_VARS;
 use std::collections::HashSet;
 use std::path::Path;
 
@@ -8,19 +16,30 @@ pub enum Severity {
     Warning,
 }
 
+impl Severity {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct ValidationDiagnostic {
     pub severity: Severity,
     pub message: String,
+    /// Metadata line the diagnostic refers to, if it is about a single line
+    pub span: Option<Span>,
 }
 
 impl std::fmt::Display for ValidationDiagnostic {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let prefix = match self.severity {
-            Severity::Error => "error",
-            Severity::Warning => "warning",
-        };
-        write!(f, "{}: {}", prefix, self.message)
+        let prefix = self.severity.label();
+        match self.span {
+            Some(span) => write!(f, "{}: line {}: {}", prefix, span.line, self.message),
+            None => write!(f, "{}: {}", prefix, self.message),
+        }
     }
 }
 
@@ -32,16 +51,19 @@ pub fn validate_script(path: &Path) -> Vec<ValidationDiagnostic> {
 pub fn validate_metadata(metadata: &CommandMetadata) -> Vec<ValidationDiagnostic> {
     let mut diagnostics = Vec::new();
     let mut seen_names: HashSet<String> = HashSet::new();
+    let mut seen_shorts: HashSet<char> = HashSet::new();
     let mut found_catchall = false;
     let mut catchall_count = 0;
 
-    for arg in &metadata.arguments {
+    for (index, arg) in metadata.arguments.iter().enumerate() {
+        let first_diagnostic = diagnostics.len();
         match arg {
             LineType::Positional(name, _desc, cfg) => {
                 if name.is_empty() {
                     diagnostics.push(ValidationDiagnostic {
                         severity: Severity::Error,
                         message: "positional argument has an empty name".to_string(),
+                        span: None,
                     });
                 }
 
@@ -49,10 +71,12 @@ pub fn validate_metadata(metadata: &CommandMetadata) -> Vec<ValidationDiagnostic
                     diagnostics.push(ValidationDiagnostic {
                         severity: Severity::Error,
                         message: format!("duplicate argument name '{}'", name),
+                        span: None,
                     });
                 }
 
-                if matches!(cfg.arg_type, Some(ArgType::CatchAll)) {
+                // Arguments with `min`/`max` take several values, like a catch-all
+                if cfg.is_multiple() {
                     catchall_count += 1;
                     found_catchall = true;
                 } else if found_catchall {
@@ -62,6 +86,37 @@ pub fn validate_metadata(metadata: &CommandMetadata) -> Vec<ValidationDiagnostic
                             "positional argument '{}' appears after catch-all argument",
                             name
                         ),
+                        span: None,
+                    });
+                }
+
+                if let (Some(min), Some(max)) = (cfg.min, cfg.max)
+                    && min > max
+                {
+                    diagnostics.push(ValidationDiagnostic {
+                        severity: Severity::Error,
+                        message: format!(
+                            "argument '{}' has 'min:{}' greater than 'max:{}'",
+                            name, min, max
+                        ),
+                        span: None,
+                    });
+                }
+                if cfg.short.is_some() {
+                    diagnostics.push(ValidationDiagnostic {
+                        severity: Severity::Error,
+                        message: format!(
+                            "positional argument '{}' cannot have 'short' (only flags have short forms)",
+                            name
+                        ),
+                        span: None,
+                    });
+                }
+                if cfg.max == Some(0) {
+                    diagnostics.push(ValidationDiagnostic {
+                        severity: Severity::Error,
+                        message: format!("argument '{}' has 'max:0', it takes no values", name),
+                        span: None,
                     });
                 }
 
@@ -72,6 +127,7 @@ pub fn validate_metadata(metadata: &CommandMetadata) -> Vec<ValidationDiagnostic
                             "positional argument '{}' cannot be 'bool' (only flags support bool)",
                             name
                         ),
+                        span: None,
                     });
                 }
 
@@ -82,6 +138,7 @@ pub fn validate_metadata(metadata: &CommandMetadata) -> Vec<ValidationDiagnostic
                             "argument '{}' has both 'required' and 'default' — 'required' will be ignored",
                             name
                         ),
+                        span: None,
                     });
                 }
 
@@ -96,16 +153,7 @@ pub fn validate_metadata(metadata: &CommandMetadata) -> Vec<ValidationDiagnostic
                             "argument '{}' combines path type with 'options' — these are mutually exclusive",
                             name
                         ),
-                    });
-                }
-
-                if matches!(cfg.arg_type, Some(ArgType::CatchAll)) && !cfg.options.is_empty() {
-                    diagnostics.push(ValidationDiagnostic {
-                        severity: Severity::Warning,
-                        message: format!(
-                            "catch-all argument '{}' has 'options' which won't be enforced per-value",
-                            name
-                        ),
+                        span: None,
                     });
                 }
             }
@@ -115,6 +163,7 @@ pub fn validate_metadata(metadata: &CommandMetadata) -> Vec<ValidationDiagnostic
                     diagnostics.push(ValidationDiagnostic {
                         severity: Severity::Error,
                         message: "flag has an empty name".to_string(),
+                        span: None,
                     });
                 }
 
@@ -122,6 +171,7 @@ pub fn validate_metadata(metadata: &CommandMetadata) -> Vec<ValidationDiagnostic
                     diagnostics.push(ValidationDiagnostic {
                         severity: Severity::Error,
                         message: format!("duplicate argument name '{}'", name),
+                        span: None,
                     });
                 }
 
@@ -132,6 +182,32 @@ pub fn validate_metadata(metadata: &CommandMetadata) -> Vec<ValidationDiagnostic
                             "flag '{}' combines 'bool' with 'options' — these are mutually exclusive",
                             name
                         ),
+                        span: None,
+                    });
+                }
+
+                if cfg.tristate && cfg.default.is_some() {
+                    diagnostics.push(ValidationDiagnostic {
+                        severity: Severity::Warning,
+                        message: format!(
+                            "flag '{}' has a default, so 'tristate' never leaves it unset",
+                            name
+                        ),
+                        span: None,
+                    });
+                }
+
+                if matches!(cfg.arg_type, Some(ArgType::Bool))
+                    && let Some(default) = &cfg.default
+                    && cfg.bool_default().is_none()
+                {
+                    diagnostics.push(ValidationDiagnostic {
+                        severity: Severity::Error,
+                        message: format!(
+                            "bool flag '{}' has default '{}' — expected 'true' or 'false'",
+                            name, default
+                        ),
+                        span: None,
                     });
                 }
 
@@ -147,6 +223,7 @@ pub fn validate_metadata(metadata: &CommandMetadata) -> Vec<ValidationDiagnostic
                             "flag '{}' combines 'bool' with a path type — these are mutually exclusive",
                             name
                         ),
+                        span: None,
                     });
                 }
 
@@ -161,6 +238,7 @@ pub fn validate_metadata(metadata: &CommandMetadata) -> Vec<ValidationDiagnostic
                             "flag '{}' combines path type with 'options' — these are mutually exclusive",
                             name
                         ),
+                        span: None,
                     });
                 }
 
@@ -171,6 +249,7 @@ pub fn validate_metadata(metadata: &CommandMetadata) -> Vec<ValidationDiagnostic
                             "flag '{}' has both 'required' and 'default' — 'required' will be ignored",
                             name
                         ),
+                        span: None,
                     });
                 }
 
@@ -181,18 +260,216 @@ pub fn validate_metadata(metadata: &CommandMetadata) -> Vec<ValidationDiagnostic
                             "flag '{}' cannot be a catch-all (only positional arguments support '...')",
                             name
                         ),
+                        span: None,
+                    });
+                }
+
+                if matches!(cfg.arg_type, Some(ArgType::Bool)) && cfg.allow_hyphen {
+                    diagnostics.push(ValidationDiagnostic {
+                        severity: Severity::Warning,
+                        message: format!(
+                            "flag '{}' is 'bool' and takes no value, 'allow-hyphen' has no effect",
+                            name
+                        ),
+                        span: None,
+                    });
+                }
+
+                if matches!(cfg.arg_type, Some(ArgType::Bool)) && cfg.expand {
+                    diagnostics.push(ValidationDiagnostic {
+                        severity: Severity::Warning,
+                        message: format!(
+                            "flag '{}' is 'bool' and takes no value, 'expand' has no effect",
+                            name
+                        ),
+                        span: None,
+                    });
+                }
+
+                if cfg.min.is_some() || cfg.max.is_some() {
+                    diagnostics.push(ValidationDiagnostic {
+                        severity: Severity::Error,
+                        message: format!(
+                            "flag '{}' cannot have 'min'/'max' (only positional arguments take several values)",
+                            name
+                        ),
+                        span: None,
                     });
                 }
+
+                if let Some(short) = cfg.short {
+                    if short == 'h' {
+                        diagnostics.push(ValidationDiagnostic {
+                            severity: Severity::Error,
+                            message: format!("flag '{}' cannot use 'short:h', -h is help", name),
+                            span: None,
+                        });
+                    } else if !seen_shorts.insert(short) {
+                        diagnostics.push(ValidationDiagnostic {
+                            severity: Severity::Error,
+                            message: format!(
+                                "flag '{}' has 'short:{}', which another flag already uses",
+                                name, short
+                            ),
+                            span: None,
+                        });
+                    }
+                }
             }
 
             LineType::Description(_) => {}
         }
+
+        if let LineType::Positional(name, _, cfg) | LineType::Flag(name, _, cfg) = arg
+            && (cfg.must_exist || cfg.create)
+        {
+            let is_path = matches!(
+                cfg.arg_type,
+                Some(ArgType::File | ArgType::Dir | ArgType::Path)
+            );
+            if cfg.must_exist && cfg.create {
+                diagnostics.push(ValidationDiagnostic {
+                    severity: Severity::Error,
+                    message: format!(
+                        "argument '{}' cannot be both 'must-exist' and 'create'",
+                        name
+                    ),
+                    span: None,
+                });
+            } else if !is_path {
+                diagnostics.push(ValidationDiagnostic {
+                    severity: Severity::Warning,
+                    message: format!(
+                        "argument '{}' is not a 'file', 'dir' or 'path', 'must-exist'/'create' have no effect",
+                        name
+                    ),
+                    span: None,
+                });
+            }
+        }
+
+        if let LineType::Positional(name, _, _) | LineType::Flag(name, _, _) = arg {
+            let env_name = format!("SHUTL_{}", name.replace('-', "_").to_uppercase());
+            if CONTEXT_ENV_VARS.contains(&env_name.as_str()) {
+                diagnostics.push(ValidationDiagnostic {
+                    severity: Severity::Warning,
+                    message: format!(
+                        "argument '{}' maps to {} which is reserved for the invocation context and will be overwritten",
+                        name, env_name
+                    ),
+                    span: None,
+                });
+            }
+        }
+
+        let span = metadata.argument_span(index);
+        for diagnostic in &mut diagnostics[first_diagnostic..] {
+            diagnostic.span = span;
+        }
+    }
+
+    // A templated default (`[default:{{input}}.out]`) refers to other arguments
+    for (index, arg) in metadata.arguments.iter().enumerate() {
+        let (LineType::Positional(name, _, cfg) | LineType::Flag(name, _, cfg)) = arg else {
+            continue;
+        };
+        for reference in cfg
+            .default
+            .as_deref()
+            .map(template_refs)
+            .unwrap_or_default()
+        {
+            let message = if reference == name {
+                format!("default of '{}' refers to itself", name)
+            } else if !seen_names.contains(reference) {
+                format!(
+                    "default of '{}' refers to unknown argument '{}'",
+                    name, reference
+                )
+            } else {
+                continue;
+            };
+            diagnostics.push(ValidationDiagnostic {
+                severity: Severity::Error,
+                message,
+                span: metadata.argument_span(index),
+            });
+        }
+    }
+
+    for spanned in &metadata.elements {
+        let (severity, message) = match &spanned.element {
+            MetadataElement::Unknown(text) => (
+                Severity::Warning,
+                format!("unrecognized metadata '{}' is ignored", text),
+            ),
+            MetadataElement::MaxMemory(size) => match parse_memory(size) {
+                Ok(_) => continue,
+                Err(e) => (Severity::Error, format!("max-memory: {}", e)),
+            },
+            MetadataElement::MaxCpuSeconds(seconds) => match parse_seconds(seconds) {
+                Ok(_) => continue,
+                Err(e) => (Severity::Error, format!("max-cpu-seconds: {}", e)),
+            },
+            MetadataElement::Priority(priority) => match priority.parse::<Priority>() {
+                Ok(_) => continue,
+                Err(e) => (Severity::Error, format!("priority: {}", e)),
+            },
+            MetadataElement::Stdin(mode) => match mode.parse::<StdinMode>() {
+                Ok(_) => continue,
+                Err(e) => (Severity::Error, format!("stdin: {}", e)),
+            },
+            MetadataElement::Output(format) => match format.parse::<OutputFormat>() {
+                Ok(_) => continue,
+                Err(e) => (Severity::Error, format!("output: {}", e)),
+            },
+            MetadataElement::EnabledIf(condition) => match condition.parse::<Condition>() {
+                Ok(_) => continue,
+                Err(e) => (Severity::Error, format!("enabled-if: {}", e)),
+            },
+            MetadataElement::Sandbox(list) => match list.parse::<Sandbox>() {
+                Ok(_) => continue,
+                Err(e) => (
+                    Severity::Error,
+                    format!("sandbox: {} (the script runs with every restriction)", e),
+                ),
+            },
+            MetadataElement::K8s(pod) => match pod.parse::<Pod>() {
+                Ok(_) => continue,
+                Err(e) => (
+                    Severity::Error,
+                    format!("k8s: {} (the script is not run)", e),
+                ),
+            },
+            MetadataElement::EnvAllow(list) | MetadataElement::EnvDeny(list) => {
+                match parse_patterns(list)
+                    .into_iter()
+                    .find(|p| !is_valid_pattern(p))
+                {
+                    None => continue,
+                    Some(pattern) => (
+                        Severity::Error,
+                        format!(
+                            "invalid variable pattern '{}', expected a name that may contain '*'",
+                            pattern
+                        ),
+                    ),
+                }
+            }
+            _ => continue,
+        };
+        diagnostics.push(ValidationDiagnostic {
+            severity,
+            message,
+            span: Some(spanned.span),
+        });
     }
 
     if catchall_count > 1 {
         diagnostics.push(ValidationDiagnostic {
             severity: Severity::Error,
             message: "multiple catch-all arguments defined — only one is allowed".to_string(),
+            span: None,
         });
     }
 
@@ -211,6 +488,29 @@ pub fn format_diagnostics(diagnostics: &[ValidationDiagnostic]) -> String {
         .join("\n")
 }
 
+/// Formats diagnostics of the script at `path` as `path:line:column: severity: message`,
+/// the format editors and terminals recognize as a jump target
+pub fn format_diagnostics_at(path: &Path, diagnostics: &[ValidationDiagnostic]) -> String {
+    diagnostics
+        .iter()
+        .map(|d| {
+            let prefix = d.severity.label();
+            match d.span {
+                Some(span) => format!(
+                    "{}:{}:{}: {}: {}",
+                    path.display(),
+                    span.line,
+                    span.column,
+                    prefix,
+                    d.message
+                ),
+                None => format!("{}: {}: {}", path.display(), prefix, d.message),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
 /// Formats diagnostics as shell comments for embedding in a script file (kubectl-style).
 pub fn format_diagnostics_as_comments(diagnostics: &[ValidationDiagnostic]) -> String {
     let mut lines = vec![
@@ -233,11 +533,114 @@ mod tests {
 
     fn meta_with(args: Vec<LineType>) -> CommandMetadata {
         CommandMetadata {
-            description: String::new(),
             arguments: args,
+            ..Default::default()
         }
     }
 
+    #[test]
+    fn test_diagnostics_point_at_metadata_line() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("x.sh");
+        std::fs::write(
+            &path,
+            "#!/bin/bash\n#@arg:name - Name\n  #@flag:name - Duplicate\n#@flag:nodesc\n",
+        )
+        .unwrap();
+        let d = validate_script(&path);
+        assert_eq!(d.len(), 2);
+        assert_eq!(
+            d[0].span,
+            Some(Span {
+                line: 3,
+                column: 3,
+                end_column: 26
+            })
+        );
+        assert_eq!(
+            d[0].to_string(),
+            "error: line 3: duplicate argument name 'name'"
+        );
+        assert_eq!(
+            d[1].to_string(),
+            "warning: line 4: unrecognized metadata 'flag:nodesc' is ignored"
+        );
+        assert_eq!(
+            format_diagnostics_at(Path::new("x.sh"), &d[..1]),
+            "x.sh:3:3: error: duplicate argument name 'name'"
+        );
+    }
+
+    #[test]
+    fn test_invalid_resource_limits() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("x.sh");
+        std::fs::write(
+            &path,
+            "#!/bin/bash\n#@max-memory: 2G\n#@max-memory: lots\n#@max-cpu-seconds: -1\n#@priority: urgent\n",
+        )
+        .unwrap();
+        let messages: Vec<_> = validate_script(&path)
+            .iter()
+            .map(|d| d.to_string())
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                "error: line 3: max-memory: invalid memory size 'lots', expected e.g. 512M or 2G",
+                "error: line 4: max-cpu-seconds: invalid number of seconds '-1'",
+                "error: line 5: priority: invalid priority 'urgent', expected normal, low, idle",
+            ]
+        );
+
+        std::fs::write(
+            &path,
+            "#!/bin/bash\n#@enabled-if: env:KUBECONFIG\n#@enabled-if: path:/usr\n",
+        )
+        .unwrap();
+        let messages: Vec<_> = validate_script(&path)
+            .iter()
+            .map(|d| d.to_string())
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                "error: line 3: enabled-if: unknown condition 'path', expected env:VAR or exec:COMMAND"
+            ]
+        );
+
+        std::fs::write(
+            &path,
+            "#!/bin/bash\n#@arg:input - Input\n#@flag:output - Output [default:{{input}}.out]\n#@flag:log - Log [default:{{ outptu }}.log]\n",
+        )
+        .unwrap();
+        let messages: Vec<_> = validate_script(&path)
+            .iter()
+            .map(|d| d.to_string())
+            .collect();
+        assert_eq!(
+            messages,
+            vec!["error: line 4: default of 'log' refers to unknown argument 'outptu'"]
+        );
+
+        std::fs::write(
+            &path,
+            "#!/bin/bash\n#@arg:input - Input [file,must-exist,create]\n#@flag:name - Name [must-exist]\n",
+        )
+        .unwrap();
+        let messages: Vec<_> = validate_script(&path)
+            .iter()
+            .map(|d| d.to_string())
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                "error: line 2: argument 'input' cannot be both 'must-exist' and 'create'",
+                "warning: line 3: argument 'name' is not a 'file', 'dir' or 'path', 'must-exist'/'create' have no effect",
+            ]
+        );
+    }
+
     #[test]
     fn test_valid_metadata_no_errors() {
         let m = meta_with(vec![
@@ -272,6 +675,32 @@ mod tests {
         assert!(d.iter().any(|d| d.message.contains("duplicate")));
     }
 
+    #[test]
+    fn test_short_flags() {
+        let short = |c| Config {
+            short: Some(c),
+            ..Default::default()
+        };
+        let m = meta_with(vec![
+            LineType::Positional("file".into(), "File".into(), short('f')),
+            LineType::Flag("verbose".into(), "Verbose".into(), short('v')),
+            LineType::Flag("version".into(), "Version".into(), short('v')),
+            LineType::Flag("host".into(), "Host".into(), short('h')),
+        ]);
+        let messages: Vec<String> = validate_metadata(&m)
+            .into_iter()
+            .map(|d| d.message)
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                "positional argument 'file' cannot have 'short' (only flags have short forms)",
+                "flag 'version' has 'short:v', which another flag already uses",
+                "flag 'host' cannot use 'short:h', -h is help",
+            ]
+        );
+    }
+
     #[test]
     fn test_bool_on_positional() {
         let m = meta_with(vec![LineType::Positional(
@@ -304,6 +733,28 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_bool_default() {
+        let flag = |default: &str| {
+            meta_with(vec![LineType::Flag(
+                "color".into(),
+                "desc".into(),
+                Config {
+                    arg_type: Some(ArgType::Bool),
+                    default: Some(default.into()),
+                    ..Default::default()
+                },
+            )])
+        };
+        assert!(validate_metadata(&flag("true")).is_empty());
+        assert!(validate_metadata(&flag("false")).is_empty());
+        let d = validate_metadata(&flag("yes"));
+        assert!(
+            d.iter()
+                .any(|d| d.severity == Severity::Error && d.message.contains("'yes'"))
+        );
+    }
+
     #[test]
     fn test_bool_with_options() {
         let m = meta_with(vec![LineType::Flag(
@@ -398,28 +849,79 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_value_counts() {
+        let m = meta_with(vec![
+            LineType::Positional(
+                "files".into(),
+                "desc".into(),
+                Config {
+                    min: Some(3),
+                    max: Some(2),
+                    ..Default::default()
+                },
+            ),
+            LineType::Positional("target".into(), "desc".into(), Config::default()),
+            LineType::Flag(
+                "tag".into(),
+                "desc".into(),
+                Config {
+                    max: Some(2),
+                    ..Default::default()
+                },
+            ),
+        ]);
+        let messages: Vec<_> = validate_metadata(&m)
+            .into_iter()
+            .map(|d| d.message)
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                "argument 'files' has 'min:3' greater than 'max:2'",
+                "positional argument 'target' appears after catch-all argument",
+                "flag 'tag' cannot have 'min'/'max' (only positional arguments take several values)",
+            ]
+        );
+    }
+
     #[test]
     fn test_format_diagnostics_as_comments() {
         let diags = vec![ValidationDiagnostic {
             severity: Severity::Error,
             message: "something wrong".into(),
+            span: None,
         }];
         let out = format_diagnostics_as_comments(&diags);
         assert!(out.contains("# error: something wrong"));
         assert!(out.contains("VALIDATION ERRORS"));
     }
 
+    #[test]
+    fn test_reserved_context_env_name() {
+        let meta = meta_with(vec![
+            LineType::Flag("root".into(), "Root".into(), Config::default()),
+            LineType::Positional("command".into(), "Command".into(), Config::default()),
+        ]);
+        let diags = validate_metadata(&meta);
+        assert_eq!(diags.len(), 1);
+        assert_eq!(diags[0].severity, Severity::Warning);
+        assert!(diags[0].message.contains("SHUTL_ROOT"));
+    }
+
     #[test]
     fn test_has_errors() {
         let only_warnings = vec![ValidationDiagnostic {
             severity: Severity::Warning,
             message: "warn".into(),
+            span: None,
         }];
         assert!(!has_errors(&only_warnings));
 
         let with_error = vec![ValidationDiagnostic {
             severity: Severity::Error,
             message: "err".into(),
+            span: None,
         }];
         assert!(has_errors(&with_error));
     }
@@ -0,0 +1,147 @@
+//! Commands defined in the config (`[commands.NAME]`) instead of a script file. Each one
+//! becomes a generated `sh` script, so it is listed, completed, documented and run like
+//! any other; the script is written below the cache directory when it is run.
+
+use crate::config::CommandConfig;
+use crate::paths;
+use crate::script::is_safe_component;
+use crate::source::{MemorySource, ScriptSource, SourceEntry, write_local_copy};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Gets the directory the generated scripts are written to, which is also the root the
+/// commands are mounted from
+pub fn commands_dir() -> Option<PathBuf> {
+    paths::cache_dir().map(|dir| dir.join("shutl").join("commands"))
+}
+
+/// Generates the script of a command: its description and arguments as metadata, then
+/// the command line
+pub fn script_contents(command: &CommandConfig) -> String {
+    let mut contents = String::from("#!/bin/sh\n");
+    if !command.description.is_empty() {
+        contents.push_str(&format!("#@description: {}\n", command.description));
+    }
+    for arg in &command.args {
+        contents.push_str(&format!("#@{}\n", arg));
+    }
+    contents.push_str(&command.run);
+    contents.push('\n');
+    contents
+}
+
+/// The config commands as scripts below `root`
+#[derive(Debug, Clone)]
+pub struct VirtualSource {
+    scripts: MemorySource,
+}
+
+impl VirtualSource {
+    /// Creates the source for `commands`. Names with components that are not plain
+    /// file names are skipped with a warning.
+    pub fn new(root: &Path, commands: &BTreeMap<String, CommandConfig>) -> Self {
+        let mut scripts = MemorySource::new();
+        scripts.add_dir(root);
+        for (name, command) in commands {
+            if !name.split('/').all(is_safe_component) {
+                log::warn!("Ignoring command {:?}: invalid name", name);
+                continue;
+            }
+            scripts.add_script(root.join(name), &script_contents(command));
+        }
+        VirtualSource { scripts }
+    }
+}
+
+impl ScriptSource for VirtualSource {
+    fn list_dir(&self, dir: &Path) -> Vec<SourceEntry> {
+        self.scripts.list_dir(dir)
+    }
+
+    fn read_to_string(&self, path: &Path) -> Option<String> {
+        self.scripts.read_to_string(path)
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        self.scripts.is_dir(path)
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        self.scripts.is_file(path)
+    }
+
+    fn is_executable(&self, path: &Path) -> bool {
+        self.scripts.is_executable(path)
+    }
+
+    /// Writes the generated script unless the file on disk is already up to date
+    fn local_path(&self, path: &Path) -> Option<PathBuf> {
+        let contents = self.scripts.read_to_string(path)?;
+        if std::fs::read_to_string(path).ok().as_deref() != Some(contents.as_str())
+            && let Err(e) = write_local_copy(path, contents.as_bytes(), true)
+        {
+            log::warn!("Failed to write {}: {}", path.display(), e);
+            return None;
+        }
+        Some(path.to_path_buf())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::EntryKind;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_virtual_source() {
+        let dir = tempdir().unwrap();
+        let root = dir.path().join("commands");
+        let commands = BTreeMap::from([
+            (
+                "ports".to_string(),
+                CommandConfig {
+                    description: "List ports".to_string(),
+                    run: "echo ports".to_string(),
+                    args: Vec::new(),
+                },
+            ),
+            (
+                "k8s/logs".to_string(),
+                CommandConfig {
+                    run: "echo \"$SHUTL_POD\"".to_string(),
+                    args: vec!["arg:pod - Pod name".to_string()],
+                    ..Default::default()
+                },
+            ),
+            ("../escape".to_string(), CommandConfig::default()),
+        ]);
+        let source = VirtualSource::new(&root, &commands);
+
+        let mut entries: Vec<_> = source
+            .list_dir(&root)
+            .into_iter()
+            .map(|e| (e.name, e.kind))
+            .collect();
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            entries,
+            vec![
+                ("k8s".to_string(), EntryKind::Dir),
+                ("ports".to_string(), EntryKind::File),
+            ]
+        );
+        let logs = root.join("k8s/logs");
+        assert_eq!(source.command_metadata(&logs).arguments.len(), 1);
+        assert_eq!(
+            source.command_metadata(&root.join("ports")).description,
+            "List ports"
+        );
+
+        let local = source.local_path(&logs).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&local).unwrap(),
+            "#!/bin/sh\n#@arg:pod - Pod name\necho \"$SHUTL_POD\"\n"
+        );
+    }
+}
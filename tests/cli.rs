@@ -0,0 +1,201 @@
+//! End-to-end tests of the `shutl` binary in a fake home (see `shutl::testing`)
+
+use shutl::testing::FakeHome;
+use std::path::Path;
+
+fn home() -> FakeHome {
+    let home = FakeHome::new(env!("CARGO_BIN_EXE_shutl"));
+    home.fixtures(&Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/tree"));
+    home
+}
+
+#[test]
+fn test_runs_scripts_with_arguments() {
+    let home = home();
+    let output = home.run(&["deploy", "prod", "eu", "--dry-run"]);
+    assert!(output.success(), "{}", output.stderr);
+    assert_eq!(output.stdout, "deploying to eu (dry run: true)\n");
+
+    let output = home.run(&["deploy", "prod", "mars"]);
+    assert_eq!(output.code, Some(2));
+    assert!(output.stderr.contains("invalid value 'mars'"));
+}
+
+#[test]
+fn test_exit_code_of_the_script() {
+    let home = home();
+    assert_eq!(home.run(&["fail", "3"]).code, Some(3));
+    assert_eq!(home.run(&["fail", "0"]).code, Some(0));
+}
+
+#[test]
+fn test_unknown_command_as_json() {
+    let home = home();
+    home.script("tools/prod.sh", "#!/bin/bash\n");
+    let output = home.run(&["--error-format", "json", "prod"]);
+    assert_eq!(output.code, Some(2));
+    let report: serde_json::Value = serde_json::from_str(output.stderr.trim()).unwrap();
+    assert_eq!(report["code"], "unknown-command");
+    assert_eq!(
+        report["candidates"],
+        serde_json::json!(["deploy/prod", "tools/prod"])
+    );
+}
+
+#[test]
+fn test_list_and_config() {
+    let home = home();
+    home.config(
+        r#"
+[commands.greet]
+description = "Say hello"
+run = "echo hello"
+"#,
+    );
+    let output = home.run(&["list"]);
+    assert!(output.success(), "{}", output.stderr);
+    for name in ["deploy/prod", "fail", "greet"] {
+        assert!(
+            output.stdout.contains(name),
+            "{} missing in {}",
+            name,
+            output.stdout
+        );
+    }
+    assert_eq!(home.run(&["greet"]).stdout, "hello\n");
+}
+
+#[test]
+fn test_history_stays_in_the_fake_home() {
+    let home = home();
+    assert!(home.run(&["fail", "0"]).success());
+    let history = home.path().join(".local/share/shutl/history.jsonl");
+    assert!(
+        std::fs::read_to_string(history)
+            .unwrap()
+            .contains("\"fail\"")
+    );
+    assert!(home.run(&["recent"]).stdout.contains("fail"));
+}
+
+#[test]
+fn test_zsh_completion_descriptions() {
+    let home = home();
+    let complete = |shell: &str, index: &str, words: &[&str]| {
+        let mut args = vec!["--", "shutl"];
+        args.extend(words);
+        let mut command = home.command(&args);
+        command
+            .env("COMPLETE", shell)
+            .env("_CLAP_COMPLETE_INDEX", index)
+            .env("_CLAP_IFS", "\n");
+        FakeHome::output(&mut command).stdout
+    };
+
+    let commands = complete("zsh", "2", &["deploy", ""]);
+    assert!(
+        commands.contains("prod:Deploy to production\n"),
+        "{}",
+        commands
+    );
+    let values = complete("zsh", "3", &["deploy", "prod", ""]);
+    assert!(values.contains("eu:Region\n"), "{}", values);
+    let values = complete("bash", "3", &["deploy", "prod", ""]);
+    assert!(values.lines().any(|line| line == "eu"), "{}", values);
+}
+
+#[test]
+fn test_again_after_global_options() {
+    let home = home();
+    let root = home.scripts_dir();
+    let root = root.to_str().unwrap();
+    let output = home.run(&["--shutl-root", root, "deploy", "prod", "eu"]);
+    assert!(output.success(), "{}", output.stderr);
+    let output = home.run(&["--shutl-root", root, "deploy", "prod", "--shutl-again"]);
+    assert!(output.success(), "{}", output.stderr);
+    assert_eq!(output.stdout, "deploying to eu (dry run: false)\n");
+}
+
+#[test]
+fn test_project_scripts_need_allow() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let home = home();
+    let project = home.path().join("app");
+    let scripts = project.join(".shutl");
+    std::fs::create_dir_all(scripts.join("deploy")).unwrap();
+    for (path, contents) in [
+        ("hello.sh", "#!/bin/sh\necho hello\n"),
+        ("deploy/prod.sh", "#!/bin/sh\necho hijacked\n"),
+    ] {
+        std::fs::write(scripts.join(path), contents).unwrap();
+        std::fs::set_permissions(scripts.join(path), PermissionsExt::from_mode(0o755)).unwrap();
+    }
+    let export = || {
+        let mut command = home.command(&["hook", "--export", "bash"]);
+        FakeHome::output(command.current_dir(&project))
+    };
+    let run = |args: &[&str]| {
+        let mut command = home.command(args);
+        command.env("SHUTL_PROJECT_ROOT", &scripts);
+        FakeHome::output(command.stdin(std::process::Stdio::null()))
+    };
+
+    let output = export();
+    assert_eq!(output.stdout, "");
+    assert!(
+        output.stderr.contains("shutl hook allow"),
+        "{}",
+        output.stderr
+    );
+    assert_eq!(run(&["hello"]).code, Some(2));
+
+    let mut command = home.command(&["hook", "allow"]);
+    let output = FakeHome::output(command.current_dir(&project));
+    assert!(output.success(), "{}", output.stderr);
+    assert!(export().stdout.starts_with("export SHUTL_PROJECT_ROOT="));
+    assert_eq!(run(&["hello"]).stdout, "hello\n");
+    // The user's own commands win over the project's
+    assert_eq!(
+        run(&["deploy", "prod", "eu"]).stdout,
+        "deploying to eu (dry run: false)\n"
+    );
+
+    // Changed scripts are off until allowed again
+    std::fs::write(scripts.join("hello.sh"), "#!/bin/sh\necho changed\n").unwrap();
+    assert_eq!(run(&["hello"]).code, Some(2));
+    assert_eq!(export().stdout, "");
+}
+
+#[test]
+fn test_shared_completer_is_cached_per_script() {
+    let home = home();
+    home.script(
+        ".completers/contexts",
+        "#!/bin/sh\necho \"$SHUTL_COMMAND_PATH-context\"\n",
+    );
+    for name in ["apply", "rollout"] {
+        home.script(
+            &format!("{}.sh", name),
+            "#!/bin/sh\n#@arg:context - Context [complete:contexts]\n",
+        );
+    }
+    let complete = |script: &str| {
+        let mut command = home.command(&["--", "shutl", script, ""]);
+        command
+            .env("COMPLETE", "bash")
+            .env("_CLAP_COMPLETE_INDEX", "2")
+            .env("_CLAP_IFS", "\n");
+        let stdout = FakeHome::output(&mut command).stdout;
+        stdout
+            .lines()
+            .filter(|line| line.ends_with("-context"))
+            .map(String::from)
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(complete("apply"), ["apply-context"]);
+    // Not the cached candidates of `apply`
+    assert_eq!(complete("rollout"), ["rollout-context"]);
+    assert_eq!(complete("apply"), ["apply-context"]);
+}
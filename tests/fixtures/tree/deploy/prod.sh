@@ -0,0 +1,5 @@
+#!/bin/bash
+#@description: Deploy to production
+#@arg:region - Region [options:eu|us]
+#@flag:dry-run - Only print what would happen [bool]
+echo "deploying to $SHUTL_REGION (dry run: ${SHUTL_DRY_RUN:-false})"
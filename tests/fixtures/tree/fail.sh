@@ -0,0 +1,4 @@
+#!/bin/bash
+#@description: Fail with the given code
+#@arg:code - Exit code
+exit "$SHUTL_CODE"